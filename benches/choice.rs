@@ -0,0 +1,44 @@
+use packrust::*;
+use std::time::{Duration, Instant};
+
+// the same statement grammar twice: once with char/keyword primitives whose first chars are
+// known, letting `or` dispatch on the next char, and once with opaque `satisfy` predicates
+fn grammar(known_first: bool) -> Parser<'static, usize> {
+    let lit = move |c: char| {
+        if known_first {
+            char(c)
+        } else {
+            satisfy(format!("'{}'", c), move |x| x == c)
+        }
+    };
+
+    let stmt = lit('a')
+        .andl(lit('='))
+        .andl(lit('1'))
+        .or(lit('b').andl(lit('(')).andl(lit(')')))
+        .or(lit('c').andl(lit('+')).andl(lit('+')))
+        .or(lit('d').andl(lit('-')).andl(lit('-')))
+        .or(lit('e').andl(lit('!')).andl(lit('!')))
+        .andl(lit(';'));
+
+    stmt.count().end()
+}
+
+fn measure(known_first: bool, source: &str) -> Duration {
+    let parser = grammar(known_first);
+    let start = Instant::now();
+    for _ in 0..20 {
+        assert_eq!(parser.run(source), Ok(500));
+    }
+    start.elapsed() / 20
+}
+
+fn main() {
+    let source = "a=1;b();c++;d--;e!!;".repeat(100);
+
+    let opaque = measure(false, &source);
+    let dispatch = measure(true, &source);
+
+    println!("opaque alternatives:     {:?}", opaque);
+    println!("first-char dispatch:     {:?}", dispatch);
+}
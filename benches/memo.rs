@@ -0,0 +1,57 @@
+use packrust::*;
+use std::time::{Duration, Instant};
+
+fn grammar(dense: bool) -> Parser<'static, usize> {
+    let ws = satisfy("space", |c| c == ' ').many().rename("ws");
+    let ident = satisfy("letter", |c| c.is_ascii_alphabetic())
+        .many()
+        .rename("ident");
+    let (ws, ident) = if dense {
+        (ws.dense_memo(), ident.dense_memo())
+    } else {
+        (ws, ident)
+    };
+
+    let word = ws.clone().andr(ident.clone()).andl(ws.clone());
+    let assign = word
+        .clone()
+        .andl(char('='))
+        .and(word.clone())
+        .andl(char(';'))
+        .map(|_| 1);
+    let call = word
+        .clone()
+        .andl(char('('))
+        .andl(char(')'))
+        .andl(char(';'))
+        .map(|_| 1);
+    let decl = word.clone().andl(char(';')).map(|_| 1);
+
+    assign
+        .or(call)
+        .or(decl)
+        .many()
+        .map(|stmts| stmts.iter().sum())
+        .end()
+}
+
+fn measure(parser: &Parser<usize>, source: &str, layout: MemoLayout) -> Duration {
+    let start = Instant::now();
+    for _ in 0..20 {
+        let ctx = &mut Context::new(source).with_memo_layout(layout);
+        assert_eq!(parser.parse(0, ctx).map(|(_, n)| n), Ok(6_000));
+    }
+    start.elapsed() / 20
+}
+
+fn main() {
+    let source = "  alpha = beta; gamma(); delta;".repeat(2_000);
+
+    let sparse = measure(&grammar(false), &source, MemoLayout::Sparse);
+    let hinted = measure(&grammar(true), &source, MemoLayout::Sparse);
+    let dense = measure(&grammar(false), &source, MemoLayout::Dense);
+
+    println!("sparse layout:               {:?}", sparse);
+    println!("sparse layout, dense hints:  {:?}", hinted);
+    println!("dense layout:                {:?}", dense);
+}
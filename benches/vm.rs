@@ -0,0 +1,51 @@
+use packrust::*;
+use std::time::{Duration, Instant};
+
+fn grammar() -> Grammar {
+    let mut g = GrammarBuilder::new();
+    g.rule("expr", |g| {
+        g.get::<i64>("expr")
+            .andl(char('+'))
+            .and(g.get::<i64>("term"))
+            .map(|(l, r)| l + r)
+            .or(g.get("term"))
+    })
+    .rule("term", |g| {
+        g.get::<i64>("term")
+            .andl(char('*'))
+            .and(g.get::<i64>("factor"))
+            .map(|(l, r)| l * r)
+            .or(g.get("factor"))
+    })
+    .rule("factor", |g| {
+        g.get::<i64>("digit")
+            .or(char('(').andr(g.get("expr")).andl(char(')')))
+    })
+    .rule("digit", |_| {
+        satisfy("digit", |c| c.is_ascii_digit()).map(|c| c.to_digit(10).unwrap() as i64)
+    })
+    .rule("file", |g| g.get::<i64>("expr").end());
+    g.build().unwrap()
+}
+
+fn measure(run: impl Fn()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..20 {
+        run();
+    }
+    start.elapsed() / 20
+}
+
+fn main() {
+    let source = "1*2+(3*4)+(5*(6+7))+".repeat(2_000) + "0";
+    let grammar = grammar();
+    let file = grammar.get::<i64>("file").unwrap();
+    let program = grammar.compile();
+
+    let combinators = measure(|| assert!(file.run(source.as_str()).is_ok()));
+    let compiled =
+        measure(|| assert_eq!(program.recognize("file", source.as_str()), Ok(source.len())));
+
+    println!("combinators: {:?}", combinators);
+    println!("bytecode:    {:?}", compiled);
+}
@@ -51,41 +51,50 @@ impl std::fmt::Display for Expr {
 fn main() {
     env_logger::init();
 
-    let expr: Parser<Expr> = lazy("expr", move |expr| {
-        let term: Parser<Expr> = {
-            let expr = expr.clone();
-            lazy("term", move |term| {
-                let digit = satisfy("digit", |c| c.is_ascii_digit());
-                let int = digit
-                    .many()
-                    .try_map(|c| c.iter().collect::<String>().parse::<i32>().ok())
-                    .map(Expr::Literal)
-                    .rename("int");
-                let factor = int
-                    .or(char('(').andr(expr.clone()).andl(char(')')))
-                    .rename("factor");
-
-                term.andl(char('*'))
-                    .and(factor.clone())
-                    .map(|(left, right)| Expr::Binary {
-                        op: Operator::Mul,
-                        left: Box::new(left),
-                        right: Box::new(right),
-                    })
-                    .or(factor)
-            })
-        };
-
-        expr.andl(char('+'))
-            .and(term.clone())
+    let mut g = GrammarBuilder::new();
+    g.rule("expr", |g| {
+        g.get::<Expr>("expr")
+            .andl(char('+'))
+            .and(g.get::<Expr>("term"))
             .map(|(left, right)| Expr::Binary {
                 op: Operator::Add,
                 left: Box::new(left),
                 right: Box::new(right),
             })
-            .or(term)
+            .or(g.get::<Expr>("term"))
+    })
+    .rule("term", |g| {
+        g.get::<Expr>("term")
+            .andl(char('*'))
+            .and(g.get::<Expr>("factor"))
+            .map(|(left, right)| Expr::Binary {
+                op: Operator::Mul,
+                left: Box::new(left),
+                right: Box::new(right),
+            })
+            .or(g.get::<Expr>("factor"))
+    })
+    .rule("factor", |g| {
+        g.get::<Expr>("int")
+            .or(char('(').andr(g.get::<Expr>("expr")).andl(char(')')))
+    })
+    .rule("int", |_| {
+        let digit = satisfy("digit", |c| c.is_ascii_digit());
+        digit
+            .many()
+            .try_map(|c| c.iter().collect::<String>().parse::<i32>().ok())
+            .map(Expr::Literal)
     });
 
+    let grammar = match g.build() {
+        Ok(grammar) => grammar,
+        Err(errors) => {
+            errors.iter().for_each(|e| eprintln!("{}", e));
+            return;
+        }
+    };
+    let expr = grammar.get::<Expr>("expr").unwrap();
+
     let source = "1*2+(3*4)+(5*(6+7))";
     let res = expr.run(source);
 
@@ -0,0 +1,31 @@
+use packrust::*;
+
+fn main() {
+    env_logger::init();
+
+    let expr: Parser<i64> = lazy("expr", |expr| {
+        let digit = satisfy("digit", |c| c.is_ascii_digit());
+        let int = digit
+            .many()
+            .try_map(|c| c.iter().collect::<String>().parse::<i64>().ok())
+            .rename("int");
+        let atom = int.or(char('(').andr(expr).andl(char(')')));
+
+        pratt(atom)
+            .prefix(char('-'), 3, |x| -x)
+            .infix(char('+'), Assoc::Left, 1, |l, r| l + r)
+            .infix(char('-'), Assoc::Left, 1, |l, r| l - r)
+            .infix(char('*'), Assoc::Left, 2, |l, r| l * r)
+            .infix(char('/'), Assoc::Left, 2, |l, r| l / r)
+            .infix(char('^'), Assoc::Right, 4, |l, r| l.pow(r as u32))
+            .build()
+    })
+    .end();
+
+    let source = "1*2+(3*4)-2^3^2/-(5*(6+7))";
+    println!("source: {}", source);
+    match expr.run(source) {
+        Ok(n) => println!("\t = {}", n),
+        Err(e) => eprintln!("{}", e),
+    }
+}
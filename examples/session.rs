@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use packrust::*;
+
+// the notifications a language server gets from an editor, cut down to what parsing needs
+enum Notification {
+    Open {
+        uri: &'static str,
+        text: &'static str,
+    },
+    Change {
+        uri: &'static str,
+        range: Range<usize>,
+        text: &'static str,
+    },
+    Close {
+        uri: &'static str,
+    },
+}
+
+fn main() {
+    env_logger::init();
+
+    // `name = value;` bindings, one per line
+    let ident = satisfy("letter", |c| c.is_ascii_alphabetic()).many_collect::<String>();
+    let int = satisfy("digit", |c| c.is_ascii_digit()).many_collect::<String>();
+    let space = satisfy("space", |c| c == ' ').many();
+    let binding = ident
+        .andl(space.clone())
+        .andl(char('='))
+        .andl(space)
+        .and(int)
+        .andl(char(';'))
+        .andl(char('\n').opt());
+    let file = binding.many();
+
+    let notifications = [
+        Notification::Open {
+            uri: "file:///a.cfg",
+            text: "width = 80;\nheight = 24;\n",
+        },
+        Notification::Open {
+            uri: "file:///b.cfg",
+            text: "depth = 3;\n",
+        },
+        // the user deletes a semicolon, then types it back
+        Notification::Change {
+            uri: "file:///a.cfg",
+            range: 23..24,
+            text: "",
+        },
+        Notification::Change {
+            uri: "file:///a.cfg",
+            range: 23..23,
+            text: ";",
+        },
+        Notification::Change {
+            uri: "file:///a.cfg",
+            range: 8..10,
+            text: "120",
+        },
+        Notification::Close {
+            uri: "file:///b.cfg",
+        },
+    ];
+
+    // one session per open document
+    let mut sessions = HashMap::new();
+    for notification in notifications {
+        let uri = match notification {
+            Notification::Open { uri, text } => {
+                sessions.insert(uri, Session::open(file.clone(), text));
+                uri
+            }
+            Notification::Change { uri, range, text } => {
+                sessions.get_mut(uri).unwrap().edit(range, text);
+                uri
+            }
+            Notification::Close { uri } => {
+                sessions.remove(uri);
+                println!("{}: closed", uri);
+                continue;
+            }
+        };
+
+        // what a server would publish after every change
+        let session = sessions.get_mut(uri).unwrap();
+        let diagnostics = session.diagnostics();
+        if diagnostics.is_empty() {
+            println!("{}: {:?}", uri, session.tree().unwrap());
+        }
+        for Diagnostic { range, message } in diagnostics {
+            println!("{}: error at {:?}: {}", uri, range, message);
+        }
+    }
+}
@@ -0,0 +1,372 @@
+use proc_macro::{Delimiter, Group, Ident, Spacing, Span, TokenStream, TokenTree};
+
+use crate::tokens::{Cursor, Error, Result, code, concat, group, literal_parser, string};
+
+enum Fields {
+    Named(Vec<(Ident, TokenStream)>),
+    Unnamed(Vec<TokenStream>),
+    Unit,
+}
+
+// one way of building the type: a struct has one, an enum one per variant
+struct Alternative {
+    // the path constructing the value, `Name` or `Name::Variant`
+    constructor: TokenStream,
+    fields: Fields,
+    rule: Group,
+    span: Span,
+}
+
+enum Item {
+    // the parser for a quoted literal
+    Literal(TokenStream),
+    // the field it parses, by index into the fields
+    Field(usize),
+}
+
+pub fn expand(input: TokenStream) -> Result<TokenStream> {
+    let mut cursor = Cursor::new(input, Span::call_site());
+    let rule = attributes(&mut cursor)?;
+    visibility(&mut cursor);
+
+    let alternatives = if cursor.eat_ident("struct") {
+        let name = cursor.ident()?;
+        no_generics(&cursor)?;
+        let fields = match cursor.next() {
+            Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => {
+                Fields::Named(named_fields(g.stream())?)
+            }
+            Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => {
+                Fields::Unnamed(unnamed_fields(g.stream()))
+            }
+            _ => Fields::Unit,
+        };
+        let rule = rule.ok_or_else(|| {
+            Error::new(
+                name.span(),
+                "expected a `#[rule(...)]` attribute on the struct",
+            )
+        })?;
+        vec![Alternative {
+            constructor: TokenTree::from(name.clone()).into(),
+            fields,
+            rule,
+            span: name.span(),
+        }]
+    } else if cursor.eat_ident("enum") {
+        let name = cursor.ident()?;
+        no_generics(&cursor)?;
+        let body = match cursor.next() {
+            Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => g,
+            _ => return Err(Error::new(name.span(), "expected the enum's variants")),
+        };
+        variants(&name, body.stream())?
+    } else {
+        return Err(Error::new(
+            cursor.span(),
+            "`Parser` can only be derived for structs and enums",
+        ));
+    };
+
+    let name = type_name(&alternatives[0]);
+    let body = alternatives
+        .iter()
+        .map(generate)
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .reduce(|left, right| {
+            concat([
+                group(Delimiter::Parenthesis, left),
+                code(".or"),
+                group(Delimiter::Parenthesis, right),
+            ])
+        })
+        .ok_or_else(|| {
+            Error::new(
+                Span::call_site(),
+                "an enum without variants can't be parsed",
+            )
+        })?;
+
+    Ok(concat([
+        code("impl ::packrust::Parse for"),
+        name.clone(),
+        group(
+            Delimiter::Brace,
+            concat([
+                code("fn parser() -> ::packrust::Parser<'static, Self>"),
+                group(
+                    Delimiter::Brace,
+                    concat([
+                        code("::packrust::__private::recursive::<Self>"),
+                        group(
+                            Delimiter::Parenthesis,
+                            concat([string(&name.to_string()), code(", ||"), body]),
+                        ),
+                    ]),
+                ),
+            ]),
+        ),
+    ]))
+}
+
+fn type_name(alternative: &Alternative) -> TokenStream {
+    alternative
+        .constructor
+        .clone()
+        .into_iter()
+        .take(1)
+        .collect()
+}
+
+// skips every attribute but `#[rule(...)]`, whose contents are returned
+fn attributes(cursor: &mut Cursor) -> Result<Option<Group>> {
+    let mut rule = None;
+    while cursor.is_punct('#') {
+        cursor.next();
+        let Some(TokenTree::Group(attribute)) = cursor.next() else {
+            return Err(Error::new(cursor.span(), "expected an attribute"));
+        };
+        let mut inner = Cursor::new(attribute.stream(), attribute.span_close());
+        if inner.eat_ident("rule") {
+            match inner.next() {
+                Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => {
+                    rule = Some(g)
+                }
+                _ => return Err(Error::new(attribute.span(), "expected `#[rule(...)]`")),
+            }
+        }
+    }
+    Ok(rule)
+}
+
+fn visibility(cursor: &mut Cursor) {
+    if cursor.eat_ident("pub")
+        && let Some(TokenTree::Group(g)) = cursor.peek()
+        && g.delimiter() == Delimiter::Parenthesis
+    {
+        cursor.next();
+    }
+}
+
+fn no_generics(cursor: &Cursor) -> Result<()> {
+    if cursor.is_punct('<') {
+        return Err(Error::new(
+            cursor.span(),
+            "`Parser` can't be derived for generic types",
+        ));
+    }
+    Ok(())
+}
+
+// splits on commas outside of angle brackets, which unlike other brackets aren't groups
+fn split_commas(stream: TokenStream) -> Vec<Vec<TokenTree>> {
+    let mut parts = vec![Vec::new()];
+    let mut depth = 0usize;
+    let mut previous: Option<TokenTree> = None;
+    for token in stream {
+        if let TokenTree::Punct(p) = &token {
+            let arrow = matches!(&previous, Some(TokenTree::Punct(q)) if q.as_char() == '-' && q.spacing() == Spacing::Joint);
+            match p.as_char() {
+                '<' => depth += 1,
+                '>' if !arrow => depth = depth.saturating_sub(1),
+                ',' if depth == 0 => {
+                    parts.push(Vec::new());
+                    previous = None;
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        previous = Some(token.clone());
+        parts.last_mut().unwrap().push(token);
+    }
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+fn named_fields(stream: TokenStream) -> Result<Vec<(Ident, TokenStream)>> {
+    split_commas(stream)
+        .into_iter()
+        .map(|field| {
+            let mut cursor = Cursor::new(field.into_iter().collect(), Span::call_site());
+            attributes(&mut cursor)?;
+            visibility(&mut cursor);
+            let name = cursor.ident()?;
+            cursor.expect_punct(':')?;
+            Ok((name, cursor.until_end()))
+        })
+        .collect()
+}
+
+fn unnamed_fields(stream: TokenStream) -> Vec<TokenStream> {
+    split_commas(stream)
+        .into_iter()
+        .map(|field| {
+            let mut cursor = Cursor::new(field.into_iter().collect(), Span::call_site());
+            let _ = attributes(&mut cursor);
+            visibility(&mut cursor);
+            cursor.until_end()
+        })
+        .collect()
+}
+
+fn variants(name: &Ident, stream: TokenStream) -> Result<Vec<Alternative>> {
+    split_commas(stream)
+        .into_iter()
+        .map(|variant| {
+            let mut cursor = Cursor::new(variant.into_iter().collect(), Span::call_site());
+            let rule = attributes(&mut cursor)?;
+            let variant = cursor.ident()?;
+            let fields = match cursor.next() {
+                Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => {
+                    Fields::Named(named_fields(g.stream())?)
+                }
+                Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => {
+                    Fields::Unnamed(unnamed_fields(g.stream()))
+                }
+                _ => Fields::Unit,
+            };
+            let rule = rule.ok_or_else(|| {
+                Error::new(
+                    variant.span(),
+                    format!("expected a `#[rule(...)]` attribute on `{}`", variant),
+                )
+            })?;
+            Ok(Alternative {
+                constructor: concat([
+                    TokenTree::from(name.clone()).into(),
+                    code("::"),
+                    TokenTree::from(variant.clone()).into(),
+                ]),
+                fields,
+                rule,
+                span: variant.span(),
+            })
+        })
+        .collect()
+}
+
+// the rule's items in order, each field must be mentioned exactly once
+fn rule_items(alternative: &Alternative) -> Result<Vec<Item>> {
+    let field_count = match &alternative.fields {
+        Fields::Named(fields) => fields.len(),
+        Fields::Unnamed(fields) => fields.len(),
+        Fields::Unit => 0,
+    };
+    let mut seen = vec![false; field_count];
+    let mut next_unnamed = 0;
+    let mut items = Vec::new();
+
+    for token in alternative.rule.stream() {
+        let span = token.span();
+        let field = match token {
+            TokenTree::Literal(literal) => {
+                items.push(Item::Literal(literal_parser(&literal)?));
+                continue;
+            }
+            TokenTree::Ident(ident) if ident.to_string() == "_" => {
+                if !matches!(alternative.fields, Fields::Unnamed(_)) {
+                    return Err(Error::new(span, "`_` stands for the next unnamed field"));
+                }
+                next_unnamed += 1;
+                next_unnamed - 1
+            }
+            TokenTree::Ident(ident) => match &alternative.fields {
+                Fields::Named(fields) => fields
+                    .iter()
+                    .position(|(name, _)| name.to_string() == ident.to_string())
+                    .ok_or_else(|| Error::new(span, format!("no field named `{}`", ident)))?,
+                _ => return Err(Error::new(span, "unnamed fields are written `_`")),
+            },
+            _ => {
+                return Err(Error::new(
+                    span,
+                    "expected a literal or a field in the rule",
+                ));
+            }
+        };
+        if field >= field_count || std::mem::replace(&mut seen[field], true) {
+            return Err(Error::new(span, "every field must appear once in the rule"));
+        }
+        items.push(Item::Field(field));
+    }
+
+    if seen.contains(&false) {
+        return Err(Error::new(
+            alternative.span,
+            "every field must appear once in the rule",
+        ));
+    }
+    if items.is_empty() {
+        return Err(Error::new(alternative.rule.span(), "the rule is empty"));
+    }
+    Ok(items)
+}
+
+// `"(" _ ")"` on `Paren(Box<Expr>)` becomes
+// `char('(').and(<Box<Expr>>::parser()).and(char(')')).map(|((_, f0), _)| Expr::Paren(f0))`
+fn generate(alternative: &Alternative) -> Result<TokenStream> {
+    let items = rule_items(alternative)?;
+    let field_type = |i: usize| match &alternative.fields {
+        Fields::Named(fields) => fields[i].1.clone(),
+        Fields::Unnamed(fields) => fields[i].clone(),
+        Fields::Unit => unreachable!("unit alternatives have no fields"),
+    };
+    let parser = |item: &Item| match item {
+        Item::Literal(parser) => parser.clone(),
+        Item::Field(i) => concat([
+            code("<"),
+            field_type(*i),
+            code("as ::packrust::Parse>::parser()"),
+        ]),
+    };
+    let binding = |item: &Item| match item {
+        Item::Literal(_) => code("_"),
+        Item::Field(i) => code(&format!("__packrust_{}", i)),
+    };
+
+    let mut combined = parser(&items[0]);
+    let mut pattern = binding(&items[0]);
+    for item in &items[1..] {
+        combined = concat([
+            combined,
+            code(".and"),
+            group(Delimiter::Parenthesis, parser(item)),
+        ]);
+        pattern = group(
+            Delimiter::Parenthesis,
+            concat([pattern, code(","), binding(item)]),
+        );
+    }
+
+    let value = match &alternative.fields {
+        Fields::Named(fields) => concat([
+            alternative.constructor.clone(),
+            group(
+                Delimiter::Brace,
+                concat(fields.iter().enumerate().map(|(i, (name, _))| {
+                    concat([
+                        TokenTree::from(name.clone()).into(),
+                        code(&format!(": __packrust_{},", i)),
+                    ])
+                })),
+            ),
+        ]),
+        Fields::Unnamed(fields) => concat([
+            alternative.constructor.clone(),
+            group(
+                Delimiter::Parenthesis,
+                concat((0..fields.len()).map(|i| code(&format!("__packrust_{},", i)))),
+            ),
+        ]),
+        Fields::Unit => alternative.constructor.clone(),
+    };
+
+    let closure = concat([code("|"), pattern, code("|"), value]);
+    Ok(concat([
+        combined,
+        code(".map"),
+        group(Delimiter::Parenthesis, closure),
+    ]))
+}
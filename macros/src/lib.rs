@@ -0,0 +1,87 @@
+//! Procedural macros for `packrust`, re-exported from it behind the `macros` feature.
+
+use proc_macro::TokenStream;
+
+mod derive;
+mod peg;
+mod rule;
+mod tokens;
+
+/// Declares a grammar as a struct with one typed `Parser` field per rule.
+///
+/// ```ignore
+/// peg! {
+///     pub grammar Arith {
+///         expr: i64 = l:expr '+' r:term { l + r } / term;
+///         term: i64 = l:term '*' r:atom { l * r } / atom;
+///         atom: i64 = '(' e:expr ')' { e } / d:['0'..='9'] { d.to_digit(10).unwrap() as i64 };
+///     }
+/// }
+///
+/// assert_eq!(Arith::new().expr.run("2*(3+4)"), Ok(14));
+/// ```
+///
+/// Each rule is `name: Type = expression;`. Expressions are PEG: `/` separates
+/// alternatives, items are `"strings"` (producing `String`), `"keywords"kw` matching only
+/// whole words, so `"if"kw` doesn't match the start of `iffy`, `'c'` chars, `[pattern]`
+/// char classes written as Rust patterns, `.`, rule names, `( ... )` groups and
+/// `#{ parser }` for any Rust expression evaluating to a parser. Items take `*`, `+` and `?` suffixes and
+/// `!` / `&` lookahead prefixes. An alternative can end in a `{ block }` action computing
+/// its value from the items labelled `name:`; without one it produces the tuple of its
+/// items' values, or the value itself for a single item. Rules may refer to each other in
+/// any order, and references to undefined rules are compile errors.
+#[proc_macro]
+pub fn peg(input: TokenStream) -> TokenStream {
+    peg::expand(input).unwrap_or_else(tokens::Error::into_compile_error)
+}
+
+/// Declares mutually recursive rules as local `Parser` variables, without nesting `lazy`
+/// calls or cloning rules into each other's closures.
+///
+/// ```ignore
+/// rule! {
+///     expr: u32 = expr.andl(char('+')).and(term).map(|(l, r)| l + r).or(term);
+///     term: u32 = term.andl(char('*')).and(factor).map(|(l, r)| l * r).or(factor);
+///     factor: u32 = digit().or(char('(').andr(expr).andl(char(')')));
+/// }
+///
+/// assert_eq!(expr.run("2*(3+4)+1"), Ok(15));
+/// ```
+///
+/// Each rule is `name: Type = parser;` where `Type` is the rule's output. Every body can
+/// mention every rule, including itself and rules declared after it, as often as it likes:
+/// mentions are cloned and resolved through a shared `GrammarBuilder` placeholder named
+/// after the rule. Avoid reusing rule names for closure parameters inside the bodies.
+#[proc_macro]
+pub fn rule(input: TokenStream) -> TokenStream {
+    rule::expand(input).unwrap_or_else(tokens::Error::into_compile_error)
+}
+
+/// Implements `packrust::Parse` for a struct or enum from `#[rule(...)]` attributes.
+///
+/// ```ignore
+/// #[derive(Clone, Parser)]
+/// enum Expr {
+///     #[rule(left "+" right)]
+///     Add { left: Box<Expr>, right: Box<Atom> },
+///     #[rule(_)]
+///     Atom(Atom),
+/// }
+///
+/// #[derive(Clone, Parser)]
+/// enum Atom {
+///     #[rule("(" _ ")")]
+///     Paren(Box<Expr>),
+///     #[rule(_)]
+///     Num(u32),
+/// }
+/// ```
+///
+/// A rule lists the literals to match, strings, chars and `"keywords"kw` as in `peg!`, and
+/// the fields to parse, in order: named fields by name, unnamed ones as `_`. Each field is parsed with its type's `Parse` impl, so fields
+/// can be other derived types (recursively, including left recursion), `Box`, `Vec`,
+/// `Option`, `char` or integers. An enum tries its variants in declaration order.
+#[proc_macro_derive(Parser, attributes(rule))]
+pub fn derive_parser(input: TokenStream) -> TokenStream {
+    derive::expand(input).unwrap_or_else(tokens::Error::into_compile_error)
+}
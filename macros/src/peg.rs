@@ -0,0 +1,389 @@
+use std::collections::{HashMap, HashSet};
+
+use proc_macro::{Delimiter, Group, Ident, Span, TokenStream, TokenTree};
+
+use crate::tokens::{Cursor, Error, Result, code, concat, group, literal_parser, string};
+
+// the builder every rule body is written against, named so user actions can't shadow it
+const BUILDER: &str = "__packrust_g";
+
+struct Rule {
+    name: Ident,
+    ty: TokenStream,
+    body: Expr,
+}
+
+enum Expr {
+    Choice(Vec<Expr>),
+    // items with their optional `label:` and the action building the value from them
+    Seq {
+        items: Vec<(Option<Ident>, Expr)>,
+        action: Option<Group>,
+    },
+    Not(Box<Expr>),
+    And(Box<Expr>),
+    Many(Box<Expr>),
+    Many1(Box<Expr>),
+    Opt(Box<Expr>),
+    // the parser for a quoted literal
+    Literal(TokenStream),
+    // a char pattern, `['a'..='z' | '_']`
+    Class(TokenStream),
+    Any,
+    Rule(Ident),
+    // a Rust expression evaluating to a parser, `#{ int() }`
+    Embed(Group),
+}
+
+pub fn expand(input: TokenStream) -> Result<TokenStream> {
+    let mut cursor = Cursor::new(input, Span::call_site());
+    let vis = if cursor.eat_ident("pub") {
+        code("pub")
+    } else {
+        TokenStream::new()
+    };
+    if !cursor.eat_ident("grammar") {
+        return Err(Error::new(cursor.span(), "expected `grammar Name { ... }`"));
+    }
+    let name = cursor.ident()?;
+    let body = match cursor.next() {
+        Some(TokenTree::Group(body)) if body.delimiter() == Delimiter::Brace => body,
+        other => {
+            let span = other.map_or(Span::call_site(), |t| t.span());
+            return Err(Error::new(span, "expected the rules in braces"));
+        }
+    };
+    if !cursor.at_end() {
+        return Err(Error::new(
+            cursor.span(),
+            "unexpected tokens after the grammar",
+        ));
+    }
+
+    let rules = parse_rules(Cursor::new(body.stream(), body.span_close()))?;
+    check(&rules)?;
+    Ok(generate(vis, name, &rules))
+}
+
+fn parse_rules(mut cursor: Cursor) -> Result<Vec<Rule>> {
+    let mut rules = Vec::new();
+    while !cursor.at_end() {
+        let name = cursor.ident()?;
+        cursor.expect_punct(':')?;
+        let ty = cursor.until_punct('=');
+        if ty.is_empty() {
+            return Err(Error::new(cursor.span(), "expected the rule's output type"));
+        }
+        cursor.expect_punct('=')?;
+        let body = parse_choice(&mut cursor)?;
+        cursor.expect_punct(';')?;
+        rules.push(Rule { name, ty, body });
+    }
+    Ok(rules)
+}
+
+fn parse_choice(cursor: &mut Cursor) -> Result<Expr> {
+    let mut alternatives = vec![parse_seq(cursor)?];
+    while cursor.eat_punct('/') {
+        alternatives.push(parse_seq(cursor)?);
+    }
+    Ok(match alternatives.len() {
+        1 => alternatives.pop().unwrap(),
+        _ => Expr::Choice(alternatives),
+    })
+}
+
+fn parse_seq(cursor: &mut Cursor) -> Result<Expr> {
+    let mut items = Vec::new();
+    let mut action = None;
+    while !cursor.at_end() && !cursor.is_punct('/') && !cursor.is_punct(';') {
+        if let Some(TokenTree::Group(block)) = cursor.peek()
+            && block.delimiter() == Delimiter::Brace
+        {
+            action = Some(block.clone());
+            cursor.next();
+            break;
+        }
+        let label = match (cursor.peek(), cursor.peek_nth(1)) {
+            (Some(TokenTree::Ident(label)), Some(TokenTree::Punct(colon)))
+                if colon.as_char() == ':' =>
+            {
+                let label = label.clone();
+                cursor.next();
+                cursor.next();
+                Some(label)
+            }
+            _ => None,
+        };
+        items.push((label, parse_prefix(cursor)?));
+    }
+
+    if items.is_empty() {
+        return Err(Error::new(cursor.span(), "expected an expression"));
+    }
+    if action.is_none() && items.len() == 1 && items[0].0.is_none() {
+        return Ok(items.pop().unwrap().1);
+    }
+    Ok(Expr::Seq { items, action })
+}
+
+fn parse_prefix(cursor: &mut Cursor) -> Result<Expr> {
+    if cursor.eat_punct('!') {
+        return Ok(Expr::Not(Box::new(parse_suffix(cursor)?)));
+    }
+    if cursor.eat_punct('&') {
+        return Ok(Expr::And(Box::new(parse_suffix(cursor)?)));
+    }
+    parse_suffix(cursor)
+}
+
+fn parse_suffix(cursor: &mut Cursor) -> Result<Expr> {
+    let primary = parse_primary(cursor)?;
+    Ok(if cursor.eat_punct('*') {
+        Expr::Many(Box::new(primary))
+    } else if cursor.eat_punct('+') {
+        Expr::Many1(Box::new(primary))
+    } else if cursor.eat_punct('?') {
+        Expr::Opt(Box::new(primary))
+    } else {
+        primary
+    })
+}
+
+fn parse_primary(cursor: &mut Cursor) -> Result<Expr> {
+    let span = cursor.span();
+    match cursor.next() {
+        Some(TokenTree::Literal(literal)) => {
+            let text = literal.to_string();
+            if text.starts_with('"') || text.starts_with('\'') || text.starts_with('r') {
+                Ok(Expr::Literal(literal_parser(&literal)?))
+            } else {
+                Err(Error::new(span, "expected a string or char literal"))
+            }
+        }
+        Some(TokenTree::Ident(ident)) => Ok(Expr::Rule(ident)),
+        Some(TokenTree::Group(inner)) => match inner.delimiter() {
+            Delimiter::Parenthesis => {
+                let mut inner = Cursor::new(inner.stream(), inner.span_close());
+                let expr = parse_choice(&mut inner)?;
+                if !inner.at_end() {
+                    return Err(Error::new(inner.span(), "expected `)`"));
+                }
+                Ok(expr)
+            }
+            Delimiter::Bracket => Ok(Expr::Class(inner.stream())),
+            _ => Err(Error::new(span, "expected an expression")),
+        },
+        Some(TokenTree::Punct(p)) if p.as_char() == '.' => Ok(Expr::Any),
+        Some(TokenTree::Punct(p)) if p.as_char() == '#' => match cursor.next() {
+            Some(TokenTree::Group(embed)) if embed.delimiter() == Delimiter::Brace => {
+                Ok(Expr::Embed(embed))
+            }
+            _ => Err(Error::new(
+                span,
+                "expected a Rust expression in braces after `#`",
+            )),
+        },
+        _ => Err(Error::new(span, "expected an expression")),
+    }
+}
+
+// every referenced rule must be defined exactly once, reported where it is misused
+fn check(rules: &[Rule]) -> Result<()> {
+    let mut defined = HashSet::new();
+    for rule in rules {
+        if !defined.insert(rule.name.to_string()) {
+            return Err(Error::new(
+                rule.name.span(),
+                format!("rule `{}` is defined twice", rule.name),
+            ));
+        }
+    }
+
+    fn walk(expr: &Expr, defined: &HashSet<String>) -> Result<()> {
+        match expr {
+            Expr::Choice(alternatives) => alternatives.iter().try_for_each(|e| walk(e, defined)),
+            Expr::Seq { items, .. } => items.iter().try_for_each(|(_, e)| walk(e, defined)),
+            Expr::Not(e) | Expr::And(e) | Expr::Many(e) | Expr::Many1(e) | Expr::Opt(e) => {
+                walk(e, defined)
+            }
+            Expr::Rule(name) if !defined.contains(&name.to_string()) => Err(Error::new(
+                name.span(),
+                format!("rule `{}` is not defined in this grammar", name),
+            )),
+            _ => Ok(()),
+        }
+    }
+    rules.iter().try_for_each(|rule| walk(&rule.body, &defined))
+}
+
+fn generate(vis: TokenStream, name: Ident, rules: &[Rule]) -> TokenStream {
+    let types: HashMap<String, &TokenStream> = rules
+        .iter()
+        .map(|rule| (rule.name.to_string(), &rule.ty))
+        .collect();
+
+    let fields = concat(rules.iter().map(|rule| {
+        concat([
+            code("pub"),
+            TokenTree::from(rule.name.clone()).into(),
+            code(": ::packrust::Parser<'static,"),
+            rule.ty.clone(),
+            code(">,"),
+        ])
+    }));
+    let definitions = concat(rules.iter().map(|rule| {
+        concat([
+            code(&format!("{}.rule::<", BUILDER)),
+            rule.ty.clone(),
+            code(">"),
+            group(
+                Delimiter::Parenthesis,
+                concat([
+                    string(&rule.name.to_string()),
+                    code(&format!(", |{}: &mut ::packrust::GrammarBuilder|", BUILDER)),
+                    generate_expr(&rule.body, &types),
+                ]),
+            ),
+            code(";"),
+        ])
+    }));
+    let getters = concat(rules.iter().map(|rule| {
+        concat([
+            TokenTree::from(rule.name.clone()).into(),
+            code(": __packrust_grammar.get::<"),
+            rule.ty.clone(),
+            code(">"),
+            group(Delimiter::Parenthesis, string(&rule.name.to_string())),
+            code(".unwrap(),"),
+        ])
+    }));
+
+    let new_body = concat([
+        code(&format!(
+            "let mut {} = ::packrust::GrammarBuilder::new();",
+            BUILDER
+        )),
+        definitions,
+        code(&format!(
+            "let __packrust_grammar = {}.build().expect(\"rules are checked by peg!\");",
+            BUILDER
+        )),
+        code("Self"),
+        group(Delimiter::Brace, getters),
+    ]);
+
+    concat([
+        code("#[derive(Clone)]"),
+        vis.clone(),
+        code("struct"),
+        TokenTree::from(name.clone()).into(),
+        group(Delimiter::Brace, fields),
+        code("impl"),
+        TokenTree::from(name.clone()).into(),
+        group(
+            Delimiter::Brace,
+            concat([
+                vis,
+                code("fn new() -> Self"),
+                group(Delimiter::Brace, new_body),
+            ]),
+        ),
+        code("impl ::core::default::Default for"),
+        TokenTree::from(name).into(),
+        code("{ fn default() -> Self { Self::new() } }"),
+    ])
+}
+
+fn generate_expr(expr: &Expr, types: &HashMap<String, &TokenStream>) -> TokenStream {
+    let parens = |inner: TokenStream| group(Delimiter::Parenthesis, inner);
+    let method =
+        |receiver: &Expr, call: &str| concat([parens(generate_expr(receiver, types)), code(call)]);
+
+    match expr {
+        Expr::Choice(alternatives) => {
+            let mut alternatives = alternatives.iter();
+            let first = parens(generate_expr(alternatives.next().unwrap(), types));
+            concat(
+                std::iter::once(first).chain(alternatives.map(|alternative| {
+                    concat([code(".or"), parens(generate_expr(alternative, types))])
+                })),
+            )
+        }
+        Expr::Seq { items, action } => generate_seq(items, action.as_ref(), types),
+        Expr::Not(item) => method(item, ".not_ahead()"),
+        Expr::And(item) => method(item, ".peek()"),
+        Expr::Many(item) => method(item, ".many()"),
+        Expr::Opt(item) => method(item, ".opt()"),
+        Expr::Many1(item) => group(
+            Delimiter::Brace,
+            concat([
+                code("let __packrust_item ="),
+                generate_expr(item, types),
+                code(
+                    "; __packrust_item.clone().and(__packrust_item.many()).map(|(first, mut rest)| { rest.insert(0, first); rest })",
+                ),
+            ]),
+        ),
+        Expr::Literal(parser) => parser.clone(),
+        Expr::Class(pattern) => concat([
+            code("::packrust::satisfy"),
+            parens(concat([
+                string(&format!("[{}]", pattern)),
+                code(", |c: char| ::core::matches!"),
+                parens(concat([code("c,"), pattern.clone()])),
+            ])),
+        ]),
+        Expr::Any => code("::packrust::any_char()"),
+        Expr::Rule(name) => concat([
+            code(&format!("{}.get::<", BUILDER)),
+            types[&name.to_string()].clone(),
+            code(">"),
+            parens(string(&name.to_string())),
+        ]),
+        Expr::Embed(block) => parens(block.stream()),
+    }
+}
+
+// `a:x y b:z { f(a, b) }` becomes `x.and(y).and(z).map(|((a, _), b)| { f(a, b) })`, without
+// an action the values of all items are returned as a flat tuple
+fn generate_seq(
+    items: &[(Option<Ident>, Expr)],
+    action: Option<&Group>,
+    types: &HashMap<String, &TokenStream>,
+) -> TokenStream {
+    // labels only matter to an action, without one every value is kept
+    let binding = |i: usize, label: &Option<Ident>| -> TokenStream {
+        match (action, label) {
+            (Some(_), Some(label)) => TokenTree::from(label.clone()).into(),
+            (Some(_), None) => code("_"),
+            (None, _) => code(&format!("__packrust_{}", i)),
+        }
+    };
+
+    let mut parser = group(Delimiter::Parenthesis, generate_expr(&items[0].1, types));
+    let mut pattern = binding(0, &items[0].0);
+    for (i, (label, item)) in items.iter().enumerate().skip(1) {
+        parser = concat([
+            parser,
+            code(".and"),
+            group(Delimiter::Parenthesis, generate_expr(item, types)),
+        ]);
+        pattern = group(
+            Delimiter::Parenthesis,
+            concat([pattern, code(","), binding(i, label)]),
+        );
+    }
+
+    let value = match action {
+        Some(action) => TokenTree::from(action.clone()).into(),
+        None if items.len() == 1 => code("__packrust_0"),
+        None => group(
+            Delimiter::Parenthesis,
+            concat((0..items.len()).map(|i| code(&format!("__packrust_{},", i)))),
+        ),
+    };
+
+    let closure = concat([code("|"), pattern, code("|"), value]);
+    concat([parser, code(".map"), group(Delimiter::Parenthesis, closure)])
+}
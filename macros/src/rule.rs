@@ -0,0 +1,139 @@
+use std::collections::HashSet;
+
+use proc_macro::{Delimiter, Group, Ident, Span, TokenStream, TokenTree};
+
+use crate::tokens::{Cursor, Error, Result, code, concat, group, string};
+
+// the builder the rules are registered with, named so rule bodies can't shadow it
+const BUILDER: &str = "__packrust_rules";
+
+struct Rule {
+    name: Ident,
+    ty: TokenStream,
+    body: TokenStream,
+}
+
+pub fn expand(input: TokenStream) -> Result<TokenStream> {
+    let mut cursor = Cursor::new(input, Span::call_site());
+    let mut rules = Vec::new();
+    while !cursor.at_end() {
+        let name = cursor.ident()?;
+        cursor.expect_punct(':')?;
+        let ty = cursor.until_punct('=');
+        if ty.is_empty() {
+            return Err(Error::new(cursor.span(), "expected the rule's output type"));
+        }
+        cursor.expect_punct('=')?;
+        let body = cursor.until_punct(';');
+        if body.is_empty() {
+            return Err(Error::new(cursor.span(), "expected the rule's parser"));
+        }
+        cursor.expect_punct(';')?;
+        rules.push(Rule { name, ty, body });
+    }
+
+    let mut names = HashSet::new();
+    for rule in &rules {
+        if !names.insert(rule.name.to_string()) {
+            return Err(Error::new(
+                rule.name.span(),
+                format!("rule `{}` is defined twice", rule.name),
+            ));
+        }
+    }
+    if rules.is_empty() {
+        return Err(Error::new(Span::call_site(), "expected at least one rule"));
+    }
+
+    let tuple = || {
+        group(
+            Delimiter::Parenthesis,
+            concat(
+                rules
+                    .iter()
+                    .map(|rule| concat([TokenTree::from(rule.name.clone()).into(), code(",")])),
+            ),
+        )
+    };
+    let placeholders = rules.iter().map(|rule| {
+        concat([
+            code("let"),
+            TokenTree::from(rule.name.clone()).into(),
+            code(&format!("= {}.get::<", BUILDER)),
+            rule.ty.clone(),
+            code(">"),
+            group(Delimiter::Parenthesis, string(&rule.name.to_string())),
+            code(";"),
+        ])
+    });
+    let definitions = rules.iter().map(|rule| {
+        concat([
+            code(&format!("{}.rule::<", BUILDER)),
+            rule.ty.clone(),
+            code(">"),
+            group(
+                Delimiter::Parenthesis,
+                concat([
+                    string(&rule.name.to_string()),
+                    code(", |_|"),
+                    clone_rules(rule.body.clone(), &names),
+                ]),
+            ),
+            code(";"),
+        ])
+    });
+
+    Ok(concat([
+        code("let"),
+        tuple(),
+        code("="),
+        group(
+            Delimiter::Brace,
+            concat([
+                code(&format!(
+                    "let mut {} = ::packrust::GrammarBuilder::new();",
+                    BUILDER
+                )),
+                concat(placeholders),
+                concat(definitions),
+                tuple(),
+            ]),
+        ),
+        code(";"),
+    ]))
+}
+
+// every mention of a rule in a body becomes `rule.clone()`, so a body can use a rule as
+// many times as it likes; names after `.` or `::` and field names before `:` are fields,
+// methods or paths rather than rules and are left alone
+fn clone_rules(body: TokenStream, names: &HashSet<String>) -> TokenStream {
+    let tokens: Vec<TokenTree> = body.into_iter().collect();
+    let is_punct = |token: Option<&TokenTree>, c: char| matches!(token, Some(TokenTree::Punct(p)) if p.as_char() == c);
+
+    let mut out = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        let previous = i.checked_sub(1).and_then(|i| tokens.get(i));
+        let next = tokens.get(i + 1);
+        match token {
+            TokenTree::Group(g) => {
+                let mut inner = Group::new(g.delimiter(), clone_rules(g.stream(), names));
+                inner.set_span(g.span());
+                out.push(TokenStream::from(TokenTree::from(inner)));
+            }
+            TokenTree::Ident(ident)
+                if names.contains(&ident.to_string())
+                    && !is_punct(previous, '.')
+                    && !is_punct(previous, ':')
+                    && !is_punct(next, ':')
+                    && !is_punct(next, '!') =>
+            {
+                out.push(concat([
+                    TokenTree::from(ident.clone()).into(),
+                    code(".clone()"),
+                ]));
+            }
+            _ => out.push(token.clone().into()),
+        }
+    }
+    concat(out)
+}
@@ -0,0 +1,187 @@
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+
+// an error to be reported as a `compile_error!` pointing at the offending tokens
+pub struct Error {
+    span: Span,
+    message: String,
+}
+
+impl Error {
+    pub fn new(span: Span, message: impl Into<String>) -> Self {
+        Error {
+            span,
+            message: message.into(),
+        }
+    }
+
+    pub fn into_compile_error(self) -> TokenStream {
+        let tokens: [TokenTree; 4] = [
+            Ident::new("compile_error", self.span).into(),
+            Punct::new('!', Spacing::Alone).into(),
+            Group::new(
+                Delimiter::Parenthesis,
+                TokenTree::from(Literal::string(&self.message)).into(),
+            )
+            .into(),
+            Punct::new(';', Spacing::Alone).into(),
+        ];
+        tokens
+            .into_iter()
+            .map(|mut token| {
+                token.set_span(self.span);
+                token
+            })
+            .collect()
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+// a position in a flat list of token trees, groups are descended into with a new cursor
+pub struct Cursor {
+    tokens: Vec<TokenTree>,
+    pos: usize,
+    end_span: Span,
+}
+
+impl Cursor {
+    pub fn new(stream: TokenStream, end_span: Span) -> Self {
+        Cursor {
+            tokens: stream.into_iter().collect(),
+            pos: 0,
+            end_span,
+        }
+    }
+
+    pub fn peek(&self) -> Option<&TokenTree> {
+        self.tokens.get(self.pos)
+    }
+
+    pub fn peek_nth(&self, n: usize) -> Option<&TokenTree> {
+        self.tokens.get(self.pos + n)
+    }
+
+    pub fn next(&mut self) -> Option<TokenTree> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    pub fn at_end(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    // where to point errors about the next token, or the end of the input
+    pub fn span(&self) -> Span {
+        self.peek().map_or(self.end_span, TokenTree::span)
+    }
+
+    pub fn is_punct(&self, c: char) -> bool {
+        matches!(self.peek(), Some(TokenTree::Punct(p)) if p.as_char() == c)
+    }
+
+    pub fn eat_punct(&mut self, c: char) -> bool {
+        let found = self.is_punct(c);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    pub fn expect_punct(&mut self, c: char) -> Result<()> {
+        if self.eat_punct(c) {
+            Ok(())
+        } else {
+            Err(Error::new(self.span(), format!("expected `{}`", c)))
+        }
+    }
+
+    pub fn is_ident(&self, name: &str) -> bool {
+        matches!(self.peek(), Some(TokenTree::Ident(i)) if i.to_string() == name)
+    }
+
+    pub fn eat_ident(&mut self, name: &str) -> bool {
+        let found = self.is_ident(name);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    pub fn ident(&mut self) -> Result<Ident> {
+        match self.peek() {
+            Some(TokenTree::Ident(ident)) => {
+                let ident = ident.clone();
+                self.pos += 1;
+                Ok(ident)
+            }
+            _ => Err(Error::new(self.span(), "expected an identifier")),
+        }
+    }
+
+    pub fn until_end(&mut self) -> TokenStream {
+        let rest = self.tokens[self.pos.min(self.tokens.len())..]
+            .iter()
+            .cloned()
+            .collect();
+        self.pos = self.tokens.len();
+        rest
+    }
+
+    // everything up to (not including) the first top level `c`
+    pub fn until_punct(&mut self, c: char) -> TokenStream {
+        let start = self.pos;
+        while !self.at_end() && !self.is_punct(c) {
+            self.pos += 1;
+        }
+        self.tokens[start..self.pos].iter().cloned().collect()
+    }
+}
+
+// generated code is written as text and parsed, user tokens are spliced in untouched so
+// errors in them still point at the macro input
+pub fn code(text: &str) -> TokenStream {
+    text.parse().expect("generated code must tokenize")
+}
+
+pub fn concat(parts: impl IntoIterator<Item = TokenStream>) -> TokenStream {
+    parts.into_iter().collect()
+}
+
+pub fn group(delimiter: Delimiter, inner: TokenStream) -> TokenStream {
+    TokenTree::from(Group::new(delimiter, inner)).into()
+}
+
+pub fn string(value: &str) -> TokenStream {
+    TokenTree::from(Literal::string(value)).into()
+}
+
+// the parser matching a quoted literal of a rule: `char` for a char, `string` for a string,
+// and `keyword`, which only matches whole words, for a string opted into it as `"if"kw`
+pub fn literal_parser(literal: &Literal) -> Result<TokenStream> {
+    let text = literal.to_string();
+    let close = text.rfind(['"', '\'', '#']).map_or(0, |i| i + 1);
+    let (quoted, suffix) = text.split_at(close);
+    let function = match (quoted.ends_with('\''), suffix) {
+        (true, "") => "::packrust::char",
+        (false, "") => "::packrust::string",
+        (false, "kw") => "::packrust::keyword",
+        _ => {
+            return Err(Error::new(
+                literal.span(),
+                "expected a char, a string, or a string with the `kw` suffix",
+            ));
+        }
+    };
+    let quoted: TokenStream = code(quoted)
+        .into_iter()
+        .map(|mut token| {
+            token.set_span(literal.span());
+            token
+        })
+        .collect();
+    Ok(concat([
+        code(function),
+        group(Delimiter::Parenthesis, quoted),
+    ]))
+}
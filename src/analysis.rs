@@ -0,0 +1,620 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::rc::Rc;
+
+use rustc_hash::FxHashMap;
+
+use crate::Parser;
+use crate::peg::shape_to_peg;
+use crate::shape::{RuleShape, Shape};
+
+/// How a rule reaches itself without consuming input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeftRecursionKind {
+    // the rule's own body starts with a call to it, `expr <- expr '+' term`
+    Direct,
+    // the call goes through other rules, `expr <- term '+' ...; term <- expr ...`
+    Indirect,
+    // the call is preceded by something that can match nothing, `expr <- '-'? expr ...`
+    Hidden,
+}
+
+/// A left-recursive rule, found by [`Grammar::left_recursion`](crate::Grammar::left_recursion)
+/// or [`Parser::left_recursion`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeftRecursion {
+    pub rule: String,
+    pub kind: LeftRecursionKind,
+    // the shortest chain of left calls leading back to the rule, starting and ending with it
+    pub cycle: Vec<String>,
+}
+
+impl fmt::Display for LeftRecursion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            LeftRecursionKind::Direct => "directly",
+            LeftRecursionKind::Indirect => "indirectly",
+            LeftRecursionKind::Hidden => "hidden",
+        };
+        write!(
+            f,
+            "rule `{}` is {} left-recursive: {}",
+            self.rule,
+            kind,
+            self.cycle.join(" -> ")
+        )
+    }
+}
+
+/// A repetition in a rule whose item can succeed without consuming input, found by
+/// [`Grammar::nullable_repetitions`](crate::Grammar::nullable_repetitions) or
+/// [`Parser::nullable_repetitions`]. It fails at runtime as soon as the item matches nothing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NullableRepetition {
+    pub rule: String,
+    // the repetition in PEG notation, `('a'?)*`
+    pub repetition: String,
+}
+
+impl fmt::Display for NullableRepetition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rule `{}` repeats `{}`, which can match nothing",
+            self.rule, self.repetition
+        )
+    }
+}
+
+/// An alternative of an ordered choice that can never be taken, because an earlier
+/// alternative matches wherever it would. Found by
+/// [`Grammar::unreachable_alternatives`](crate::Grammar::unreachable_alternatives) or
+/// [`Parser::unreachable_alternatives`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnreachableAlternative {
+    pub rule: String,
+    // both alternatives in PEG notation
+    pub alternative: String,
+    pub shadowed_by: String,
+}
+
+impl fmt::Display for UnreachableAlternative {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rule `{}` can never take `{}`, `{}` comes first and matches wherever it would",
+            self.rule, self.alternative, self.shadowed_by
+        )
+    }
+}
+
+// every rule reachable from some roots, with the facts the grammar checks are built on;
+// opaque parsers can't be looked into, so they are assumed to consume input and call nothing
+pub(crate) struct Analysis<'s, 'a> {
+    rules: Vec<&'s Rc<RuleShape<'a>>>,
+    index: FxHashMap<usize, usize>,
+    nullable: Vec<bool>,
+    // rules that succeed on any input, a stronger property than being nullable
+    infallible: Vec<bool>,
+}
+
+impl<'s, 'a> Analysis<'s, 'a> {
+    pub(crate) fn new(roots: impl IntoIterator<Item = &'s Rc<RuleShape<'a>>>) -> Self {
+        let mut analysis = Analysis {
+            rules: Vec::new(),
+            index: FxHashMap::default(),
+            nullable: Vec::new(),
+            infallible: Vec::new(),
+        };
+        roots.into_iter().for_each(|rule| analysis.add(rule));
+        let mut next = 0;
+        while let Some(rule) = analysis.rules.get(next) {
+            next += 1;
+            if let Some(body) = rule.body() {
+                analysis.collect(body);
+            }
+        }
+
+        // both properties are least fixed points, start from "no rule has it" and grow
+        analysis.nullable = vec![false; analysis.rules.len()];
+        analysis.infallible = vec![false; analysis.rules.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for i in 0..analysis.rules.len() {
+                let Some(body) = analysis.rules[i].body() else {
+                    continue;
+                };
+                if !analysis.nullable[i] && analysis.is_nullable(body) {
+                    analysis.nullable[i] = true;
+                    changed = true;
+                }
+                if !analysis.infallible[i] && analysis.is_infallible(body) {
+                    analysis.infallible[i] = true;
+                    changed = true;
+                }
+            }
+        }
+        analysis
+    }
+
+    fn add(&mut self, rule: &'s Rc<RuleShape<'a>>) {
+        if !self.index.contains_key(&rule.id()) {
+            self.index.insert(rule.id(), self.rules.len());
+            self.rules.push(rule);
+        }
+    }
+
+    fn collect(&mut self, shape: &'s Shape<'a>) {
+        match shape {
+            Shape::Seq(items) | Shape::Choice(items) => {
+                items.iter().for_each(|item| self.collect(item))
+            }
+            Shape::Many(item) | Shape::Opt(item) | Shape::Not(item) | Shape::And(item) => {
+                self.collect(item)
+            }
+            Shape::Rule(rule) => self.add(rule),
+            _ => {}
+        }
+    }
+
+    // whether the shape can succeed without consuming anything
+    pub(crate) fn is_nullable(&self, shape: &Shape<'a>) -> bool {
+        match shape {
+            Shape::Char(_) | Shape::Any | Shape::Class(..) | Shape::Opaque(..) => false,
+            Shape::Keyword(keyword) => keyword.is_empty(),
+            Shape::Seq(items) => items.iter().all(|item| self.is_nullable(item)),
+            Shape::Choice(items) => items.iter().any(|item| self.is_nullable(item)),
+            Shape::Many(_) | Shape::Opt(_) | Shape::Not(_) | Shape::And(_) | Shape::Eof => true,
+            Shape::Rule(rule) => self
+                .index
+                .get(&rule.id())
+                .is_some_and(|&i| self.nullable[i]),
+        }
+    }
+
+    // whether the shape succeeds wherever it is tried; a repetition fails when its body
+    // matches nothing, so it only always succeeds if its body can't
+    fn is_infallible(&self, shape: &Shape<'a>) -> bool {
+        match shape {
+            Shape::Opt(_) => true,
+            Shape::Many(item) => !self.is_nullable(item),
+            Shape::Keyword(keyword) => keyword.is_empty(),
+            Shape::Seq(items) => items.iter().all(|item| self.is_infallible(item)),
+            Shape::Choice(items) => items.iter().any(|item| self.is_infallible(item)),
+            Shape::And(item) => self.is_infallible(item),
+            Shape::Rule(rule) => self
+                .index
+                .get(&rule.id())
+                .is_some_and(|&i| self.infallible[i]),
+            _ => false,
+        }
+    }
+
+    // whether `earlier` matches wherever `later` would, so that in an ordered choice
+    // `earlier / later` the second alternative is never reached; only the patterns that
+    // can be seen from the shapes alone are caught
+    fn shadows(&self, earlier: &Shape<'a>, later: &Shape<'a>) -> bool {
+        if self.is_infallible(earlier) {
+            return true;
+        }
+        let earlier_items = sequence(earlier);
+        let later_items = sequence(later);
+
+        // `p / p q`, the first alternative matches the second one's prefix
+        if later_items.len() >= earlier_items.len()
+            && earlier_items
+                .iter()
+                .zip(&later_items)
+                .all(|(e, l)| same(e, l))
+        {
+            return true;
+        }
+        // `'=' / '=='`, literals that are a prefix of the other alternative's literals
+        if earlier_items
+            .iter()
+            .all(|item| matches!(item, Shape::Char(_) | Shape::Keyword(_)))
+        {
+            let prefix = literal_prefix(&earlier_items);
+            return literal_prefix(&later_items).starts_with(&prefix);
+        }
+        match earlier_items.as_slice() {
+            // `.` matches wherever anything that consumes input does
+            [Shape::Any] => !self.is_nullable(later),
+            // `[0-9] / '5' ...`, a class accepting the other alternative's first char
+            [Shape::Class(_, predicate)] => literal_prefix(&later_items)
+                .chars()
+                .next()
+                .is_some_and(|c| predicate(c)),
+            _ => false,
+        }
+    }
+
+    // the rules the shape may call before consuming anything, each flagged with whether
+    // something nullable was passed over on the way
+    fn left_calls(&self, shape: &Shape<'a>, hidden: bool, out: &mut Vec<(usize, bool)>) {
+        match shape {
+            Shape::Seq(items) => {
+                let mut hidden = hidden;
+                for item in items {
+                    self.left_calls(item, hidden, out);
+                    if !self.is_nullable(item) {
+                        break;
+                    }
+                    hidden = true;
+                }
+            }
+            Shape::Choice(items) => items
+                .iter()
+                .for_each(|item| self.left_calls(item, hidden, out)),
+            Shape::Many(item) | Shape::Opt(item) | Shape::Not(item) | Shape::And(item) => {
+                self.left_calls(item, hidden, out)
+            }
+            Shape::Rule(rule) => {
+                if let Some(&i) = self.index.get(&rule.id()) {
+                    out.push((i, hidden));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn nullable_repetitions(&self) -> Vec<NullableRepetition> {
+        fn visit<'a>(
+            analysis: &Analysis<'_, 'a>,
+            rule: &str,
+            shape: &Shape<'a>,
+            out: &mut Vec<NullableRepetition>,
+        ) {
+            match shape {
+                Shape::Seq(items) | Shape::Choice(items) => items
+                    .iter()
+                    .for_each(|item| visit(analysis, rule, item, out)),
+                Shape::Many(item) if analysis.is_nullable(item) => out.push(NullableRepetition {
+                    rule: rule.to_string(),
+                    repetition: shape_to_peg(shape),
+                }),
+                Shape::Many(item) | Shape::Opt(item) | Shape::Not(item) | Shape::And(item) => {
+                    visit(analysis, rule, item, out)
+                }
+                _ => {}
+            }
+        }
+
+        let mut out = Vec::new();
+        for rule in &self.rules {
+            if let Some(body) = rule.body() {
+                visit(self, &rule.name, body, &mut out);
+            }
+        }
+        out
+    }
+
+    pub(crate) fn unreachable_alternatives(&self) -> Vec<UnreachableAlternative> {
+        fn visit<'a>(
+            analysis: &Analysis<'_, 'a>,
+            rule: &str,
+            shape: &Shape<'a>,
+            out: &mut Vec<UnreachableAlternative>,
+        ) {
+            match shape {
+                Shape::Choice(items) => {
+                    for (j, later) in items.iter().enumerate() {
+                        if let Some(earlier) = items[..j]
+                            .iter()
+                            .find(|earlier| analysis.shadows(earlier, later))
+                        {
+                            out.push(UnreachableAlternative {
+                                rule: rule.to_string(),
+                                alternative: shape_to_peg(later),
+                                shadowed_by: shape_to_peg(earlier),
+                            });
+                        }
+                    }
+                    items
+                        .iter()
+                        .for_each(|item| visit(analysis, rule, item, out));
+                }
+                Shape::Seq(items) => items
+                    .iter()
+                    .for_each(|item| visit(analysis, rule, item, out)),
+                Shape::Many(item) | Shape::Opt(item) | Shape::Not(item) | Shape::And(item) => {
+                    visit(analysis, rule, item, out)
+                }
+                _ => {}
+            }
+        }
+
+        let mut out = Vec::new();
+        for rule in &self.rules {
+            if let Some(body) = rule.body() {
+                visit(self, &rule.name, body, &mut out);
+            }
+        }
+        out
+    }
+
+    pub(crate) fn left_recursion(&self) -> Vec<LeftRecursion> {
+        let edges: Vec<Vec<(usize, bool)>> = self
+            .rules
+            .iter()
+            .map(|rule| {
+                let mut calls = Vec::new();
+                if let Some(body) = rule.body() {
+                    self.left_calls(body, false, &mut calls);
+                }
+                calls
+            })
+            .collect();
+
+        (0..self.rules.len())
+            .filter_map(|start| {
+                let (kind, cycle) = if edges[start].contains(&(start, false)) {
+                    (LeftRecursionKind::Direct, vec![start, start])
+                } else if let Some(cycle) = shortest_cycle(&edges, start, false) {
+                    (LeftRecursionKind::Indirect, cycle)
+                } else {
+                    (
+                        LeftRecursionKind::Hidden,
+                        shortest_cycle(&edges, start, true)?,
+                    )
+                };
+                Some(LeftRecursion {
+                    rule: self.rules[start].name.clone(),
+                    kind,
+                    cycle: cycle
+                        .into_iter()
+                        .map(|i| self.rules[i].name.clone())
+                        .collect(),
+                })
+            })
+            .collect()
+    }
+}
+
+fn sequence<'s, 'a>(shape: &'s Shape<'a>) -> Vec<&'s Shape<'a>> {
+    match shape {
+        Shape::Seq(items) => items.iter().map(Rc::as_ref).collect(),
+        _ => vec![shape],
+    }
+}
+
+// the text a sequence is known to start with, from its leading literals
+fn literal_prefix(items: &[&Shape]) -> String {
+    let mut prefix = String::new();
+    for item in items {
+        match item {
+            Shape::Char(c) => prefix.push(*c),
+            Shape::Keyword(keyword) => prefix.push_str(keyword),
+            _ => break,
+        }
+    }
+    prefix
+}
+
+// structural equality, classes and opaque parsers are told apart by their labels and
+// rules by identity
+fn same(a: &Shape, b: &Shape) -> bool {
+    let all_same = |a: &[Rc<Shape>], b: &[Rc<Shape>]| {
+        a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same(a, b))
+    };
+    match (a, b) {
+        (Shape::Char(a), Shape::Char(b)) => a == b,
+        (Shape::Keyword(a), Shape::Keyword(b)) => a == b,
+        (Shape::Any, Shape::Any) | (Shape::Eof, Shape::Eof) => true,
+        (Shape::Class(a, _), Shape::Class(b, _)) | (Shape::Opaque(a, _), Shape::Opaque(b, _)) => {
+            a == b
+        }
+        (Shape::Seq(a), Shape::Seq(b)) | (Shape::Choice(a), Shape::Choice(b)) => all_same(a, b),
+        (Shape::Many(a), Shape::Many(b))
+        | (Shape::Opt(a), Shape::Opt(b))
+        | (Shape::Not(a), Shape::Not(b))
+        | (Shape::And(a), Shape::And(b)) => same(a, b),
+        (Shape::Rule(a), Shape::Rule(b)) => a.id() == b.id(),
+        _ => false,
+    }
+}
+
+// breadth first search from `start` back to itself, only through unhidden calls unless
+// `hidden` is set
+fn shortest_cycle(edges: &[Vec<(usize, bool)>], start: usize, hidden: bool) -> Option<Vec<usize>> {
+    let mut parent: Vec<Option<usize>> = vec![None; edges.len()];
+    let mut queue = VecDeque::from([start]);
+    while let Some(rule) = queue.pop_front() {
+        for &(next, through_nullable) in &edges[rule] {
+            if through_nullable && !hidden {
+                continue;
+            }
+            if next == start {
+                let mut cycle = vec![rule];
+                let mut at = rule;
+                while at != start {
+                    at = parent[at].expect("every queued rule but the start has a parent");
+                    cycle.push(at);
+                }
+                cycle.reverse();
+                cycle.push(start);
+                return Some(cycle);
+            }
+            if parent[next].is_none() {
+                parent[next] = Some(rule);
+                queue.push_back(next);
+            }
+        }
+    }
+    None
+}
+
+impl<'a, T> Parser<'a, T>
+where
+    T: Clone + 'static,
+{
+    /// The rules reachable from this parser that can call themselves without consuming
+    /// input, with the chain of calls each one recurses through.
+    pub fn left_recursion(&self) -> Vec<LeftRecursion> {
+        let root = self.as_rule();
+        Analysis::new([&root]).left_recursion()
+    }
+
+    /// The repetitions reachable from this parser whose item can match nothing.
+    pub fn nullable_repetitions(&self) -> Vec<NullableRepetition> {
+        let root = self.as_rule();
+        Analysis::new([&root]).nullable_repetitions()
+    }
+
+    /// The alternatives reachable from this parser that an earlier alternative of the same
+    /// choice always wins over.
+    pub fn unreachable_alternatives(&self) -> Vec<UnreachableAlternative> {
+        let root = self.as_rule();
+        Analysis::new([&root]).unreachable_alternatives()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn test_left_recursion() {
+        let mut g = GrammarBuilder::new();
+        g.rule("expr", |g| {
+            g.get::<char>("expr")
+                .andr(char('+'))
+                .andr(g.get::<char>("term"))
+                .or(g.get("term"))
+        })
+        .rule("term", |g| {
+            g.get::<char>("factor").andl(char('*')).or(g.get("factor"))
+        })
+        .rule("factor", |g| {
+            g.get::<char>("term").andl(char('!')).or(g.get("num"))
+        })
+        .rule("num", |_| satisfy("digit", |c| c.is_ascii_digit()));
+        let grammar = g.build().unwrap();
+
+        let report = grammar.left_recursion();
+        assert_eq!(
+            report
+                .iter()
+                .map(|lr| (lr.rule.as_str(), lr.kind, lr.cycle.join(" ")))
+                .collect::<Vec<_>>(),
+            [
+                ("expr", LeftRecursionKind::Direct, "expr expr".to_string()),
+                (
+                    "factor",
+                    LeftRecursionKind::Indirect,
+                    "factor term factor".to_string()
+                ),
+                (
+                    "term",
+                    LeftRecursionKind::Indirect,
+                    "term factor term".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            report[1].to_string(),
+            "rule `factor` is indirectly left-recursive: factor -> term -> factor"
+        );
+
+        // `build` turns these away, but lazy rules are only checked on request
+        let neg = lazy("neg", |neg| {
+            char('-').opt().andr(neg).andl(char('x')).or(char('y'))
+        });
+        assert_eq!(
+            neg.left_recursion(),
+            [LeftRecursion {
+                rule: "neg".to_string(),
+                kind: LeftRecursionKind::Hidden,
+                cycle: vec!["neg".to_string(), "neg".to_string()],
+            }]
+        );
+
+        let list = lazy("list", |list| list.andl(char(',')).or(char('x')));
+        assert_eq!(list.left_recursion().len(), 1);
+        assert!(char('x').many().left_recursion().is_empty());
+    }
+
+    #[test]
+    fn test_nullable_repetition() {
+        let space = lazy("space", |_| char(' ').many());
+        let list = char('[')
+            .and(space.and(char('x').opt()).many())
+            .and(char(']'));
+        assert_eq!(
+            list.nullable_repetitions(),
+            [NullableRepetition {
+                rule: list.name.clone(),
+                repetition: "(space 'x'?)*".to_string(),
+            }]
+        );
+        assert!(list.run("[ x x]").is_err());
+        assert!(char('x').many().nullable_repetitions().is_empty());
+
+        let words = keyword("").many();
+        assert_eq!(
+            words.run("ab").unwrap_err().to_string(),
+            "ab\n^\nrepeated parser matched nothing and would loop forever\n"
+        );
+    }
+
+    #[test]
+    fn test_unreachable_alternatives() {
+        let mut g = GrammarBuilder::new();
+        g.rule("int", |_| satisfy("digit", |c| c.is_ascii_digit()).many())
+            .rule("number", |g| {
+                g.get::<Vec<char>>("int")
+                    .or(g.get::<Vec<char>>("int").andl(char('u')))
+            })
+            .rule("op", |_| {
+                keyword("==")
+                    .or(keyword("="))
+                    .or(keyword("=>"))
+                    .or(char('!').and(char('=')).map(|_| "!=".to_string()))
+                    .or(keyword("!=="))
+            })
+            .rule("digit", |_| {
+                satisfy("[0-9]", |c| c.is_ascii_digit())
+                    .or(char('5'))
+                    .or(char('x'))
+            });
+        let grammar = g.build().unwrap();
+
+        let report = grammar.unreachable_alternatives();
+        assert_eq!(
+            report
+                .iter()
+                .map(|u| (
+                    u.rule.as_str(),
+                    u.alternative.as_str(),
+                    u.shadowed_by.as_str()
+                ))
+                .collect::<Vec<_>>(),
+            [
+                ("digit", "'5'", "[0-9]"),
+                ("number", "int 'u'", "int"),
+                ("op", "'=>'", "'='"),
+                ("op", "'!=='", "'!' '='"),
+            ]
+        );
+        assert_eq!(
+            report[2].to_string(),
+            "rule `op` can never take `'=>'`, `'='` comes first and matches wherever it would"
+        );
+
+        let spaces = char(' ').many().or(char('\t').many());
+        assert_eq!(spaces.unreachable_alternatives().len(), 1);
+        // a repetition of something that can match nothing fails rather than matching it
+        let blanks = char(' ').opt().many().map(|_| ());
+        let blanks = blanks.or(char('\t').many().map(|_| ()));
+        assert!(blanks.unreachable_alternatives().is_empty());
+        assert_eq!(blanks.run("\t"), Ok(()));
+        assert!(
+            any_char()
+                .map(Some)
+                .or(char('a').opt())
+                .unreachable_alternatives()
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,142 @@
+/// Asserts that a parser accepts the whole input, panicking with the pretty error if it
+/// doesn't, and evaluates to the parsed value. Given a pattern, the value must also match
+/// it, as with `matches!`.
+///
+/// ```
+/// use packrust::*;
+///
+/// let digits = satisfy("digit", |c| c.is_ascii_digit()).many();
+/// assert_parses!(digits, "123", ref v if v.len() == 3);
+/// let value = assert_parses!(digits, "42");
+/// assert_eq!(value, vec!['4', '2']);
+/// ```
+#[macro_export]
+macro_rules! assert_parses {
+    ($parser:expr, $input:expr $(,)?) => {{
+        let input = $input;
+        match $parser.run_partial(input) {
+            Ok(value) => value,
+            Err(partial) => panic!(
+                "assertion failed: parser rejected {:?}\n{}",
+                input, partial.error
+            ),
+        }
+    }};
+    ($parser:expr, $input:expr, $pattern:pat $(if $guard:expr)? $(,)?) => {{
+        let value = $crate::assert_parses!($parser, $input);
+        match value {
+            $pattern $(if $guard)? => {}
+            ref value => panic!(
+                "assertion failed: {:?} parsed as {:?}, which doesn't match `{}`",
+                $input,
+                value,
+                stringify!($pattern $(if $guard)?)
+            ),
+        }
+        value
+    }};
+}
+
+/// Asserts that a parser rejects the input, panicking with the value it produced if it
+/// doesn't, and evaluates to the error. With `at = pos` the error must also be reported at
+/// that byte offset, otherwise the panic shows the pretty error it got instead.
+///
+/// ```
+/// use packrust::*;
+///
+/// let sum = char('1').and(char('+')).and(char('2'));
+/// assert_fails!(sum, "1+", at = 2);
+/// ```
+#[macro_export]
+macro_rules! assert_fails {
+    ($parser:expr, $input:expr $(,)?) => {{
+        let input = $input;
+        match $parser.run_partial(input) {
+            Ok(value) => panic!(
+                "assertion failed: parser accepted {:?} as {:?}",
+                input, value
+            ),
+            Err(partial) => partial.error,
+        }
+    }};
+    ($parser:expr, $input:expr, at = $at:expr $(,)?) => {{
+        let error = $crate::assert_fails!($parser, $input);
+        let at: usize = $at;
+        if error.pos() != at {
+            panic!(
+                "assertion failed: expected {:?} to be rejected at {}, but it was at {}\n{}",
+                $input,
+                at,
+                error.pos(),
+                error
+            );
+        }
+        error
+    }};
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Expr {
+        Num(u32),
+        Add(Box<Expr>, Box<Expr>),
+    }
+
+    fn expr() -> Parser<'static, Expr> {
+        lazy("expr", |expr| {
+            let num = satisfy("digit", |c| c.is_ascii_digit())
+                .try_map(|c| c.to_digit(10))
+                .map(Expr::Num);
+            expr.andl(char('+'))
+                .and(num.clone())
+                .map(|(a, b)| Expr::Add(Box::new(a), Box::new(b)))
+                .or(num)
+        })
+    }
+
+    #[test]
+    fn test_assert_parses() {
+        assert_parses!(expr(), "1+2", Expr::Add(..));
+        assert_parses!(expr(), "7", Expr::Num(n) if n == 7);
+        assert_eq!(assert_parses!(&expr(), "3"), Expr::Num(3));
+    }
+
+    #[test]
+    #[should_panic(expected = "parser rejected \"1+\"")]
+    fn test_assert_parses_rejected() {
+        assert_parses!(expr(), "1+");
+    }
+
+    #[test]
+    #[should_panic(expected = "\"1\" parsed as Num(1), which doesn't match `Expr::Add(..)`")]
+    fn test_assert_parses_mismatch() {
+        assert_parses!(expr(), "1", Expr::Add(..));
+    }
+
+    fn sum() -> Parser<'static, (char, char)> {
+        let digit = satisfy("digit", |c| c.is_ascii_digit());
+        digit.clone().andl(char('+')).and(digit)
+    }
+
+    #[test]
+    fn test_assert_fails() {
+        assert_fails!(expr(), "+");
+        assert_fails!(sum(), "1+", at = 2);
+        assert_eq!(assert_fails!(sum(), "1+2x", at = 3).pos(), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "rejected at 0, but it was at 2")]
+    fn test_assert_fails_position() {
+        assert_fails!(sum(), "1+", at = 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "parser accepted \"1+2\"")]
+    fn test_assert_fails_accepted() {
+        assert_fails!(expr(), "1+2");
+    }
+}
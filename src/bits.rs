@@ -0,0 +1,244 @@
+use std::cell::RefCell;
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::input::found;
+use crate::{Bytes, Context, Input, ParseError, Parser, Pos, Reason, Text, elem};
+
+// binary input indexed by bit offset, most significant bit of each byte first, so parsers
+// can pick apart flags fields and packed encodings; their results are memoized per bit
+#[derive(Clone)]
+pub struct Bits {
+    bytes: Bytes,
+    range: Range<usize>,
+}
+
+impl Bits {
+    pub fn new(bytes: impl Into<Bytes>) -> Self {
+        let bytes = bytes.into();
+        Bits {
+            range: 0..bytes.len() * 8,
+            bytes,
+        }
+    }
+
+    /// Where these bits start in the input they were sliced from, in bits.
+    pub fn offset(&self) -> usize {
+        self.range.start
+    }
+
+    pub fn len(&self) -> usize {
+        self.range.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.range.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = bool> + '_ {
+        self.range.clone().map(|bit| self.bit(bit))
+    }
+
+    fn bit(&self, bit: usize) -> bool {
+        self.bytes.as_slice()[bit / 8] & (0x80 >> (bit % 8)) != 0
+    }
+}
+
+impl std::fmt::Debug for Bits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bits: String = self.iter().map(|b| if b { '1' } else { '0' }).collect();
+        write!(f, "{}@{:?}", bits, self.range)
+    }
+}
+
+impl PartialEq for Bits {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl Eq for Bits {}
+
+impl Input for Bits {
+    type Elem = bool;
+    type Slice = Bits;
+
+    fn len(&self) -> usize {
+        self.range.len()
+    }
+
+    fn next(&self, pos: Pos) -> Option<(bool, Pos)> {
+        let bit = self.range.start + pos;
+        (bit < self.range.end).then(|| (self.bit(bit), pos + 1))
+    }
+
+    fn slice(&self, range: Range<Pos>) -> Bits {
+        assert!(
+            range.start <= range.end && range.end <= self.len(),
+            "slice {:?} out of bounds for {} bits",
+            range,
+            self.len()
+        );
+        Bits {
+            bytes: self.bytes.clone(),
+            range: self.range.start + range.start..self.range.start + range.end,
+        }
+    }
+}
+
+pub fn bool_bit<'a>() -> Parser<'a, bool, Bits> {
+    elem("bit", |_| true)
+}
+
+/// Matches the next `n` bits, at most 32, as an unsigned number read most significant bit
+/// first.
+pub fn bits<'a>(n: usize) -> Parser<'a, u32, Bits> {
+    assert!(n <= 32, "can't read {} bits into a u32", n);
+    let name = format!("{} bits", n);
+    let raw_parser = {
+        let label: Text = Text::from(name.as_str());
+        Rc::new(move |pos, ctx: &mut Context<Bits>| {
+            let end = pos + n;
+            if end <= ctx.source.len() {
+                ctx.examine(end);
+                let value = ctx
+                    .source
+                    .slice(pos..end)
+                    .iter()
+                    .fold(0, |acc, bit| acc << 1 | bit as u32);
+                return Ok((end, value));
+            }
+            ctx.ran_out();
+            let pos = ctx.source.len();
+            Err(ParseError {
+                source: ctx.clone_source(),
+                pos,
+                reason: Reason::Expected {
+                    label: label.clone(),
+                    found: found(&ctx.source, pos),
+                },
+            })
+        })
+    };
+
+    Parser::new(name, raw_parser)
+}
+
+/// Skips what is left of the current byte, matching nothing at a byte boundary.
+pub fn align<'a>() -> Parser<'a, (), Bits> {
+    let raw_parser = Rc::new(|pos: Pos, ctx: &mut Context<Bits>| {
+        let boundary = (ctx.source.offset() + pos).next_multiple_of(8) - ctx.source.offset();
+        if boundary > ctx.source.len() {
+            ctx.ran_out();
+        }
+        Ok((boundary.min(ctx.source.len()), ()))
+    });
+
+    Parser::new(String::from("align"), raw_parser)
+}
+
+impl<'a, T> Parser<'a, T, Bits>
+where
+    T: Clone + 'static,
+{
+    /// Runs this parser on the bits of byte input, from the byte it is called at, and
+    /// carries on from the byte boundary after the last bit it matched.
+    pub fn byte_aligned(self) -> Parser<'a, T, Bytes> {
+        let name = format!("bits({})", self.name);
+        // bit positions are memoized in a context of their own, kept for as long as the
+        // byte input stays the same
+        let bit_ctx: RefCell<Option<Context<Bits>>> = RefCell::new(None);
+        let raw_parser = Rc::new(move |pos: Pos, ctx: &mut Context<Bytes>| {
+            let mut bit_ctx = bit_ctx.borrow_mut();
+            let input = Bits::new(ctx.source.clone());
+            match bit_ctx.as_mut() {
+                Some(bit_ctx) if bit_ctx.source().bytes.is_same(&input.bytes) => {}
+                Some(bit_ctx) => bit_ctx.set_source(input),
+                None => *bit_ctx = Some(Context::from_source(input)),
+            }
+            let bit_ctx = bit_ctx.as_mut().unwrap();
+            if ctx.streaming {
+                bit_ctx.streaming = true;
+            } else {
+                bit_ctx.finish();
+            }
+
+            bit_ctx.reach = pos * 8;
+            let (result, fatal) = ctx.nested(bit_ctx, |bit_ctx| self.parse(pos * 8, bit_ctx));
+            ctx.examine(bit_ctx.reach.div_ceil(8));
+            if bit_ctx.incomplete {
+                ctx.ran_out();
+            }
+            match result {
+                Ok((end, val)) => Ok((end.div_ceil(8), val)),
+                Err(error) if fatal => Err(ctx.abort(error.pos / 8, error.reason)),
+                Err(error) => Err(ParseError {
+                    source: ctx.clone_source(),
+                    pos: error.pos / 8,
+                    reason: error.reason,
+                }),
+            }
+        });
+
+        Parser::new(name, raw_parser)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn test_bits() {
+        // a flags byte: a version in 3 bits, two flags, then padding to the byte boundary
+        let header = bits(3)
+            .and(bool_bit())
+            .and(bool_bit())
+            .andl(align())
+            .and(bits(8));
+
+        let ctx = &mut Context::from_bits([0b1010_1000, 0x7f]);
+        assert_eq!(header.parse(0, ctx), Ok((16, (((5, false), true), 0x7f))));
+        let ctx = &mut Context::from_bits([0b1010_1000]);
+        let error = header.parse(0, ctx).unwrap_err();
+        assert_eq!(error.pos(), 8);
+        assert!(error.to_string().ends_with("expected 8 bits got EOF\n"));
+
+        let nibbles = bits(4).many().recognize();
+        let ctx = &mut Context::from_bits([0xa5]);
+        let (end, slice) = nibbles.parse(0, ctx).unwrap();
+        assert_eq!(
+            (end, format!("{:?}", slice)),
+            (8, String::from("10100101@0..8"))
+        );
+    }
+
+    #[test]
+    fn test_byte_aligned() {
+        let flags = bits(3).and(bool_bit()).byte_aligned();
+        let packet = byte(0xff).andr(flags).and(take_bytes(1)).end();
+
+        let ctx = &mut Context::from_bytes([0xff, 0b0111_0000, 0x2a]);
+        let (end, ((version, flag), rest)) = packet.parse(0, ctx).unwrap();
+        assert_eq!((end, version, flag, rest), (3, 3, true, Bytes::new([0x2a])));
+
+        let ctx = &mut Context::from_bytes([0xff]);
+        let error = packet.parse(0, ctx).unwrap_err();
+        assert_eq!(error.pos(), 1);
+        assert!(error.to_string().ends_with("expected 3 bits got EOF\n"));
+
+        // the bits are parsed under the options of the byte context, and their fatal errors
+        // end its parse too
+        let nibbles = bits(4).many().byte_aligned().opt();
+        let ctx = &mut Context::from_bytes([0xab; 16]).with_fuel(1000);
+        assert!(nibbles.parse(0, ctx).is_ok());
+        let used = 1000 - ctx.fuel().unwrap();
+        assert!(used > 32);
+        let ctx = &mut Context::from_bytes([0xab; 16]).with_fuel(10);
+        let error = nibbles.parse(0, ctx).unwrap_err();
+        assert!(error.to_string().ends_with("budget exceeded\n"));
+        assert_eq!(ctx.fuel(), Some(0));
+        let ctx = &mut Context::from_bytes([0xab; 16]).with_depth_limit(2);
+        assert!(nibbles.parse(0, ctx).is_err());
+    }
+}
@@ -0,0 +1,560 @@
+use std::cell::RefCell;
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::input::found;
+use crate::{Context, Input, ParseError, Parser, Pos, Reason, Text, elem};
+
+// binary input indexed by byte offset; like a source, the storage is shared, so cloning the
+// bytes or slicing them never copies them
+#[derive(Clone)]
+pub struct Bytes {
+    data: Data,
+    range: Range<usize>,
+}
+
+#[derive(Clone)]
+enum Data {
+    Owned(Rc<[u8]>),
+    #[cfg(feature = "memmap2")]
+    Mapped(Rc<memmap2::Mmap>),
+}
+
+impl Data {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            Data::Owned(data) => data,
+            #[cfg(feature = "memmap2")]
+            Data::Mapped(map) => map,
+        }
+    }
+
+    fn ptr_eq(&self, other: &Data) -> bool {
+        match (self, other) {
+            (Data::Owned(a), Data::Owned(b)) => Rc::ptr_eq(a, b),
+            #[cfg(feature = "memmap2")]
+            (Data::Mapped(a), Data::Mapped(b)) => Rc::ptr_eq(a, b),
+            #[cfg(feature = "memmap2")]
+            _ => false,
+        }
+    }
+}
+
+impl Bytes {
+    pub fn new(data: impl Into<Rc<[u8]>>) -> Self {
+        let data: Rc<[u8]> = data.into();
+        Bytes {
+            range: 0..data.len(),
+            data: Data::Owned(data),
+        }
+    }
+
+    /// Maps the file at `path` into memory and parses it in place, reading the bytes from
+    /// it directly.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be changed, by this process or any other, while the bytes or a
+    /// slice of them is alive.
+    #[cfg(feature = "memmap2")]
+    pub unsafe fn from_file(path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        // SAFETY: passed on to the caller
+        let map = unsafe { crate::source::map_file(path.as_ref())? };
+        Ok(Bytes {
+            range: 0..map.len(),
+            data: Data::Mapped(Rc::new(map)),
+        })
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data.as_slice()[self.range.clone()]
+    }
+
+    /// Where these bytes start in the input they were sliced from.
+    pub fn offset(&self) -> usize {
+        self.range.start
+    }
+
+    pub fn len(&self) -> usize {
+        self.range.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.range.is_empty()
+    }
+
+    // the same bytes of the same storage, without comparing them
+    pub(crate) fn is_same(&self, other: &Bytes) -> bool {
+        self.data.ptr_eq(&other.data) && self.range == other.range
+    }
+}
+
+impl From<&[u8]> for Bytes {
+    fn from(data: &[u8]) -> Self {
+        Bytes::new(data)
+    }
+}
+
+impl From<Vec<u8>> for Bytes {
+    fn from(data: Vec<u8>) -> Self {
+        Bytes::new(data)
+    }
+}
+
+impl std::fmt::Debug for Bytes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:02x?}@{:?}", self.as_slice(), self.range)
+    }
+}
+
+impl PartialEq for Bytes {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl Eq for Bytes {}
+
+impl PartialEq<[u8]> for Bytes {
+    fn eq(&self, other: &[u8]) -> bool {
+        self.as_slice() == other
+    }
+}
+
+impl PartialEq<&[u8]> for Bytes {
+    fn eq(&self, other: &&[u8]) -> bool {
+        self.as_slice() == *other
+    }
+}
+
+impl<const N: usize> PartialEq<[u8; N]> for Bytes {
+    fn eq(&self, other: &[u8; N]) -> bool {
+        self.as_slice() == other
+    }
+}
+
+impl std::hash::Hash for Bytes {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.as_slice().hash(state);
+    }
+}
+
+impl Input for Bytes {
+    type Elem = u8;
+    type Slice = Bytes;
+
+    fn len(&self) -> usize {
+        self.range.len()
+    }
+
+    fn next(&self, pos: Pos) -> Option<(u8, Pos)> {
+        self.as_slice().get(pos).map(|&b| (b, pos + 1))
+    }
+
+    fn slice(&self, range: Range<Pos>) -> Bytes {
+        assert!(
+            range.start <= range.end && range.end <= self.len(),
+            "slice {:?} out of bounds for {} bytes",
+            range,
+            self.len()
+        );
+        Bytes {
+            data: self.data.clone(),
+            range: self.range.start + range.start..self.range.start + range.end,
+        }
+    }
+}
+
+pub fn satisfy_byte<'a, I: Input<Elem = u8>>(
+    name: impl Into<String>,
+    f: impl Fn(u8) -> bool + 'a,
+) -> Parser<'a, u8, I> {
+    elem(name, move |&b| f(b))
+}
+
+pub fn byte<'a, I: Input<Elem = u8>>(b: u8) -> Parser<'a, u8, I> {
+    satisfy_byte(format!("0x{:02x}", b), move |x| x == b)
+}
+
+/// Matches the next `n` bytes, whatever they are.
+pub fn take_bytes<'a, I: Input<Elem = u8>>(n: usize) -> Parser<'a, I::Slice, I> {
+    let name = format!("{} bytes", n);
+    let raw_parser = {
+        let label: Text = Text::from(name.as_str());
+        Rc::new(move |pos: Pos, ctx: &mut Context<I>| {
+            let end = pos.checked_add(n);
+            if let Some(end) = end.filter(|&end| end <= ctx.source.len()) {
+                ctx.examine(end);
+                return Ok((end, ctx.source.slice(pos..end)));
+            }
+            // the input ran out partway, which is where the error points
+            ctx.ran_out();
+            let pos = ctx.source.len();
+            Err(ParseError {
+                source: ctx.clone_source(),
+                pos,
+                reason: Reason::Expected {
+                    label: label.clone(),
+                    found: found(&ctx.source, pos),
+                },
+            })
+        })
+    };
+
+    Parser::new(name, raw_parser)
+}
+
+// a fixed-width number, read from the next `N` bytes
+fn fixed<'a, T, I, const N: usize>(name: &str, decode: fn([u8; N]) -> T) -> Parser<'a, T, I>
+where
+    T: Clone + 'static,
+    I: Input<Elem = u8>,
+{
+    let name = name.to_string();
+    let raw_parser = {
+        let label: Text = Text::from(name.as_str());
+        Rc::new(move |pos, ctx: &mut Context<I>| {
+            let mut buf = [0; N];
+            let mut end = pos;
+            for b in buf.iter_mut() {
+                let Some((next, after)) = ctx.next(end) else {
+                    return Err(ParseError {
+                        source: ctx.clone_source(),
+                        pos: end,
+                        reason: Reason::Expected {
+                            label: label.clone(),
+                            found: found(&ctx.source, end),
+                        },
+                    });
+                };
+                (*b, end) = (next, after);
+            }
+            Ok((end, decode(buf)))
+        })
+    };
+
+    Parser::new(name, raw_parser)
+}
+
+pub fn u8<'a, I: Input<Elem = u8>>() -> Parser<'a, u8, I> {
+    fixed("u8", |[b]| b)
+}
+
+pub fn u16_le<'a, I: Input<Elem = u8>>() -> Parser<'a, u16, I> {
+    fixed("u16", u16::from_le_bytes)
+}
+
+pub fn u16_be<'a, I: Input<Elem = u8>>() -> Parser<'a, u16, I> {
+    fixed("u16", u16::from_be_bytes)
+}
+
+pub fn u32_le<'a, I: Input<Elem = u8>>() -> Parser<'a, u32, I> {
+    fixed("u32", u32::from_le_bytes)
+}
+
+pub fn u32_be<'a, I: Input<Elem = u8>>() -> Parser<'a, u32, I> {
+    fixed("u32", u32::from_be_bytes)
+}
+
+pub fn u64_le<'a, I: Input<Elem = u8>>() -> Parser<'a, u64, I> {
+    fixed("u64", u64::from_le_bytes)
+}
+
+pub fn u64_be<'a, I: Input<Elem = u8>>() -> Parser<'a, u64, I> {
+    fixed("u64", u64::from_be_bytes)
+}
+
+// the groups of 7 bits of a LEB128 number, low first, up to the byte without a
+// continuation bit; the position is where it ends, or where it ran out or overflowed
+fn leb128_groups<I: Input<Elem = u8>>(
+    pos: Pos,
+    ctx: &mut Context<I>,
+    signed: bool,
+) -> Result<(Pos, u64, u32), Pos> {
+    let (mut value, mut shift, mut end) = (0, 0, pos);
+    loop {
+        let (b, after) = ctx.next(end).ok_or(end)?;
+        let group = (b & 0x7f) as u64;
+        // the tenth group only has room for the top bit, the rest of it must be zero, or
+        // copies of that bit for signed numbers
+        let fits = match shift {
+            0..63 => true,
+            63 if signed => group == 0 || group == 0x7f,
+            63 => group <= 1,
+            _ => false,
+        };
+        if !fits {
+            return Err(end);
+        }
+        value |= group << shift;
+        shift += 7;
+        end = after;
+        if b & 0x80 == 0 {
+            return Ok((end, value, shift));
+        }
+    }
+}
+
+fn leb128<'a, T, I>(signed: bool, decode: fn(u64, u32) -> T) -> Parser<'a, T, I>
+where
+    T: Clone + 'static,
+    I: Input<Elem = u8>,
+{
+    let name = String::from("varint");
+    let raw_parser = {
+        let label: Text = Text::from(name.as_str());
+        Rc::new(
+            move |pos, ctx: &mut Context<I>| match leb128_groups(pos, ctx, signed) {
+                Ok((end, value, shift)) => Ok((end, decode(value, shift))),
+                Err(pos) => Err(ParseError {
+                    source: ctx.clone_source(),
+                    pos,
+                    reason: match ctx.source.next(pos) {
+                        None => Reason::Expected {
+                            label: label.clone(),
+                            found: found(&ctx.source, pos),
+                        },
+                        Some(_) => Reason::Message("varint overflows 64 bits"),
+                    },
+                }),
+            },
+        )
+    };
+
+    Parser::new(name, raw_parser)
+}
+
+/// An unsigned LEB128 varint, as protobuf and WebAssembly encode them.
+pub fn uleb128<'a, I: Input<Elem = u8>>() -> Parser<'a, u64, I> {
+    leb128(false, |value, _| value)
+}
+
+/// A signed LEB128 varint, sign-extended from its last group.
+pub fn sleb128<'a, I: Input<Elem = u8>>() -> Parser<'a, i64, I> {
+    leb128(true, |value, shift| {
+        let value = value as i64;
+        if shift < 64 && value & (1 << (shift - 1)) != 0 {
+            value | -1 << shift
+        } else {
+            value
+        }
+    })
+}
+
+/// Parses a length with `len`, then parses `body` from exactly that many bytes, as if they
+/// were all the input there was: the body can't read past them and fails if it doesn't
+/// consume them all.
+pub fn length_prefixed<'a, L, T, I>(
+    len: Parser<'a, L, I>,
+    body: Parser<'a, T, I>,
+) -> Parser<'a, T, I>
+where
+    L: TryInto<usize> + Clone + 'static,
+    T: Clone + 'static,
+    I: Input<Elem = u8, Slice = I>,
+{
+    let name = format!("{}[{}]", body.name, len.name);
+    // the context the body was last parsed in, kept to reuse its allocations; a body that
+    // nests length-prefixed parts of its own makes another while this one is taken
+    let spare: RefCell<Option<Context<I>>> = RefCell::new(None);
+    let raw_parser = {
+        let label: Text = Text::from(name.as_str());
+        Rc::new(move |pos, ctx: &mut Context<I>| {
+            let (start, n) = len.parse(pos, ctx)?;
+            let end = n.try_into().ok().and_then(|n| start.checked_add(n));
+            let Some(end) = end.filter(|&end| end <= ctx.source.len()) else {
+                ctx.ran_out();
+                let pos = ctx.source.len();
+                return Err(ParseError {
+                    source: ctx.clone_source(),
+                    pos,
+                    reason: Reason::Expected {
+                        label: label.clone(),
+                        found: found(&ctx.source, pos),
+                    },
+                });
+            };
+
+            ctx.examine(end);
+            let input = ctx.source.slice(start..end);
+            let mut inner = match spare.take() {
+                Some(mut inner) => {
+                    inner.set_source(input);
+                    inner
+                }
+                None => Context::from_source(input),
+            };
+            let (result, fatal) = ctx.nested(&mut inner, |inner| body.parse(0, inner));
+            let result = match result {
+                Ok((used, val)) if start + used == end => Ok((end, val)),
+                Err(error) if fatal => Err(ctx.abort(start + error.pos, error.reason)),
+                Ok((used, _)) => Err(ParseError {
+                    source: ctx.clone_source(),
+                    pos: start + used,
+                    reason: Reason::ExpectedEof {
+                        found: found(&ctx.source, start + used),
+                    },
+                }),
+                Err(error) => Err(ParseError {
+                    source: ctx.clone_source(),
+                    pos: start + error.pos,
+                    reason: error.reason,
+                }),
+            };
+            *spare.borrow_mut() = Some(inner);
+            result
+        })
+    };
+
+    Parser::new(name, raw_parser)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn test_bytes() {
+        // a PNG-like header: signature, then a chunk of one length byte, a tag and its body
+        let signature = byte(0x89).and(take_bytes(3)).recognize();
+        let chunk = take_bytes(1)
+            .map(|len: Bytes| len.as_slice()[0] as usize)
+            .and(satisfy_byte("tag", |b| b.is_ascii_uppercase()).many())
+            .and(take_bytes(2));
+        let file = signature.and(chunk).end();
+
+        let ctx = &mut Context::from_bytes([0x89, b'P', b'N', b'G', 2, b'I', b'D', 7, 9]);
+        let (end, (signature, ((len, tag), body))) = file.parse(0, ctx).unwrap();
+        assert_eq!(end, 9);
+        assert_eq!(signature, *b"\x89PNG");
+        assert_eq!((len, tag), (2, vec![b'I', b'D']));
+        assert_eq!(body, [7, 9]);
+        assert_eq!(body.offset(), 7);
+
+        let ctx = &mut Context::from_bytes([0x89, b'P', b'N', b'G', 2, b'I', b'D', 7]);
+        let error = file.parse(0, ctx).unwrap_err();
+        assert_eq!(error.pos(), 8);
+        assert!(error.to_string().ends_with("expected 2 bytes got EOF\n"));
+
+        let ctx = &mut Context::from_bytes([0x88]);
+        let error = file.parse(0, ctx).unwrap_err();
+        assert_eq!(error.pos(), 0);
+        assert!(error.to_string().ends_with("expected 0x89 got 0x88\n"));
+
+        // a count too large to add to the position runs out of input rather than overflowing
+        let ctx = &mut Context::from_bytes([1, 2]);
+        let huge = byte(1).andr(take_bytes(usize::MAX));
+        assert_eq!(huge.parse(0, ctx).unwrap_err().pos(), 2);
+    }
+
+    #[test]
+    fn test_bytes_memoized_by_offset() {
+        let zero = byte(0).map(|_| 0);
+        let run = lazy("run", move |run| {
+            run.and(byte(1)).map(|(n, _)| n + 1).or(zero.clone())
+        });
+
+        let ctx = &mut Context::from_bytes([0, 1, 1, 1, 2]);
+        assert_eq!(run.parse(0, ctx), Ok((4, 3)));
+        let hits = ctx.stats().hits;
+        assert_eq!(run.parse(0, ctx), Ok((4, 3)));
+        assert_eq!(ctx.stats().hits, hits + 1);
+        assert!(run.parse(4, ctx).is_err());
+    }
+
+    #[test]
+    fn test_numbers() {
+        let ctx = &mut Context::from_bytes([0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0]);
+        assert_eq!(u8().parse(0, ctx), Ok((1, 0x12)));
+        assert_eq!(u16_le().parse(0, ctx), Ok((2, 0x3412)));
+        assert_eq!(u16_be().parse(0, ctx), Ok((2, 0x1234)));
+        assert_eq!(u32_le().parse(4, ctx), Ok((8, 0xf0debc9a)));
+        assert_eq!(u32_be().parse(4, ctx), Ok((8, 0x9abcdef0)));
+        assert_eq!(u64_le().parse(0, ctx), Ok((8, 0xf0debc9a78563412)));
+        assert_eq!(u64_be().parse(0, ctx), Ok((8, 0x123456789abcdef0)));
+        let error = u32_be().parse(6, ctx).unwrap_err();
+        assert_eq!(error.pos(), 8);
+        assert!(error.to_string().ends_with("expected u32 got EOF\n"));
+    }
+
+    #[test]
+    fn test_leb128() {
+        let varints = |bytes: &[u8]| {
+            let ctx = &mut Context::from_bytes(bytes);
+            (uleb128().parse(0, ctx), sleb128().parse(0, ctx))
+        };
+        assert_eq!(varints(&[0x02]), (Ok((1, 2)), Ok((1, 2))));
+        assert_eq!(varints(&[0x7e]), (Ok((1, 126)), Ok((1, -2))));
+        assert_eq!(
+            varints(&[0xe5, 0x8e, 0x26]),
+            (Ok((3, 624485)), Ok((3, 624485)))
+        );
+        assert_eq!(varints(&[0xc0, 0xbb, 0x78]).1, Ok((3, -123456)));
+
+        let mut max = vec![0xff; 9];
+        max.push(0x01);
+        assert_eq!(varints(&max).0, Ok((10, u64::MAX)));
+        let mut min = vec![0x80; 9];
+        min.push(0x7f);
+        assert_eq!(varints(&min).1, Ok((10, i64::MIN)));
+
+        let mut long = vec![0xff; 10];
+        long.push(0x01);
+        let error = varints(&long).0.unwrap_err();
+        assert_eq!(error.pos(), 9);
+        assert!(error.to_string().ends_with("varint overflows 64 bits\n"));
+        let error = varints(&[0x80, 0x80]).0.unwrap_err();
+        assert!(error.to_string().ends_with("expected varint got EOF\n"));
+    }
+
+    #[test]
+    fn test_length_prefixed() {
+        // nested type-length-value records: a tag byte, a length, then the value or records
+        let record = lazy("record", |record| {
+            let leaf = byte(1).andr(length_prefixed(u8(), u8().many()).map(Tree::Leaf));
+            let node = byte(2).andr(length_prefixed(u8(), record.many()).map(Tree::Node));
+            leaf.or(node)
+        });
+
+        let ctx = &mut Context::from_bytes([2, 7, 1, 2, 8, 9, 1, 1, 5, 0xff]);
+        assert_eq!(
+            record.parse(0, ctx),
+            Ok((
+                9,
+                Tree::Node(vec![Tree::Leaf(vec![8, 9]), Tree::Leaf(vec![5])])
+            ))
+        );
+
+        // the body is cut off at its length, and has to use all of it
+        let pair = length_prefixed(u8(), u16_be());
+        let ctx = &mut Context::from_bytes([3, 0, 1, 2]);
+        let error = pair.parse(0, ctx).unwrap_err();
+        assert_eq!(error.pos(), 3);
+        assert!(error.to_string().ends_with("expected EOF found 0x02\n"));
+        let ctx = &mut Context::from_bytes([1, 0, 1]);
+        let error = pair.parse(0, ctx).unwrap_err();
+        assert_eq!(error.pos(), 2);
+        assert!(error.to_string().ends_with("expected u16 got EOF\n"));
+        let ctx = &mut Context::from_bytes([4, 0, 1]);
+        let error = pair.parse(0, ctx).unwrap_err();
+        assert_eq!(error.pos(), 3);
+
+        // the body is parsed under the options of the outer context, and its fatal errors
+        // end the outer parse too
+        let bytes = [2, 7, 1, 2, 8, 9, 1, 1, 5];
+        let ctx = &mut Context::from_bytes(bytes).with_fuel(1000);
+        assert!(record.parse(0, ctx).is_ok());
+        let used = 1000 - ctx.fuel().unwrap();
+        let ctx = &mut Context::from_bytes(bytes).with_fuel(used - 1);
+        let error = record.clone().opt().parse(0, ctx).unwrap_err();
+        assert!(error.to_string().ends_with("budget exceeded\n"));
+        let ctx = &mut Context::from_bytes(bytes).with_depth_limit(8);
+        assert!(record.opt().parse(0, ctx).is_err());
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Tree {
+        Leaf(Vec<u8>),
+        Node(Vec<Tree>),
+    }
+}
@@ -1,203 +1,943 @@
 use std::rc::Rc;
 
+use rustc_hash::{FxHashMap, FxHashSet};
+
 use crate::Context;
+use crate::Found;
+use crate::Input;
 use crate::ParseError;
 use crate::Parser;
+use crate::Pos;
+use crate::Punctuated;
+use crate::Reason;
+use crate::RuleShape;
+use crate::Shape;
+use crate::Text;
+use crate::input::{found, next_char};
 
-impl<T> Parser<T>
+impl<'a, T, I> Parser<'a, T, I>
 where
     T: Clone + 'static,
+    I: Input,
 {
-    pub fn rename(self, name: impl Into<String>) -> Parser<T> {
+    pub fn rename(self, name: impl Into<String>) -> Parser<'a, T, I> {
         Parser {
             name: name.into(),
-            id: self.id,
-            raw_parser: self.raw_parser,
+            ..self
+        }
+    }
+
+    /// Stores this rule's memo entries in a per-position column instead of a hash map.
+    /// Worth it for rules tried at nearly every position, such as trivia or identifiers.
+    pub fn dense_memo(self) -> Parser<'a, T, I> {
+        Parser {
+            dense_memo: true,
+            ..self
         }
     }
 
-    pub fn map<S: Clone + 'static>(self, f: impl Fn(T) -> S + 'static) -> Parser<S> {
+    /// Keeps this parser out of the memo table and re-runs it whenever it is called, which is
+    /// cheaper for terminals like `char('+')`. Left recursion is only detected at memoized
+    /// rules, so a cycle must still pass through one (rules made with `lazy` are).
+    pub fn no_memo(self) -> Parser<'a, T, I> {
+        Parser {
+            memoize: false,
+            ..self
+        }
+    }
+
+    pub fn map<S: Clone + 'static>(self, f: impl Fn(T) -> S + 'a) -> Parser<'a, S, I> {
         let name = self.name;
-        let raw_parser = Rc::new(move |pos, ctx: &mut Context| {
+        let first = self.first.clone();
+        let shape = self.shape.clone();
+        let raw_parser = Rc::new(move |pos, ctx: &mut Context<I>| {
             let (pos, val) = (self.raw_parser)(pos, ctx)?;
             Ok((pos, f(val)))
         });
         Parser::new(name, raw_parser)
+            .with_first(first)
+            .with_shape(shape)
     }
 
-    pub fn try_map<S: Clone + 'static>(self, f: impl Fn(T) -> Option<S> + 'static) -> Parser<S> {
+    /// Like `map`, but the output is kept behind an `Rc`, so it doesn't need to be `Clone`
+    /// and reusing a memoized result is a reference count bump rather than a deep clone.
+    ///
+    /// The memo table doesn't share entries by itself: parsers hand back their output by
+    /// value, so a memo hit clones whatever was stored, and outputs are `Clone` throughout
+    /// the API. For a large or non-`Clone` output, like an AST, sharing it here is what
+    /// makes its memo hits cheap.
+    pub fn map_shared<S: 'static>(self, f: impl Fn(T) -> S + 'a) -> Parser<'a, Rc<S>, I> {
+        self.map(move |val| Rc::new(f(val)))
+    }
+
+    pub fn try_map<S: Clone + 'static>(self, f: impl Fn(T) -> Option<S> + 'a) -> Parser<'a, S, I> {
         let name = self.name;
-        let raw_parser = Rc::new(move |pos, ctx: &mut Context| {
+        let first = self.first.clone();
+        let raw_parser = Rc::new(move |pos, ctx: &mut Context<I>| {
             let (pos, val) = (self.raw_parser)(pos, ctx)?;
             let Some(val) = f(val) else {
                 return Err(ParseError {
                     source: ctx.clone_source(),
                     pos,
-                    reason: String::from("try map failed: got None"),
+                    reason: Reason::Message("try map failed: got None"),
                 });
             };
             Ok((pos, val))
         });
-        Parser::new(name, raw_parser)
+        Parser::new(name, raw_parser).with_first(first)
     }
 
-    pub fn and<S: Clone + 'static>(self, right: Parser<S>) -> Parser<(T, S)> {
+    pub fn and<S: Clone + 'static>(self, right: Parser<'a, S, I>) -> Parser<'a, (T, S), I> {
         let name = format!("({}{})", self.name, right.name);
-        let raw_parser = Rc::new(move |pos, ctx: &mut Context| {
+        let first = self.first.clone();
+        let shape = Shape::seq(&self.shape, &right.shape);
+        let raw_parser = Rc::new(move |pos, ctx: &mut Context<I>| {
             let (pos, left_result) = self.parse(pos, ctx)?;
             let (pos, right_result) = right.parse(pos, ctx)?;
             Ok((pos, (left_result, right_result)))
         });
         Parser::new(name, raw_parser)
+            .with_first(first)
+            .with_shape(shape)
     }
 
-    pub fn andl<S: Clone + 'static>(self, right: Parser<S>) -> Parser<T> {
+    pub fn andl<S: Clone + 'static>(self, right: Parser<'a, S, I>) -> Parser<'a, T, I> {
         self.and(right).map(|(left, _)| left)
     }
 
-    pub fn andr<S: Clone + 'static>(self, right: Parser<S>) -> Parser<S> {
+    pub fn andr<S: Clone + 'static>(self, right: Parser<'a, S, I>) -> Parser<'a, S, I> {
         self.and(right).map(|(_, right)| right)
     }
 
-    pub fn many(self) -> Parser<Vec<T>> {
+    pub fn many(self) -> Parser<'a, Vec<T>, I> {
+        self.many_collect()
+    }
+
+    pub fn many_collect<C: FromIterator<T> + Clone + 'static>(self) -> Parser<'a, C, I> {
         let name = format!("({}*)", self.name);
-        let raw_parser = Rc::new(move |pos, ctx: &mut Context| {
-            let mut acc = Vec::new();
+        let shape = Shape::many(&self.shape);
+        let raw_parser = Rc::new(move |pos, ctx: &mut Context<I>| {
+            let mut pos = pos;
+            let mut stalled = false;
+            let acc = std::iter::from_fn(|| {
+                let (new_pos, val) = self.parse(pos, ctx).ok()?;
+                stalled = new_pos == pos;
+                pos = new_pos;
+                (!stalled).then_some(val)
+            })
+            .collect();
+            if stalled {
+                return Err(no_progress(pos, ctx));
+            }
+
+            Ok((pos, acc))
+        });
+
+        Parser::new(name, raw_parser).with_shape(shape)
+    }
+
+    pub fn sep_by<S: Clone + 'static>(self, sep: Parser<'a, S, I>) -> Parser<'a, Vec<T>, I> {
+        self.sep_by_full(sep).map(Punctuated::into_items)
+    }
+
+    pub fn sep_by_full<S: Clone + 'static>(
+        self,
+        sep: Parser<'a, S, I>,
+    ) -> Parser<'a, Punctuated<T, S>, I> {
+        let name = format!("({} ({}{})*)?", self.name, sep.name, self.name);
+        let shape = Shape::opt(&Shape::seq(
+            &self.shape,
+            &Shape::many(&Shape::seq(&sep.shape, &self.shape)),
+        ));
+        let raw_parser = Rc::new(move |pos, ctx: &mut Context<I>| {
+            let mut acc = Punctuated::new();
+            let Ok((mut pos, first)) = self.parse(pos, ctx) else {
+                return Ok((pos, acc));
+            };
+            acc.items.push(first);
+
+            while let Ok((sep_pos, s)) = sep.parse(pos, ctx)
+                && let Ok((new_pos, val)) = self.parse(sep_pos, ctx)
+            {
+                if new_pos == pos {
+                    return Err(no_progress(pos, ctx));
+                }
+                acc.separators.push((pos..sep_pos, s));
+                acc.items.push(val);
+                pos = new_pos;
+            }
+
+            Ok((pos, acc))
+        });
+
+        Parser::new(name, raw_parser).with_shape(shape)
+    }
+
+    pub fn count(self) -> Parser<'a, usize, I> {
+        let name = format!("#({}*)", self.name);
+        let shape = Shape::many(&self.shape);
+        let raw_parser = Rc::new(move |pos, ctx: &mut Context<I>| {
             let mut pos = pos;
+            let mut count = 0;
+
+            while let Ok((new_pos, _)) = self.parse(pos, ctx) {
+                if new_pos == pos {
+                    return Err(no_progress(pos, ctx));
+                }
+                pos = new_pos;
+                count += 1;
+            }
+
+            Ok((pos, count))
+        });
+
+        Parser::new(name, raw_parser).with_shape(shape)
+    }
+
+    pub fn foldl<A: Clone + 'static>(
+        self,
+        init: Parser<'a, A, I>,
+        op: impl Fn(A, T) -> A + 'a,
+    ) -> Parser<'a, A, I> {
+        let name = format!("({}{}*)", init.name, self.name);
+        let first = init.first.clone();
+        let shape = Shape::seq(&init.shape, &Shape::many(&self.shape));
+        let raw_parser = Rc::new(move |pos, ctx: &mut Context<I>| {
+            let (mut pos, mut acc) = init.parse(pos, ctx)?;
 
             while let Ok((new_pos, val)) = self.parse(pos, ctx) {
+                if new_pos == pos {
+                    return Err(no_progress(pos, ctx));
+                }
                 pos = new_pos;
-                acc.push(val);
+                acc = op(acc, val);
             }
 
             Ok((pos, acc))
         });
 
         Parser::new(name, raw_parser)
+            .with_first(first)
+            .with_shape(shape)
     }
 
-    pub fn opt(self) -> Parser<Option<T>> {
-        let name = format!("({}?)", self.name);
-        let raw_parser = Rc::new(move |pos, ctx: &mut Context| match self.parse(pos, ctx) {
-            Ok((pos, val)) => Ok((pos, Some(val))),
-            Err(_) => Ok((pos, None)),
+    pub fn foldr<A: Clone + 'static>(
+        self,
+        last: Parser<'a, A, I>,
+        op: impl Fn(T, A) -> A + 'a,
+    ) -> Parser<'a, A, I> {
+        let name = format!("({}*{})", self.name, last.name);
+        let shape = Shape::seq(&Shape::many(&self.shape), &last.shape);
+        // the items are collected first and folded from the back, so a long run doesn't
+        // nest a native call per item
+        let raw_parser = Rc::new(move |pos, ctx: &mut Context<I>| {
+            let mut pos = pos;
+            let mut items = Vec::new();
+            while let Ok((new_pos, val)) = self.parse(pos, ctx) {
+                if new_pos == pos {
+                    return Err(no_progress(pos, ctx));
+                }
+                items.push(val);
+                pos = new_pos;
+            }
+            let (pos, last) = last.parse(pos, ctx)?;
+            Ok((
+                pos,
+                items.into_iter().rev().fold(last, |acc, val| op(val, acc)),
+            ))
+        });
+
+        Parser::new(name, raw_parser).with_shape(shape)
+    }
+
+    /// Hands back the part of the input this parser matched instead of its value, a
+    /// [`Substr`](crate::Substr) for text.
+    pub fn recognize(self) -> Parser<'a, I::Slice, I> {
+        let name = self.name.clone();
+        let first = self.first.clone();
+        let shape = self.shape.clone();
+        let raw_parser = Rc::new(move |pos, ctx: &mut Context<I>| {
+            let (end, _) = self.parse(pos, ctx)?;
+            Ok((end, ctx.source.slice(pos..end)))
         });
+
         Parser::new(name, raw_parser)
+            .with_first(first)
+            .with_shape(shape)
     }
 
-    pub fn or(self, right: Parser<T>) -> Parser<T> {
-        let name = format!("({}/{})", self.name, right.name);
-        let raw_parser = Rc::new(move |pos, ctx: &mut Context| {
-            let e1 = match self.parse(pos, ctx) {
-                ok @ Ok(_) => return ok,
-                Err(e) => e,
-            };
+    /// Once this parser succeeds, drops the memo entries of every position before where it
+    /// ended. Put it after units the grammar never backtracks into again, such as top-level
+    /// items, so memory stays bounded on large inputs; backtracking past a cut is still
+    /// correct, it just re-parses.
+    pub fn cut(self) -> Parser<'a, T, I> {
+        let name = self.name.clone();
+        let first = self.first.clone();
+        let shape = self.shape.clone();
+        let raw_parser = Rc::new(move |pos, ctx: &mut Context<I>| {
+            let (end, val) = self.parse(pos, ctx)?;
+            ctx.cut(end);
+            Ok((end, val))
+        });
 
-            let e2 = match right.parse(pos, ctx) {
-                ok @ Ok(_) => return ok,
-                Err(e) => e,
-            };
+        Parser::new(name, raw_parser)
+            .with_first(first)
+            .with_shape(shape)
+    }
+
+    pub fn opt(self) -> Parser<'a, Option<T>, I> {
+        let name = format!("({}?)", self.name);
+        let shape = Shape::opt(&self.shape);
+        let raw_parser = Rc::new(
+            move |pos, ctx: &mut Context<I>| match self.parse(pos, ctx) {
+                Ok((pos, val)) => Ok((pos, Some(val))),
+                Err(_) => Ok((pos, None)),
+            },
+        );
+        Parser::new(name, raw_parser).with_shape(shape)
+    }
+
+    /// Succeeds without consuming input where this parser fails, and fails where it matches.
+    pub fn not_ahead(self) -> Parser<'a, (), I> {
+        let name = format!("!{}", self.name);
+        let shape = Rc::new(Shape::Not(self.shape.clone()));
+        let raw_parser = {
+            let label: Text = Text::from(format!("not {}", self.name));
+            Rc::new(
+                move |pos, ctx: &mut Context<I>| match self.parse(pos, ctx) {
+                    Ok(_) => Err(ParseError {
+                        source: ctx.clone_source(),
+                        pos,
+                        reason: Reason::Expected {
+                            label: label.clone(),
+                            found: found(&ctx.source, pos),
+                        },
+                    }),
+                    Err(_) => Ok((pos, ())),
+                },
+            )
+        };
+        Parser::new(name, raw_parser).with_shape(shape)
+    }
 
-            if e1.pos >= e2.pos { Err(e1) } else { Err(e2) }
+    /// Matches this parser and hands back its value, but leaves the position where it was.
+    pub fn peek(self) -> Parser<'a, T, I> {
+        let name = format!("&{}", self.name);
+        let shape = Rc::new(Shape::And(self.shape.clone()));
+        let raw_parser = Rc::new(move |pos, ctx: &mut Context<I>| {
+            // what was looked at ahead isn't part of the tree, nor does it change the state
+            let mark = ctx.events_mark();
+            let state_mark = ctx.state_mark();
+            let (_, val) = self.parse(pos, ctx)?;
+            ctx.truncate_events(mark);
+            ctx.rollback_state(state_mark);
+            Ok((pos, val))
         });
+        Parser::new(name, raw_parser).with_shape(shape)
+    }
+
+    pub fn or(self, right: Parser<'a, T, I>) -> Parser<'a, T, I> {
+        let name = format!("({}/{})", self.name, right.name);
+        let first = match (&self.first, &right.first) {
+            (Some(l), Some(r)) => {
+                let mut union = l.to_vec();
+                union.extend(r.iter().filter(|c| !l.contains(c)));
+                Some(Rc::from(union))
+            }
+            _ => None,
+        };
+        let shape = Shape::choice(&self.shape, &right.shape);
+        let raw_parser = {
+            let label: Text = Text::from(name.as_str());
+            Rc::new(move |pos, ctx: &mut Context<I>| {
+                // a branch whose first chars are known and don't include the next one can't
+                // match, so it's skipped without invoking (and memoizing) it
+                let next = next_char(ctx, pos);
+                let viable = |p: &Parser<'a, T, I>| match (&p.first, next) {
+                    (None, _) => true,
+                    (Some(first), Some(c)) => first.contains(&c),
+                    (Some(_), None) => false,
+                };
+
+                let e1 = if viable(&self) {
+                    match self.parse(pos, ctx) {
+                        ok @ Ok(_) => return ok,
+                        Err(e) => Some(e),
+                    }
+                } else {
+                    None
+                };
+                let e2 = if viable(&right) {
+                    match right.parse(pos, ctx) {
+                        ok @ Ok(_) => return ok,
+                        Err(e) => Some(e),
+                    }
+                } else {
+                    None
+                };
+
+                match (e1, e2) {
+                    (Some(e1), Some(e2)) if e1.pos >= e2.pos => Err(e1),
+                    (_, Some(e)) | (Some(e), None) => Err(e),
+                    (None, None) => Err(ParseError {
+                        source: ctx.clone_source(),
+                        pos,
+                        reason: Reason::Expected {
+                            label: label.clone(),
+                            found: found(&ctx.source, pos),
+                        },
+                    }),
+                }
+            })
+        };
 
         Parser::new(name, raw_parser)
+            .with_first(first)
+            .with_shape(shape)
     }
 
-    pub fn end(self) -> Parser<T> {
+    pub fn end(self) -> Parser<'a, T, I> {
         let name = String::from("end");
-        let raw_parser = Rc::new(move |pos, ctx: &mut Context| {
+        let first = self.first.clone();
+        let shape = Shape::seq(&self.shape, &Rc::new(Shape::Eof));
+        let raw_parser = Rc::new(move |pos, ctx: &mut Context<I>| {
             let (pos, val) = self.parse(pos, ctx)?;
-            match ctx.source.get(pos) {
-                Some(c) => Err(ParseError {
+            match ctx.next(pos) {
+                Some(_) => Err(ParseError {
                     source: ctx.clone_source(),
                     pos,
-                    reason: format!("expected EOF found {}", c),
+                    reason: Reason::ExpectedEof {
+                        found: found(&ctx.source, pos),
+                    },
                 }),
                 None => Ok((pos, val)),
             }
         });
 
         Parser::new(name, raw_parser)
+            .with_first(first)
+            .with_shape(shape)
     }
 }
 
-pub fn satisfy(name: impl Into<String>, f: impl Fn(char) -> bool + 'static) -> Parser<char> {
+pub fn satisfy<'a>(name: impl Into<String>, f: impl Fn(char) -> bool + 'a) -> Parser<'a, char> {
     let name = name.into();
+    let f: Rc<dyn Fn(char) -> bool + 'a> = Rc::new(f);
+    let shape = Rc::new(Shape::Class(Rc::from(name.as_str()), f.clone()));
     let raw_parser = {
-        let name = name.clone();
-        Rc::new(move |pos, ctx: &mut Context| match ctx.source.get(pos) {
-            Some(&c) if f(c) => Ok((pos + 1, c)),
-            Some(c) => Err(ParseError {
-                source: ctx.clone_source(),
-                pos,
-                reason: format!("expected {} got {}", name, c),
-            }),
-            None => Err(ParseError {
-                source: ctx.clone_source(),
-                pos,
-                reason: format!("expected {} got EOF", name),
-            }),
-        })
+        let label: Text = Text::from(name.as_str());
+        Rc::new(
+            move |pos, ctx: &mut Context| match ctx.next(pos).map(|(c, _)| c) {
+                Some(c) if f(c) => Ok((pos + c.len_utf8(), c)),
+                found => Err(ParseError {
+                    source: ctx.clone_source(),
+                    pos,
+                    reason: Reason::Expected {
+                        label: label.clone(),
+                        found: Found::from(found),
+                    },
+                }),
+            },
+        )
     };
 
-    Parser::new(name, raw_parser)
+    Parser::new(name, raw_parser).with_shape(shape)
 }
 
-pub fn any_char() -> Parser<char> {
-    satisfy("(any char)", |_| true)
+pub fn any_char<'a>() -> Parser<'a, char> {
+    satisfy("(any char)", |_| true).with_shape(Rc::new(Shape::Any))
 }
 
-pub fn char(c: char) -> Parser<char> {
+pub fn char<'a>(c: char) -> Parser<'a, char> {
     satisfy(format!("'{}'", c), move |x| x == c)
+        .with_first(Some(Rc::from([c])))
+        .with_shape(Rc::new(Shape::Char(c)))
+}
+
+// what may continue an identifier, so a keyword can't be followed by one
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
 }
 
-pub fn keyword(keyword: impl Into<String>) -> Parser<String> {
-    let name = keyword.into();
-    let keyword = name.clone().chars().collect::<Vec<char>>();
+/// Matches `keyword`. One that ends like an identifier, such as `if`, only matches where
+/// it isn't followed by more of one, so it doesn't match the start of `ifield`; `string`
+/// matches text regardless of what follows it.
+pub fn keyword<'a>(keyword: impl Into<String>) -> Parser<'a, String> {
+    let keyword = keyword.into();
+    if !keyword.ends_with(is_ident_char) {
+        return string(keyword);
+    }
+    let boundary = Rc::new(Shape::Not(Rc::new(Shape::Class(
+        Rc::from("identifier char"),
+        Rc::new(is_ident_char),
+    ))));
+    let shape = Shape::seq(
+        &Rc::new(Shape::Keyword(Rc::from(keyword.as_str()))),
+        &boundary,
+    );
+    literal(keyword, true).with_shape(shape)
+}
+
+/// Matches the text `text`, whatever follows it.
+pub fn string<'a>(text: impl Into<String>) -> Parser<'a, String> {
+    let text = text.into();
+    let shape = Rc::new(Shape::Keyword(Rc::from(text.as_str())));
+    literal(text, false).with_shape(shape)
+}
+
+/// Matches the text `text` in any case, as far as ASCII letters go, producing it as it was
+/// written in the input. `string_caseless`, with the `unicode-case` feature, compares all
+/// of Unicode instead.
+pub fn string_ci<'a>(text: impl Into<String>) -> Parser<'a, String> {
+    let text = text.into();
+    let first = text.chars().next().map(|c| {
+        let (lower, upper) = (c.to_ascii_lowercase(), c.to_ascii_uppercase());
+        Rc::from(if lower == upper {
+            vec![c]
+        } else {
+            vec![lower, upper]
+        })
+    });
+    string_folded(format!("\"{}\"i", text), &text, |c, folded| {
+        folded.push(c.to_ascii_lowercase())
+    })
+    .with_first(first)
+}
+
+// matches text that, mapped through `fold` one char at a time, is `text` mapped the same
+// way; a char may fold to several, so the two needn't be the same length
+pub(crate) fn string_folded<'a>(
+    name: String,
+    text: &str,
+    fold: fn(char, &mut String),
+) -> Parser<'a, String> {
+    let mut folded = String::new();
+    text.chars().for_each(|c| fold(c, &mut folded));
+    let label = Text::from(name.as_str());
+    let raw_parser = Rc::new(move |pos, ctx: &mut Context| {
+        let (mut end, mut matched, mut seen) = (pos, String::new(), String::new());
+        while seen.len() < folded.len() {
+            match ctx.next(end) {
+                Some((c, next)) => {
+                    fold(c, &mut seen);
+                    matched.push(c);
+                    end = next;
+                }
+                None => break,
+            }
+            if !folded.starts_with(&seen) {
+                break;
+            }
+        }
+        if seen != folded {
+            return Err(ParseError {
+                source: ctx.clone_source(),
+                pos,
+                reason: Reason::Expected {
+                    label: label.clone(),
+                    found: found(&ctx.source, pos),
+                },
+            });
+        }
+        Ok((end, matched))
+    });
+    Parser::new(name, raw_parser)
+}
+
+/// Matches `c` in either case if it is an ASCII letter, and only `c` otherwise, producing
+/// the char as it was written in the input.
+pub fn char_ci<'a>(c: char) -> Parser<'a, char> {
+    let (lower, upper) = (c.to_ascii_lowercase(), c.to_ascii_uppercase());
+    satisfy(format!("'{}'i", c), move |x| x.eq_ignore_ascii_case(&c))
+        .with_first(Some(Rc::from([lower, upper])))
+}
+
+/// Matches a line break however the text was written, `\n`, `\r\n`, or a lone `\r` as
+/// classic Mac OS ended lines, and produces it as it was.
+pub fn newline<'a>() -> Parser<'a, String> {
+    let raw_parser = Rc::new(|pos, ctx: &mut Context| {
+        let newline = match next_char(ctx, pos) {
+            Some('\n') => "\n",
+            Some('\r') if next_char(ctx, pos + 1) == Some('\n') => "\r\n",
+            Some('\r') => "\r",
+            _ => {
+                return Err(ParseError {
+                    source: ctx.clone_source(),
+                    pos,
+                    reason: Reason::Expected {
+                        label: Text::from("newline"),
+                        found: found(&ctx.source, pos),
+                    },
+                });
+            }
+        };
+        Ok((pos + newline.len(), String::from(newline)))
+    });
+    Parser::new(String::from("newline"), raw_parser).with_first(Some(Rc::from(['\n', '\r'])))
+}
+
+/// Matches the rest of the line, possibly nothing, up to but not including the line break
+/// or the end of the input.
+pub fn line<'a>() -> Parser<'a, String> {
+    satisfy("line", |c| c != '\n' && c != '\r')
+        .many_collect::<String>()
+        .rename("line")
+}
+
+fn literal<'a>(keyword: String, bounded: bool) -> Parser<'a, String> {
+    let name = keyword.clone();
+    let first = keyword.chars().next().map(|c| Rc::from([c]));
     let raw_parser = {
-        let name = name.clone();
+        let label: Text = Text::from(format!("\"{}\"", name));
         Rc::new(move |pos, ctx: &mut Context| {
-            if ctx
-                .source
-                .get(pos..)
-                .is_some_and(|s| s.starts_with(&keyword))
+            ctx.examine(pos + keyword.len());
+            let end = pos + keyword.len();
+            if ctx.source.starts_with_at(pos, &keyword)
+                && !(bounded && next_char(ctx, end).is_some_and(is_ident_char))
             {
-                Ok((pos + keyword.len(), name.clone()))
+                Ok((end, keyword.clone()))
             } else {
+                // what there is of the input may be the start of the keyword, compared as
+                // bytes since the input may end inside one of the keyword's chars
+                let available = ctx.source.len() - pos;
+                if available < keyword.len()
+                    && (ctx.source).starts_with_bytes_at(pos, &keyword.as_bytes()[..available])
+                {
+                    ctx.ran_out();
+                }
                 Err(ParseError {
                     source: ctx.clone_source(),
                     pos,
-                    reason: String::from(""),
+                    reason: Reason::Expected {
+                        label: label.clone(),
+                        found: Found::from(ctx.source.get(pos)),
+                    },
                 })
             }
         })
     };
 
+    Parser::new(name, raw_parser).with_first(first)
+}
+
+/// Matches an identifier, a letter or `_` followed by letters, digits and `_`, other than
+/// the `reserved` words; see [`Keywords`] to share them with the keyword parsers.
+pub fn ident_except<'a>(
+    reserved: impl IntoIterator<Item = impl Into<String>>,
+) -> Parser<'a, String> {
+    Keywords::new(reserved).identifier()
+}
+
+/// The reserved words of a language, built into a set once and shared by every parser made
+/// from it: its `keyword`s, and its `identifier`, which matches every identifier but them,
+/// so the two can't disagree about what is reserved.
+///
+/// ```
+/// use packrust::*;
+///
+/// let keywords = Keywords::new(["let", "in"]);
+/// let name = keywords.identifier();
+/// let binding = keywords
+///     .keyword("let")
+///     .andr(char(' '))
+///     .andr(name.clone())
+///     .andl(keywords.keyword(" in"));
+/// assert_eq!(binding.run("let inner in"), Ok(String::from("inner")));
+/// assert!(binding.run("let in in").is_err());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Keywords {
+    words: Rc<FxHashSet<String>>,
+}
+
+impl Keywords {
+    pub fn new(words: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Keywords {
+            words: Rc::new(words.into_iter().map(Into::into).collect()),
+        }
+    }
+
+    pub fn contains(&self, word: &str) -> bool {
+        self.words.contains(word.trim())
+    }
+
+    /// Matches `word` as the `keyword` function does. Panics if `word`, without surrounding
+    /// whitespace, isn't in the set, as identifiers could then be spelled like it.
+    pub fn keyword<'a>(&self, word: &str) -> Parser<'a, String> {
+        assert!(
+            self.contains(word),
+            "`{}` is not a keyword of the set",
+            word
+        );
+        keyword(word)
+    }
+
+    /// Matches an identifier, a letter or `_` followed by letters, digits and `_`, other
+    /// than the words in the set.
+    pub fn identifier<'a>(&self) -> Parser<'a, String> {
+        let reserved = self.words.clone();
+        let start = satisfy("identifier", |c| c.is_alphabetic() || c == '_');
+        let ident = start
+            .and(satisfy("identifier char", is_ident_char).many_collect::<String>())
+            .map(|(first, rest)| format!("{}{}", first, rest));
+        let raw_parser = Rc::new(move |pos, ctx: &mut Context| {
+            let (end, word) = ident.parse(pos, ctx)?;
+            if reserved.contains(&word) {
+                return Err(ParseError {
+                    source: ctx.clone_source(),
+                    pos,
+                    reason: Reason::Expected {
+                        label: Text::from("identifier"),
+                        found: Found::Elem(Text::from(format!("keyword {}", word))),
+                    },
+                });
+            }
+            Ok((end, word))
+        });
+        Parser::new(String::from("identifier"), raw_parser)
+    }
+}
+
+/// Matches one element `f` accepts, the counterpart of `satisfy` for inputs other than text.
+pub fn elem<'a, I: Input>(
+    name: impl Into<String>,
+    f: impl Fn(&I::Elem) -> bool + 'a,
+) -> Parser<'a, I::Elem, I> {
+    elem_map(name, move |elem| f(&elem).then_some(elem))
+}
+
+/// Matches exactly `expected`, the counterpart of `char` for inputs other than text.
+pub fn just<'a, I: Input>(expected: I::Elem) -> Parser<'a, I::Elem, I> {
+    elem(format!("{:?}", expected), move |elem| *elem == expected)
+}
+
+/// Matches one element `f` turns into a value, which it produces. [`select!`](crate::select)
+/// writes these for token patterns.
+pub fn elem_map<'a, T: Clone + 'static, I: Input>(
+    name: impl Into<String>,
+    f: impl Fn(I::Elem) -> Option<T> + 'a,
+) -> Parser<'a, T, I> {
+    let name = name.into();
+    let raw_parser = {
+        let label: Text = Text::from(name.as_str());
+        Rc::new(move |pos, ctx: &mut Context<I>| {
+            if let Some((elem, end)) = ctx.next(pos)
+                && let Some(val) = f(elem)
+            {
+                return Ok((end, val));
+            }
+            Err(ParseError {
+                source: ctx.clone_source(),
+                pos,
+                reason: Reason::Expected {
+                    label: label.clone(),
+                    found: found(&ctx.source, pos),
+                },
+            })
+        })
+    };
+
     Parser::new(name, raw_parser)
 }
 
-pub fn lazy<T: Clone + 'static>(
+pub fn chainl1<'a, T, F, I>(term: Parser<'a, T, I>, op: Parser<'a, F, I>) -> Parser<'a, T, I>
+where
+    T: Clone + 'static,
+    F: Fn(T, T) -> T + Clone + 'static,
+    I: Input,
+{
+    let name = format!("chainl1({} {})", term.name, op.name);
+    let first = term.first.clone();
+    let shape = Shape::seq(
+        &term.shape,
+        &Shape::many(&Shape::seq(&op.shape, &term.shape)),
+    );
+    let raw_parser = Rc::new(move |pos, ctx: &mut Context<I>| {
+        let (mut pos, mut acc) = term.parse(pos, ctx)?;
+
+        while let Ok((op_pos, f)) = op.parse(pos, ctx)
+            && let Ok((new_pos, right)) = term.parse(op_pos, ctx)
+        {
+            if new_pos == pos {
+                return Err(no_progress(pos, ctx));
+            }
+            pos = new_pos;
+            acc = f(acc, right);
+        }
+
+        Ok((pos, acc))
+    });
+
+    Parser::new(name, raw_parser)
+        .with_first(first)
+        .with_shape(shape)
+}
+
+pub fn chainr1<'a, T, F, I>(term: Parser<'a, T, I>, op: Parser<'a, F, I>) -> Parser<'a, T, I>
+where
+    T: Clone + 'static,
+    F: Fn(T, T) -> T + Clone + 'static,
+    I: Input,
+{
+    let name = format!("chainr1({} {})", term.name, op.name);
+    let first = term.first.clone();
+    let shape = Shape::seq(
+        &term.shape,
+        &Shape::many(&Shape::seq(&op.shape, &term.shape)),
+    );
+    let raw_parser = Rc::new(move |pos, ctx: &mut Context<I>| {
+        let (mut pos, first) = term.parse(pos, ctx)?;
+        let mut rest = Vec::new();
+
+        while let Ok((op_pos, f)) = op.parse(pos, ctx)
+            && let Ok((new_pos, right)) = term.parse(op_pos, ctx)
+        {
+            if new_pos == pos {
+                return Err(no_progress(pos, ctx));
+            }
+            pos = new_pos;
+            rest.push((f, right));
+        }
+
+        let mut rest = rest.into_iter().rev();
+        let Some((mut f, mut acc)) = rest.next() else {
+            return Ok((pos, first));
+        };
+        for (prev_f, left) in rest {
+            acc = f(left, acc);
+            f = prev_f;
+        }
+
+        Ok((pos, f(first, acc)))
+    });
+
+    Parser::new(name, raw_parser)
+        .with_first(first)
+        .with_shape(shape)
+}
+
+/// Tries `alternatives` in order, like chaining them with `or`, but looks the next char up
+/// in a table built from their first chars so only the alternatives that can start with it
+/// are tried.
+pub fn choice<'a, T: Clone + 'static, I: Input>(
+    alternatives: impl IntoIterator<Item = Parser<'a, T, I>>,
+) -> Parser<'a, T, I> {
+    let alternatives: Vec<Parser<'a, T, I>> = alternatives.into_iter().collect();
+    let name = format!(
+        "({})",
+        alternatives
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect::<Vec<_>>()
+            .join("/")
+    );
+    let shape = alternatives
+        .iter()
+        .map(|p| p.shape.clone())
+        .reduce(|l, r| Shape::choice(&l, &r))
+        .unwrap_or_else(|| Rc::new(Shape::Choice(Vec::new())));
+    let first = alternatives
+        .iter()
+        .map(|p| p.first.clone())
+        .collect::<Option<Vec<_>>>()
+        .map(|firsts| {
+            let mut union: Vec<char> = Vec::new();
+            for c in firsts.iter().flat_map(|f| f.iter()) {
+                if !union.contains(c) {
+                    union.push(*c);
+                }
+            }
+            Rc::from(union)
+        });
+
+    // alternatives with unknown first chars may start with anything, so they are tried for
+    // every char, in order with the ones listed for it
+    let unknown: Vec<usize> = (0..alternatives.len())
+        .filter(|&i| alternatives[i].first.is_none())
+        .collect();
+    let mut table: FxHashMap<char, Vec<usize>> = FxHashMap::default();
+    for (i, p) in alternatives.iter().enumerate() {
+        for &c in p.first.iter().flat_map(|f| f.iter()) {
+            table.entry(c).or_insert_with(|| unknown.clone()).push(i);
+        }
+    }
+    table.values_mut().for_each(|candidates| {
+        candidates.sort_unstable();
+        candidates.dedup();
+    });
+
+    let label: Text = Text::from(name.as_str());
+    let raw_parser = Rc::new(move |pos, ctx: &mut Context<I>| {
+        let next = next_char(ctx, pos);
+        let candidates = next.and_then(|c| table.get(&c)).unwrap_or(&unknown);
+        let mut error: Option<ParseError> = None;
+        for &i in candidates {
+            match alternatives[i].parse(pos, ctx) {
+                ok @ Ok(_) => return ok,
+                Err(e) if error.as_ref().is_none_or(|farthest| e.pos > farthest.pos) => {
+                    error = Some(e)
+                }
+                Err(_) => {}
+            }
+        }
+        Err(error.unwrap_or_else(|| ParseError {
+            source: ctx.clone_source(),
+            pos,
+            reason: Reason::Expected {
+                label: label.clone(),
+                found: found(&ctx.source, pos),
+            },
+        }))
+    });
+
+    Parser::new(name, raw_parser)
+        .with_first(first)
+        .with_shape(shape)
+}
+
+// a repetition whose item succeeds without consuming anything would match it at the same
+// position forever, so it fails instead of hanging
+pub(crate) const NO_PROGRESS: &str = "repeated parser matched nothing and would loop forever";
+
+fn no_progress<I: Input>(pos: Pos, ctx: &Context<I>) -> ParseError {
+    ParseError {
+        source: ctx.clone_source(),
+        pos,
+        reason: Reason::Message(NO_PROGRESS),
+    }
+}
+
+/// A parser that can refer to itself, built by `get_parser` from a placeholder standing in
+/// for it. The placeholder may come first, as in `expr <- expr '+' term / term`: such left
+/// recursion is resolved by growing a seed one repetition at a time, so the results always
+/// lean left, `1+2+3` is `(1+2)+3`, wherever the rule is entered and however often it is
+/// grown again inside its own operands. A rule that recurses on its right as well, like
+/// `expr <- expr '-' expr / 'n'`, takes as long a right operand as it can and so groups to
+/// the right instead.
+pub fn lazy<'a, T: Clone + 'static, I: Input>(
     name: impl Into<String>,
-    get_parser: impl Fn(Parser<T>) -> Parser<T> + 'static,
-) -> Parser<T> {
+    get_parser: impl Fn(Parser<'a, T, I>) -> Parser<'a, T, I> + 'a,
+) -> Parser<'a, T, I> {
     use std::cell::OnceCell;
     use std::rc::Rc;
 
     let name = name.into();
     let cell = Rc::new(OnceCell::new());
     let cell_for_parse = cell.clone();
+    let rule = RuleShape::new(&name);
 
     let placeholder = Parser::new(
         name,
-        Rc::new(move |pos, ctx: &mut Context| {
-            let real: &Parser<T> = cell_for_parse.get().expect("uninitialized lazy parser");
+        Rc::new(move |pos, ctx: &mut Context<I>| {
+            let real: &Parser<'a, T, I> = cell_for_parse.get().expect("uninitialized lazy parser");
             real.parse(pos, ctx)
         }),
-    );
+    )
+    .with_shape(Rc::new(Shape::Rule(rule.clone())));
 
     let real = get_parser(placeholder.clone());
 
+    let _ = rule.body.set(real.shape.clone());
     let _ = cell.set(real);
 
     placeholder
@@ -213,6 +953,8 @@ mod test {
 
         let ctx = &mut Context::new("abc");
         assert_eq!(any_char.parse(0, ctx), Ok((1, 'a')));
+        let ctx = &mut Context::new("éa");
+        assert_eq!(any_char.parse(0, ctx), Ok((2, 'é')));
         let ctx = &mut Context::new("");
         assert!(any_char.parse(0, ctx).is_err());
     }
@@ -314,9 +1056,452 @@ mod test {
         assert_eq!(kw_int.parse(0, ctx), Ok((3, String::from("int"))));
         let ctx = &mut Context::new("int 10");
         assert_eq!(kw_int.parse(0, ctx), Ok((3, String::from("int"))));
+        let ctx = &mut Context::segmented("xint", 2);
+        assert_eq!(kw_int.parse(1, ctx), Ok((4, String::from("int"))));
         let ctx = &mut Context::new("bcd");
         assert!(kw_int.parse(0, ctx).is_err());
         let ctx = &mut Context::new("");
         assert!(kw_int.parse(0, ctx).is_err());
+
+        // a keyword is a whole word, unlike a string
+        let ctx = &mut Context::new("integer");
+        assert!(kw_int.parse(0, ctx).is_err());
+        assert_eq!(string("int").parse(0, ctx), Ok((3, String::from("int"))));
+        assert_eq!(
+            keyword("int").run_partial("int_x").unwrap_err().prefix,
+            None
+        );
+        assert_eq!(
+            keyword("int")
+                .run_partial("int(")
+                .unwrap_err()
+                .prefix
+                .unwrap()
+                .0,
+            3
+        );
+        assert_eq!(
+            keyword("<=")
+                .run_partial("<=>")
+                .unwrap_err()
+                .prefix
+                .unwrap()
+                .0,
+            2
+        );
+
+        // streamed input that ends early waits for more only where it is the start of the
+        // keyword, even when it ends inside one of the keyword's chars
+        let euro = string("€uro");
+        let ctx = &mut Context::new("é").with_streaming();
+        assert!(!euro.parse(0, ctx).unwrap_err().is_incomplete());
+        let ctx = &mut Context::new("x").with_streaming();
+        assert!(!euro.parse(0, ctx).unwrap_err().is_incomplete());
+        let ctx = &mut Context::new("€u").with_streaming();
+        assert!(euro.parse(0, ctx).unwrap_err().is_incomplete());
+        ctx.feed("ro");
+        ctx.finish();
+        assert_eq!(euro.parse(0, ctx), Ok((6, String::from("€uro"))));
+    }
+
+    #[test]
+    fn test_ident_except() {
+        let ident = ident_except(["if", "else"]);
+        assert_eq!(ident.run("iffy"), Ok(String::from("iffy")));
+        assert_eq!(ident.run("_x1"), Ok(String::from("_x1")));
+        assert!(ident.run("1x").is_err());
+        let error = ident.run("else").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "else\n^\nexpected identifier got keyword else\n"
+        );
+
+        // a set shares its words between the keywords and the identifier
+        let keywords = Keywords::new(["fn"]);
+        let item = keywords
+            .keyword("fn")
+            .andl(char(' '))
+            .and(keywords.identifier());
+        assert!(item.run("fn fn").is_err());
+        assert_eq!(
+            item.run("fn fnord"),
+            Ok((String::from("fn"), String::from("fnord")))
+        );
+        assert!(!keywords.contains("if"));
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        let select = string_ci("Select").andl(char(' ')).and(char_ci('x'));
+        assert_eq!(select.run("sELECT X"), Ok((String::from("sELECT"), 'X')));
+        // only ASCII letters fold
+        assert!(string_ci("é").run("É").is_err());
+        let error = select.run("selec x").unwrap_err();
+        assert_eq!(error.pos(), 0);
+        assert_eq!(
+            error.to_string(),
+            "selec x\n^\nexpected \"Select\"i got s\n"
+        );
+    }
+
+    #[test]
+    fn test_newline() {
+        let lines = line().sep_by(newline()).end();
+        assert_eq!(
+            lines.run("a\r\nb\rc\n\nd").unwrap(),
+            ["a", "b", "c", "", "d"]
+        );
+        let ctx = &mut Context::new("\r\n\r");
+        assert_eq!(newline().parse(0, ctx), Ok((2, String::from("\r\n"))));
+        assert_eq!(newline().parse(2, ctx), Ok((3, String::from("\r"))));
+        assert!(newline().parse(3, ctx).is_err());
+        assert_eq!(line().parse(3, ctx), Ok((3, String::new())));
+
+        // with universal newlines, a lone `\r` starts a line for columns and errors too
+        let text = "ab\rcd\r\nef";
+        let ctx = &mut Context::new(text).with_universal_newlines();
+        assert_eq!(crate::column().parse(4, ctx), Ok((4, 1)));
+        assert_eq!(ctx.source_map().unwrap().line_col(7), (2, 0));
+        assert_eq!(Context::new(text).source_map().unwrap().line_col(7), (1, 0));
+        let mut db = crate::SourceDb::new();
+        let id = db.add("mac.txt", text);
+        let error = char('x')
+            .parse(4, &mut db.context(id).with_universal_newlines())
+            .unwrap_err();
+        assert!(error.to_string().starts_with("mac.txt:2:2\n"));
+    }
+
+    #[test]
+    fn test_run_partial() {
+        let digit = satisfy("digit", |c| c.is_ascii_digit());
+        let digits = digit.many().map(|c| c.iter().collect::<String>());
+
+        assert_eq!(digits.run_partial("123"), Ok(String::from("123")));
+        let partial = digits.run_partial("12a4").unwrap_err();
+        assert_eq!(partial.prefix, Some((2, String::from("12"))));
+        assert_eq!(partial.error.pos, 2);
+        let partial = char('a').run_partial("b").unwrap_err();
+        assert_eq!(partial.prefix, None);
+    }
+
+    #[test]
+    fn test_chainl1() {
+        let digit =
+            satisfy("digit", |c| c.is_ascii_digit()).map(|c| c.to_digit(10).unwrap() as i32);
+        let sub = char('-').map(|_| |a: i32, b: i32| a - b);
+        let p = chainl1(digit, sub);
+
+        let ctx = &mut Context::new("9-3-2");
+        assert_eq!(p.parse(0, ctx), Ok((5, 4)));
+        let ctx = &mut Context::new("9-");
+        assert_eq!(p.parse(0, ctx), Ok((1, 9)));
+        let ctx = &mut Context::new("");
+        assert!(p.parse(0, ctx).is_err());
+    }
+
+    #[test]
+    fn test_chainr1() {
+        let digit =
+            satisfy("digit", |c| c.is_ascii_digit()).map(|c| c.to_digit(10).unwrap() as i32);
+        let sub = char('-').map(|_| |a: i32, b: i32| a - b);
+        let p = chainr1(digit, sub);
+
+        let ctx = &mut Context::new("9-3-2");
+        assert_eq!(p.parse(0, ctx), Ok((5, 8)));
+        let ctx = &mut Context::new("9-3-");
+        assert_eq!(p.parse(0, ctx), Ok((3, 6)));
+        let ctx = &mut Context::new("");
+        assert!(p.parse(0, ctx).is_err());
+    }
+
+    #[test]
+    fn test_dense_memo() {
+        let digit = satisfy("digit", |c| c.is_ascii_digit()).dense_memo();
+        let sum = lazy("sum", move |sum| {
+            sum.andl(char('+'))
+                .and(digit.clone())
+                .map(|(n, c)| n + c.to_digit(10).unwrap())
+                .or(digit.clone().map(|c| c.to_digit(10).unwrap()))
+                .dense_memo()
+        });
+
+        let ctx = &mut Context::new("1+2+3");
+        assert_eq!(sum.parse(0, ctx), Ok((5, 6)));
+        let ctx = &mut Context::new("1+");
+        assert_eq!(sum.parse(0, ctx), Ok((1, 1)));
+        let ctx = &mut Context::new("");
+        assert!(sum.parse(0, ctx).is_err());
+    }
+
+    #[test]
+    fn test_no_memo() {
+        let calls = std::cell::Cell::new(0);
+        let letter = |memoize: bool| {
+            let letter = satisfy("a", |c| {
+                calls.set(calls.get() + 1);
+                c == 'a'
+            });
+            if memoize { letter } else { letter.no_memo() }
+        };
+
+        for (memoize, expected_calls) in [(true, 1), (false, 2)] {
+            calls.set(0);
+            let a = letter(memoize);
+            let p = a.clone().andl(char('b')).or(a.andl(char('c')));
+            let ctx = &mut Context::new("ac");
+            assert_eq!(p.parse(0, ctx), Ok((2, 'a')));
+            assert_eq!(calls.get(), expected_calls);
+        }
+
+        let digit = satisfy("digit", |c| c.is_ascii_digit())
+            .map(|c| c.to_digit(10).unwrap())
+            .no_memo();
+        let sum = lazy("sum", move |sum| {
+            sum.andl(char('+').no_memo())
+                .and(digit.clone())
+                .map(|(n, m)| n + m)
+                .or(digit.clone())
+        });
+        let ctx = &mut Context::new("1+2+3");
+        assert_eq!(sum.parse(0, ctx), Ok((5, 6)));
+    }
+
+    #[test]
+    fn test_without_memo() {
+        let digit = satisfy("digit", |c| c.is_ascii_digit()).map(|c| c.to_digit(10).unwrap());
+        let sum = chainl1(digit.clone(), char('+').map(|_| |n, m| n + m));
+        let ctx = &mut Context::new("1+2+3").without_memo();
+        assert_eq!(sum.parse(0, ctx), Ok((5, 6)));
+
+        // left recursion ends the parse rather than `or` quietly parsing just the first digit
+        let sum = crate::fixture::sum();
+        let ctx = &mut Context::new("1+2+3").without_memo();
+        assert_eq!(
+            sum.parse(0, ctx).unwrap_err().to_string(),
+            "1+2+3\n^\nrule `sum` is left-recursive\n"
+        );
+        // unless it is asked to fail like a mismatch
+        let ctx = &mut Context::new("1+2+3")
+            .without_memo()
+            .with_left_recursion(crate::LeftRecursionPolicy::Fail);
+        assert_eq!(sum.parse(0, ctx), Ok((1, 1)));
+
+        let list = lazy("list", |list: Parser<char>| list.andl(char(',')));
+        let ctx = &mut Context::new("1,2").without_memo();
+        assert!(list.opt().parse(0, ctx).is_err());
+    }
+
+    #[test]
+    fn test_foldl() {
+        let digit = satisfy("digit", |c| c.is_ascii_digit()).map(|c| c.to_digit(10).unwrap());
+        let p = char('-').andr(digit.clone()).foldl(digit, |acc, n| acc - n);
+
+        let ctx = &mut Context::new("9-3-2");
+        assert_eq!(p.parse(0, ctx), Ok((5, 4)));
+        let ctx = &mut Context::new("9-");
+        assert_eq!(p.parse(0, ctx), Ok((1, 9)));
+        let ctx = &mut Context::new("");
+        assert!(p.parse(0, ctx).is_err());
+    }
+
+    #[test]
+    fn test_foldr() {
+        let digit = satisfy("digit", |c| c.is_ascii_digit()).map(|c| c.to_digit(10).unwrap());
+        let p = digit.clone().andl(char('-')).foldr(digit, |n, acc| n - acc);
+
+        let ctx = &mut Context::new("9-3-2");
+        assert_eq!(p.parse(0, ctx), Ok((5, 8)));
+        let ctx = &mut Context::new("9");
+        assert_eq!(p.parse(0, ctx), Ok((1, 9)));
+        let ctx = &mut Context::new("9-");
+        assert!(p.parse(0, ctx).is_err());
+
+        // however long the run, it is folded without recursing per item
+        let long = "1-".repeat(100_000) + "1";
+        assert_eq!(
+            p.parse(0, &mut Context::new(long.as_str())),
+            Ok((long.len(), 1))
+        );
+    }
+
+    #[test]
+    fn test_lazy_left_recursive_alternatives() {
+        let digit =
+            satisfy("digit", |c| c.is_ascii_digit()).map(|c| c.to_digit(10).unwrap() as i32);
+        let expr = lazy("expr", move |expr| {
+            expr.clone()
+                .andl(char('+'))
+                .and(digit.clone())
+                .map(|(l, r)| l + r)
+                .or(expr.andl(char('-')).and(digit.clone()).map(|(l, r)| l - r))
+                .or(digit.clone())
+        });
+
+        let ctx = &mut Context::new("9-3+2-1");
+        assert_eq!(expr.parse(0, ctx), Ok((7, 7)));
+        let ctx = &mut Context::new("9-");
+        assert_eq!(expr.parse(0, ctx), Ok((1, 9)));
+    }
+
+    #[test]
+    fn test_many_collect() {
+        let digit = satisfy("digit", |c| c.is_ascii_digit());
+        let digits = digit.clone().many_collect::<String>();
+        let distinct = digit.many_collect::<std::collections::BTreeSet<char>>();
+
+        let ctx = &mut Context::new("1213a");
+        assert_eq!(digits.parse(0, ctx), Ok((4, String::from("1213"))));
+        assert_eq!(distinct.parse(0, ctx), Ok((4, ['1', '2', '3'].into())));
+        let ctx = &mut Context::new("");
+        assert_eq!(digits.parse(0, ctx), Ok((0, String::new())));
+    }
+
+    #[test]
+    fn test_count() {
+        let hashes = char('#').count();
+
+        let ctx = &mut Context::new("### title");
+        assert_eq!(hashes.parse(0, ctx), Ok((3, 3)));
+        let ctx = &mut Context::new("title");
+        assert_eq!(hashes.parse(0, ctx), Ok((0, 0)));
+        let ctx = &mut Context::new("");
+        assert_eq!(hashes.parse(0, ctx), Ok((0, 0)));
+    }
+
+    #[test]
+    fn test_or_dispatch() {
+        let p = keyword("let")
+            .or(keyword("fn"))
+            .or(char('x').map(|c| c.to_string()));
+
+        let ctx = &mut Context::new("fn");
+        ctx.profile = Some(crate::profile::Profile::default());
+        assert_eq!(p.parse(0, ctx), Ok((2, String::from("fn"))));
+        let profile = ctx.profile.take().unwrap();
+        assert!(!profile.rules.contains_key("let"));
+        let ctx = &mut Context::new("y");
+        assert!(p.parse(0, ctx).is_err());
+        let ctx = &mut Context::new("");
+        assert!(p.parse(0, ctx).is_err());
+    }
+
+    #[test]
+    fn test_choice_dispatch() {
+        let word = satisfy("letter", |c| c.is_ascii_alphabetic())
+            .many()
+            .map(|cs| cs.into_iter().collect::<String>());
+        let p = choice([keyword("let"), keyword("loop"), keyword("if"), word]);
+
+        let ctx = &mut Context::new("if");
+        ctx.profile = Some(crate::profile::Profile::default());
+        assert_eq!(p.parse(0, ctx), Ok((2, String::from("if"))));
+        let profile = ctx.profile.take().unwrap();
+        assert!(!profile.rules.contains_key("let"));
+        assert!(!profile.rules.contains_key("loop"));
+        assert_eq!(p.run("loop"), Ok(String::from("loop")));
+        assert_eq!(p.run("lox"), Ok(String::from("lox")));
+
+        let overlapping = keyword("ab").or(keyword("ac")).or(keyword("b"));
+        let ctx = &mut Context::new("b");
+        ctx.profile = Some(crate::profile::Profile::default());
+        assert_eq!(overlapping.parse(0, ctx), Ok((1, String::from("b"))));
+        assert!(!ctx.profile.take().unwrap().rules.contains_key("ab"));
+        assert_eq!(overlapping.run("ac"), Ok(String::from("ac")));
+        assert!(choice([char('a'), char('b')]).run("c").is_err());
+    }
+
+    #[test]
+    fn test_sep_by() {
+        let digit = satisfy("digit", |c| c.is_ascii_digit());
+        let list = digit.clone().sep_by(char(','));
+        let full = digit.sep_by_full(char(',').or(char(';')));
+
+        let ctx = &mut Context::new("1,2,3,");
+        assert_eq!(list.parse(0, ctx), Ok((5, vec!['1', '2', '3'])));
+        let (pos, punctuated) = full.parse(0, &mut Context::new("1,2;3")).unwrap();
+        assert_eq!(pos, 5);
+        assert_eq!(punctuated.items, vec!['1', '2', '3']);
+        assert_eq!(punctuated.separators, vec![(1..2, ','), (3..4, ';')]);
+        let ctx = &mut Context::new("");
+        assert_eq!(list.parse(0, ctx), Ok((0, vec![])));
+    }
+
+    #[test]
+    fn test_map_shared() {
+        #[derive(Debug, PartialEq)]
+        struct Digit(u32);
+
+        let digit =
+            satisfy("digit", |c| c.is_ascii_digit()).map_shared(|c| Digit(c.to_digit(10).unwrap()));
+        let p = digit.clone().andl(char('+')).or(digit);
+
+        let ctx = &mut Context::new("1");
+        let (pos, first) = p.parse(0, ctx).unwrap();
+        assert_eq!((pos, &*first), (1, &Digit(1)));
+        let (_, again) = p.parse(0, ctx).unwrap();
+        assert!(Rc::ptr_eq(&first, &again));
+    }
+
+    #[test]
+    fn test_set_source() {
+        let digits = satisfy("digit", |c| c.is_ascii_digit()).many_collect::<String>();
+
+        let ctx = &mut Context::new("123");
+        assert_eq!(digits.parse(0, ctx), Ok((3, String::from("123"))));
+        ctx.set_source(crate::Source::new("45"));
+        assert_eq!(digits.parse(0, ctx), Ok((2, String::from("45"))));
+    }
+
+    #[test]
+    fn test_output_without_debug() {
+        #[derive(Clone)]
+        struct Opaque(char);
+
+        let p = lazy("opaques", |p| {
+            p.andl(char(','))
+                .and(any_char().map(Opaque))
+                .map(|(_, o)| o)
+                .or(any_char().map(Opaque))
+        });
+
+        let ctx = &mut Context::new("a,b");
+        assert!(matches!(p.parse(0, ctx), Ok((3, Opaque('b')))));
+    }
+
+    #[test]
+    fn test_borrowing_closure() {
+        let vowels = String::from("aeiou");
+        let vowel = satisfy("vowel", |c| vowels.contains(c));
+        let p = vowel.count();
+
+        let ctx = &mut Context::new("aeb");
+        assert_eq!(p.parse(0, ctx), Ok((2, 2)));
+    }
+
+    #[test]
+    fn test_recognize() {
+        let digit = satisfy("digit", |c| c.is_ascii_digit());
+        let number = digit.clone().and(digit.many()).recognize();
+
+        let ctx = &mut Context::new("123+4");
+        let (pos, text) = number.parse(0, ctx).unwrap();
+        assert_eq!((pos, text.range()), (3, 0..3));
+        assert_eq!(text, "123");
+        assert_eq!(
+            number.parse(4, ctx).map(|(_, s)| s.to_string()),
+            Ok(String::from("4"))
+        );
+        let ctx = &mut Context::new("+");
+        assert!(number.parse(0, ctx).is_err());
+    }
+
+    #[test]
+    fn test_error_display() {
+        let err = char('a').run("b").unwrap_err();
+        assert_eq!(err.to_string(), "b\n^\nexpected 'a' got b\n");
+        let err = keyword("let").run("é le").unwrap_err();
+        assert!(err.to_string().ends_with("expected \"let\" got é\n"));
+        let err = char('a').end().run("ab").unwrap_err();
+        assert_eq!(err.to_string(), "ab\n ^\nexpected EOF found b\n");
     }
 }
@@ -1,33 +1,755 @@
-use rustc_hash::FxHashMap;
 use std::any::Any;
+use std::cell::OnceCell;
+use std::fmt::Debug;
+use std::hash::BuildHasher;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
-use crate::CacheKey;
+use crate::ParseObserver;
+use crate::cst::{self, Event, Recorded};
+use crate::memo::{
+    Completed, Edit, EvictionPolicy, MemoEntry, MemoLayout, MemoOptions, MemoTable, PosHasher,
+    Trail,
+};
+use crate::profile::{Profile, ProfileReport};
+use crate::source::{SharedText, Source};
+use crate::source_db::SourceId;
+use crate::source_map::SourceMap;
+use crate::trace::CallTree;
+use crate::{Bits, Bytes, Tokens};
+use crate::{CacheKey, ErrorSource, Input, ParseError, Pos, Reason, Text};
 
-pub struct Context {
-    pub cache: FxHashMap<CacheKey, Box<dyn Any>>,
-    pub source: Vec<char>,
+/// Counters describing how the memo table and the left recursion machinery were used.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub hits: usize,
+    pub misses: usize,
+    // entries dropped by a cut, the memo window, the entry limit or an edit
+    pub evictions: usize,
+    pub entries: usize,
+    pub peak_entries: usize,
+    // deepest nesting of memoized calls
+    pub max_depth: usize,
+    // times a left-recursive seed was grown, including the final attempt that stopped it
+    pub lr_iterations: usize,
+}
+
+/// What a rule calling itself at the position it started from, possibly through other rules
+/// that consumed nothing, does.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LeftRecursionPolicy {
+    /// Grow a seed from the alternatives that don't recurse, so left recursion parses as
+    /// it reads.
+    #[default]
+    Grow,
+    /// Stop the whole parse with an error naming the rule, for grammars meant to be LL
+    /// where left recursion can only be a mistake.
+    Error,
+    /// Fail the recursive call like any other mismatch, as plain PEG does.
+    Fail,
+}
+
+// parser calls between two looks at the cancellation flag and the clock
+const CANCEL_CHECK_INTERVAL: u32 = 1024;
+
+// what can stop a parse from outside, looked at every so many parser calls
+#[derive(Default)]
+pub(crate) struct Interrupt {
+    flag: Option<Arc<AtomicBool>>,
+    deadline: Option<Instant>,
+    calls: u32,
+}
+
+impl Interrupt {
+    pub(crate) fn is_due(&mut self) -> bool {
+        let check = self.calls.is_multiple_of(CANCEL_CHECK_INTERVAL);
+        self.calls = self.calls.wrapping_add(1);
+        check
+            && (self
+                .flag
+                .as_ref()
+                .is_some_and(|flag| flag.load(Ordering::Relaxed))
+                || self
+                    .deadline
+                    .is_some_and(|deadline| Instant::now() >= deadline))
+    }
+}
+
+/// Everything a parse keeps track of besides the parsers themselves: the input, by default
+/// text, the memo table and the options set on it.
+pub struct Context<I = Source> {
+    pub(crate) cache: MemoTable,
+    pub(crate) source: I,
+    // the text handed to every ParseError, materialized at most once per source
+    text: OnceCell<Option<ErrorSource>>,
+    // built from that text the first time it is asked for
+    source_map: OnceCell<Option<SourceMap>>,
+    // which source of a SourceDb the text is, for errors to say; forgotten once the text
+    // is replaced or changed, as it no longer is that source
+    pub(crate) origin: Option<(SourceId, Text)>,
     pub lr_stack: Vec<CacheKey>,
     call_path: Vec<CacheKey>,
-    pending_evictions: FxHashMap<CacheKey, Vec<CacheKey>>,
+    completed: Completed,
+    // false for plain recursive descent, where nothing is looked up in the memo table
+    pub(crate) memoize: bool,
+    pub(crate) left_recursion: LeftRecursionPolicy,
+    // an error that ends the parse wherever it is raised, every call made after it fails
+    // with it until the source is set again
+    pub(crate) fatal: Option<ParseError>,
+    // parser calls currently nested inside each other, each of which is a native call
+    pub(crate) depth: usize,
+    pub(crate) depth_limit: Option<usize>,
+    // parser calls left before the parse is given up on
+    pub(crate) fuel: Option<usize>,
+    pub(crate) interrupt: Option<Interrupt>,
+    // while more input may still arrive, a parse that runs into the end of what is there
+    // can't be decided yet; `incomplete` stays set from the first time that happens until
+    // the input changes, as memoized results may have depended on it
+    pub(crate) streaming: bool,
+    pub(crate) incomplete: bool,
+    // the end of the input the memoized call in progress has looked at so far, stored with
+    // its result so an edit can tell which results it leaves alone
+    pub(crate) reach: Pos,
+    // whether a lone `\r` ends a line for columns and line numbers, not just `\n`
+    pub(crate) universal_newlines: bool,
+    // whether the memoized call in progress has looked back to the start of a line for a
+    // column, which an edit earlier on that line changes even though it is behind the call
+    pub(crate) looked_behind: bool,
+    // the indentation of each block being parsed, innermost last
+    pub(crate) indents: Vec<usize>,
+    // what the parse so far has recorded for a concrete syntax tree, if asked to
+    events: Option<Vec<Recorded>>,
+    // what parsers read with `state` and change with `update_state`, and the values it had
+    // before each change the parse so far made, so a call that fails can put it back
+    state: Option<Box<dyn Any>>,
+    state_log: Vec<Box<dyn Any>>,
+    // how many times the state has been changed and read, so calls that changed it or
+    // depend on it aren't memoized
+    pub(crate) state_changes: usize,
+    pub(crate) state_reads: usize,
+    pub(crate) profile: Option<Profile>,
+    pub(crate) stats: Stats,
+    pub(crate) observer: Option<Box<dyn ParseObserver>>,
+    trace: Option<CallTree>,
 }
 
 impl Context {
     pub fn new(source: impl Into<String>) -> Self {
+        Self::from_source(Source::new(source))
+    }
+
+    /// Stores the input in segments of at most `chunk_size` bytes, split between chars,
+    /// rather than one contiguous buffer.
+    pub fn segmented(source: impl AsRef<str>, chunk_size: usize) -> Self {
+        Self::from_source(Source::segmented(source.as_ref().chars(), chunk_size))
+    }
+
+    #[cfg(feature = "ropey")]
+    pub fn from_rope(rope: ropey::Rope) -> Self {
+        Self::from_source(Source::from_rope(rope))
+    }
+
+    /// Parses the file at `path` in place, mapped into memory rather than read; see
+    /// [`Source::from_file`]. With `with_memo_window` as well, files much larger than memory
+    /// can be parsed.
+    ///
+    /// # Safety
+    ///
+    /// As for [`Source::from_file`], the file must not be changed while the context, or
+    /// anything holding on to its source, is alive.
+    #[cfg(feature = "memmap2")]
+    pub unsafe fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        // SAFETY: passed on to the caller
+        unsafe { Source::from_file(path) }.map(Self::from_source)
+    }
+
+    /// Sizes the memo table of every rule for `expected_entries` positions up front, so
+    /// large inputs don't pay for rehashing it partway through the parse.
+    pub fn with_capacity(source: impl Into<String>, expected_entries: usize) -> Self {
+        Self::new(source).with_memo_options(|options| options.capacity = expected_entries)
+    }
+
+    /// Starts over with `source`, keeping the memo tables, stacks and options of the
+    /// previous parse, so parsing many short inputs with one context doesn't allocate them
+    /// afresh for every one. See [`Parser::run_with`](crate::Parser::run_with).
+    pub fn reset(&mut self, source: impl Into<String>) {
+        self.set_source(Source::new(source));
+    }
+
+    /// Replaces the text in `range` with `text` and keeps what was memoized about the rest:
+    /// results of calls that never looked at the replaced text stay, those after it move
+    /// along with their text, and the next parse only redoes the others. Values are kept as
+    /// they were computed, so positions inside them, like the range of a `Substr` after the
+    /// edit, still count in the text before it.
+    pub fn edit(&mut self, range: Range<usize>, text: &str) {
+        let rest = self.source.slice(range.end..self.source.len());
+        let edit = Edit {
+            start: range.start,
+            end: range.end,
+            len: text.len(),
+            line_end: range.end
+                + rest
+                    .chars()
+                    .take_while(|&c| c != '\n')
+                    .map(char::len_utf8)
+                    .sum::<usize>(),
+        };
+        self.source = self.source.edit(range, text);
+        self.edited(&edit);
+    }
+
+    /// Appends the next chunk of a streamed input, see `with_streaming`. The text already
+    /// there is kept as it is rather than copied, and so are the memoized results that
+    /// didn't look at its end, so parsing again from the start only redoes the others.
+    pub fn feed(&mut self, chunk: &str) {
+        let edit = appended(self.source.len(), chunk.len());
+        self.source.append(chunk);
+        self.edited(&edit);
+    }
+
+    // where the line holding `pos` starts, noted as looking behind the call in progress
+    pub(crate) fn line_start(&mut self, pos: Pos) -> Pos {
+        self.looked_behind = true;
+        self.source.line_start(pos, self.universal_newlines)
+    }
+}
+
+impl Context<Bytes> {
+    pub fn from_bytes(bytes: impl AsRef<[u8]>) -> Self {
+        Self::from_source(Bytes::new(bytes.as_ref()))
+    }
+
+    /// Appends the next chunk of a streamed input, see `with_streaming`. Bytes are kept in
+    /// one piece, so the ones already there are copied along, but the memoized results that
+    /// didn't look at their end are kept as with text.
+    pub fn feed(&mut self, chunk: &[u8]) {
+        let edit = appended(self.source.len(), chunk.len());
+        self.source = Bytes::new([self.source.as_slice(), chunk].concat());
+        self.edited(&edit);
+    }
+}
+
+impl<Tok> Context<Tokens<Tok>>
+where
+    Tok: Clone + PartialEq + Debug + 'static,
+{
+    pub fn from_tokens(tokens: &[Tok]) -> Self {
+        Self::from_source(Tokens::new(tokens))
+    }
+
+    /// Parses the tokens a [`logos`] lexer produces; see [`Tokens::from_logos`].
+    #[cfg(feature = "logos")]
+    pub fn from_logos<'s>(lexer: logos::Lexer<'s, Tok>) -> Result<Self, ParseError>
+    where
+        Tok: logos::Logos<'s, Source = str>,
+    {
+        Tokens::from_logos(lexer).map(Self::from_source)
+    }
+}
+
+impl Context<Bits> {
+    pub fn from_bits(bytes: impl AsRef<[u8]>) -> Self {
+        Self::from_source(Bits::new(bytes.as_ref()))
+    }
+}
+
+impl<I: Input> Context<I> {
+    pub fn from_source(source: I) -> Self {
         Context {
-            cache: FxHashMap::default(),
-            source: source.into().chars().collect(),
+            cache: MemoTable::new(source.len() + 1, MemoOptions::default()),
+            source,
+            text: OnceCell::new(),
+            source_map: OnceCell::new(),
+            origin: None,
             lr_stack: Vec::new(),
             call_path: Vec::new(),
-            pending_evictions: FxHashMap::default(),
+            completed: Completed::default(),
+            memoize: true,
+            left_recursion: LeftRecursionPolicy::default(),
+            fatal: None,
+            depth: 0,
+            depth_limit: None,
+            fuel: None,
+            interrupt: None,
+            streaming: false,
+            incomplete: false,
+            reach: 0,
+            looked_behind: false,
+            universal_newlines: false,
+            indents: Vec::new(),
+            events: None,
+            state: None,
+            state_log: Vec::new(),
+            state_changes: 0,
+            state_reads: 0,
+            profile: None,
+            stats: Stats::default(),
+            observer: None,
+            trace: None,
         }
     }
 
-    pub(crate) fn clone_source(&self) -> String {
-        self.source.iter().collect()
+    pub fn with_memo_layout(self, layout: MemoLayout) -> Self {
+        self.with_memo_options(|options| options.layout = layout)
+    }
+
+    /// Hashes memo positions with `hasher` instead of the default FxHash.
+    pub fn with_hasher<S: BuildHasher + 'static>(self, hasher: S) -> Self {
+        self.with_memo_options(|options| options.hasher = PosHasher::new(hasher))
+    }
+
+    /// Keeps memo entries only for roughly the last `window` bytes behind the furthest
+    /// position reached, so memory stays bounded however long the input is. A parser that
+    /// backtracks further than that is still correct, it just parses that part again.
+    pub fn with_memo_window(self, window: usize) -> Self {
+        self.with_memo_options(|options| options.window = Some(window))
+    }
+
+    /// Caps the number of memo entries, evicting by `policy` once there are more than
+    /// `max_entries`. Evicted results are simply parsed again if they are needed.
+    pub fn with_memo_limit(self, max_entries: usize, policy: EvictionPolicy) -> Self {
+        self.with_memo_options(|options| options.limit = Some((max_entries, policy)))
+    }
+
+    fn with_memo_options(mut self, configure: impl FnOnce(&mut MemoOptions)) -> Self {
+        let mut options = self.cache.options().clone();
+        configure(&mut options);
+        self.cache = MemoTable::new(self.source.len() + 1, options);
+        self
+    }
+
+    /// Parses by plain recursive descent: nothing is memoized, which is cheaper for many small
+    /// inputs. There is no seed to grow either, so a rule calling itself at the position it
+    /// started from ends the parse with an error, as with [`LeftRecursionPolicy::Error`],
+    /// unless the policy is [`LeftRecursionPolicy::Fail`].
+    pub fn without_memo(mut self) -> Self {
+        self.memoize = false;
+        self
+    }
+
+    /// Chooses how left recursion is handled, growing a seed unless told otherwise.
+    pub fn with_left_recursion(mut self, policy: LeftRecursionPolicy) -> Self {
+        self.left_recursion = policy;
+        self
+    }
+
+    /// Ends the parse with a "nesting too deep" error once `limit` parser calls are nested
+    /// inside each other, rather than overflowing the stack on input like `((((...`. Every
+    /// combinator counts, so a level of nesting in the input takes several calls.
+    ///
+    /// Parsers call each other natively, so this limit is what keeps them from running out
+    /// of stack. Only a compiled [`Program`](crate::Program) runs on a stack of its own and
+    /// accepts arbitrarily deep input, and it recognizes the input without producing values.
+    pub fn with_depth_limit(mut self, limit: usize) -> Self {
+        self.depth_limit = Some(limit);
+        self
+    }
+
+    /// Ends the parse with a "budget exceeded" error after `fuel` parser calls, memo hits
+    /// included, bounding the work an untrusted grammar or document can cause. The budget
+    /// covers everything parsed with this context, a new source doesn't refill it.
+    pub fn with_fuel(mut self, fuel: usize) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    /// The parser calls left in the budget set by `with_fuel`, if any.
+    pub fn fuel(&self) -> Option<usize> {
+        self.fuel
+    }
+
+    /// Ends the parse with a cancelled error soon after `flag` is set, so another thread can
+    /// abort it, say when the document being parsed has changed.
+    pub fn with_cancellation(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.interrupt.get_or_insert_with(Interrupt::default).flag = Some(flag);
+        self
+    }
+
+    /// Ends the parse with a cancelled error soon after `deadline` has passed.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.interrupt
+            .get_or_insert_with(Interrupt::default)
+            .deadline = Some(deadline);
+        self
+    }
+
+    /// Like `with_deadline`, `timeout` from now.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        self.with_deadline(Instant::now() + timeout)
+    }
+
+    /// Counters for everything parsed since the context was created or its source was set.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            evictions: self.cache.evictions(),
+            entries: self.cache.len(),
+            peak_entries: self.cache.peak_len(),
+            ..self.stats
+        }
+    }
+
+    /// Records call counts, memo hits and time per rule (by name), read back with `report`.
+    pub fn with_profiling(mut self) -> Self {
+        self.profile = Some(Profile::default());
+        self
+    }
+
+    /// The rules profiled so far, slowest first; empty unless `with_profiling` was used.
+    pub fn report(&self) -> ProfileReport {
+        ProfileReport::new(self.profile.as_ref().unwrap_or(&Profile::default()))
+    }
+
+    pub fn set_observer(&mut self, observer: impl ParseObserver + 'static) {
+        self.observer = Some(Box::new(observer));
+    }
+
+    /// Records the nodes and tokens parsers mark with `node` and `as_token` as the input is
+    /// parsed, read back with `events` once it has been.
+    pub fn with_events(mut self) -> Self {
+        self.events = Some(Vec::new());
+        self
+    }
+
+    /// The events of the last parse, empty unless `with_events` was used.
+    pub fn events(&self) -> Vec<Event> {
+        let mut events = Vec::new();
+        if let Some(recorded) = &self.events {
+            cst::unpack(recorded, 0, &mut events);
+        }
+        events
+    }
+
+    /// Gives parsers a value of their own to keep track of things while parsing, like the
+    /// names declared so far, read with [`state`](crate::state) and changed with
+    /// [`update_state`](crate::Parser::update_state). Changes made by a call that fails
+    /// are undone, so a branch that backtracks leaves no trace in it.
+    ///
+    /// The state isn't a type parameter of the context: every parser and combinator names
+    /// the context it runs in, so one would have to be threaded through all of them, and
+    /// parsers written without state couldn't be used alongside those with it. The price
+    /// is that a parser reading a state of another type than the context holds panics,
+    /// naming the type it expected, when it runs.
+    pub fn with_state<S: 'static>(mut self, state: S) -> Self {
+        self.state = Some(Box::new(state));
+        self
+    }
+
+    /// The state as the last parse left it, None if there is none of type `S`.
+    pub fn state<S: 'static>(&self) -> Option<&S> {
+        self.state.as_ref()?.downcast_ref()
+    }
+
+    /// Records every call made while parsing, so it can be browsed with `dump_trace_html`.
+    pub fn with_trace(mut self) -> Self {
+        self.trace = Some(CallTree::default());
+        self
+    }
+
+    /// Writes a self-contained HTML page with the recorded call tree: where each rule was
+    /// tried, what it matched, which calls were memo hits and how left recursion grew.
+    /// Only calls made after `with_trace` are in it.
+    pub fn dump_trace_html(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let out = io::BufWriter::new(std::fs::File::create(path)?);
+        self.write_trace_html(out)
+    }
+
+    pub fn write_trace_html(&self, out: impl Write) -> io::Result<()> {
+        let empty = CallTree::default();
+        let trace = self.trace.as_ref().unwrap_or(&empty);
+        let text = self.source.text().unwrap_or_else(|| SharedText::from(""));
+        trace.write_html(&text, out)
+    }
+
+    /// Every entry currently in the memo table, ordered by position and rule name.
+    pub fn memo_entries(&self) -> Vec<MemoEntry> {
+        self.cache.entries(&self.lr_stack)
+    }
+
+    pub fn source(&self) -> &I {
+        &self.source
+    }
+
+    /// Line and column conversions for the text being parsed, built once per source; None
+    /// for inputs that aren't text and weren't lexed from any.
+    pub fn source_map(&self) -> Option<&SourceMap> {
+        self.source_map
+            .get_or_init(|| {
+                let text = self.clone_source()?.text;
+                Some(SourceMap::from_text(text, self.universal_newlines))
+            })
+            .as_ref()
+    }
+
+    // moves or drops the memoized results after the source was changed by `edit`, and
+    // forgets the rest of the previous parse
+    fn edited(&mut self, edit: &Edit) {
+        self.text = OnceCell::new();
+        self.source_map = OnceCell::new();
+        self.origin = None;
+        let source = self.clone_source();
+        self.cache.relocate(edit, self.source.len() + 1, &source);
+        self.lr_stack.clear();
+        self.indents.clear();
+        self.call_path.clear();
+        self.completed.clear();
+        self.fatal = None;
+        self.depth = 0;
+        self.incomplete = false;
+        self.reach = 0;
+        self.looked_behind = false;
+    }
+
+    // memo entries are keyed by position only, so they are dropped whenever the source
+    // changes instead of being served for text they were never computed from
+    pub fn set_source(&mut self, source: I) {
+        self.cache.clear(source.len() + 1);
+        self.source = source;
+        self.text = OnceCell::new();
+        self.source_map = OnceCell::new();
+        self.origin = None;
+        self.lr_stack.clear();
+        self.indents.clear();
+        self.call_path.clear();
+        self.completed.clear();
+        self.fatal = None;
+        self.depth = 0;
+        self.incomplete = false;
+        self.reach = 0;
+        self.looked_behind = false;
+        self.stats = Stats::default();
+    }
+
+    /// Treats the input as the part of a stream received so far: a parse that needs to look
+    /// past its end fails with an error for which [`ParseError::is_incomplete`] holds,
+    /// rather than deciding as if the input ended there. Add to it with `feed` and call
+    /// `finish` once the stream is over.
+    pub fn with_streaming(mut self) -> Self {
+        self.streaming = true;
+        self
+    }
+
+    /// Counts a lone `\r` as a line break wherever positions are turned into lines and
+    /// columns, in the source map, layout columns and error locations, as well as `\n` and
+    /// `\r\n`, for text written on any platform.
+    pub fn with_universal_newlines(mut self) -> Self {
+        self.universal_newlines = true;
+        self.text = OnceCell::new();
+        self.source_map = OnceCell::new();
+        self
+    }
+
+    /// Marks the input as complete, so its end is parsed as the end of input from now on.
+    /// Memoized results stay, they were computed from the same input either way.
+    pub fn finish(&mut self) {
+        self.streaming = false;
+        self.incomplete = false;
+    }
+
+    pub(crate) fn record(&mut self, event: impl FnOnce() -> Event) {
+        if let Some(events) = self.events.as_mut() {
+            events.push(Recorded::Event(event()));
+        }
+    }
+
+    pub(crate) fn events_mark(&self) -> Option<usize> {
+        self.events.as_ref().map(Vec::len)
+    }
+
+    pub(crate) fn truncate_events(&mut self, mark: Option<usize>) {
+        if let (Some(events), Some(mark)) = (self.events.as_mut(), mark) {
+            events.truncate(mark);
+        }
+    }
+
+    // the state a parser reads, which has to be there as the parser expects it
+    pub(crate) fn expect_state<S: 'static>(&mut self) -> &S {
+        self.state_reads += 1;
+        self.state().unwrap_or_else(|| missing_state::<S>())
+    }
+
+    pub(crate) fn state_mark(&self) -> usize {
+        self.state_log.len()
+    }
+
+    pub(crate) fn update_state<S: Clone + 'static>(&mut self, f: impl FnOnce(&mut S)) {
+        let state = self
+            .state
+            .as_mut()
+            .and_then(|state| state.downcast_mut::<S>())
+            .unwrap_or_else(|| missing_state::<S>());
+        let before = state.clone();
+        f(state);
+        self.state_log.push(Box::new(before));
+        self.state_changes += 1;
+    }
+
+    // undoes every change made to the state since `mark`
+    pub(crate) fn rollback_state(&mut self, mark: usize) {
+        while self.state_log.len() > mark {
+            self.state = self.state_log.pop();
+        }
+    }
+
+    pub(crate) fn clear_state_log(&mut self) {
+        self.state_log.clear();
+    }
+
+    pub(crate) fn clear_events(&mut self) {
+        if let Some(events) = self.events.as_mut() {
+            events.clear();
+        }
+    }
+
+    // takes what a memoized call at `pos` recorded since `mark` off the log, packed to be
+    // stored with its result if it is kept
+    pub(crate) fn take_events(
+        &mut self,
+        mark: Option<usize>,
+        pos: Pos,
+        keep: bool,
+    ) -> Option<Rc<[Recorded]>> {
+        let (events, mark) = (self.events.as_mut()?, mark?);
+        if !keep {
+            events.truncate(mark);
+            return None;
+        }
+        Some(cst::pack(events.drain(mark..), pos))
+    }
+
+    // puts the events of a memoized call at `pos` back on the log
+    pub(crate) fn replay(&mut self, pos: Pos, recorded: Option<Rc<[Recorded]>>) {
+        if let (Some(events), Some(recorded)) = (self.events.as_mut(), recorded) {
+            events.push(Recorded::Group(pos, recorded));
+        }
+    }
+
+    pub(crate) fn trail(&self, events: &Option<Rc<[Recorded]>>) -> Trail {
+        Trail {
+            reach: self.reach,
+            looked_behind: self.looked_behind,
+            events: events.clone(),
+        }
+    }
+
+    // notes that a parser needed input past the end of what is there
+    pub(crate) fn ran_out(&mut self) {
+        self.examine(self.source.len() + 1);
+        if self.streaming {
+            self.incomplete = true;
+        }
+    }
+
+    // notes that a parser's result depends on the input up to `end`
+    pub(crate) fn examine(&mut self, end: Pos) {
+        self.reach = self.reach.max(end);
+    }
+
+    // the element at `pos`, for primitives, so what they look at is noticed
+    pub(crate) fn next(&mut self, pos: Pos) -> Option<(I::Elem, Pos)> {
+        let next = self.source.next(pos);
+        match next {
+            Some((_, end)) => self.examine(end),
+            None => self.ran_out(),
+        }
+        next
+    }
+
+    pub(crate) fn clone_source(&self) -> Option<ErrorSource> {
+        self.text
+            .get_or_init(|| {
+                let text = self.source.text()?;
+                let spans = self.source.spans();
+                let origin = self.origin.clone();
+                Some(ErrorSource {
+                    text,
+                    spans,
+                    origin,
+                    universal_newlines: self.universal_newlines,
+                })
+            })
+            .clone()
+    }
+
+    // fails the parse with an error that no combinator can recover from
+    pub(crate) fn abort(&mut self, pos: Pos, reason: Reason) -> ParseError {
+        let error = ParseError {
+            source: self.clone_source(),
+            pos,
+            reason,
+        };
+        self.fatal = Some(error.clone());
+        error
+    }
+
+    // runs `parse` in `inner`, a context of its own for part of this one's input, under the
+    // options of this one: the fuel and interrupt are handed over for the call and back after
+    // it, the depth limit counts the calls already nested here, and memoization and left
+    // recursion are handled alike. Also says whether the inner parse was ended by an error no
+    // combinator may recover from, which the caller ends this parse with too
+    pub(crate) fn nested<J: Input, R>(
+        &mut self,
+        inner: &mut Context<J>,
+        parse: impl FnOnce(&mut Context<J>) -> R,
+    ) -> (R, bool) {
+        inner.memoize = self.memoize;
+        inner.left_recursion = self.left_recursion;
+        inner.depth_limit = self
+            .depth_limit
+            .map(|limit| limit.saturating_sub(self.depth));
+        inner.fuel = self.fuel.take();
+        inner.interrupt = self.interrupt.take();
+        let result = parse(inner);
+        self.fuel = inner.fuel.take();
+        self.interrupt = inner.interrupt.take();
+        (result, inner.fatal.take().is_some())
+    }
+
+    pub(crate) fn observe(&mut self, mut event: impl FnMut(&mut dyn ParseObserver)) {
+        if let Some(observer) = self.observer.as_mut() {
+            event(observer.as_mut());
+        }
+        if let Some(trace) = self.trace.as_mut() {
+            event(trace);
+        }
+    }
+
+    pub(crate) fn cut(&mut self, pos: Pos) {
+        self.cache.prune_before(pos, &self.call_path);
+        trace_log!(
+            debug,
+            "cut at {}, {} memo entries left",
+            pos,
+            self.cache.len()
+        );
+    }
+
+    pub(crate) fn advance_frontier(&mut self, pos: Pos) {
+        self.cache.advance(pos, &self.call_path);
+    }
+
+    pub(crate) fn enforce_memo_limit(&mut self) {
+        self.cache.enforce_limit(&self.call_path);
     }
 
     pub(crate) fn push_call_path(&mut self, key: CacheKey) {
         self.call_path.push(key);
+        self.stats.max_depth = self.stats.max_depth.max(self.call_path.len());
+    }
+
+    // a call can only re-enter a rule at the same position through callers that consumed
+    // nothing, and those sit together at the top of the call path
+    pub(crate) fn is_in_progress(&self, key: CacheKey) -> bool {
+        let (_, pos) = key;
+        self.call_path
+            .iter()
+            .rev()
+            .take_while(|&&(_, caller_pos)| caller_pos == pos)
+            .any(|&caller| caller == key)
     }
 
     pub(crate) fn pop_call_path(&mut self, key: CacheKey) {
@@ -35,36 +757,292 @@ impl Context {
         self.call_path.pop();
     }
 
-    pub(crate) fn schedule_cache_eviction(&mut self, key: CacheKey) {
-        let dependents = self.pending_evictions.entry(key).or_default();
+    pub(crate) fn completed_since(&self) -> usize {
+        self.completed.mark()
+    }
+
+    // called once the call has left the call path
+    pub(crate) fn complete(&mut self, key: CacheKey) {
+        let caller_pos = self.call_path.last().map(|&(_, pos)| pos);
+        self.completed.complete(key, caller_pos);
+    }
+
+    pub(crate) fn forget_completed(&mut self, since: usize) {
+        for stale in self.completed.forget(since) {
+            self.cache.remove(&stale);
+        }
+    }
+}
+
+// the panic of a parser whose context holds no state of the type it uses
+fn missing_state<S>() -> ! {
+    panic!(
+        "the context has no state of type `{}` for a parser to use",
+        std::any::type_name::<S>()
+    )
+}
+
+// appending `len` positions to an input `end` long, as an edit: the end of the input, which
+// results that ran out of it looked at, is replaced by the new text and a new end
+fn appended(end: Pos, len: usize) -> Edit {
+    Edit {
+        start: end,
+        end: end + 1,
+        len: len + 1,
+        line_end: end + 1,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    use super::CANCEL_CHECK_INTERVAL;
+    use crate::*;
+
+    #[test]
+    fn test_stats() {
+        let sum = fixture::sum();
+
+        let ctx = &mut Context::new("1+2+3");
+        assert_eq!(sum.parse(0, ctx), Ok((5, 6)));
+        let stats = ctx.stats();
+        assert_eq!(stats.lr_iterations, 3);
+        assert_eq!(stats.evictions, 0);
+        // what was computed from the seeds is forgotten once the sum has grown
+        assert!(stats.entries < stats.peak_entries);
+        assert!(stats.max_depth > 1);
+
+        assert_eq!(sum.parse(0, ctx), Ok((5, 6)));
+        assert_eq!(ctx.stats().hits, stats.hits + 1);
+        assert_eq!(ctx.stats().misses, stats.misses);
+
+        let ctx = &mut Context::new("1+2+3").with_memo_limit(4, EvictionPolicy::Lru);
+        assert_eq!(sum.parse(0, ctx), Ok((5, 6)));
+        assert!(ctx.stats().evictions > 0);
+        assert!(ctx.stats().peak_entries > ctx.stats().entries);
+        ctx.set_source(Source::new(""));
+        assert_eq!(ctx.stats(), Stats::default());
+    }
+
+    #[test]
+    fn test_left_recursion_policy() {
+        let sum = fixture::sum();
+
+        let grow = &mut Context::new("1+2+3").with_left_recursion(LeftRecursionPolicy::Grow);
+        assert_eq!(sum.parse(0, grow), Ok((5, 6)));
+
+        let fail = &mut Context::new("1+2+3").with_left_recursion(LeftRecursionPolicy::Fail);
+        assert_eq!(sum.parse(0, fail), Ok((1, 1)));
+        assert_eq!(fail.stats().lr_iterations, 0);
 
-        for &ancestor in self.call_path.iter().rev() {
-            if ancestor == key {
-                break;
-            } else {
-                dependents.push(ancestor);
+        // not even a parser that can do without the sum gets past the error
+        let optional = sum.clone().opt();
+        for plain in [false, true] {
+            let mut ctx = Context::new("1+2+3").with_left_recursion(LeftRecursionPolicy::Error);
+            if plain {
+                ctx = ctx.without_memo();
             }
+            let error = optional.parse(0, &mut ctx).unwrap_err();
+            assert_eq!(error.pos(), 0);
+            assert!(
+                error
+                    .to_string()
+                    .ends_with("rule `sum` is left-recursive\n")
+            );
+            assert_eq!(any_char().parse(0, &mut ctx), Err(error));
+
+            // the error only lasts until the source is replaced
+            ctx.set_source(Source::new("7"));
+            assert_eq!(any_char().parse(0, &mut ctx), Ok((1, '7')));
         }
     }
 
-    pub(crate) fn execute_cache_eviction(&mut self, key: CacheKey) {
-        let Some(dependents) = self.pending_evictions.get(&key).cloned() else {
-            return;
-        };
+    #[test]
+    fn test_depth_limit() {
+        let nested = lazy("nested", |nested| {
+            char('(').andr(nested).andl(char(')')).or(char('x'))
+        });
+        assert_eq!(nested.run("((x))"), Ok('x'));
+
+        let ctx = &mut Context::new("((x))").with_depth_limit(100);
+        assert_eq!(nested.parse(0, ctx), Ok((5, 'x')));
+
+        let deep = format!("{}x{}", "(".repeat(100_000), ")".repeat(100_000));
+        let ctx = &mut Context::new(deep).with_depth_limit(200);
+        let error = nested.clone().opt().parse(0, ctx).unwrap_err();
+        assert!(error.pos() > 0 && error.pos() < 200);
+        assert!(
+            error
+                .to_string()
+                .ends_with(&format!("nesting too deep at pos {}\n", error.pos()))
+        );
+    }
+
+    #[test]
+    fn test_fuel() {
+        let list = satisfy("digit", |c| c.is_ascii_digit()).sep_by(char(','));
+
+        let ctx = &mut Context::new("1,2,3").with_fuel(1000);
+        assert_eq!(list.parse(0, ctx), Ok((5, vec!['1', '2', '3'])));
+        let used = 1000 - ctx.fuel().unwrap();
+        assert!(used > 5);
+
+        let ctx = &mut Context::new("1,2,3").with_fuel(used - 1);
+        let error = list.clone().opt().parse(0, ctx).unwrap_err();
+        assert!(error.to_string().ends_with("budget exceeded\n"));
+        assert_eq!(ctx.fuel(), Some(0));
+
+        ctx.set_source(Source::new("1"));
+        assert!(list.parse(0, ctx).is_err());
+        assert_eq!(Context::new("").fuel(), None);
+    }
+
+    #[test]
+    fn test_cancellation() {
+        let digits = satisfy("digit", |c| c.is_ascii_digit()).many();
+        let flag = Arc::new(AtomicBool::new(false));
+        let ctx = &mut Context::new("123").with_cancellation(flag.clone());
+        assert_eq!(digits.parse(0, ctx), Ok((3, vec!['1', '2', '3'])));
+
+        flag.store(true, Ordering::Relaxed);
+        let ctx = &mut Context::new("123").with_cancellation(flag);
+        let error = digits.clone().opt().parse(0, ctx).unwrap_err();
+        assert!(error.is_cancelled());
+        assert!(error.to_string().ends_with("parse cancelled\n"));
+
+        // set partway through, the flag is noticed within a bounded number of calls
+        let flag = Arc::new(AtomicBool::new(false));
+        let cancel = flag.clone();
+        let digits = satisfy("digit", move |c| {
+            if c == '5' {
+                cancel.store(true, Ordering::Relaxed);
+            }
+            c.is_ascii_digit()
+        })
+        .many();
+        let long = "0123456789".repeat(1000);
+        let ctx = &mut Context::new(long).with_cancellation(flag);
+        let error = digits.parse(0, ctx).unwrap_err();
+        assert!(error.is_cancelled());
+        assert!(error.pos() < 10 * CANCEL_CHECK_INTERVAL as usize);
+
+        let ctx = &mut Context::new("123").with_timeout(Duration::ZERO);
+        assert!(digits.parse(0, ctx).unwrap_err().is_cancelled());
+        let ctx = &mut Context::new("123").with_timeout(Duration::from_secs(60));
+        assert!(digits.parse(0, ctx).is_ok());
+        assert!(!any_char().run("").unwrap_err().is_cancelled());
+    }
+
+    #[test]
+    fn test_streaming() {
+        let number = satisfy("digit", |c| c.is_ascii_digit())
+            .many()
+            .recognize()
+            .map(|digits| digits.to_string().parse::<u32>().unwrap());
+        let list = number.sep_by(char(',')).andl(keyword("end")).end();
+
+        let ctx = &mut Context::new("12,3").with_streaming();
+        let error = list.parse(0, ctx).unwrap_err();
+        assert!(error.is_incomplete());
+        assert_eq!(error.pos(), 4);
+        ctx.feed(",45e");
+        assert!(list.parse(0, ctx).unwrap_err().is_incomplete());
+        ctx.feed("nd");
+        assert!(list.parse(0, ctx).unwrap_err().is_incomplete());
+        ctx.finish();
+        assert_eq!(list.parse(0, ctx), Ok((10, vec![12, 3, 45])));
+
+        // what was parsed before a chunk is kept, only what ran out of input is redone
+        let ctx = &mut Context::new("1,".repeat(100) + "1").with_streaming();
+        assert!(list.parse(0, ctx).unwrap_err().is_incomplete());
+        let misses = ctx.stats().misses;
+        ctx.feed(",2end");
+        ctx.finish();
+        assert_eq!(list.parse(0, ctx).unwrap().1.len(), 102);
+        assert!(ctx.stats().misses - misses < 20);
+        assert_eq!(ctx.source().chunks().count(), 2);
+
+        // a mismatch before the end fails as usual
+        let ctx = &mut Context::new("12;3").with_streaming();
+        assert!(!list.parse(0, ctx).unwrap_err().is_incomplete());
 
-        for dependent in dependents {
-            self.execute_cache_eviction(dependent);
-            self.cache.remove(&dependent);
+        let header = byte(0xff).andr(u16_be());
+        let ctx = &mut Context::from_bytes([0xff, 0x01]).with_streaming();
+        assert!(header.parse(0, ctx).unwrap_err().is_incomplete());
+        ctx.feed(&[0x02]);
+        assert_eq!(header.parse(0, ctx), Ok((3, 0x0102)));
+    }
+
+    #[test]
+    fn test_edit() {
+        let digit = satisfy("digit", |c| c.is_ascii_digit());
+        let number = digit
+            .clone()
+            .and(digit.many_collect::<String>())
+            .map(|(first, rest)| format!("{}{}", first, rest));
+        let sum = fixture::sum_of(number, |n, m| format!("({}+{})", n, m));
+        let program = sum.sep_by(char(';')).end();
+
+        let text = "1+22+3;44+5;6+77+8;9";
+        let ctx = &mut Context::new(text);
+        assert!(program.parse(0, ctx).is_ok());
+        let misses = ctx.stats().misses;
+
+        // each edit is checked against parsing the edited text from scratch
+        let edits = [
+            (7..9, "4"),
+            (0..0, "0"),
+            (12..13, ";+"),
+            (3..4, ""),
+            (20..20, ";10"),
+            (5..7, "+"),
+            (10..14, ""),
+            (5..6, ""),
+        ];
+        let mut text = String::from(text);
+        for (range, replacement) in edits {
+            text.replace_range(range.clone(), replacement);
+            ctx.edit(range, replacement);
+            let misses_before = ctx.stats().misses;
+            let reparsed = program.parse(0, ctx);
+            assert_eq!(reparsed, program.parse(0, &mut Context::new(text.as_str())));
+            if let Err(error) = &reparsed {
+                assert!(error.to_string().starts_with(&text));
+            }
+            assert!(ctx.stats().misses - misses_before < misses);
         }
+        assert_eq!(ctx.source().to_string(), text);
     }
 
-    pub(crate) fn clear_cache_eviction_schedule(&mut self, key: CacheKey) {
-        let Some(dependents) = self.pending_evictions.remove(&key) else {
-            return;
-        };
+    #[cfg(feature = "memmap2")]
+    #[test]
+    fn test_from_file() {
+        let path = std::env::temp_dir().join(format!("packrust-{}.txt", std::process::id()));
+        std::fs::write(&path, "1,2,3").unwrap();
+        // the maps are dropped before the file is written again
+        {
+            let list = satisfy("digit", |c| c.is_ascii_digit()).sep_by(char(','));
+            // SAFETY: nothing writes the file until the maps are dropped
+            let ctx = &mut unsafe { Context::from_file(&path) }.unwrap();
+            assert_eq!(list.parse(0, ctx), Ok((5, vec!['1', '2', '3'])));
+            assert_eq!(ctx.source().as_str(), Some("1,2,3"));
+            // errors point into the mapping instead of a copy of it
+            let error = char(';').parse(0, ctx).unwrap_err();
+            let text = error.source.as_ref().unwrap().text.as_ptr();
+            assert_eq!(text, ctx.source().as_str().unwrap().as_ptr());
 
-        for dependent in dependents {
-            self.clear_cache_eviction_schedule(dependent)
+            // SAFETY: as above
+            let bytes = unsafe { Bytes::from_file(&path) }.unwrap();
+            let ctx = &mut Context::from_source(bytes);
+            assert_eq!(byte(b'1').parse(0, ctx), Ok((1, b'1')));
         }
+        std::fs::write(&path, b"\xff").unwrap();
+        // SAFETY: the file is never mapped
+        let error = unsafe { Context::from_file(&path) }.err().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        std::fs::remove_file(&path).unwrap();
     }
 }
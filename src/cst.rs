@@ -0,0 +1,343 @@
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::{Input, Parser, Pos};
+
+/// What a parse recorded about the shape of the text, in order, for building a concrete
+/// syntax tree such as a rowan green tree: nodes come from [`Parser::node`] and tokens
+/// from [`Parser::as_token`]. Only what the successful parse matched is in it, whatever
+/// backtracking tried on the way, along with the text [`Parser::recover`] skipped over, so
+/// the tree covers all of the text even when it is broken.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    StartNode(Rc<str>),
+    Token(Rc<str>, Range<usize>),
+    FinishNode,
+    /// An error node over text that didn't parse, with what was wrong with it.
+    Error(Range<usize>, Rc<str>),
+    /// Whitespace or comments from [`Parser::as_trivia`] or [`Parser::padded_by`]. It
+    /// isn't attached to any token: it comes in text order between the events around it,
+    /// inside whichever node was open when it was matched, so a tree builder adds it as a
+    /// token of that node, as rowan does. Trivia from `padded_by` is trailing, right after
+    /// the events of the parser it pads.
+    Trivia(Range<usize>),
+}
+
+impl Event {
+    fn map_span(&self, f: impl Fn(Range<usize>) -> Range<usize>) -> Event {
+        match self {
+            Event::Token(kind, span) => Event::Token(kind.clone(), f(span.clone())),
+            Event::Error(span, message) => Event::Error(f(span.clone()), message.clone()),
+            Event::Trivia(span) => Event::Trivia(f(span.clone())),
+            event => event.clone(),
+        }
+    }
+}
+
+// events as a context records them: the events of a memoized call are packed into a group
+// once it is done, shared with its memo entry so a memo hit replays them without copying,
+// and kept relative to where the call started so an edit that moves the call leaves them be
+#[derive(Debug, Clone)]
+pub(crate) enum Recorded {
+    Event(Event),
+    Group(Pos, Rc<[Recorded]>),
+}
+
+// turns the events of a call at `pos`, recorded with absolute positions, into a group
+pub(crate) fn pack(events: impl Iterator<Item = Recorded>, pos: Pos) -> Rc<[Recorded]> {
+    events
+        .map(|recorded| match recorded {
+            Recorded::Event(event) => {
+                Recorded::Event(event.map_span(|span| span.start - pos..span.end - pos))
+            }
+            Recorded::Group(start, group) => Recorded::Group(start - pos, group),
+        })
+        .collect()
+}
+
+pub(crate) fn unpack(recorded: &[Recorded], base: Pos, events: &mut Vec<Event>) {
+    for recorded in recorded {
+        match recorded {
+            Recorded::Event(event) => {
+                events.push(event.map_span(|span| base + span.start..base + span.end))
+            }
+            Recorded::Group(start, group) => unpack(group, base + start, events),
+        }
+    }
+}
+
+impl<'a, T, I> Parser<'a, T, I>
+where
+    T: Clone + 'static,
+    I: Input,
+{
+    /// Wraps the events of what this parser matches in a node of `kind`.
+    pub fn node(self, kind: &str) -> Parser<'a, T, I> {
+        let kind: Rc<str> = Rc::from(kind);
+        let name = self.name.clone();
+        let first = self.first.clone();
+        let shape = self.shape.clone();
+        let raw_parser = Rc::new(move |pos, ctx: &mut crate::Context<I>| {
+            ctx.record(|| Event::StartNode(kind.clone()));
+            let (end, val) = self.parse(pos, ctx)?;
+            ctx.record(|| Event::FinishNode);
+            Ok((end, val))
+        });
+
+        Parser::new(name, raw_parser)
+            .with_first(first)
+            .with_shape(shape)
+    }
+
+    /// Records what this parser matches as a single token of `kind`, dropping the events
+    /// of the parsers it is made of.
+    pub fn as_token(self, kind: &str) -> Parser<'a, T, I> {
+        let kind: Rc<str> = Rc::from(kind);
+        let name = self.name.clone();
+        let first = self.first.clone();
+        let shape = self.shape.clone();
+        let raw_parser = Rc::new(move |pos, ctx: &mut crate::Context<I>| {
+            let mark = ctx.events_mark();
+            let (end, val) = self.parse(pos, ctx)?;
+            ctx.truncate_events(mark);
+            ctx.record(|| Event::Token(kind.clone(), pos..end));
+            Ok((end, val))
+        });
+
+        Parser::new(name, raw_parser)
+            .with_first(first)
+            .with_shape(shape)
+    }
+
+    /// Records what this parser matches as trivia, dropping the events of the parsers it is
+    /// made of, such as whitespace and comments before the first token of a file. The
+    /// trivia goes where this parser is in the grammar, leading whatever follows it.
+    pub fn as_trivia(self) -> Parser<'a, T, I> {
+        let name = self.name.clone();
+        let first = self.first.clone();
+        let shape = self.shape.clone();
+        let raw_parser = Rc::new(move |pos, ctx: &mut crate::Context<I>| {
+            let mark = ctx.events_mark();
+            let (end, val) = self.parse(pos, ctx)?;
+            ctx.truncate_events(mark);
+            if end > pos {
+                ctx.record(|| Event::Trivia(pos..end));
+            }
+            Ok((end, val))
+        });
+
+        Parser::new(name, raw_parser)
+            .with_first(first)
+            .with_shape(shape)
+    }
+
+    /// Matches this parser, then skips what `trivia` matches after it, like whitespace and
+    /// comments. With events the skipped text isn't dropped but kept as trivia trailing the
+    /// token or node this parser recorded, so the events cover the text byte for byte. It
+    /// is outside the node of `p.node(kind).padded_by(trivia)` and its last child in
+    /// `p.padded_by(trivia).node(kind)`.
+    pub fn padded_by<U>(self, trivia: Parser<'a, U, I>) -> Parser<'a, T, I>
+    where
+        U: Clone + 'static,
+    {
+        self.andl(trivia.as_trivia())
+    }
+
+    /// Where this parser fails, skips what `skip` matches instead and produces `fallback`,
+    /// recording the skipped text, empty if `skip` matched nothing, as an error node that
+    /// says why this parser failed. Fails as before where `skip` does too.
+    pub fn recover<U>(self, skip: Parser<'a, U, I>, fallback: T) -> Parser<'a, T, I>
+    where
+        U: Clone + 'static,
+    {
+        let name = self.name.clone();
+        let shape = self.shape.clone();
+        let raw_parser = Rc::new(move |pos, ctx: &mut crate::Context<I>| {
+            let error = match self.parse(pos, ctx) {
+                Err(error) if ctx.fatal.is_none() => error,
+                result => return result,
+            };
+            let Ok((end, _)) = skip.parse(pos, ctx) else {
+                return Err(error);
+            };
+            ctx.record(|| Event::Error(pos..end, Rc::from(error.reason.to_string())));
+            Ok((end, fallback.clone()))
+        });
+
+        Parser::new(name, raw_parser).with_shape(shape)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::rc::Rc;
+
+    use crate::*;
+
+    fn start(kind: &str) -> Event {
+        Event::StartNode(kind.into())
+    }
+
+    fn token(kind: &str, span: std::ops::Range<usize>) -> Event {
+        Event::Token(kind.into(), span)
+    }
+
+    #[test]
+    fn test_events() {
+        let int = satisfy("digit", |c| c.is_ascii_digit())
+            .many()
+            .as_token("int");
+        let plus = char('+').as_token("plus");
+        let sum = lazy("sum", move |sum| {
+            sum.and(plus.clone())
+                .and(int.clone())
+                .map(|_| ())
+                .node("add")
+                .or(int.clone().map(|_| ()))
+        });
+        // a statement that is first tried as something else, so its events are replayed
+        let statement = sum
+            .clone()
+            .andl(char('?'))
+            .node("query")
+            .or(sum.node("stmt"));
+
+        let ctx = &mut Context::new("1+22+3").with_events();
+        assert!(statement.parse(0, ctx).is_ok());
+        assert_eq!(
+            ctx.events(),
+            [
+                start("stmt"),
+                start("add"),
+                start("add"),
+                token("int", 0..1),
+                token("plus", 1..2),
+                token("int", 2..4),
+                Event::FinishNode,
+                token("plus", 4..5),
+                token("int", 5..6),
+                Event::FinishNode,
+                Event::FinishNode,
+            ]
+        );
+
+        // moved by an edit, the replayed events still point at their text
+        ctx.edit(0..0, "10+");
+        assert!(statement.parse(0, ctx).is_ok());
+        let fresh = &mut Context::new("10+1+22+3").with_events();
+        assert!(statement.parse(0, fresh).is_ok());
+        assert_eq!(ctx.events(), fresh.events());
+        assert!(ctx.stats().hits > 0);
+
+        // a context that doesn't record events has none
+        let ctx = &mut Context::new("1");
+        assert!(statement.parse(0, ctx).is_ok());
+        assert!(ctx.events().is_empty());
+    }
+
+    #[test]
+    fn test_trivia() {
+        let space = satisfy("space", char::is_whitespace).many();
+        let comment = keyword("#").and(satisfy("any", |c| c != '\n').many());
+        let trivia = space.clone().and(comment.and(space).opt());
+        let letter = satisfy("letter", char::is_alphabetic);
+        let word = letter
+            .clone()
+            .and(letter.clone().many())
+            .as_token("word")
+            .padded_by(trivia.clone());
+        let line = word.many().node("words");
+        let file = trivia.as_trivia().andr(line).node("file");
+
+        let text = " a  b # note\n c";
+        let ctx = &mut Context::new(text).with_events();
+        assert_eq!(file.parse(0, ctx).map(|(end, _)| end), Ok(text.len()));
+        let events = ctx.events();
+        assert_eq!(
+            events,
+            [
+                start("file"),
+                Event::Trivia(0..1),
+                start("words"),
+                token("word", 1..2),
+                Event::Trivia(2..4),
+                token("word", 4..5),
+                Event::Trivia(5..14),
+                token("word", 14..15),
+                Event::FinishNode,
+                Event::FinishNode,
+            ]
+        );
+
+        // nothing in the text is left out
+        let covered: String = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Token(_, span) | Event::Trivia(span) => Some(&text[span.clone()]),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(covered, text);
+
+        // the trivia `padded_by` adds trails inside or outside a node depending on which
+        // of the two wraps the other
+        let word = letter.clone().as_token("word");
+        let outside = word.clone().node("w").padded_by(char(' ')).many();
+        let ctx = &mut Context::new("a b").with_events();
+        assert!(outside.parse(0, ctx).is_ok());
+        assert_eq!(
+            ctx.events()[..4],
+            [
+                start("w"),
+                token("word", 0..1),
+                Event::FinishNode,
+                Event::Trivia(1..2)
+            ]
+        );
+        let inside = word.padded_by(char(' ')).node("w").many();
+        let ctx = &mut Context::new("a b").with_events();
+        assert!(inside.parse(0, ctx).is_ok());
+        assert_eq!(
+            ctx.events()[..4],
+            [
+                start("w"),
+                token("word", 0..1),
+                Event::Trivia(1..2),
+                Event::FinishNode
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recover() {
+        let int = satisfy("digit", |c| c.is_ascii_digit())
+            .and(satisfy("digit", |c| c.is_ascii_digit()).many())
+            .as_token("int");
+        let semi = char(';').as_token("semi");
+        let junk = satisfy("junk", |c| c != ';').many();
+        let statement = int.map(|_| ()).recover(junk, ()).and(semi).node("stmt");
+        let file = statement.many().node("file");
+
+        let ctx = &mut Context::new("1;x+;;").with_events();
+        assert_eq!(file.parse(0, ctx).map(|(end, _)| end), Ok(6));
+        assert_eq!(
+            ctx.events(),
+            [
+                start("file"),
+                start("stmt"),
+                token("int", 0..1),
+                token("semi", 1..2),
+                Event::FinishNode,
+                start("stmt"),
+                Event::Error(2..4, Rc::from("expected digit got x")),
+                token("semi", 4..5),
+                Event::FinishNode,
+                start("stmt"),
+                Event::Error(5..5, Rc::from("expected digit got ;")),
+                token("semi", 5..6),
+                Event::FinishNode,
+                Event::FinishNode,
+            ]
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use std::fmt::Write;
+use std::rc::Rc;
+
+use rustc_hash::FxHashMap;
+
+use crate::Parser;
+use crate::peg::shape_to_peg;
+use crate::shape::{RuleShape, Shape};
+
+// draws every rule as a box with its body as a tree of operator nodes below it; a
+// reference to a rule is a dashed edge to that rule's box, so cycles in the grammar show
+// up as cycles in the picture
+struct DotWriter<'s, 'a> {
+    out: String,
+    // the node of every rule seen so far, by rule identity
+    rules: FxHashMap<usize, usize>,
+    queue: Vec<&'s Rc<RuleShape<'a>>>,
+    nodes: usize,
+}
+
+impl<'s, 'a> DotWriter<'s, 'a> {
+    fn new() -> Self {
+        DotWriter {
+            out: String::from("digraph grammar {\n    node [fontname=\"monospace\"];\n"),
+            rules: FxHashMap::default(),
+            queue: Vec::new(),
+            nodes: 0,
+        }
+    }
+
+    fn node(&mut self, label: &str, attributes: &str) -> usize {
+        let node = self.nodes;
+        self.nodes += 1;
+        let _ = writeln!(
+            self.out,
+            "    n{} [label=\"{}\", {}];",
+            node,
+            escape(label),
+            attributes
+        );
+        node
+    }
+
+    fn rule(&mut self, rule: &'s Rc<RuleShape<'a>>) -> usize {
+        if let Some(&node) = self.rules.get(&rule.id()) {
+            return node;
+        }
+        let node = self.node(&rule.name, "shape=box, style=bold");
+        self.rules.insert(rule.id(), node);
+        self.queue.push(rule);
+        node
+    }
+
+    fn rules(mut self, roots: impl IntoIterator<Item = &'s Rc<RuleShape<'a>>>) -> String {
+        roots.into_iter().for_each(|rule| {
+            self.rule(rule);
+        });
+        let mut next = 0;
+        while let Some(&rule) = self.queue.get(next) {
+            next += 1;
+            let node = self.rules[&rule.id()];
+            match rule.body() {
+                Some(body) => self.edge(node, body, None),
+                None => {
+                    let undefined = self.node("undefined", "shape=plaintext");
+                    let _ = writeln!(self.out, "    n{} -> n{};", node, undefined);
+                }
+            }
+        }
+        self.out.push_str("}\n");
+        self.out
+    }
+
+    // an edge from `parent` to the node drawn for `shape`, labelled with the position in
+    // a sequence when order matters
+    fn edge(&mut self, parent: usize, shape: &'s Shape<'a>, position: Option<usize>) {
+        let mut attributes = Vec::new();
+        let child = match shape {
+            Shape::Rule(rule) => {
+                attributes.push(String::from("style=dashed"));
+                self.rule(rule)
+            }
+            _ => self.shape(shape),
+        };
+        if let Some(i) = position {
+            attributes.push(format!("label=\"{}\"", i + 1));
+        }
+        let _ = write!(self.out, "    n{} -> n{}", parent, child);
+        if !attributes.is_empty() {
+            let _ = write!(self.out, " [{}]", attributes.join(", "));
+        }
+        self.out.push_str(";\n");
+    }
+
+    fn shape(&mut self, shape: &'s Shape<'a>) -> usize {
+        let (label, items): (&str, &'s [Rc<Shape<'a>>]) = match shape {
+            Shape::Seq(items) if !items.is_empty() => ("seq", items),
+            Shape::Choice(items) => ("/", items),
+            Shape::Many(item) => ("*", std::slice::from_ref(item)),
+            Shape::Opt(item) => ("?", std::slice::from_ref(item)),
+            Shape::Not(item) => ("!", std::slice::from_ref(item)),
+            Shape::And(item) => ("&", std::slice::from_ref(item)),
+            // everything else is a leaf, labelled the way PEG writes it
+            _ => return self.node(&shape_to_peg(shape), "shape=plaintext"),
+        };
+        let node = self.node(label, "shape=circle");
+        let ordered = matches!(shape, Shape::Seq(_) | Shape::Choice(_));
+        for (i, item) in items.iter().enumerate() {
+            self.edge(node, item, ordered.then_some(i));
+        }
+        node
+    }
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+pub(crate) fn rules_to_dot<'s, 'a: 's>(
+    roots: impl IntoIterator<Item = &'s Rc<RuleShape<'a>>>,
+) -> String {
+    DotWriter::new().rules(roots)
+}
+
+impl<'a, T> Parser<'a, T>
+where
+    T: Clone + 'static,
+{
+    /// Draws this parser and every rule it reaches as a Graphviz `digraph`: rules are boxes,
+    /// their bodies trees of operators and literals, and references to rules dashed edges.
+    pub fn to_dot(&self) -> String {
+        let root = self.as_rule();
+        rules_to_dot([&root])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn test_to_dot() {
+        let mut g = GrammarBuilder::new();
+        g.rule("sum", |g| {
+            g.get::<char>("sum")
+                .andl(char('+'))
+                .andr(g.get::<char>("atom"))
+                .or(g.get("atom"))
+        })
+        .rule("atom", |_| char('"').or(any_char()));
+
+        assert_eq!(
+            g.build().unwrap().to_dot(),
+            r#"digraph grammar {
+    node [fontname="monospace"];
+    n0 [label="atom", shape=box, style=bold];
+    n1 [label="sum", shape=box, style=bold];
+    n2 [label="/", shape=circle];
+    n3 [label="'\"'", shape=plaintext];
+    n2 -> n3 [label="1"];
+    n4 [label=".", shape=plaintext];
+    n2 -> n4 [label="2"];
+    n0 -> n2;
+    n5 [label="/", shape=circle];
+    n6 [label="seq", shape=circle];
+    n6 -> n1 [style=dashed, label="1"];
+    n7 [label="'+'", shape=plaintext];
+    n6 -> n7 [label="2"];
+    n6 -> n0 [style=dashed, label="3"];
+    n5 -> n6 [label="1"];
+    n5 -> n0 [style=dashed, label="2"];
+    n1 -> n5;
+}
+"#
+        );
+        assert!(
+            char('a')
+                .many()
+                .to_dot()
+                .contains("[label=\"*\", shape=circle]")
+        );
+    }
+}
@@ -0,0 +1,26 @@
+// grammars the tests of several modules share
+
+use crate::*;
+
+// `sum <- sum '+' operand / operand`, left-recursive, with `add` folding each `+`
+pub(crate) fn sum_of<'a, T: Clone + 'static>(
+    operand: Parser<'a, T>,
+    add: impl Fn(T, T) -> T + Clone + 'a,
+) -> Parser<'a, T> {
+    lazy("sum", move |sum| {
+        let add = add.clone();
+        sum.andl(char('+'))
+            .and(operand.clone())
+            .map(move |(n, m)| add(n, m))
+            .or(operand.clone())
+    })
+}
+
+// single digits added up, so "1+2+3" parses to 6
+pub(crate) fn sum<'a>() -> Parser<'a, u32> {
+    sum_of(digit(), |n, m| n + m)
+}
+
+pub(crate) fn digit<'a>() -> Parser<'a, u32> {
+    satisfy("digit", |c| c.is_ascii_digit()).map(|c| c.to_digit(10).unwrap())
+}
@@ -0,0 +1,216 @@
+use std::rc::Rc;
+
+use rand_core::Rng;
+use rustc_hash::FxHashMap;
+
+use crate::Parser;
+use crate::shape::{RuleShape, Shape};
+
+// generated sentences aren't always accepted, PEG's ordered choice and greedy repetition
+// reject some of what the shapes alone allow, so a few are tried before giving up
+const ATTEMPTS: usize = 100;
+
+// chars tried for `.` and for `satisfy` predicates, which can only be sampled
+const CANDIDATES: std::ops::RangeInclusive<char> = '\t'..='~';
+
+// a random walk through the shapes, expanding at most `max_depth` rules deep before it
+// steers towards the alternatives that finish soonest
+struct Generator<'r, 's, 'a, R> {
+    rng: &'r mut R,
+    max_depth: usize,
+    // the fewest nested rule expansions each rule needs to finish, usize::MAX if it can't
+    heights: FxHashMap<usize, usize>,
+    rules: Vec<&'s Rc<RuleShape<'a>>>,
+}
+
+impl<'r, 's, 'a, R: Rng> Generator<'r, 's, 'a, R> {
+    fn new(rng: &'r mut R, max_depth: usize, root: &'s Rc<RuleShape<'a>>) -> Self {
+        let mut generator = Generator {
+            rng,
+            max_depth,
+            heights: FxHashMap::default(),
+            rules: Vec::new(),
+        };
+        generator.collect(root);
+
+        // a least fixed point again, every rule starts out unable to finish
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for i in 0..generator.rules.len() {
+                let rule = generator.rules[i];
+                let height = rule
+                    .body()
+                    .map_or(usize::MAX, |body| generator.height(body).saturating_add(1));
+                if height < generator.heights[&rule.id()] {
+                    generator.heights.insert(rule.id(), height);
+                    changed = true;
+                }
+            }
+        }
+        generator
+    }
+
+    fn collect(&mut self, rule: &'s Rc<RuleShape<'a>>) {
+        if self.heights.contains_key(&rule.id()) {
+            return;
+        }
+        self.heights.insert(rule.id(), usize::MAX);
+        self.rules.push(rule);
+        fn visit<'s, 'a>(shape: &'s Shape<'a>, found: &mut Vec<&'s Rc<RuleShape<'a>>>) {
+            match shape {
+                Shape::Seq(items) | Shape::Choice(items) => {
+                    items.iter().for_each(|item| visit(item, found))
+                }
+                Shape::Many(item) | Shape::Opt(item) | Shape::Not(item) | Shape::And(item) => {
+                    visit(item, found)
+                }
+                Shape::Rule(rule) => found.push(rule),
+                _ => {}
+            }
+        }
+        let mut found = Vec::new();
+        if let Some(body) = rule.body() {
+            visit(body, &mut found);
+        }
+        found.into_iter().for_each(|rule| self.collect(rule));
+    }
+
+    fn height(&self, shape: &Shape<'a>) -> usize {
+        match shape {
+            Shape::Seq(items) => items.iter().map(|i| self.height(i)).max().unwrap_or(0),
+            Shape::Choice(items) => items
+                .iter()
+                .map(|i| self.height(i))
+                .min()
+                .unwrap_or(usize::MAX),
+            Shape::Rule(rule) => self.heights[&rule.id()],
+            Shape::Opaque(..) => usize::MAX,
+            _ => 0,
+        }
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.rng.next_u64() % n as u64) as usize
+    }
+
+    // appends a sentence of `shape` to `out`, or returns false if it can't make one
+    fn shape(&mut self, shape: &Shape<'a>, depth: usize, out: &mut String) -> bool {
+        let deep = depth >= self.max_depth;
+        match shape {
+            Shape::Char(c) => out.push(*c),
+            Shape::Keyword(keyword) => out.push_str(keyword),
+            Shape::Any => {
+                let c = self.sample(&|_| true);
+                out.extend(c);
+            }
+            Shape::Class(_, predicate) => match self.sample(predicate.as_ref()) {
+                Some(c) => out.push(c),
+                None => return false,
+            },
+            Shape::Seq(items) => return items.iter().all(|item| self.shape(item, depth, out)),
+            Shape::Choice(items) => {
+                let finite: Vec<&Rc<Shape<'a>>> = items
+                    .iter()
+                    .filter(|item| self.height(item) != usize::MAX)
+                    .collect();
+                let chosen = if deep {
+                    finite.iter().min_by_key(|item| self.height(item)).copied()
+                } else if finite.is_empty() {
+                    None
+                } else {
+                    Some(finite[self.below(finite.len())])
+                };
+                return chosen.is_some_and(|item| self.shape(item, depth, out));
+            }
+            Shape::Many(item) => {
+                let times = if deep { 0 } else { self.below(4) };
+                return (0..times).all(|_| self.shape(item, depth, out));
+            }
+            Shape::Opt(item) => {
+                if !deep && self.below(2) == 0 {
+                    return self.shape(item, depth, out);
+                }
+            }
+            // lookaheads consume nothing, whether they hold is left to the final check
+            Shape::Not(_) | Shape::And(_) | Shape::Eof => {}
+            Shape::Rule(rule) => {
+                return rule
+                    .body()
+                    .is_some_and(|body| self.shape(body, depth + 1, out));
+            }
+            Shape::Opaque(..) => return false,
+        }
+        true
+    }
+
+    // a char accepted by `predicate`, looked for from a random starting point
+    fn sample(&mut self, predicate: &dyn Fn(char) -> bool) -> Option<char> {
+        let candidates: Vec<char> = CANDIDATES.collect();
+        let start = self.below(candidates.len());
+        (0..candidates.len())
+            .map(|i| candidates[(start + i) % candidates.len()])
+            .find(|&c| predicate(c))
+    }
+}
+
+impl<'a, T> Parser<'a, T>
+where
+    T: Clone + 'static,
+{
+    /// A random string this parser accepts in full, expanding rules at most about
+    /// `max_depth` deep. Sentences are built from the parser's structure and checked by
+    /// parsing them, so None means no accepted sentence turned up after a number of tries,
+    /// which is always the case for parsers made of opaque parts such as `try_map`.
+    pub fn generate(&self, rng: &mut impl Rng, max_depth: usize) -> Option<String> {
+        let root = self.as_rule();
+        let whole = self.clone().end();
+        let mut generator = Generator::new(rng, max_depth, &root);
+        (0..ATTEMPTS).find_map(|_| {
+            let mut sentence = String::new();
+            let body = root.body()?;
+            (generator.shape(body, 0, &mut sentence) && whole.run(&sentence).is_ok())
+                .then_some(sentence)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand_core::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    use crate::*;
+
+    #[test]
+    fn test_generate() {
+        let mut g = GrammarBuilder::new();
+        g.rule("expr", |g| {
+            g.get::<()>("expr")
+                .andl(char('+'))
+                .andl(g.get::<()>("term"))
+                .or(g.get("term"))
+        })
+        .rule("term", |g| {
+            char('(')
+                .andr(g.get::<()>("expr"))
+                .andl(char(')'))
+                .or(satisfy("digit", |c| c.is_ascii_digit()).map(|_| ()))
+        });
+        let expr = g.build().unwrap().get::<()>("expr").unwrap();
+
+        let rng = &mut XorShiftRng::seed_from_u64(7);
+        let sentences: Vec<String> = (0..20).map(|_| expr.generate(rng, 5).unwrap()).collect();
+        let whole = expr.end();
+        assert!(sentences.iter().all(|s| whole.run(s).is_ok()));
+        assert!(sentences.iter().any(|s| s.contains('+')));
+        assert!(sentences.iter().any(|s| s.contains('(')));
+
+        let keyword_list = keyword("let").or(keyword("fn")).sep_by(char(' '));
+        let sentence = keyword_list.generate(rng, 3).unwrap();
+        assert!(keyword_list.clone().end().run(&sentence).is_ok());
+
+        let opaque = satisfy("digit", |c| c.is_ascii_digit()).try_map(|c| c.to_digit(10));
+        assert_eq!(opaque.generate(rng, 3), None);
+    }
+}
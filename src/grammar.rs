@@ -0,0 +1,615 @@
+use std::any::{Any, TypeId};
+use std::cell::OnceCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::analysis::Analysis;
+use crate::dot::rules_to_dot;
+use crate::peg::rules_to_peg;
+use crate::railroad::railroad_svg;
+use crate::shape::{RuleShape, Shape};
+use crate::{
+    Context, LeftRecursion, LeftRecursionKind, NullableRepetition, Parser, Program,
+    UnreachableAlternative,
+};
+
+// one entry per rule name, created by whichever of `rule` or `get` mentions it first;
+// every reference shares the same placeholder, so the rule is memoized (and its left
+// recursion detected) under a single identity no matter how many times it is used
+struct Rule {
+    type_id: TypeId,
+    type_name: &'static str,
+    // a `Parser<'static, T>` that forwards to the definition once it is set
+    placeholder: Box<dyn Any>,
+    // the `Rc<OnceCell<Parser<'static, T>>>` the placeholder reads from
+    definition: Box<dyn Any>,
+    shape: Rc<RuleShape<'static>>,
+    defined: bool,
+}
+
+type Definition<T> = Rc<OnceCell<Parser<'static, T>>>;
+
+fn forward<T: Clone + 'static>(
+    name: &str,
+) -> (Parser<'static, T>, Definition<T>, Rc<RuleShape<'static>>) {
+    let definition: Definition<T> = Rc::new(OnceCell::new());
+    let cell = definition.clone();
+    let shape = RuleShape::new(name);
+    let rule = name.to_string();
+    let placeholder = Parser::new(
+        name.to_string(),
+        Rc::new(move |pos, ctx: &mut Context| {
+            let real = cell
+                .get()
+                .unwrap_or_else(|| panic!("rule `{}` used before it was defined", rule));
+            real.parse(pos, ctx)
+        }),
+    )
+    .with_shape(Rc::new(Shape::Rule(shape.clone())));
+    (placeholder, definition, shape)
+}
+
+impl Rule {
+    fn new<T: Clone + 'static>(name: &str) -> Self {
+        let (placeholder, definition, shape) = forward::<T>(name);
+        Rule {
+            type_id: TypeId::of::<T>(),
+            type_name: std::any::type_name::<T>(),
+            placeholder: Box::new(placeholder),
+            definition: Box::new(definition),
+            shape,
+            defined: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GrammarError {
+    // referenced somewhere but never given a definition
+    Undefined(String),
+    // defined by more than one call to `rule`
+    Redefined(String),
+    // used with two different output types
+    TypeMismatch {
+        rule: String,
+        expected: &'static str,
+        found: &'static str,
+    },
+    // reaches itself only past something that can match nothing, `a <- 'b'? a 'x' / 'y'`
+    HiddenLeftRecursion(LeftRecursion),
+}
+
+impl std::fmt::Display for GrammarError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GrammarError::Undefined(rule) => write!(f, "rule `{}` is never defined", rule),
+            GrammarError::Redefined(rule) => write!(f, "rule `{}` is defined twice", rule),
+            GrammarError::TypeMismatch {
+                rule,
+                expected,
+                found,
+            } => write!(
+                f,
+                "rule `{}` produces {} but is used as {}",
+                rule, expected, found
+            ),
+            GrammarError::HiddenLeftRecursion(recursion) => write!(
+                f,
+                "{}, so it recurses at positions where the part before the call matches \
+                 nothing and not elsewhere",
+                recursion
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GrammarError {}
+
+/// Registers rules by name so they can refer to each other (and themselves) in any order,
+/// without nesting `lazy` calls and cloning parsers into every closure.
+///
+/// ```
+/// use packrust::*;
+///
+/// let mut g = GrammarBuilder::new();
+/// g.rule("sum", |g| {
+///     g.get::<i32>("sum")
+///         .andl(char('+'))
+///         .and(g.get::<i32>("digit"))
+///         .map(|(l, r)| l + r)
+///         .or(g.get("digit"))
+/// });
+/// g.rule("digit", |_| {
+///     satisfy("digit", |c| c.is_ascii_digit()).map(|c| c.to_digit(10).unwrap() as i32)
+/// });
+///
+/// let grammar = g.build().unwrap();
+/// let sum = grammar.get::<i32>("sum").unwrap();
+/// assert_eq!(sum.run("1+2+3"), Ok(6));
+/// ```
+#[derive(Default)]
+pub struct GrammarBuilder {
+    rules: BTreeMap<String, Rule>,
+    errors: Vec<GrammarError>,
+}
+
+impl GrammarBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A reference to the rule called `name`, which may be defined before or after this
+    /// call. Using one name with two output types is reported by `build`.
+    pub fn get<T: Clone + 'static>(&mut self, name: &str) -> Parser<'static, T> {
+        match self.entry::<T>(name).placeholder.downcast_ref() {
+            Some(placeholder) => Parser::clone(placeholder),
+            // already recorded as a mismatch, hand out a rule that can never be built
+            None => forward::<T>(name).0,
+        }
+    }
+
+    /// Declares the rule called `name` ahead of its definition, to be given later with
+    /// [`GrammarBuilder::define`]. The same as [`GrammarBuilder::get`], named for grammars
+    /// that declare all their rules up front.
+    pub fn forward<T: Clone + 'static>(&mut self, name: &str) -> Parser<'static, T> {
+        self.get(name)
+    }
+
+    /// Defines the rule called `name`. The closure receives the builder so the body can
+    /// reference other rules with [`GrammarBuilder::get`].
+    pub fn rule<T: Clone + 'static>(
+        &mut self,
+        name: &str,
+        define: impl FnOnce(&mut Self) -> Parser<'static, T>,
+    ) -> &mut Self {
+        let parser = define(self);
+        self.define(name, parser)
+    }
+
+    /// Defines the rule called `name` as `parser`, which may use rules obtained from
+    /// [`GrammarBuilder::forward`] before they are defined themselves.
+    pub fn define<T: Clone + 'static>(
+        &mut self,
+        name: &str,
+        parser: Parser<'static, T>,
+    ) -> &mut Self {
+        let rule = self.entry::<T>(name);
+        if std::mem::replace(&mut rule.defined, true) {
+            self.errors.push(GrammarError::Redefined(name.to_string()));
+        } else if let Some(definition) = rule.definition.downcast_ref::<Definition<T>>() {
+            let _ = rule.shape.body.set(parser.shape.clone());
+            let _ = definition.set(parser);
+        }
+        self
+    }
+
+    /// Checks that every referenced rule was defined exactly once with a single output
+    /// type, and that no rule is hidden left-recursive, reporting all problems at once.
+    ///
+    /// Growing a seed only gives predictable results when a rule calls itself before
+    /// consuming anything wherever it is tried. A rule like `a <- 'b'? a 'x' / 'y'` does so
+    /// only where the `'b'` is missing, which makes the same input parse differently
+    /// depending on the position it is at.
+    pub fn build(mut self) -> Result<Grammar, Vec<GrammarError>> {
+        let mut errors = std::mem::take(&mut self.errors);
+        errors.extend(
+            self.rules
+                .iter()
+                .filter(|(_, rule)| !rule.defined)
+                .map(|(name, _)| GrammarError::Undefined(name.clone())),
+        );
+        if errors.is_empty() {
+            errors.extend(
+                Analysis::new(self.rules.values().map(|rule| &rule.shape))
+                    .left_recursion()
+                    .into_iter()
+                    .filter(|recursion| recursion.kind == LeftRecursionKind::Hidden)
+                    .map(GrammarError::HiddenLeftRecursion),
+            );
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(Grammar {
+            rules: self
+                .rules
+                .into_iter()
+                .map(|(name, rule)| (name, (rule.placeholder, rule.shape)))
+                .collect(),
+        })
+    }
+
+    fn entry<T: Clone + 'static>(&mut self, name: &str) -> &mut Rule {
+        let rule = self
+            .rules
+            .entry(name.to_string())
+            .or_insert_with(|| Rule::new::<T>(name));
+        if rule.type_id != TypeId::of::<T>() {
+            self.errors.push(GrammarError::TypeMismatch {
+                rule: name.to_string(),
+                expected: rule.type_name,
+                found: std::any::type_name::<T>(),
+            });
+        }
+        rule
+    }
+}
+
+/// A complete set of named rules, produced by [`GrammarBuilder::build`].
+pub struct Grammar {
+    rules: BTreeMap<String, (Box<dyn Any>, Rc<RuleShape<'static>>)>,
+}
+
+impl Grammar {
+    /// The rule called `name`, or None if there is no such rule or it produces a type
+    /// other than `T`.
+    pub fn get<T: Clone + 'static>(&self, name: &str) -> Option<Parser<'static, T>> {
+        self.rules
+            .get(name)?
+            .0
+            .downcast_ref::<Parser<'static, T>>()
+            .cloned()
+    }
+
+    /// The names of all rules, in alphabetical order.
+    pub fn rule_names(&self) -> impl Iterator<Item = &str> {
+        self.rules.keys().map(String::as_str)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.rules.contains_key(name)
+    }
+
+    /// Lowers every rule to bytecode for fast recognition, see [`Program`]. The program
+    /// only checks whether input matches: the combinators describe the grammar it runs, but
+    /// their `map`s and other actions are left out, so the values the rules produce still
+    /// come from parsing with the rules themselves, as [`Grammar::get`] gives them.
+    pub fn compile(&self) -> Program {
+        Program::compile(self.rules.values().map(|(_, shape)| shape))
+    }
+
+    /// The left-recursive rules, with the chain of calls each one recurses through.
+    pub fn left_recursion(&self) -> Vec<LeftRecursion> {
+        Analysis::new(self.rules.values().map(|(_, shape)| shape)).left_recursion()
+    }
+
+    /// The repetitions whose item can succeed without consuming input.
+    pub fn nullable_repetitions(&self) -> Vec<NullableRepetition> {
+        Analysis::new(self.rules.values().map(|(_, shape)| shape)).nullable_repetitions()
+    }
+
+    /// The alternatives that an earlier alternative of the same choice always wins over.
+    pub fn unreachable_alternatives(&self) -> Vec<UnreachableAlternative> {
+        Analysis::new(self.rules.values().map(|(_, shape)| shape)).unreachable_alternatives()
+    }
+
+    /// Draws every rule as a Graphviz `digraph`, see [`Parser::to_dot`].
+    pub fn to_dot(&self) -> String {
+        rules_to_dot(self.rules.values().map(|(_, shape)| shape))
+    }
+
+    /// One SVG railroad diagram per rule, by rule name.
+    pub fn to_railroad(&self) -> BTreeMap<String, String> {
+        self.rules
+            .iter()
+            .map(|(name, (_, shape))| {
+                let body = shape
+                    .body()
+                    .cloned()
+                    .unwrap_or_else(|| Rc::new(Shape::Seq(Vec::new())));
+                (name.clone(), railroad_svg(name, &body))
+            })
+            .collect()
+    }
+
+    /// Writes every rule in PEG notation, one `name <- body` definition per line.
+    pub fn to_peg(&self) -> String {
+        rules_to_peg(self.rules.values().map(|(_, shape)| shape))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    fn int() -> Parser<'static, i32> {
+        satisfy("digit", |c| c.is_ascii_digit())
+            .many()
+            .try_map(|s| s.iter().collect::<String>().parse().ok())
+    }
+
+    #[test]
+    fn test_mutual_recursion() {
+        let mut g = GrammarBuilder::new();
+        g.rule("expr", |g| {
+            g.get::<i32>("expr")
+                .andl(char('+'))
+                .and(g.get::<i32>("term"))
+                .map(|(l, r)| l + r)
+                .or(g.get("term"))
+        })
+        .rule("term", |g| {
+            g.get::<i32>("term")
+                .andl(char('*'))
+                .and(g.get::<i32>("factor"))
+                .map(|(l, r)| l * r)
+                .or(g.get("factor"))
+        })
+        .rule("factor", |g| {
+            int().or(char('(').andr(g.get("expr")).andl(char(')')))
+        });
+
+        let grammar = g.build().unwrap();
+        let expr = grammar.get::<i32>("expr").unwrap();
+        assert_eq!(expr.run("2*(3+4)+1"), Ok(15));
+        assert_eq!(
+            grammar.rule_names().collect::<Vec<_>>(),
+            ["expr", "factor", "term"]
+        );
+        assert!(grammar.get::<String>("expr").is_none());
+    }
+
+    // parsers for tricky grammars below build a bracketed tree of what they matched
+    fn lit(c: char) -> Parser<'static, String> {
+        char(c).map(String::from)
+    }
+
+    fn seq(
+        left: Parser<'static, String>,
+        right: Parser<'static, String>,
+    ) -> Parser<'static, String> {
+        left.and(right).map(|(l, r)| format!("({}{})", l, r))
+    }
+
+    #[test]
+    fn test_indirect_left_recursion() {
+        // three rules in one cycle, each of which can be where the parse starts
+        let mut g = GrammarBuilder::new();
+        g.rule("a", |g| seq(g.get("b"), lit('a')).or(lit('x')))
+            .rule("b", |g| seq(g.get("c"), lit('b')))
+            .rule("c", |g| seq(g.get("a"), lit('c')));
+        let grammar = g.build().unwrap();
+        let rule = |name| grammar.get::<String>(name).unwrap().end();
+        assert_eq!(rule("a").run("xcbacba"), Ok("((((((xc)b)a)c)b)a)".into()));
+        assert_eq!(rule("b").run("xcbacb"), Ok("(((((xc)b)a)c)b)".into()));
+        assert_eq!(rule("c").run("xcbac"), Ok("((((xc)b)a)c)".into()));
+        assert!(rule("b").run("xcba").is_err());
+
+        // entered through `sum`, the cycle's head is `sum` and not `expr`, so the second
+        // alternative reads `expr` from the memo table while the seed is still growing
+        let mut g = GrammarBuilder::new();
+        g.rule("expr", |g| g.get::<String>("sum")).rule("sum", |g| {
+            seq(seq(g.get("expr"), lit('+')), lit('n'))
+                .or(seq(seq(g.get("expr"), lit('-')), lit('n')))
+                .or(lit('n'))
+        });
+        let grammar = g.build().unwrap();
+        for name in ["sum", "expr"] {
+            let rule = grammar.get::<String>(name).unwrap().end();
+            assert_eq!(rule.run("n-n+n"), Ok("((((n-)n)+)n)".into()));
+        }
+
+        // two cycles sharing a rule: B grows its own seed inside every attempt to grow A
+        // S -> A '-' A, A -> B 'b' / 'b', B -> B 'a' / A 'a'
+        let mut g = GrammarBuilder::new();
+        g.rule("s", |g| seq(seq(g.get("a"), lit('-')), g.get("a")))
+            .rule("a", |g| seq(g.get("b"), lit('b')).or(lit('b')))
+            .rule("b", |g| {
+                seq(g.get("b"), lit('a')).or(seq(g.get("a"), lit('a')))
+            });
+        let s = g.build().unwrap().get::<String>("s").unwrap().end();
+        assert_eq!(s.run("baab-baab"), Ok("(((((ba)a)b)-)(((ba)a)b))".into()));
+        assert_eq!(s.run("bab-baaab"), Ok("((((ba)b)-)((((ba)a)a)b))".into()));
+    }
+
+    #[test]
+    fn test_left_recursion_under_other_rules() {
+        // the left-recursive part of Java's primary expressions, reached through a rule
+        // that isn't left-recursive itself: t = this, i = field, m( = method call
+        let mut g = GrammarBuilder::new();
+        g.rule("primary", |g| g.get::<String>("no_new_array"))
+            .rule("no_new_array", |g| {
+                g.get::<String>("method")
+                    .or(g.get("field"))
+                    .or(g.get("array"))
+                    .or(lit('t'))
+            })
+            .rule("method", |g| {
+                seq(seq(g.get("primary"), lit('.')), seq(lit('m'), lit('(')))
+                    .or(seq(lit('m'), lit('(')))
+            })
+            .rule("field", |g| seq(seq(g.get("primary"), lit('.')), lit('i')))
+            .rule("array", |g| {
+                seq(seq(g.get("primary"), lit('[')), seq(lit('e'), lit(']')))
+            });
+        let primary = g.build().unwrap().get::<String>("primary").unwrap().end();
+        assert_eq!(primary.run("t.i.i"), Ok("((((t.)i).)i)".into()));
+        assert_eq!(
+            primary.run("t.i[e].m("),
+            Ok("((((((t.)i)[)(e])).)(m())".into())
+        );
+
+        // the same left-recursive rules grown at several positions of one parse, nested
+        // inside each other's growth through parentheses
+        let mut g = GrammarBuilder::new();
+        g.rule("e", |g| {
+            seq(seq(g.get("e"), lit('+')), g.get("t")).or(g.get("t"))
+        })
+        .rule("t", |g| {
+            seq(seq(g.get("t"), lit('*')), g.get("f")).or(g.get("f"))
+        })
+        .rule("f", |g| {
+            seq(seq(lit('('), g.get("e")), lit(')')).or(lit('n'))
+        });
+        let e = g.build().unwrap().get::<String>("e").unwrap().end();
+        assert_eq!(
+            e.run("n*(n+n*n)+n"),
+            Ok("((((n*)(((((n+)((n*)n))))))+)n)".into())
+        );
+
+        // two heads whose cycles run through each other
+        // A -> A 'a' / B / 'x', B -> B 'b' / A 'c'
+        let mut g = GrammarBuilder::new();
+        g.rule("a", |g| {
+            seq(g.get("a"), lit('a')).or(g.get("b")).or(lit('x'))
+        })
+        .rule("b", |g| {
+            seq(g.get("b"), lit('b')).or(seq(g.get("a"), lit('c')))
+        });
+        let grammar = g.build().unwrap();
+        let a = grammar.get::<String>("a").unwrap().end();
+        assert_eq!(a.run("xacbbaca"), Ok("(((((((xa)c)b)b)a)c)a)".into()));
+        assert!(a.run("xcbcab").is_err());
+        let b = grammar.get::<String>("b").unwrap().end();
+        assert_eq!(b.run("xcb"), Ok("((xc)b)".into()));
+    }
+
+    #[test]
+    fn test_left_recursion_is_left_associative() {
+        // e -> e '+' t / e '-' t / t, t -> t '*' f / f, f -> '(' e ')' / 'n'
+        let mut g = GrammarBuilder::new();
+        g.rule("e", |g| {
+            seq(seq(g.get("e"), lit('+')), g.get("t"))
+                .or(seq(seq(g.get("e"), lit('-')), g.get("t")))
+                .or(g.get("t"))
+        })
+        .rule("t", |g| {
+            seq(seq(g.get("t"), lit('*')), g.get("f")).or(g.get("f"))
+        })
+        .rule("f", |g| {
+            seq(seq(lit('('), g.get("e")), lit(')')).or(lit('n'))
+        });
+        let grown = g.build().unwrap().get::<String>("e").unwrap().end();
+
+        // the same grammar with its repetitions folded to the left by hand
+        let operator = |op| char(op).map(move |_| move |l, r| format!("(({}{}){})", l, op, r));
+        let folded = lazy("e", move |e| {
+            let f = seq(seq(lit('('), e), lit(')')).or(lit('n'));
+            chainl1(chainl1(f, operator('*')), operator('+').or(operator('-')))
+        })
+        .end();
+
+        // every input up to eight chars in which operands and operators alternate, which
+        // nests parentheses and mixes operators at every position a left-recursive rule
+        // can start from; unbalanced parentheses and dangling operators are left in
+        let mut inputs = vec![String::new()];
+        let mut next = 0;
+        while let Some(input) = inputs.get(next).cloned() {
+            next += 1;
+            if input.len() == 8 {
+                continue;
+            }
+            let after_operand = input.ends_with(['n', ')']);
+            let candidates = if after_operand { "+-*)" } else { "n(" };
+            inputs.extend(candidates.chars().map(|c| format!("{}{}", input, c)));
+        }
+        let mut accepted = 0;
+        for input in &inputs {
+            let expected = folded.run(input.as_str()).ok();
+            assert_eq!(grown.run(input.as_str()).ok(), expected, "{:?}", input);
+            accepted += expected.is_some() as usize;
+        }
+        assert!(accepted > 100);
+        assert_eq!(
+            grown.run("n-n-(n+n-n)*n"),
+            Ok("((((n-)n)-)(((((((((n+)n)-)n))))*)n))".into())
+        );
+
+        // recursing on both sides, the right operand is a call of its own that grows too
+        let both = lazy("e", |e| seq(seq(e.clone(), lit('-')), e).or(lit('n'))).end();
+        assert_eq!(both.run("n-n-n"), Ok("((n-)((n-)n))".into()));
+    }
+
+    #[cfg(feature = "macros")]
+    #[test]
+    fn test_rule_macro() {
+        rule! {
+            list: Vec<i32> = char('[').andr(items.opt()).andl(char(']')).map(Option::unwrap_or_default);
+            items: Vec<i32> = items.andl(char(',')).and(value).map(|(mut l, v)| { l.push(v); l })
+                .or(value.map(|v| vec![v]));
+            value: i32 = int().or(list.map(|l| l.iter().sum()));
+        }
+
+        assert_eq!(list.run("[1,[2,3],[]]"), Ok(vec![1, 5, 0]));
+        assert_eq!(value.run("[4,[5]]"), Ok(9));
+        assert_eq!(items.name, "items");
+    }
+
+    #[test]
+    fn test_forward_define() {
+        let mut r = GrammarBuilder::new();
+        let expr = r.forward::<i32>("expr");
+        let term = r.forward::<i32>("term");
+        let factor = r.forward::<i32>("factor");
+        r.define(
+            "expr",
+            expr.clone()
+                .andl(char('-'))
+                .and(term.clone())
+                .map(|(l, r)| l - r)
+                .or(term.clone()),
+        )
+        .define(
+            "term",
+            term.andl(char('/'))
+                .and(factor.clone())
+                .map(|(l, r)| l / r)
+                .or(factor.clone()),
+        )
+        .define(
+            "factor",
+            int().or(char('(').andr(expr.clone()).andl(char(')'))),
+        );
+        let grammar = r.build().unwrap();
+        assert_eq!(grammar.get::<i32>("expr").unwrap().run("8-(6-1)/2"), Ok(6));
+
+        let mut r = GrammarBuilder::new();
+        let item = r.forward::<i32>("item");
+        r.define("list", item.sep_by(char(',')));
+        assert_eq!(
+            r.build().err().unwrap(),
+            [GrammarError::Undefined("item".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_validation() {
+        let mut g = GrammarBuilder::new();
+        g.rule("list", |g| g.get::<i32>("item").many())
+            .rule("item", |_| int())
+            .rule("item", |_| int())
+            .rule("pair", |g| {
+                g.get::<i32>("left").and(g.get::<String>("item"))
+            });
+
+        let errors = g.build().err().unwrap();
+        assert_eq!(
+            errors,
+            [
+                GrammarError::Redefined("item".to_string()),
+                GrammarError::TypeMismatch {
+                    rule: "item".to_string(),
+                    expected: "i32",
+                    found: "alloc::string::String",
+                },
+                GrammarError::Undefined("left".to_string()),
+            ]
+        );
+
+        let mut g = GrammarBuilder::new();
+        g.rule("neg", |g| {
+            g.get::<Option<char>>("sign")
+                .andr(g.get::<char>("neg"))
+                .andl(char('x'))
+                .or(char('y'))
+        })
+        .rule("sign", |_| char('-').opt());
+        let errors = g.build().err().unwrap();
+        assert_eq!(
+            errors[0].to_string(),
+            "rule `neg` is hidden left-recursive: neg -> neg, so it recurses at positions \
+             where the part before the call matches nothing and not elsewhere"
+        );
+    }
+}
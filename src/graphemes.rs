@@ -0,0 +1,179 @@
+use std::fmt::Debug;
+use std::ops::{Deref, Range};
+
+use unicode_segmentation::{GraphemeCursor, UnicodeSegmentation};
+
+use crate::{Input, Parser, Pos, SharedText, Text, elem};
+
+/// Text as a sequence of extended grapheme clusters, what a reader sees as one character
+/// each, like `e` with a combining accent or a flag made of two regional indicators. Over
+/// it, [`grapheme`] and `elem` match whole clusters where `any_char` and `satisfy` would
+/// split them. Positions are byte offsets, as for any text, but only ever fall between
+/// clusters.
+///
+/// ```
+/// use packrust::*;
+///
+/// let text = "e\u{301}🇫🇷!";
+/// let chars = any_char().many().run(text).unwrap();
+/// assert_eq!(chars.len(), 5);
+///
+/// let ctx = &mut Context::from_source(Graphemes::new(text));
+/// let (_, clusters) = grapheme().many().parse(0, ctx).unwrap();
+/// assert_eq!(clusters, ["e\u{301}", "🇫🇷", "!"]);
+/// ```
+#[derive(Clone)]
+pub struct Graphemes {
+    text: Text,
+    range: Range<usize>,
+}
+
+/// One extended grapheme cluster of [`Graphemes`], sharing their text.
+#[derive(Clone)]
+pub struct Grapheme {
+    text: Text,
+    range: Range<usize>,
+}
+
+impl Graphemes {
+    pub fn new(text: impl Into<String>) -> Self {
+        let text = Text::from(text.into());
+        Graphemes {
+            range: 0..text.len(),
+            text,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text[self.range.clone()]
+    }
+}
+
+impl Grapheme {
+    pub fn as_str(&self) -> &str {
+        &self.text[self.range.clone()]
+    }
+}
+
+impl Deref for Grapheme {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq for Grapheme {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl PartialEq<&str> for Grapheme {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl Debug for Grapheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl Debug for Graphemes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl Input for Graphemes {
+    type Elem = Grapheme;
+    type Slice = Graphemes;
+
+    fn len(&self) -> usize {
+        self.range.len()
+    }
+
+    fn next(&self, pos: Pos) -> Option<(Grapheme, Pos)> {
+        let text = self.as_str();
+        if pos >= text.len() {
+            return None;
+        }
+        let end = GraphemeCursor::new(pos, text.len(), true)
+            .next_boundary(text, 0)
+            .ok()??;
+        let start = self.range.start;
+        let grapheme = Grapheme {
+            text: self.text.clone(),
+            range: start + pos..start + end,
+        };
+        Some((grapheme, end))
+    }
+
+    fn slice(&self, range: Range<Pos>) -> Graphemes {
+        assert!(
+            range.start <= range.end && range.end <= self.len(),
+            "slice {:?} out of bounds for {} bytes",
+            range,
+            self.len()
+        );
+        Graphemes {
+            text: self.text.clone(),
+            range: self.range.start + range.start..self.range.start + range.end,
+        }
+    }
+
+    fn text(&self) -> Option<SharedText> {
+        if self.range == (0..self.text.len()) {
+            return Some(SharedText::from(self.text.clone()));
+        }
+        Some(SharedText::from(self.as_str()))
+    }
+}
+
+/// Matches any one grapheme cluster, the counterpart of `any_char` for [`Graphemes`].
+pub fn grapheme<'a>() -> Parser<'a, Grapheme, Graphemes> {
+    elem("grapheme", |_| true)
+}
+
+// how many grapheme clusters there are in `text`, for columns as a reader counts them
+pub(crate) fn count(text: &str) -> usize {
+    text.graphemes(true).count()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn test_graphemes() {
+        // a family emoji joined with zero width joiners, a Hangul syllable in jamo, CRLF
+        let text = "👨‍👩‍👧 \u{1100}\u{1161}\u{11a8}\r\nx";
+        let ctx = &mut Context::from_source(Graphemes::new(text));
+        let (end, clusters) = grapheme().many().parse(0, ctx).unwrap();
+        assert_eq!(end, text.len());
+        assert_eq!(clusters.len(), 5);
+        assert_eq!(clusters[2], "\u{1100}\u{1161}\u{11a8}");
+        assert_eq!(clusters[3], "\r\n");
+
+        // errors point into the text at the cluster that didn't match
+        let space = elem("space", |g: &Grapheme| g.as_str() == " ");
+        let error = grapheme()
+            .and(grapheme())
+            .and(space)
+            .parse(0, ctx)
+            .unwrap_err();
+        assert_eq!(error.pos(), text.find('\u{1100}').unwrap());
+
+        let ctx = &mut Context::from_source(Graphemes::new("ab\u{308}c").slice(1..4));
+        assert_eq!(
+            grapheme().recognize().parse(0, ctx).unwrap().1.as_str(),
+            "b\u{308}"
+        );
+
+        let map = SourceMap::new(text);
+        assert_eq!(map.line_col_graphemes(text.len()), (1, 1));
+        assert_eq!(map.line_col_graphemes(text.find('\r').unwrap()), (0, 3));
+    }
+}
@@ -0,0 +1,142 @@
+use std::any::Any;
+use std::fmt::Debug;
+use std::ops::Range;
+
+use crate::{Context, Found, Pos, SharedText, Source, Spans, Substr, Text};
+
+/// What a [`Context`](crate::Context) parses: a sequence of elements addressed by position.
+/// Text, as a [`Source`], is the default and what the char-level parsers work on; the
+/// combinators that only arrange other parsers (`and`, `or`, `many`, `lazy`, ...) work on
+/// any input.
+///
+/// Positions are whatever the input counts in, byte offsets for text, and an element may
+/// span several of them; `next` says where the following one starts.
+pub trait Input: 'static {
+    type Elem: Clone + PartialEq + Debug + 'static;
+    /// What `recognize` hands back for the part of the input a parser matched.
+    type Slice: Clone + 'static;
+
+    /// The position just past the last element.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The element at `pos` and the position of the one after it, None at the end.
+    fn next(&self, pos: Pos) -> Option<(Self::Elem, Pos)>;
+
+    fn slice(&self, range: Range<Pos>) -> Self::Slice;
+
+    /// The whole input as text, which error messages quote and point into; inputs that
+    /// aren't text leave it out and their errors only give the position.
+    fn text(&self) -> Option<SharedText> {
+        None
+    }
+
+    /// The span of `text` each position stands for, for inputs whose positions count
+    /// something else, like tokens, so errors still point into the text.
+    fn spans(&self) -> Option<Spans> {
+        None
+    }
+}
+
+impl Input for Source {
+    type Elem = char;
+    type Slice = Substr;
+
+    fn len(&self) -> usize {
+        Source::len(self)
+    }
+
+    fn next(&self, pos: Pos) -> Option<(char, Pos)> {
+        self.get(pos).map(|c| (c, pos + c.len_utf8()))
+    }
+
+    fn slice(&self, range: Range<Pos>) -> Substr {
+        Source::slice(self, range)
+    }
+
+    fn text(&self) -> Option<SharedText> {
+        Some(self.shared_text())
+    }
+}
+
+// the char at `pos` when the input is text, for the first-char tables `or` and `choice`
+// consult; only parsers over text have first chars, so other inputs never get here
+pub(crate) fn next_char<I: Input>(ctx: &mut Context<I>, pos: Pos) -> Option<char> {
+    let (elem, _) = ctx.next(pos)?;
+    (&elem as &dyn Any).downcast_ref::<char>().copied()
+}
+
+// what an error reports was found at `pos`
+pub(crate) fn found<I: Input>(input: &I, pos: Pos) -> Found {
+    match input.next(pos) {
+        None => Found::Eof,
+        Some((elem, _)) => {
+            let any = &elem as &dyn Any;
+            if let Some(&c) = any.downcast_ref::<char>() {
+                Found::Char(c)
+            } else if let Some(&b) = any.downcast_ref::<u8>() {
+                Found::Byte(b)
+            } else if let Some(&b) = any.downcast_ref::<bool>() {
+                Found::Bit(b)
+            } else {
+                Found::Elem(Text::from(format!("{:?}", elem)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ops::Range;
+
+    use crate::*;
+
+    // a list of numbers, each of which is one element at one position
+    struct Numbers(Vec<u32>);
+
+    impl Input for Numbers {
+        type Elem = u32;
+        type Slice = Vec<u32>;
+
+        fn len(&self) -> usize {
+            self.0.len()
+        }
+
+        fn next(&self, pos: usize) -> Option<(u32, usize)> {
+            self.0.get(pos).map(|&n| (n, pos + 1))
+        }
+
+        fn slice(&self, range: Range<usize>) -> Vec<u32> {
+            self.0[range].to_vec()
+        }
+    }
+
+    fn number<'a>(name: &str, f: impl Fn(u32) -> bool + 'a) -> Parser<'a, u32, Numbers> {
+        elem(name, move |&n| f(n))
+    }
+
+    #[test]
+    fn test_custom_input() {
+        // every number summed, left-recursively: run <- run n / n
+        let run = lazy("run", |run| {
+            run.and(number("n", |_| true))
+                .map(|(sum, n)| sum + n)
+                .or(number("n", |_| true))
+        });
+        let even = number("even", |n| n % 2 == 0);
+        let evens = even.clone().many().recognize();
+
+        let ctx = &mut Context::from_source(Numbers(vec![2, 4, 5, 6]));
+        assert_eq!(run.parse(0, ctx), Ok((4, 17)));
+        assert_eq!(evens.parse(0, ctx), Ok((2, vec![2, 4])));
+        let error = even.clone().end().parse(0, ctx).unwrap_err();
+        assert_eq!(error.pos(), 1);
+        assert!(error.to_string().ends_with("expected EOF found 4\n"));
+        let error = even.parse(2, ctx).unwrap_err();
+        assert!(error.to_string().ends_with("expected even got 5\n"));
+        assert_eq!(ctx.stats().lr_iterations, 4);
+    }
+}
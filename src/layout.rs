@@ -0,0 +1,318 @@
+use std::rc::Rc;
+
+use crate::input::{found, next_char};
+use crate::{Context, ParseError, Parser, Pos, Reason, Text};
+
+// the indentation of the line starting at `pos`, how many spaces and tabs it starts with,
+// and where the rest of the line starts
+fn indentation(ctx: &mut Context, pos: Pos) -> (usize, Pos) {
+    let (mut width, mut pos) = (0, pos);
+    while let Some(c @ (' ' | '\t')) = next_char(ctx, pos) {
+        width += 1;
+        pos += c.len_utf8();
+    }
+    (width, pos)
+}
+
+// steps over lines holding nothing but whitespace from the start of a line, to the start
+// of the next line that holds something, or the end of the input
+fn skip_blank_lines(ctx: &mut Context, pos: Pos) -> Pos {
+    let mut line = pos;
+    loop {
+        let (_, mut end) = indentation(ctx, line);
+        if next_char(ctx, end) == Some('\r') {
+            end += 1;
+        }
+        match next_char(ctx, end) {
+            Some('\n') => line = end + 1,
+            None => return end,
+            Some(_) => return line,
+        }
+    }
+}
+
+// the column of `pos`, in chars from the start of its line
+fn column_at(ctx: &mut Context, pos: Pos) -> usize {
+    let start = ctx.line_start(pos);
+    ctx.source.slice(start..pos).chars().count()
+}
+
+// a parser matching nothing where `accept` takes the column and the innermost indentation
+// level, if any
+fn column_check<'a>(
+    name: String,
+    label: String,
+    accept: impl Fn(usize, Option<usize>) -> bool + 'a,
+) -> Parser<'a, ()> {
+    let label = Text::from(label);
+    let raw_parser = Rc::new(move |pos, ctx: &mut Context| {
+        if !accept(column_at(ctx, pos), ctx.indents.last().copied()) {
+            return Err(ParseError {
+                source: ctx.clone_source(),
+                pos,
+                reason: Reason::Expected {
+                    label: label.clone(),
+                    found: found(&ctx.source, pos),
+                },
+            });
+        }
+        Ok((pos, ()))
+    });
+    Parser::new(name, raw_parser)
+}
+
+fn layout_error(ctx: &Context, pos: Pos, message: &'static str) -> ParseError {
+    ParseError {
+        source: ctx.clone_source(),
+        pos,
+        reason: Reason::Message(message),
+    }
+}
+
+/// Parses a block of `item`s by the off-side rule, as Python or YAML lay them out: from
+/// the start of a line, the first item sets the indentation of the block, which has to be
+/// deeper than that of the block around it, and every line indented as much starts the
+/// next item, until a line indented less. Items parse from after the indentation and
+/// consume their line break; blank lines between them are skipped. Spaces and tabs each
+/// count as one column.
+///
+/// ```
+/// use packrust::*;
+///
+/// let name = satisfy("letter", char::is_alphabetic).many_collect::<String>();
+/// let line = lazy("line", move |line| {
+///     let nested = name
+///         .clone()
+///         .andl(keyword(":\n"))
+///         .and(block(line))
+///         .map(|(name, items)| format!("{}{{{}}}", name, items.join(" ")));
+///     nested.or(name.clone().andl(char('\n')))
+/// });
+/// let file = block(line).end();
+///
+/// let text = "a\nif:\n  b\n\n  while:\n    c\nd\n";
+/// assert_eq!(file.run(text).unwrap(), ["a", "if{b while{c}}", "d"]);
+/// assert!(file.run("if:\nb\n").is_err());
+/// ```
+pub fn block<'a, T: Clone + 'static>(item: Parser<'a, T>) -> Parser<'a, Vec<T>> {
+    let name = format!("block({})", item.name);
+    let raw_parser = Rc::new(move |pos, ctx: &mut Context| {
+        let pos = skip_blank_lines(ctx, pos);
+        let (level, start) = indentation(ctx, pos);
+        if ctx.indents.last().is_some_and(|&outer| level <= outer) || start == ctx.source.len() {
+            return Err(layout_error(ctx, start, "expected an indented block"));
+        }
+
+        // the stack is popped on every way out, so a block that fails leaves it as it was
+        ctx.indents.push(level);
+        let result = (|| {
+            let (mut items, mut pos) = (Vec::new(), pos);
+            loop {
+                let (indent, start) = indentation(ctx, pos);
+                if start == ctx.source.len() || indent < level {
+                    return Ok((pos, items));
+                }
+                if indent > level {
+                    return Err(layout_error(ctx, start, "unexpected indentation"));
+                }
+                let (end, val) = item.parse(start, ctx)?;
+                if end == start {
+                    return Err(layout_error(ctx, start, "block item matched nothing"));
+                }
+                items.push(val);
+                pos = skip_blank_lines(ctx, end);
+            }
+        })();
+        ctx.indents.pop();
+        result
+    });
+    Parser::new(name, raw_parser).no_memo()
+}
+
+/// Matches nothing and produces the column of the current position, counted in chars from
+/// 0 at the start of the line.
+pub fn column<'a>() -> Parser<'a, usize> {
+    let raw_parser = Rc::new(|pos, ctx: &mut Context| Ok((pos, column_at(ctx, pos))));
+    Parser::new(String::from("column"), raw_parser)
+}
+
+/// Matches nothing and produces the indentation of the line the current position is on,
+/// to compare other lines against.
+pub fn line_indent<'a>() -> Parser<'a, usize> {
+    let raw_parser = Rc::new(|pos, ctx: &mut Context| {
+        let start = ctx.line_start(pos);
+        Ok((pos, indentation(ctx, start).0))
+    });
+    Parser::new(String::from("line_indent"), raw_parser)
+}
+
+/// Matches nothing at column `n`, and fails elsewhere.
+pub fn at_column<'a>(n: usize) -> Parser<'a, ()> {
+    column_check(
+        format!("at_column({})", n),
+        format!("column {}", n),
+        move |column, _| column == n,
+    )
+}
+
+/// Matches nothing past column `n`, and fails at or before it.
+pub fn indented_more_than<'a>(n: usize) -> Parser<'a, ()> {
+    column_check(
+        format!("indented_more_than({})", n),
+        format!("column past {}", n),
+        move |column, _| column > n,
+    )
+}
+
+/// Matches nothing where the column is past the indentation of the innermost block, or
+/// the line `with_indent` was used on, and anywhere outside of them.
+///
+/// What it matches depends on the levels around it and not just the position, so it isn't
+/// memoized, nor are `dedent`, `block` and `with_indent`. Rules built on them are, and one
+/// called at the same position under different levels should be made `no_memo` as well.
+pub fn indent<'a>() -> Parser<'a, ()> {
+    column_check(
+        String::from("indent"),
+        String::from("deeper indentation"),
+        |column, level| level.is_none_or(|level| column > level),
+    )
+    .no_memo()
+}
+
+/// Matches nothing where the column is before the indentation of the innermost block, or
+/// the line `with_indent` was used on, and fails outside of them.
+pub fn dedent<'a>() -> Parser<'a, ()> {
+    column_check(
+        String::from("dedent"),
+        String::from("shallower indentation"),
+        |column, level| level.is_some_and(|level| column < level),
+    )
+    .no_memo()
+}
+
+impl<'a, T: Clone + 'static> Parser<'a, T> {
+    /// Parses with the indentation of the line this parser starts on as the innermost
+    /// level, which `indent` and `dedent` compare columns against, like the items of a
+    /// `block`; for layouts such as a definition continuing on lines indented deeper.
+    pub fn with_indent(self) -> Parser<'a, T> {
+        let name = self.name.clone();
+        let first = self.first.clone();
+        let shape = self.shape.clone();
+        let raw_parser = Rc::new(move |pos, ctx: &mut Context| {
+            let start = ctx.line_start(pos);
+            let level = indentation(ctx, start).0;
+            ctx.indents.push(level);
+            let result = self.parse(pos, ctx);
+            ctx.indents.pop();
+            result
+        });
+
+        Parser::new(name, raw_parser)
+            .with_first(first)
+            .with_shape(shape)
+            .no_memo()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    fn file<'a>() -> Parser<'a, Vec<String>> {
+        let name = satisfy("letter", char::is_alphabetic).many_collect::<String>();
+        let line = lazy("line", move |line| {
+            let nested = name
+                .clone()
+                .andl(keyword(":\n"))
+                .and(block(line))
+                .map(|(name, items)| format!("{}{{{}}}", name, items.join(" ")));
+            nested.or(name.clone().andl(char('\n')))
+        });
+        block(line).end()
+    }
+
+    #[test]
+    fn test_block() {
+        let text = "a\nif:\n  b\n  \n  if:\n\t\t\tc\n\n  if:\n      d\n  e\nf\n";
+        assert_eq!(file().run(text).unwrap(), ["a", "if{b if{c} if{d} e}", "f"]);
+        // the first block may start indented, after blank lines
+        assert_eq!(file().run("\n  a\n  b\n").unwrap(), ["a", "b"]);
+
+        let error = file().run("if:\nb\n").unwrap_err();
+        assert_eq!(error.pos(), 4);
+        assert_eq!(
+            error.to_string().lines().last(),
+            Some("expected an indented block")
+        );
+        let error = file().run("a\n  b\n").unwrap_err();
+        assert_eq!(error.pos(), 4);
+        assert_eq!(
+            error.to_string().lines().last(),
+            Some("unexpected indentation")
+        );
+        // a line indented between two levels doesn't belong to either
+        let error = file().run("if:\n    a\n  b\n").unwrap_err();
+        assert_eq!(error.pos(), 12);
+    }
+
+    #[test]
+    fn test_columns() {
+        let space = char(' ').many();
+        let ctx = &mut Context::new("ab\n  cd");
+        let col = column();
+        assert_eq!(col.parse(6, ctx), Ok((6, 3)));
+        assert_eq!(line_indent().parse(6, ctx), Ok((6, 2)));
+        assert_eq!(line_indent().parse(1, ctx), Ok((1, 0)));
+        assert!(at_column(2).parse(5, ctx).is_ok());
+        assert!(at_column(2).parse(6, ctx).is_err());
+        assert!(indented_more_than(1).parse(5, ctx).is_ok());
+        assert!(indented_more_than(2).parse(5, ctx).is_err());
+        // an edit earlier on the line moves what comes after it to another column
+        ctx.edit(3..3, "x");
+        assert_eq!(col.parse(7, ctx), Ok((7, 4)));
+
+        // what didn't look for its column is kept after an edit earlier on its line
+        let items = satisfy("digit", |c| c.is_ascii_digit()).sep_by(char(','));
+        let ctx = &mut Context::new("1,2,3,4,5,6,7,8");
+        assert!(items.parse(0, ctx).is_ok());
+        let misses = ctx.stats().misses;
+        ctx.edit(0..1, "9");
+        assert!(items.parse(0, ctx).is_ok());
+        assert!(ctx.stats().misses - misses < misses / 2);
+
+        // a definition continues on the lines indented deeper than its first
+        let word = satisfy("letter", char::is_alphabetic).many_collect::<String>();
+        let continuation = char('\n').and(space).andr(indent());
+        let body = word.clone().sep_by(char(' ').map(|_| ()).or(continuation));
+        let definition = word
+            .andl(keyword(" = "))
+            .and(body)
+            .andl(char('\n'))
+            .with_indent();
+        let file = definition.many().end();
+        let text = "f = a\n  b c\n g\ng = d\n";
+        let strings = |words: &[&str]| words.iter().map(|w| w.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            file.run(text).unwrap(),
+            [
+                (String::from("f"), strings(&["a", "b", "c", "g"])),
+                (String::from("g"), strings(&["d"])),
+            ]
+        );
+
+        // the same parser matches differently under different levels at one position
+        let ind = indent();
+        let start = char('a').and(char('\n')).and(char(' ').many());
+        let nested = start
+            .clone()
+            .andr(ind.clone().with_indent())
+            .andr(char('x'));
+        let flat = start.andr(ind).andr(char('b'));
+        assert!(nested.or(flat).run("a\n  b").is_ok());
+
+        // outside of any level everything is deeper and nothing shallower
+        let ctx = &mut Context::new("x");
+        assert!(indent().parse(0, ctx).is_ok());
+        assert!(dedent().parse(0, ctx).is_err());
+    }
+}
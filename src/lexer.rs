@@ -0,0 +1,221 @@
+use std::rc::Rc;
+
+use crate::input::found;
+use crate::{
+    Context, Input, ParseError, Parser, Pos, Reason, SharedText, Substr, Text, Tokens, elem,
+};
+
+/// A token a [`Lexer`] produced: the kind of the rule that matched and the text it matched.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Token {
+    kind: Rc<str>,
+    text: Substr,
+}
+
+impl Token {
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    pub fn text(&self) -> &Substr {
+        &self.text
+    }
+}
+
+impl std::fmt::Debug for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {:?}", self.kind, self.text.to_string())
+    }
+}
+
+// a rule is a token kind, or None for text that is skipped
+type Rule<'a> = (Option<Rc<str>>, Parser<'a, ()>);
+
+/// Splits text into [`Token`]s with parsers: at every position each rule is tried, and the
+/// one matching the most text wins, the first one registered on a tie. What skip rules
+/// match, like whitespace and comments, is dropped.
+///
+/// ```
+/// use packrust::*;
+///
+/// let digits = satisfy("digit", |c| c.is_ascii_digit()).many();
+/// let lexer = Lexer::builder()
+///     .token("int", digits)
+///     .token("plus", char('+'))
+///     .skip(char(' '))
+///     .build();
+///
+/// let int = token("int").map(|tok| tok.text().to_string().parse::<u32>().unwrap());
+/// let sum = token("plus").andr(int.clone()).foldl(int, |a, b| a + b).end();
+/// let ctx = &mut Context::from_source(lexer.lex("1 + 20 + 3").unwrap());
+/// assert_eq!(sum.parse(0, ctx), Ok((5, 24)));
+/// ```
+pub struct Lexer<'a> {
+    rules: Vec<Rule<'a>>,
+    // what an error says was expected where no rule matched
+    label: Text,
+}
+
+pub struct LexerBuilder<'a> {
+    rules: Vec<Rule<'a>>,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn builder() -> LexerBuilder<'a> {
+        LexerBuilder { rules: Vec::new() }
+    }
+
+    /// Splits `source` into tokens, failing where no rule matches anything.
+    pub fn lex(&self, source: impl Into<String>) -> Result<Tokens<Token>, ParseError> {
+        // one context for every rule, so a rule that others call is parsed once per position
+        let ctx = &mut Context::new(source);
+        let mut tokens = Vec::new();
+        let mut pos = 0;
+        while pos < ctx.source.len() {
+            let mut longest: Option<(Pos, &Option<Rc<str>>)> = None;
+            for (kind, parser) in &self.rules {
+                if let Ok((end, ())) = parser.parse(pos, ctx)
+                    && end > longest.map_or(pos, |(longest, _)| longest)
+                {
+                    longest = Some((end, kind));
+                }
+            }
+            let Some((end, kind)) = longest else {
+                return Err(ParseError {
+                    source: ctx.clone_source(),
+                    pos,
+                    reason: Reason::Expected {
+                        label: self.label.clone(),
+                        found: found(&ctx.source, pos),
+                    },
+                });
+            };
+            if let Some(kind) = kind {
+                let text = ctx.source.slice(pos..end);
+                tokens.push((
+                    Token {
+                        kind: kind.clone(),
+                        text,
+                    },
+                    pos..end,
+                ));
+            }
+            pos = end;
+        }
+
+        let text = ctx.source.text().unwrap_or_else(|| SharedText::from(""));
+        Ok(Tokens::with_text(tokens, text))
+    }
+}
+
+impl<'a> LexerBuilder<'a> {
+    /// Adds a rule producing tokens of `kind` from what `parser` matches.
+    pub fn token<T: Clone + 'static>(
+        mut self,
+        kind: impl Into<String>,
+        parser: Parser<'a, T>,
+    ) -> Self {
+        let kind: Rc<str> = Rc::from(kind.into());
+        self.rules.push((Some(kind), parser.map(|_| ())));
+        self
+    }
+
+    /// Adds a rule for text to step over without producing a token.
+    pub fn skip<T: Clone + 'static>(mut self, parser: Parser<'a, T>) -> Self {
+        self.rules.push((None, parser.map(|_| ())));
+        self
+    }
+
+    pub fn build(self) -> Lexer<'a> {
+        let kinds: Vec<&str> = self
+            .rules
+            .iter()
+            .filter_map(|(kind, _)| kind.as_deref())
+            .collect();
+        let label = match kinds.as_slice() {
+            [] => Text::from("end of input"),
+            _ => Text::from(kinds.join(" or ")),
+        };
+        Lexer {
+            rules: self.rules,
+            label,
+        }
+    }
+}
+
+/// Matches a token of `kind` from a [`Lexer`].
+pub fn token<'a>(kind: &str) -> Parser<'a, Token, Tokens<Token>> {
+    let kind: Rc<str> = Rc::from(kind);
+    elem(kind.to_string(), move |tok: &Token| tok.kind == kind)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    fn lexer<'a>() -> Lexer<'a> {
+        let ident = satisfy("letter", |c| c.is_ascii_alphabetic())
+            .and(satisfy("alphanumeric", |c| c.is_ascii_alphanumeric()).many());
+        let comment = keyword("//").and(satisfy("any", |c| c != '\n').many());
+        Lexer::builder()
+            .token("let", keyword("let"))
+            .token("ident", ident)
+            .token("int", satisfy("digit", |c| c.is_ascii_digit()).many())
+            .token("op", choice([keyword("=="), keyword("="), keyword("/")]))
+            .skip(satisfy("space", char::is_whitespace))
+            .skip(comment)
+            .build()
+    }
+
+    #[test]
+    fn test_lexer() {
+        let tokens = lexer()
+            .lex("let letter == 42 // the answer\n / x1")
+            .unwrap();
+        let lexed: Vec<(&str, String)> = tokens
+            .as_slice()
+            .iter()
+            .map(|tok| (tok.kind(), tok.text().to_string()))
+            .collect();
+        assert_eq!(
+            lexed,
+            [
+                ("let", "let"),
+                ("ident", "letter"),
+                ("op", "=="),
+                ("int", "42"),
+                ("op", "/"),
+                ("ident", "x1"),
+            ]
+            .map(|(kind, text)| (kind, text.to_string()))
+        );
+
+        let error = lexer().lex("let x = #").unwrap_err();
+        assert_eq!(error.pos(), 8);
+        assert_eq!(
+            error.to_string(),
+            "let x = #\n        ^\nexpected let or ident or int or op got #\n"
+        );
+    }
+
+    #[test]
+    fn test_lexer_and_parser() {
+        let binding = token("let")
+            .andr(token("ident"))
+            .andl(token("op"))
+            .and(token("int").or(token("ident")));
+
+        let ctx = &mut Context::from_source(lexer().lex("let a = 1").unwrap());
+        let (_, (name, value)) = binding.parse(0, ctx).unwrap();
+        assert_eq!(*name.text(), "a");
+        assert_eq!(*value.text(), "1");
+
+        // errors from either stage point into the text
+        let ctx = &mut Context::from_source(lexer().lex("let a = let").unwrap());
+        let error = binding.parse(0, ctx).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "let a = let\n        ^\nexpected int got let \"let\"\n"
+        );
+    }
+}
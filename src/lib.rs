@@ -1,34 +1,321 @@
+// the engine logs every call it makes, which is far too much for the hot path, so the
+// logging is only compiled in with the `trace` feature
+macro_rules! trace_log {
+    ($level:ident, $($arg:tt)*) => {
+        #[cfg(feature = "trace")]
+        log::$level!($($arg)*)
+    };
+}
+
+// lets the code `peg!` generates name this crate as `::packrust` in its own tests
+#[cfg(feature = "macros")]
+extern crate self as packrust;
+
+mod analysis;
+mod assert;
+mod bits;
+mod bytes;
 mod combinators;
 mod context;
+mod cst;
+mod dot;
+#[cfg(test)]
+mod fixture;
+#[cfg(feature = "rand")]
+mod generate;
+mod grammar;
+#[cfg(feature = "unicode-segmentation")]
+mod graphemes;
+mod input;
+mod layout;
+mod lexer;
+mod memo;
+mod numeric;
+mod observer;
+mod operator_table;
+mod parse;
+mod peg;
+mod peg_loader;
+mod pratt;
+mod profile;
+mod punctuated;
+mod query;
+mod railroad;
+mod reader;
+mod session;
+mod shape;
+#[cfg(feature = "threaded")]
+mod shared;
+mod source;
+mod source_db;
+mod source_map;
+mod spanned;
+mod state;
+#[cfg(feature = "proptest")]
+mod strategy;
+mod string_literal;
+mod tokens;
+mod trace;
+mod unicode;
+mod vm;
 
-use log::{debug, info, trace};
+use std::any::Any;
+use std::ops::Range;
 use std::rc::Rc;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
+pub use crate::analysis::{
+    LeftRecursion, LeftRecursionKind, NullableRepetition, UnreachableAlternative,
+};
+pub use crate::bits::{Bits, align, bits, bool_bit};
+pub use crate::bytes::{
+    Bytes, byte, length_prefixed, satisfy_byte, sleb128, take_bytes, u8, u16_be, u16_le, u32_be,
+    u32_le, u64_be, u64_le, uleb128,
+};
 pub use crate::combinators::*;
-pub use crate::context::Context;
+pub use crate::context::{Context, LeftRecursionPolicy, Stats};
+pub use crate::cst::Event;
+pub use crate::grammar::{Grammar, GrammarBuilder, GrammarError};
+#[cfg(feature = "unicode-segmentation")]
+pub use crate::graphemes::{Grapheme, Graphemes, grapheme};
+pub use crate::input::Input;
+pub use crate::layout::{
+    at_column, block, column, dedent, indent, indented_more_than, line_indent,
+};
+pub use crate::lexer::{Lexer, LexerBuilder, Token, token};
+pub use crate::memo::{EvictionPolicy, MemoEntry, MemoLayout, MemoState};
+pub use crate::numeric::{Float, Integer, float, integer, radix_integer};
+pub use crate::observer::ParseObserver;
+pub use crate::operator_table::OperatorTable;
+pub use crate::parse::Parse;
+pub use crate::peg_loader::{PegError, Tree, load_peg};
+pub use crate::pratt::{Assoc, Pratt, pratt};
+pub use crate::profile::{MemoComparison, ProfileReport, RuleComparison, RuleReport};
+pub use crate::punctuated::Punctuated;
+pub use crate::query::{ParseOutcome, parse_file};
+pub use crate::session::{Diagnostic, Session};
+#[cfg(feature = "threaded")]
+pub use crate::shared::SharedParser;
+pub use crate::source::{SharedText, Source, Substr};
+pub use crate::source_db::{SourceDb, SourceId, SourceSpan};
+pub use crate::source_map::SourceMap;
+pub use crate::spanned::Spanned;
+pub use crate::state::{guard, state};
+pub use crate::string_literal::{StringLiteralBuilder, string_literal};
+pub use crate::tokens::Tokens;
+pub use crate::trace::TraceWriter;
+#[cfg(feature = "unicode-ident")]
+pub use crate::unicode::identifier;
+#[cfg(feature = "unicode-categories")]
+pub use crate::unicode::{
+    GeneralCategory, category, letter, mark, number, punctuation, separator, symbol,
+};
+#[cfg(feature = "unicode-case")]
+pub use crate::unicode::{char_caseless, string_caseless};
+pub use crate::vm::Program;
+#[cfg(feature = "macros")]
+pub use packrust_macros::{Parser, peg, rule};
+
+// used by the code `#[derive(Parser)]` generates
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::parse::recursive;
+}
+
+use crate::memo::{Memo, Trail, grows};
+use crate::profile::Profile;
+use crate::shape::{RuleShape, Shape};
 
 type ParserId = usize;
 type Pos = usize;
 type CacheKey = (ParserId, Pos);
 
-#[derive(Debug, Clone, PartialEq)]
+// the text errors hold on to, atomically counted with the `threaded` feature so errors can
+// be sent back from the threads that produced them
+#[cfg(not(feature = "threaded"))]
+type Text = Rc<str>;
+#[cfg(feature = "threaded")]
+type Text = std::sync::Arc<str>;
+#[cfg(not(feature = "threaded"))]
+type Spans = Rc<[Range<usize>]>;
+#[cfg(feature = "threaded")]
+type Spans = std::sync::Arc<[Range<usize>]>;
+#[cfg(all(feature = "memmap2", not(feature = "threaded")))]
+type Mapping = Rc<memmap2::Mmap>;
+#[cfg(all(feature = "memmap2", feature = "threaded"))]
+type Mapping = std::sync::Arc<memmap2::Mmap>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ParseError {
-    source: String,
+    // None for inputs that aren't text
+    source: Option<ErrorSource>,
     pos: usize,
-    reason: String,
+    reason: Reason,
+}
+
+// the text an error points into, and for inputs like tokens whose positions count
+// something else, the span of text each position stands for; the id and name of the text
+// when it is one of the sources of a SourceDb, and whether a lone `\r` ends its lines
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ErrorSource {
+    text: SharedText,
+    spans: Option<Spans>,
+    origin: Option<(SourceId, Text)>,
+    universal_newlines: bool,
+}
+
+// kept unformatted until the error is displayed, so failing (which backtracking does
+// constantly) only bumps reference counts instead of allocating a message
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Reason {
+    Expected { label: Text, found: Found },
+    ExpectedEof { found: Found },
+    Message(&'static str),
+    LeftRecursion { rule: Text },
+    TooDeep { pos: usize },
+    BudgetExceeded,
+    Cancelled,
+    Incomplete,
+}
+
+impl std::fmt::Display for Reason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Reason::Expected { label, found } => write!(f, "expected {} got {}", label, found),
+            Reason::ExpectedEof { found } => write!(f, "expected EOF found {}", found),
+            Reason::Message(message) => write!(f, "{}", message),
+            Reason::LeftRecursion { rule } => write!(f, "rule `{}` is left-recursive", rule),
+            Reason::TooDeep { pos } => write!(f, "nesting too deep at pos {}", pos),
+            Reason::BudgetExceeded => write!(f, "budget exceeded"),
+            Reason::Cancelled => write!(f, "parse cancelled"),
+            Reason::Incomplete => write!(f, "incomplete input, more is needed"),
+        }
+    }
+}
+
+// what was in the input where an element was expected; other than chars, elements are
+// shown by their Debug representation
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Found {
+    Eof,
+    Char(char),
+    Byte(u8),
+    Bit(bool),
+    Elem(Text),
+}
+
+impl From<Option<char>> for Found {
+    fn from(c: Option<char>) -> Self {
+        c.map_or(Found::Eof, Found::Char)
+    }
+}
+
+impl std::fmt::Display for Found {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Found::Eof => write!(f, "EOF"),
+            Found::Char(c) => write!(f, "{}", c),
+            Found::Byte(b) => write!(f, "0x{:02x}", b),
+            Found::Bit(b) => write!(f, "{}", *b as u8),
+            Found::Elem(elem) => write!(f, "{}", elem),
+        }
+    }
+}
+
+impl ParseError {
+    /// The position in the input the error was reported at, a byte offset for text.
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    /// The byte offset into the source text the error points at. That is `pos` unless the
+    /// input was made of something else, like tokens, that knows where in the text it came
+    /// from; the end of the input is the end of its last element.
+    pub fn offset(&self) -> usize {
+        match self
+            .source
+            .as_ref()
+            .and_then(|source| source.spans.as_ref())
+        {
+            Some(spans) => spans
+                .get(self.pos)
+                .map(|span| span.start)
+                .or_else(|| spans.last().map(|span| span.end))
+                .unwrap_or(0),
+            None => self.pos,
+        }
+    }
+
+    /// The source in a [`SourceDb`] the error was found in, None for a context that wasn't
+    /// made by one.
+    pub fn source_id(&self) -> Option<SourceId> {
+        Some(self.source.as_ref()?.origin.as_ref()?.0)
+    }
+
+    /// The name the source the error was found in was added to a [`SourceDb`] under.
+    pub fn source_name(&self) -> Option<&str> {
+        Some(&self.source.as_ref()?.origin.as_ref()?.1)
+    }
+
+    /// Where in which source of a [`SourceDb`] the error points.
+    pub fn source_span(&self) -> Option<SourceSpan> {
+        let offset = self.offset();
+        Some(SourceSpan {
+            source: self.source_id()?,
+            range: offset..offset,
+        })
+    }
+
+    /// Whether the parse was stopped by a cancellation flag or deadline set on the context,
+    /// rather than failing on the input.
+    pub fn is_cancelled(&self) -> bool {
+        self.reason == Reason::Cancelled
+    }
+
+    /// Whether a streamed input ran out before the parse could be decided, so it should be
+    /// tried again once more of it has been fed to the context.
+    pub fn is_incomplete(&self) -> bool {
+        self.reason == Reason::Incomplete
+    }
 }
 
 impl std::fmt::Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "{}", self.source)?;
-        writeln!(f, "{}^", " ".repeat(self.pos))?;
+        let Some(ErrorSource {
+            text,
+            origin,
+            universal_newlines,
+            ..
+        }) = &self.source
+        else {
+            writeln!(f, "at {}", self.pos)?;
+            return writeln!(f, "{}", self.reason);
+        };
+        let offset = self.offset();
+        if let Some((_, name)) = origin {
+            let map = SourceMap::from_text(text.clone(), *universal_newlines);
+            let (line, col) = map.line_col(offset);
+            writeln!(f, "{}:{}:{}", name, line + 1, col + 1)?;
+        }
+        writeln!(f, "{}", text)?;
+        let column = text.get(..offset).map_or(offset, |s| s.chars().count());
+        writeln!(f, "{}^", " ".repeat(column))?;
         writeln!(f, "{}", self.reason)
     }
 }
 
 type ParseResult<T> = Result<(Pos, T), ParseError>;
-type RawParser<T> = Rc<dyn Fn(Pos, &mut Context) -> ParseResult<T>>;
+
+/// What `run_partial` hands back when it doesn't parse the whole input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartialParse<T> {
+    /// Where the parser stopped and what it produced up to there; None when it failed
+    /// outright, as then there is no value for any part of the input.
+    pub prefix: Option<(Pos, T)>,
+    pub error: ParseError,
+}
+
+type RawParser<'a, T, I = Source> = Rc<dyn Fn(Pos, &mut Context<I>) -> ParseResult<T> + 'a>;
 
 #[derive(Debug, Clone)]
 enum CacheEntry<T> {
@@ -36,103 +323,397 @@ enum CacheEntry<T> {
     Result(ParseResult<T>),
 }
 
-#[derive(Clone)]
-pub struct Parser<T> {
+/// A parser producing a `T` from an input of type `I`, text unless said otherwise.
+///
+/// Outputs are `Clone`: a parser hands back its output by value, so reusing a memoized
+/// result clones it. An output that is large or can't be cloned, like an AST, is produced
+/// behind an `Rc` with [`Parser::map_shared`], which makes each memo hit a pointer clone.
+pub struct Parser<'a, T, I = Source> {
     name: String,
-    id: ParserId,
-    raw_parser: RawParser<T>,
+    raw_parser: RawParser<'a, T, I>,
+    memo: Rc<Memo<T>>,
+    memoize: bool,
+    dense_memo: bool,
+    // chars a successful parse can start with, only known for parsers that always consume
+    first: Option<Rc<[char]>>,
+    shape: Rc<Shape<'a>>,
+}
+
+// derived, it would only clone parsers over inputs that are Clone themselves
+impl<T, I> Clone for Parser<'_, T, I> {
+    fn clone(&self) -> Self {
+        Parser {
+            name: self.name.clone(),
+            raw_parser: self.raw_parser.clone(),
+            memo: self.memo.clone(),
+            memoize: self.memoize,
+            dense_memo: self.dense_memo,
+            first: self.first.clone(),
+            shape: self.shape.clone(),
+        }
+    }
+}
+
+impl<T, I> Parser<'_, T, I> {
+    // a parser is identified by its memo, which clones and renamed copies share, so there
+    // is no process-wide counter making identities depend on construction order
+    pub(crate) fn id(&self) -> ParserId {
+        Rc::as_ptr(&self.memo).addr()
+    }
 }
 
-impl<T> Parser<T>
+impl<'a, T, I> Parser<'a, T, I>
 where
     T: Clone + 'static,
+    I: Input,
 {
-    fn new(name: String, raw_parser: RawParser<T>) -> Parser<T> {
-        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
-
+    fn new(name: String, raw_parser: RawParser<'a, T, I>) -> Parser<'a, T, I> {
+        let shape = {
+            let raw_parser = raw_parser.clone();
+            // shapes are only run by compiled programs, which parse text, so a parser over
+            // any other input is never asked to recognize anything through its shape
+            Rc::new(Shape::Opaque(
+                Rc::from(name.as_str()),
+                Rc::new(move |pos, ctx: &mut Context| {
+                    let ctx = (ctx as &mut dyn Any)
+                        .downcast_mut::<Context<I>>()
+                        .expect("only parsers over text are compiled");
+                    raw_parser(pos, ctx).map(|(end, _)| end)
+                }),
+            ))
+        };
         Parser {
             name,
-            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
             raw_parser,
+            memo: Rc::default(),
+            memoize: true,
+            dense_memo: false,
+            first: None,
+            shape,
+        }
+    }
+
+    pub(crate) fn with_first(self, first: Option<Rc<[char]>>) -> Parser<'a, T, I> {
+        Parser { first, ..self }
+    }
+
+    pub(crate) fn with_shape(self, shape: Rc<Shape<'a>>) -> Parser<'a, T, I> {
+        Parser { shape, ..self }
+    }
+
+    // the rule this parser is, or a new rule named after it for parsers that aren't rules,
+    // so whole-grammar tools can start from any parser
+    pub(crate) fn as_rule(&self) -> Rc<RuleShape<'a>> {
+        match self.shape.as_ref() {
+            Shape::Rule(rule) => rule.clone(),
+            _ => {
+                let rule = RuleShape::new(&self.name);
+                let _ = rule.body.set(self.shape.clone());
+                rule
+            }
         }
     }
 
-    pub fn parse(&self, pos: Pos, ctx: &mut Context) -> ParseResult<T> {
-        trace!("called {} at {}", self.name, pos);
-        let key = (self.id, pos);
+    pub fn parse(&self, pos: Pos, ctx: &mut Context<I>) -> ParseResult<T> {
+        // one span per call, so subscribers can filter by rule and see calls nest
+        #[cfg(feature = "tracing")]
+        let span = tracing::trace_span!(
+            "parse",
+            rule = %self.name,
+            pos,
+            matched = tracing::field::Empty,
+            end = tracing::field::Empty,
+        )
+        .entered();
 
-        if let Some(cached) = ctx.cache.get(&key) {
-            let entry = cached
-                .downcast_ref::<CacheEntry<T>>()
-                .cloned()
-                .expect("failed to cast Any to Entry<T>");
+        if let Some(error) = &ctx.fatal {
+            return Err(error.clone());
+        }
+        if ctx.depth_limit.is_some_and(|limit| ctx.depth >= limit) {
+            return Err(ctx.abort(pos, Reason::TooDeep { pos }));
+        }
+        if ctx
+            .interrupt
+            .as_mut()
+            .is_some_and(|interrupt| interrupt.is_due())
+        {
+            return Err(ctx.abort(pos, Reason::Cancelled));
+        }
+        if let Some(fuel) = ctx.fuel.as_mut() {
+            if *fuel == 0 {
+                return Err(ctx.abort(pos, Reason::BudgetExceeded));
+            }
+            *fuel -= 1;
+        }
+        if ctx.depth == 0 {
+            ctx.clear_events();
+            ctx.clear_state_log();
+        }
+        let events_mark = ctx.events_mark();
+        let state_mark = ctx.state_mark();
+        ctx.depth += 1;
+        ctx.observe(|observer| observer.on_enter(&self.name, pos));
+        let mut result = self.parse_memoized(pos, ctx);
+        ctx.depth -= 1;
+        if result.is_err() {
+            ctx.truncate_events(events_mark);
+            ctx.rollback_state(state_mark);
+        }
+        // combinators that recover from failures, like `opt` or `or`, may have swallowed it
+        if let Some(error) = &ctx.fatal {
+            result = Err(error.clone());
+        } else if ctx.incomplete && ctx.depth == 0 {
+            // nested calls carry on as if the input ended here, only the result of the
+            // whole parse is held back
+            result = Err(ParseError {
+                source: ctx.clone_source(),
+                pos: ctx.source.len(),
+                reason: Reason::Incomplete,
+            });
+        }
+        ctx.observe(|observer| {
+            observer.on_exit(&self.name, pos, result.as_ref().map(|(end, _)| *end))
+        });
+
+        #[cfg(feature = "tracing")]
+        match &result {
+            Ok((end, _)) => span.record("matched", true).record("end", end),
+            Err(_) => span.record("matched", false),
+        };
+        result
+    }
+
+    fn parse_memoized(&self, pos: Pos, ctx: &mut Context<I>) -> ParseResult<T> {
+        trace_log!(trace, "called {} at {}", self.name, pos);
+        let key = (self.id(), pos);
+
+        if self.memoize
+            && ctx.memoize
+            && let Some((entry, trail)) = self.memo.get(&ctx.cache, pos)
+        {
+            ctx.reach = ctx.reach.max(trail.reach);
+            ctx.looked_behind |= trail.looked_behind;
+            ctx.replay(pos, trail.events);
             match entry {
                 CacheEntry::LeftRecursion => {
-                    info!("left recursion detected: {} at {}", self.name, pos);
-                    ctx.lr_stack.push(key);
-                    ctx.schedule_cache_eviction(key);
-
-                    return Err(ParseError {
-                        source: ctx.clone_source(),
+                    trace_log!(info, "left recursion detected: {} at {}", self.name, pos);
+                    // a rule with several left-recursive alternatives hits its own
+                    // placeholder more than once before the seed is known
+                    if ctx.left_recursion == LeftRecursionPolicy::Grow
+                        && !ctx.lr_stack.contains(&key)
+                    {
+                        ctx.lr_stack.push(key);
+                    }
+                    return Err(self.left_recursion_error(
                         pos,
-                        reason: String::from("failed to resolve left recursion"),
-                    });
+                        ctx,
+                        "failed to resolve left recursion",
+                    ));
                 }
                 CacheEntry::Result(res) => {
+                    ctx.stats.hits += 1;
+                    if let Some(profile) = ctx.profile.as_mut() {
+                        profile.record_hit(&self.name);
+                    }
+                    ctx.observe(|observer| observer.on_memo_hit(&self.name, pos));
                     return res;
                 }
             }
         }
 
-        ctx.cache
-            .insert(key, Box::new(CacheEntry::<T>::LeftRecursion));
+        if self.memoize && ctx.memoize {
+            ctx.stats.misses += 1;
+        }
+        let (state_changes, state_reads) = (ctx.state_changes, ctx.state_reads);
+        let started = ctx
+            .profile
+            .as_mut()
+            .map(|profile| profile.enter(&self.name));
+        let result = if !self.memoize {
+            (self.raw_parser)(pos, ctx)
+        } else if !ctx.memoize {
+            self.parse_plain(key, ctx)
+        } else {
+            self.parse_uncached(key, ctx)
+        };
+        if let (Some(profile), Some(started)) = (ctx.profile.as_mut(), started) {
+            profile.exit(&self.name, started);
+        }
+
+        // a call that changed the state has to make that change every time it is called, and
+        // one that read it may match differently once the state is different
+        if self.memoize
+            && ctx.memoize
+            && (ctx.state_changes != state_changes || ctx.state_reads != state_reads)
+        {
+            ctx.cache.remove(&key);
+        }
+        result
+    }
+
+    // without a memo table there is no seed to grow, so re-entering a rule at the position
+    // it started from ends the parse, unless left recursion is to fail like any mismatch;
+    // otherwise `or` would quietly take another alternative
+    fn parse_plain(&self, key: CacheKey, ctx: &mut Context<I>) -> ParseResult<T> {
+        let (_, pos) = key;
+        if ctx.is_in_progress(key) {
+            if ctx.left_recursion == LeftRecursionPolicy::Fail {
+                return Err(self.left_recursion_error(
+                    pos,
+                    ctx,
+                    "left recursion requires memoization",
+                ));
+            }
+            let rule = Text::from(self.name.as_str());
+            return Err(ctx.abort(pos, Reason::LeftRecursion { rule }));
+        }
+
         ctx.push_call_path(key);
+        let result = (self.raw_parser)(pos, ctx);
+        ctx.pop_call_path(key);
+        result
+    }
+
+    // the failure a left-recursive call returns, which with `LeftRecursionPolicy::Error`
+    // also ends the parse
+    fn left_recursion_error(
+        &self,
+        pos: Pos,
+        ctx: &mut Context<I>,
+        unresolved: &'static str,
+    ) -> ParseError {
+        let reason = match ctx.left_recursion {
+            LeftRecursionPolicy::Grow => Reason::Message(unresolved),
+            LeftRecursionPolicy::Error => Reason::LeftRecursion {
+                rule: Text::from(self.name.as_str()),
+            },
+            LeftRecursionPolicy::Fail => Reason::Message("left recursion is treated as a failure"),
+        };
+        if ctx.left_recursion == LeftRecursionPolicy::Error {
+            return ctx.abort(pos, reason);
+        }
+        ParseError {
+            source: ctx.clone_source(),
+            pos,
+            reason,
+        }
+    }
+
+    fn parse_uncached(&self, key: CacheKey, ctx: &mut Context<I>) -> ParseResult<T> {
+        let (_, pos) = key;
+
+        ctx.advance_frontier(pos);
+        // how far this call looks is tracked on its own, then added to its caller's
+        let outer_reach = std::mem::replace(&mut ctx.reach, pos);
+        let outer_looked_behind = std::mem::take(&mut ctx.looked_behind);
+        let events_mark = ctx.events_mark();
+
+        self.memo.insert(
+            &mut ctx.cache,
+            &self.name,
+            key,
+            CacheEntry::LeftRecursion,
+            Trail {
+                reach: pos,
+                looked_behind: false,
+                events: None,
+            },
+            self.dense_memo,
+        );
+        ctx.push_call_path(key);
+        let mark = ctx.completed_since();
 
         let mut result = (self.raw_parser)(pos, ctx);
+        let mut events = ctx.take_events(events_mark, pos, result.is_ok());
 
-        debug!("cache insertion: {} at {}", self.name, pos);
-        ctx.cache
-            .insert(key, Box::new(CacheEntry::Result(result.clone())));
+        trace_log!(debug, "cache insertion: {} at {}", self.name, pos);
+        let trail = ctx.trail(&events);
+        self.memo.insert(
+            &mut ctx.cache,
+            &self.name,
+            key,
+            CacheEntry::Result(result.clone()),
+            trail,
+            self.dense_memo,
+        );
+        ctx.enforce_memo_limit();
 
-        if let Some(nearest_lr_key) = ctx.lr_stack.last()
-            && *nearest_lr_key == key
-        {
-            info!("start left recursion expansion: {} at {}", self.name, pos);
-            let mut best_res @ Ok((mut best_pos, _)) = result else {
-                let popped = ctx.lr_stack.pop();
-                debug_assert_eq!(popped, Some(key));
-                ctx.pop_call_path(key);
-                return result;
-            };
-
-            loop {
-                ctx.execute_cache_eviction(key);
-
-                if let new_res @ Ok((new_pos, _)) = (self.raw_parser)(pos, ctx)
-                    && (best_pos < new_pos || (best_pos == new_pos && best_res.is_err()))
-                {
-                    best_pos = new_pos;
-                    best_res = new_res.clone();
-                    info!("cache update: {} at {}", self.name, pos);
-                    ctx.cache
-                        .insert(key, Box::new(CacheEntry::Result(best_res.clone())));
-                } else {
-                    break;
+        // the rule re-entered itself, possibly through others that consumed nothing, so its
+        // result is only a seed; every call completed since it started ran at this position
+        // and may have seen the previous seed, so all of them are forgotten before each
+        // attempt to grow it, not just the callers that were on the path back to it
+        if ctx.lr_stack.contains(&key) {
+            trace_log!(
+                info,
+                "start left recursion expansion: {} at {}",
+                self.name,
+                pos
+            );
+            if let Ok((mut best_pos, _)) = result {
+                loop {
+                    ctx.stats.lr_iterations += 1;
+                    ctx.forget_completed(mark);
+                    let state_mark = ctx.state_mark();
+
+                    let new_res = (self.raw_parser)(pos, ctx);
+                    let grown = grows(best_pos, new_res.as_ref().ok().map(|&(end, _)| end));
+                    let new_events = ctx.take_events(events_mark, pos, grown);
+                    // an attempt that isn't kept mustn't leave its changes to the state
+                    if !grown {
+                        ctx.rollback_state(state_mark);
+                    }
+                    match new_res {
+                        Ok((new_pos, _)) if grown => {
+                            best_pos = new_pos;
+                            result = new_res;
+                            events = new_events;
+                            ctx.observe(|observer| observer.on_lr_grow(&self.name, pos, new_pos));
+                            trace_log!(info, "cache update: {} at {}", self.name, pos);
+                            let trail = ctx.trail(&events);
+                            self.memo.insert(
+                                &mut ctx.cache,
+                                &self.name,
+                                key,
+                                CacheEntry::Result(result.clone()),
+                                trail,
+                                self.dense_memo,
+                            );
+                        }
+                        _ => break,
+                    }
                 }
             }
 
-            info!("cache fix: {} at {}", self.name, pos);
-
-            ctx.clear_cache_eviction_schedule(key);
-            let popped = ctx.lr_stack.pop();
-            debug_assert_eq!(popped, Some(key));
-            result = best_res
+            trace_log!(info, "cache fix: {} at {}", self.name, pos);
+            // the attempt that stopped the growth looked further than the seed it kept
+            let trail = ctx.trail(&events);
+            self.memo.insert(
+                &mut ctx.cache,
+                &self.name,
+                key,
+                CacheEntry::Result(result.clone()),
+                trail,
+                self.dense_memo,
+            );
+            ctx.lr_stack.retain(|&head| head != key);
+            // what was computed from the final seed is only right while this call is still
+            // in progress, called afresh those rules would grow seeds of their own
+            ctx.forget_completed(mark);
         }
 
         ctx.pop_call_path(key);
+        ctx.complete(key);
+        ctx.reach = ctx.reach.max(outer_reach);
+        ctx.looked_behind |= outer_looked_behind;
+        ctx.replay(pos, events);
         result
     }
+}
 
+impl<'a, T> Parser<'a, T>
+where
+    T: Clone + 'static,
+{
     pub fn run(&self, source: impl Into<String>) -> Result<T, ParseError> {
         let ctx = &mut Context::new(source);
         match self.parse(0, ctx) {
@@ -140,4 +721,144 @@ where
             Err(e) => Err(e),
         }
     }
+
+    /// Like `run`, but parses in `ctx`, reset to `source` first, so its allocations and
+    /// options carry over from one input to the next.
+    pub fn run_with(&self, ctx: &mut Context, source: impl Into<String>) -> Result<T, ParseError> {
+        ctx.reset(source);
+        self.parse(0, ctx).map(|(_, val)| val)
+    }
+
+    /// Like `run`, but a parser that stops short of the end of the input still hands back
+    /// the value it produced for the leading portion along with the error. If it fails
+    /// outright there is no such value, and only the error comes back; to keep what was
+    /// parsed before an error, parse a sequence of items, like with `many`, which stops at
+    /// the first item that doesn't match.
+    pub fn run_partial(&self, source: impl Into<String>) -> Result<T, PartialParse<T>> {
+        let ctx = &mut Context::new(source);
+        match self.parse(0, ctx) {
+            Ok((pos, val)) if pos == ctx.source.len() => Ok(val),
+            Ok((pos, val)) => Err(PartialParse {
+                prefix: Some((pos, val)),
+                error: ParseError {
+                    source: ctx.clone_source(),
+                    pos,
+                    reason: Reason::ExpectedEof {
+                        found: Found::from(ctx.source.get(pos)),
+                    },
+                },
+            }),
+            Err(error) => Err(PartialParse {
+                prefix: None,
+                error,
+            }),
+        }
+    }
+
+    /// Parses `source` once with memoization and once without, reporting per rule how many
+    /// calls the memo table saved and how the time spent in each rule changed. Without
+    /// memoization left recursion can't be resolved, so for a left-recursive grammar the
+    /// second parse stops at the first left-recursive call.
+    pub fn compare_memo(&self, source: impl Into<String>) -> MemoComparison {
+        let source = source.into();
+        let (memo, memo_time) = self.profiled(&source, true);
+        let (plain, plain_time) = self.profiled(&source, false);
+        MemoComparison::new(memo, memo_time, plain, plain_time)
+    }
+
+    fn profiled(&self, source: &str, memoize: bool) -> (Profile, Duration) {
+        let ctx = &mut Context::new(source);
+        ctx.memoize = memoize;
+        ctx.profile = Some(Profile::default());
+
+        let started = Instant::now();
+        let _ = self.parse(0, ctx);
+        let elapsed = started.elapsed();
+
+        (ctx.profile.take().unwrap_or_default(), elapsed)
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod test {
+    use crate::*;
+    use std::sync::Mutex;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    #[derive(Debug, Default, PartialEq)]
+    struct SpanFields {
+        rule: String,
+        matched: Option<bool>,
+    }
+
+    impl Visit for SpanFields {
+        fn record_bool(&mut self, field: &Field, value: bool) {
+            if field.name() == "matched" {
+                self.matched = Some(value);
+            }
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            if field.name() == "rule" {
+                self.rule = format!("{:?}", value);
+            }
+        }
+    }
+
+    // the fields of every span, indexed by span id
+    #[derive(Default)]
+    struct Spans(Mutex<Vec<SpanFields>>);
+
+    impl Subscriber for &'static Spans {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut spans = self.0.lock().unwrap();
+            let mut fields = SpanFields::default();
+            span.record(&mut fields);
+            spans.push(fields);
+            Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, span: &Id, values: &Record<'_>) {
+            let mut spans = self.0.lock().unwrap();
+            values.record(&mut spans[span.into_u64() as usize - 1]);
+        }
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, _: &Event<'_>) {}
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn test_spans() {
+        let spans: &'static Spans = Box::leak(Box::default());
+        let p = satisfy("a", |c| c == 'a').or(satisfy("b", |c| c == 'b'));
+
+        tracing::subscriber::with_default(spans, || {
+            let ctx = &mut Context::new("b");
+            assert_eq!(p.parse(0, ctx), Ok((1, 'b')));
+        });
+
+        let spans: Vec<_> = spans
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|span| (span.rule.clone(), span.matched))
+            .collect();
+        assert_eq!(
+            spans,
+            [
+                (String::from("(a/b)"), Some(true)),
+                (String::from("a"), Some(false)),
+                (String::from("b"), Some(true)),
+            ]
+        );
+    }
 }
@@ -0,0 +1,920 @@
+use rustc_hash::{FxBuildHasher, FxHashMap};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
+use std::rc::Rc;
+
+use crate::cst::Recorded;
+use crate::{CacheEntry, CacheKey, ErrorSource, ParserId, Pos};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MemoLayout {
+    // one hash map per rule keyed by position, only pays for entries that exist
+    #[default]
+    Sparse,
+    // a column per rule indexed by position, no hashing of positions but every rule that
+    // gets memoized allocates a slot for each position of the input
+    Dense,
+}
+
+// where a memoized call stands in the left recursion algorithm
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoState {
+    // a placeholder marking the call as running, hitting it means left recursion
+    Initial,
+    // a left-recursive seed that is still being grown
+    InProgress,
+    Fixed,
+}
+
+// a memo entry as seen from outside, `end` is None for failures and placeholders
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoEntry {
+    pub rule: String,
+    pub pos: usize,
+    pub state: MemoState,
+    pub end: Option<usize>,
+}
+
+// which entries go first once a context's memo table reaches its size limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    // the entries stored or looked up the longest time ago
+    #[default]
+    Lru,
+    // the entries for the positions furthest behind the furthest position reached
+    FarthestFromFrontier,
+}
+
+type ContextId = usize;
+
+// hashes memo positions with whatever BuildHasher the context was given; the hash is
+// computed once per lookup and carried in the key, so the table itself never needs to
+// know the hasher's type (and the default stays a direct call)
+#[derive(Clone, Default)]
+pub(crate) enum PosHasher {
+    #[default]
+    Fx,
+    Custom(Rc<dyn Fn(Pos) -> u64>),
+}
+
+impl PosHasher {
+    pub(crate) fn new<S: BuildHasher + 'static>(hasher: S) -> Self {
+        PosHasher::Custom(Rc::new(move |pos| hasher.hash_one(pos)))
+    }
+
+    fn key(&self, pos: Pos) -> HashedPos {
+        let hash = match self {
+            PosHasher::Fx => FxBuildHasher.hash_one(pos),
+            PosHasher::Custom(hash) => hash(pos),
+        };
+        HashedPos { pos, hash }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct HashedPos {
+    pos: Pos,
+    hash: u64,
+}
+
+impl PartialEq for HashedPos {
+    fn eq(&self, other: &Self) -> bool {
+        self.pos == other.pos
+    }
+}
+
+impl Eq for HashedPos {}
+
+impl Hash for HashedPos {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.hash);
+    }
+}
+
+// hands the precomputed hash of a HashedPos straight to the map
+#[derive(Default)]
+struct Prehashed(u64);
+
+impl Hasher for Prehashed {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = self.0.rotate_left(8) ^ u64::from(byte);
+        }
+    }
+
+    fn write_u64(&mut self, hash: u64) {
+        self.0 = hash;
+    }
+}
+
+// how the tables a context hands out are set up, kept when the source is replaced
+#[derive(Clone, Default)]
+pub(crate) struct MemoOptions {
+    pub(crate) layout: MemoLayout,
+    // entries each sparse table is sized for up front
+    pub(crate) capacity: usize,
+    pub(crate) hasher: PosHasher,
+    // how far behind the frontier entries are kept, unbounded when None
+    pub(crate) window: Option<usize>,
+    pub(crate) limit: Option<(usize, EvictionPolicy)>,
+}
+
+// the results of one parser, owned by the parser so that lookups stay typed; there is a
+// table per context the parser is being run in, which is nearly always just one, but an
+// action may well start a nested parse with the same grammar
+pub(crate) struct Memo<T> {
+    tables: RefCell<Vec<(ContextId, Table<T>)>>,
+}
+
+struct Slot<T> {
+    // the output as the parser produced it, cloned on every hit; outputs that are costly to
+    // clone are shared with `map_shared` rather than by the table
+    entry: CacheEntry<T>,
+    // tick of the context's clock when the entry was last stored or read
+    used: Cell<u64>,
+    trail: Trail,
+}
+
+// what a call leaves behind besides its result
+#[derive(Clone)]
+pub(crate) struct Trail {
+    // the end of the input the call looked at, past the end of the input if it looked for
+    // more, so an edit knows which results it can't have changed
+    pub(crate) reach: Pos,
+    // whether it looked back to the start of its line for a column
+    pub(crate) looked_behind: bool,
+    // the events it recorded, for a context that records them
+    pub(crate) events: Option<Rc<[Recorded]>>,
+}
+
+// memo keys in the order they were completed, so growing a seed can forget every result
+// that was computed from the previous one; only calls made at the position of a call still
+// in progress there are kept, nothing else can depend on a seed. Both the combinators and
+// the VM keep one, keyed by parser and by rule
+#[derive(Debug, Default)]
+pub(crate) struct Completed(Vec<CacheKey>);
+
+impl Completed {
+    pub(crate) fn mark(&self) -> usize {
+        self.0.len()
+    }
+
+    // `key` is done, and `caller_pos` is where the call that made it started, if any
+    pub(crate) fn complete(&mut self, key: CacheKey, caller_pos: Option<Pos>) {
+        let (_, pos) = key;
+        if caller_pos == Some(pos) {
+            self.0.push(key);
+        } else {
+            // no call is in progress at this position any more, and calls at later
+            // positions finished before this one did
+            let keep = self.0.iter().rposition(|&(_, p)| p < pos);
+            self.0.truncate(keep.map_or(0, |i| i + 1));
+        }
+    }
+
+    // the keys completed since `mark`, whose memo entries are to be removed
+    pub(crate) fn forget(&mut self, mark: usize) -> impl Iterator<Item = CacheKey> + '_ {
+        self.0.drain(mark..)
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+// whether an attempt to grow a left-recursive seed ending at `best` did, ending at `end`;
+// the seed stops growing at the first attempt that doesn't get further
+pub(crate) fn grows(best: Pos, end: Option<Pos>) -> bool {
+    end.is_some_and(|end| end > best)
+}
+
+// the text in `start..end` replaced by `len` bytes
+pub(crate) struct Edit {
+    pub(crate) start: Pos,
+    pub(crate) end: Pos,
+    pub(crate) len: usize,
+    // where the line the edit ends on ends, before it; what starts on the rest of that line
+    // is in another column after it, which results that looked for their column depend on
+    pub(crate) line_end: Pos,
+}
+
+impl Edit {
+    // where a position at or after the end of the edit moves to
+    fn shift(&self, pos: Pos) -> Pos {
+        pos - self.end + self.start + self.len
+    }
+
+    // the slot moved to where its text now is, or None if it looked at the edited text
+    fn relocate<T>(
+        &self,
+        pos: Pos,
+        slot: &mut Slot<T>,
+        source: &Option<ErrorSource>,
+    ) -> Option<Pos> {
+        let pos = if slot.trail.reach <= self.start {
+            pos
+        } else if pos >= self.end && !(slot.trail.looked_behind && pos <= self.line_end) {
+            slot.trail.reach = self.shift(slot.trail.reach);
+            match &mut slot.entry {
+                CacheEntry::Result(Ok((end, _))) => *end = self.shift(*end),
+                CacheEntry::Result(Err(error)) => error.pos = self.shift(error.pos),
+                CacheEntry::LeftRecursion => {}
+            }
+            self.shift(pos)
+        } else {
+            return None;
+        };
+        // errors show the text they were found in
+        if let CacheEntry::Result(Err(error)) = &mut slot.entry {
+            error.source = source.clone();
+        }
+        Some(pos)
+    }
+}
+
+enum Table<T> {
+    Sparse {
+        entries: HashMap<HashedPos, Slot<T>, BuildHasherDefault<Prehashed>>,
+        hasher: PosHasher,
+    },
+    // boxed so the column is a pointer per position however large the parser's output is;
+    // slots before `pruned` have been cleared by a cut, apart from those in `kept` which
+    // were still in progress at the time
+    Dense {
+        column: Vec<Option<Box<Slot<T>>>>,
+        pruned: Pos,
+        kept: Vec<Pos>,
+    },
+}
+
+impl<T> Table<T> {
+    fn get(&self, pos: Pos) -> Option<&Slot<T>> {
+        match self {
+            Table::Sparse { entries, hasher } => entries.get(&hasher.key(pos)),
+            Table::Dense { column, .. } => column.get(pos)?.as_deref(),
+        }
+    }
+
+    // whether the position had no entry before
+    fn insert(&mut self, pos: Pos, slot: Slot<T>) -> bool {
+        match self {
+            Table::Sparse { entries, hasher } => entries.insert(hasher.key(pos), slot).is_none(),
+            Table::Dense { column, .. } => {
+                if column.len() <= pos {
+                    column.resize_with(pos + 1, || None);
+                }
+                match &mut column[pos] {
+                    Some(old) => {
+                        **old = slot;
+                        false
+                    }
+                    empty => {
+                        *empty = Some(Box::new(slot));
+                        true
+                    }
+                }
+            }
+        }
+    }
+
+    fn remove(&mut self, pos: Pos) -> bool {
+        match self {
+            Table::Sparse { entries, hasher } => entries.remove(&hasher.key(pos)).is_some(),
+            Table::Dense { column, .. } => column
+                .get_mut(pos)
+                .is_some_and(|slot| slot.take().is_some()),
+        }
+    }
+
+    // both return how many entries were dropped
+    fn prune(&mut self, before: Pos, keep: &dyn Fn(Pos) -> bool) -> usize {
+        match self {
+            Table::Sparse { entries, .. } => {
+                let len = entries.len();
+                entries.retain(|key, _| key.pos >= before || keep(key.pos));
+                len - entries.len()
+            }
+            Table::Dense {
+                column,
+                pruned,
+                kept,
+            } => {
+                let end = before.min(column.len()).max(*pruned);
+                let candidates: Vec<Pos> = kept.drain(..).chain(*pruned..end).collect();
+                let mut removed = 0;
+                for pos in candidates {
+                    if pos >= before || keep(pos) {
+                        kept.push(pos);
+                    } else if column[pos].take().is_some() {
+                        removed += 1;
+                    }
+                }
+                *pruned = end;
+                removed
+            }
+        }
+    }
+
+    fn retain(&mut self, keep: &dyn Fn(Pos, u64) -> bool) -> usize {
+        match self {
+            Table::Sparse { entries, .. } => {
+                let len = entries.len();
+                entries.retain(|key, slot| keep(key.pos, slot.used.get()));
+                len - entries.len()
+            }
+            Table::Dense { column, .. } => {
+                let mut removed = 0;
+                for (pos, slot) in column.iter_mut().enumerate() {
+                    if slot
+                        .as_ref()
+                        .is_some_and(|slot| !keep(pos, slot.used.get()))
+                    {
+                        *slot = None;
+                        removed += 1;
+                    }
+                }
+                removed
+            }
+        }
+    }
+
+    // applies an edit to every entry, dropping those it may have changed; returns how many
+    fn relocate(&mut self, edit: &Edit, column_len: usize, source: &Option<ErrorSource>) -> usize {
+        match self {
+            Table::Sparse { entries, hasher } => {
+                let len = entries.len();
+                for (key, mut slot) in std::mem::take(entries) {
+                    if let Some(pos) = edit.relocate(key.pos, &mut slot, source) {
+                        entries.insert(hasher.key(pos), slot);
+                    }
+                }
+                len - entries.len()
+            }
+            Table::Dense {
+                column,
+                pruned,
+                kept,
+            } => {
+                let mut removed = 0;
+                let mut moved: Vec<Option<Box<Slot<T>>>> = (0..column_len).map(|_| None).collect();
+                for (pos, slot) in column.drain(..).enumerate() {
+                    let Some(mut slot) = slot else { continue };
+                    match edit.relocate(pos, &mut slot, source) {
+                        Some(pos) => moved[pos] = Some(slot),
+                        None => removed += 1,
+                    }
+                }
+                *column = moved;
+                *pruned = 0;
+                kept.clear();
+                removed
+            }
+        }
+    }
+
+    // empties the table for a source `column_len` positions long, keeping its allocation
+    fn clear(&mut self, column_len: usize) {
+        match self {
+            Table::Sparse { entries, .. } => entries.clear(),
+            Table::Dense {
+                column,
+                pruned,
+                kept,
+            } => {
+                column.clear();
+                column.resize_with(column_len, || None);
+                *pruned = 0;
+                kept.clear();
+            }
+        }
+    }
+
+    fn for_each(&self, f: &mut dyn FnMut(Pos, &Slot<T>)) {
+        match self {
+            Table::Sparse { entries, .. } => {
+                for (key, slot) in entries {
+                    f(key.pos, slot);
+                }
+            }
+            Table::Dense { column, .. } => {
+                for (pos, slot) in column.iter().enumerate() {
+                    if let Some(slot) = slot {
+                        f(pos, slot);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<T> Default for Memo<T> {
+    fn default() -> Self {
+        Memo {
+            tables: RefCell::new(Vec::new()),
+        }
+    }
+}
+
+impl<T: Clone + 'static> Memo<T> {
+    pub(crate) fn get(&self, cache: &MemoTable, pos: Pos) -> Option<(CacheEntry<T>, Trail)> {
+        let tables = self.tables.borrow();
+        let (_, table) = tables.iter().find(|(id, _)| *id == cache.id())?;
+        let slot = table.get(pos)?;
+        slot.used.set(cache.tick());
+        Some((slot.entry.clone(), slot.trail.clone()))
+    }
+
+    pub(crate) fn insert(
+        self: &Rc<Self>,
+        cache: &mut MemoTable,
+        name: &str,
+        key: CacheKey,
+        entry: CacheEntry<T>,
+        trail: Trail,
+        dense: bool,
+    ) {
+        let (parser, pos) = key;
+        let mut tables = self.tables.borrow_mut();
+        let table = match tables.iter().position(|(id, _)| *id == cache.id()) {
+            Some(i) => &mut tables[i].1,
+            None => {
+                // the context hands the table back when it is dropped or its source changes
+                cache.memos.insert(parser, self.clone());
+                cache.names.insert(parser, name.to_string());
+                let options = &cache.options;
+                let table = if dense || options.layout == MemoLayout::Dense {
+                    Table::Dense {
+                        column: (0..cache.column_len).map(|_| None).collect(),
+                        pruned: 0,
+                        kept: Vec::new(),
+                    }
+                } else {
+                    Table::Sparse {
+                        entries: HashMap::with_capacity_and_hasher(
+                            options.capacity,
+                            Default::default(),
+                        ),
+                        hasher: options.hasher.clone(),
+                    }
+                };
+                tables.push((cache.id(), table));
+                &mut tables.last_mut().unwrap().1
+            }
+        };
+        let slot = Slot {
+            entry,
+            used: Cell::new(cache.tick()),
+            trail,
+        };
+        if table.insert(pos, slot) {
+            cache.len += 1;
+            cache.peak_len = cache.peak_len.max(cache.len);
+        }
+    }
+}
+
+// what the context needs from the memo of a parser whose output type it does not know;
+// everything that drops entries reports how many went
+trait ErasedMemo {
+    fn remove(&self, context: ContextId, pos: Pos) -> bool;
+    fn prune(&self, context: ContextId, before: Pos, keep: &dyn Fn(Pos) -> bool) -> usize;
+    fn retain(&self, context: ContextId, keep: &dyn Fn(Pos, u64) -> bool) -> usize;
+    // the position, last use and end position (None for a placeholder, Some(None) for a
+    // failure) of every entry
+    fn for_each(&self, context: ContextId, f: &mut dyn FnMut(Pos, u64, Option<Option<Pos>>));
+    fn relocate(
+        &self,
+        context: ContextId,
+        edit: &Edit,
+        column_len: usize,
+        source: &Option<ErrorSource>,
+    ) -> usize;
+    fn clear(&self, context: ContextId, column_len: usize);
+    fn release(&self, context: ContextId);
+}
+
+impl<T> Memo<T> {
+    fn with_table<R>(&self, context: ContextId, f: impl FnOnce(&mut Table<T>) -> R) -> Option<R> {
+        let mut tables = self.tables.borrow_mut();
+        let (_, table) = tables.iter_mut().find(|(id, _)| *id == context)?;
+        Some(f(table))
+    }
+}
+
+impl<T> ErasedMemo for Memo<T> {
+    fn remove(&self, context: ContextId, pos: Pos) -> bool {
+        self.with_table(context, |table| table.remove(pos))
+            .unwrap_or(false)
+    }
+
+    fn prune(&self, context: ContextId, before: Pos, keep: &dyn Fn(Pos) -> bool) -> usize {
+        self.with_table(context, |table| table.prune(before, keep))
+            .unwrap_or(0)
+    }
+
+    fn retain(&self, context: ContextId, keep: &dyn Fn(Pos, u64) -> bool) -> usize {
+        self.with_table(context, |table| table.retain(keep))
+            .unwrap_or(0)
+    }
+
+    fn for_each(&self, context: ContextId, f: &mut dyn FnMut(Pos, u64, Option<Option<Pos>>)) {
+        self.with_table(context, |table| {
+            table.for_each(&mut |pos, slot| {
+                let end = match &slot.entry {
+                    CacheEntry::LeftRecursion => None,
+                    CacheEntry::Result(result) => Some(result.as_ref().ok().map(|(end, _)| *end)),
+                };
+                f(pos, slot.used.get(), end)
+            })
+        });
+    }
+
+    fn relocate(
+        &self,
+        context: ContextId,
+        edit: &Edit,
+        column_len: usize,
+        source: &Option<ErrorSource>,
+    ) -> usize {
+        self.with_table(context, |table| table.relocate(edit, column_len, source))
+            .unwrap_or(0)
+    }
+
+    fn clear(&self, context: ContextId, column_len: usize) {
+        self.with_table(context, |table| table.clear(column_len));
+    }
+
+    fn release(&self, context: ContextId) {
+        self.tables.borrow_mut().retain(|(id, _)| *id != context);
+    }
+}
+
+// the memo state of one context: the parsers that hold a table for it, so entries can be
+// evicted by key and every table freed once the context is gone
+pub(crate) struct MemoTable {
+    // its address identifies the context to the tables it registered, and it lives until
+    // they are released
+    token: Rc<()>,
+    memos: FxHashMap<ParserId, Rc<dyn ErasedMemo>>,
+    names: FxHashMap<ParserId, String>,
+    column_len: usize,
+    options: MemoOptions,
+    len: usize,
+    peak_len: usize,
+    evictions: usize,
+    clock: Cell<u64>,
+    // furthest position a memoized rule has been tried at, and the position everything
+    // before which was last dropped for falling out of the window
+    frontier: Pos,
+    horizon: Pos,
+}
+
+impl MemoTable {
+    pub(crate) fn new(column_len: usize, options: MemoOptions) -> Self {
+        MemoTable {
+            token: Rc::new(()),
+            memos: FxHashMap::default(),
+            names: FxHashMap::default(),
+            column_len,
+            options,
+            len: 0,
+            peak_len: 0,
+            evictions: 0,
+            clock: Cell::new(0),
+            frontier: 0,
+            horizon: 0,
+        }
+    }
+
+    fn id(&self) -> ContextId {
+        Rc::as_ptr(&self.token).addr()
+    }
+
+    pub(crate) fn options(&self) -> &MemoOptions {
+        &self.options
+    }
+
+    fn tick(&self) -> u64 {
+        let now = self.clock.get() + 1;
+        self.clock.set(now);
+        now
+    }
+
+    pub(crate) fn remove(&mut self, key: &CacheKey) {
+        let (parser, pos) = *key;
+        if let Some(memo) = self.memos.get(&parser)
+            && memo.remove(self.id(), pos)
+        {
+            self.len -= 1;
+        }
+    }
+
+    // drops every entry before `before` except those of calls still in progress, which the
+    // left recursion machinery relies on
+    pub(crate) fn prune_before(&mut self, before: Pos, in_progress: &[CacheKey]) {
+        for (&parser, memo) in &self.memos {
+            let removed = memo.prune(self.id(), before, &|pos| {
+                in_progress.contains(&(parser, pos))
+            });
+            self.len -= removed;
+            self.evictions += removed;
+        }
+    }
+
+    // entries are dropped in steps of a whole window so that the tables are not swept on
+    // every advance, which keeps at most two windows' worth alive
+    pub(crate) fn advance(&mut self, pos: Pos, in_progress: &[CacheKey]) {
+        let Some(window) = self.options.window else {
+            return;
+        };
+        if pos <= self.frontier {
+            return;
+        }
+        self.frontier = pos;
+
+        let horizon = pos.saturating_sub(window);
+        if horizon >= self.horizon + window.max(1) {
+            self.prune_before(horizon, in_progress);
+            self.horizon = horizon;
+        }
+    }
+
+    // once over the limit, a quarter of it is evicted in one sweep rather than an entry per
+    // insertion, since finding the victims means looking at every entry
+    pub(crate) fn enforce_limit(&mut self, in_progress: &[CacheKey]) {
+        let Some((limit, policy)) = self.options.limit else {
+            return;
+        };
+        if self.len <= limit {
+            return;
+        }
+
+        let score = |pos: Pos, used: u64| match policy {
+            EvictionPolicy::Lru => used,
+            EvictionPolicy::FarthestFromFrontier => pos as u64,
+        };
+        let mut scores = Vec::with_capacity(self.len);
+        for memo in self.memos.values() {
+            memo.for_each(self.id(), &mut |pos, used, _| scores.push(score(pos, used)));
+        }
+        let excess = (self.len - (limit - limit / 4)).min(scores.len() - 1);
+        let (_, &mut cutoff, _) = scores.select_nth_unstable(excess);
+
+        for (&parser, memo) in &self.memos {
+            let removed = memo.retain(self.id(), &|pos, used| {
+                score(pos, used) >= cutoff || in_progress.contains(&(parser, pos))
+            });
+            self.len -= removed;
+            self.evictions += removed;
+        }
+    }
+
+    // `growing` are the rules whose left-recursive seed is still being grown
+    pub(crate) fn entries(&self, growing: &[CacheKey]) -> Vec<MemoEntry> {
+        let mut entries = Vec::with_capacity(self.len);
+        for (&parser, memo) in &self.memos {
+            memo.for_each(self.id(), &mut |pos, _, end| {
+                let state = match end {
+                    None => MemoState::Initial,
+                    Some(_) if growing.contains(&(parser, pos)) => MemoState::InProgress,
+                    Some(_) => MemoState::Fixed,
+                };
+                entries.push(MemoEntry {
+                    rule: self.names[&parser].clone(),
+                    pos,
+                    state,
+                    end: end.flatten(),
+                });
+            });
+        }
+        entries.sort_by(|a, b| a.pos.cmp(&b.pos).then_with(|| a.rule.cmp(&b.rule)));
+        entries
+    }
+
+    // drops every entry and starts over for a new source, while the parsers keep the
+    // tables they registered, so their allocations serve the next parse too
+    pub(crate) fn clear(&mut self, column_len: usize) {
+        for memo in self.memos.values() {
+            memo.clear(self.id(), column_len);
+        }
+        self.column_len = column_len;
+        self.len = 0;
+        self.peak_len = 0;
+        self.evictions = 0;
+        self.frontier = 0;
+        self.horizon = 0;
+    }
+
+    // carries the tables over an edit of the source, which is now `column_len` positions
+    // long; entries whose calls looked at the edited text are dropped
+    pub(crate) fn relocate(
+        &mut self,
+        edit: &Edit,
+        column_len: usize,
+        source: &Option<ErrorSource>,
+    ) {
+        for memo in self.memos.values() {
+            let removed = memo.relocate(self.id(), edit, column_len, source);
+            self.len -= removed;
+            self.evictions += removed;
+        }
+        self.column_len = column_len;
+        self.frontier = 0;
+        self.horizon = 0;
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn peak_len(&self) -> usize {
+        self.peak_len
+    }
+
+    pub(crate) fn evictions(&self) -> usize {
+        self.evictions
+    }
+}
+
+impl Drop for MemoTable {
+    fn drop(&mut self) {
+        for memo in self.memos.values() {
+            memo.release(self.id());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn test_dense_layout() {
+        let sum = fixture::sum();
+
+        let ctx = &mut Context::new("1+2+3").with_memo_layout(MemoLayout::Dense);
+        assert_eq!(sum.parse(0, ctx), Ok((5, 6)));
+        assert_eq!(sum.parse(2, ctx), Ok((5, 5)));
+        let ctx = &mut Context::new("").with_memo_layout(MemoLayout::Dense);
+        assert!(sum.parse(0, ctx).is_err());
+    }
+
+    #[test]
+    fn test_tables_per_context() {
+        let word = satisfy("letter", |c| c.is_alphabetic()).many_collect::<String>();
+
+        let first = &mut Context::new("abc");
+        let second = &mut Context::new("xy");
+        assert_eq!(word.parse(0, first), Ok((3, String::from("abc"))));
+        assert_eq!(word.parse(0, second), Ok((2, String::from("xy"))));
+        assert_eq!(word.parse(0, first), Ok((3, String::from("abc"))));
+        assert_eq!(word.memo.tables.borrow().len(), 2);
+
+        second.set_source(Source::new("uvw"));
+        assert_eq!(word.parse(0, second), Ok((3, String::from("uvw"))));
+        assert_eq!(word.memo.tables.borrow().len(), 2);
+    }
+
+    #[test]
+    fn test_reset() {
+        let word = satisfy("letter", |c| c.is_alphabetic()).many_collect::<String>();
+        for layout in [MemoLayout::Sparse, MemoLayout::Dense] {
+            let ctx = &mut Context::new("").with_memo_layout(layout);
+            for input in ["abc", "x", "", "hello", "a1"] {
+                let expected = input.trim_end_matches(char::is_numeric);
+                assert_eq!(word.run_with(ctx, input), Ok(String::from(expected)));
+                assert_eq!(ctx.stats().entries, ctx.memo_entries().len());
+            }
+            // the table registered for the first input served all of them
+            assert_eq!(word.memo.tables.borrow().len(), 1);
+            ctx.reset("xyz");
+            assert_eq!(ctx.stats(), Stats::default());
+            assert_eq!(word.parse(1, ctx), Ok((3, String::from("yz"))));
+        }
+    }
+
+    #[test]
+    fn test_tables_released_with_context() {
+        let word = satisfy("letter", |c| c.is_alphabetic()).many_collect::<String>();
+
+        {
+            let ctx = &mut Context::new("abc");
+            assert!(word.parse(0, ctx).is_ok());
+            assert_eq!(word.memo.tables.borrow().len(), 1);
+        }
+        assert!(word.memo.tables.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_capacity_and_hasher() {
+        let sum = fixture::sum();
+
+        let ctx = &mut Context::with_capacity("1+2+3", 16);
+        assert_eq!(sum.parse(0, ctx), Ok((5, 6)));
+        let ctx = &mut Context::new("1+2+3").with_hasher(std::hash::RandomState::new());
+        assert_eq!(sum.parse(0, ctx), Ok((5, 6)));
+        ctx.set_source(Source::new("4+5"));
+        assert_eq!(sum.parse(0, ctx), Ok((3, 9)));
+    }
+
+    #[test]
+    fn test_cut() {
+        let ident = satisfy("letter", |c| c.is_ascii_alphabetic()).many();
+        let stmt = ident.andl(char(';'));
+        let source = "ab;cd;ef;".repeat(20);
+
+        let ctx = &mut Context::new(source.as_str());
+        assert!(stmt.clone().many().end().parse(0, ctx).is_ok());
+        let uncut = ctx.cache.len();
+
+        for layout in [MemoLayout::Sparse, MemoLayout::Dense] {
+            let ctx = &mut Context::new(source.as_str()).with_memo_layout(layout);
+            assert_eq!(
+                stmt.clone()
+                    .cut()
+                    .many()
+                    .end()
+                    .parse(0, ctx)
+                    .map(|(_, v)| v.len()),
+                Ok(60)
+            );
+            assert!(ctx.cache.len() < 10, "{} entries left", ctx.cache.len());
+            assert!(uncut > 100);
+        }
+
+        let sum = fixture::sum();
+        let stmts = sum.andl(char(';')).cut().many();
+        let ctx = &mut Context::new("1+2;3+4+5;");
+        assert_eq!(stmts.parse(0, ctx), Ok((10, vec![3, 12])));
+    }
+
+    #[test]
+    fn test_memo_window() {
+        let ident = satisfy("letter", |c| c.is_ascii_alphabetic()).many();
+        let stmt = ident.andl(char(';'));
+        let stmts = stmt.many().end();
+        let source = "ab;cd;ef;".repeat(100);
+
+        for layout in [MemoLayout::Sparse, MemoLayout::Dense] {
+            let ctx = &mut Context::new(source.as_str())
+                .with_memo_layout(layout)
+                .with_memo_window(16);
+            assert_eq!(stmts.parse(0, ctx).map(|(_, v)| v.len()), Ok(300));
+            assert!(ctx.cache.len() < 100, "{} entries left", ctx.cache.len());
+        }
+    }
+
+    #[test]
+    fn test_memo_limit() {
+        let sum = fixture::sum();
+        let stmts = sum.andl(char(';')).many().end();
+        let source = "1+2;3+4+5;".repeat(50);
+
+        for layout in [MemoLayout::Sparse, MemoLayout::Dense] {
+            for policy in [EvictionPolicy::Lru, EvictionPolicy::FarthestFromFrontier] {
+                let ctx = &mut Context::new(source.as_str())
+                    .with_memo_layout(layout)
+                    .with_memo_limit(20, policy);
+                assert_eq!(
+                    stmts.parse(0, ctx).map(|(_, v)| v),
+                    Ok([3, 12].repeat(50)),
+                    "{:?} {:?}",
+                    layout,
+                    policy
+                );
+                assert!(ctx.cache.len() <= 20, "{} entries left", ctx.cache.len());
+            }
+        }
+    }
+
+    #[test]
+    fn test_memo_entries() {
+        let sum = fixture::sum();
+
+        let ctx = &mut Context::new("1+2");
+        assert_eq!(sum.parse(0, ctx), Ok((3, 3)));
+        let entries = ctx.memo_entries();
+        assert_eq!(entries.len(), ctx.stats().entries);
+        assert!(entries.iter().all(|entry| entry.state == MemoState::Fixed));
+        assert_eq!(
+            entries.iter().find(|entry| entry.rule == "sum"),
+            Some(&MemoEntry {
+                rule: String::from("sum"),
+                pos: 0,
+                state: MemoState::Fixed,
+                end: Some(3),
+            })
+        );
+        assert!(entries.contains(&MemoEntry {
+            rule: String::from("'+'"),
+            pos: 3,
+            state: MemoState::Fixed,
+            end: None,
+        }));
+    }
+}
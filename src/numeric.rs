@@ -0,0 +1,227 @@
+use std::rc::Rc;
+use std::str::FromStr;
+
+use crate::{Context, Parser, Reason, char, char_ci, satisfy};
+
+/// The integer types `integer` and `radix_integer` produce, which a big integer type can
+/// implement as well.
+pub trait Integer: Clone + 'static {
+    /// Whether literals of this type may start with `-`.
+    const SIGNED: bool;
+
+    /// Converts digits in base `radix` after an optional `+` or `-`, None if the value
+    /// doesn't fit.
+    fn from_str_radix(text: &str, radix: u32) -> Option<Self>;
+}
+
+macro_rules! impl_integer {
+    ($signed:literal, $($t:ty),*) => {$(
+        impl Integer for $t {
+            const SIGNED: bool = $signed;
+
+            fn from_str_radix(text: &str, radix: u32) -> Option<Self> {
+                <$t>::from_str_radix(text, radix).ok()
+            }
+        }
+    )*};
+}
+
+impl_integer!(true, i8, i16, i32, i64, i128, isize);
+impl_integer!(false, u8, u16, u32, u64, u128, usize);
+
+/// The floating point types `float` produces.
+pub trait Float: FromStr + Clone + 'static {
+    fn is_finite(&self) -> bool;
+}
+
+impl Float for f32 {
+    fn is_finite(&self) -> bool {
+        f32::is_finite(*self)
+    }
+}
+
+impl Float for f64 {
+    fn is_finite(&self) -> bool {
+        f64::is_finite(*self)
+    }
+}
+
+// digits in base `radix`, single underscores allowed between them, without the underscores
+fn digits<'a>(radix: u32) -> Parser<'a, String> {
+    let label = match radix {
+        2 => "binary digit",
+        8 => "octal digit",
+        16 => "hex digit",
+        _ => "digit",
+    };
+    let digit = satisfy(label, move |c| c.is_digit(radix));
+    let separated = char('_').opt().andr(digit.clone());
+    digit
+        .and(separated.many())
+        .map(|(first, rest)| std::iter::once(first).chain(rest).collect())
+}
+
+// an optional sign, `-` only where `signed`, kept as text to put in front of the digits
+fn sign<'a>(signed: bool) -> Parser<'a, String> {
+    let sign = if signed {
+        char('+').or(char('-'))
+    } else {
+        char('+')
+    };
+    sign.opt()
+        .map(|sign| sign.map(String::from).unwrap_or_default())
+}
+
+// converts what `literal` matched with `convert`, and where the literal is well formed but
+// its value doesn't fit, ends the parse with `message` rather than failing as if it didn't
+// match, which backtracking would hide behind some other error
+fn checked<'a, S: Clone + 'static, T: Clone + 'static>(
+    name: &str,
+    literal: Parser<'a, S>,
+    convert: impl Fn(S) -> Option<T> + 'a,
+    message: &'static str,
+) -> Parser<'a, T> {
+    let first = literal.first.clone();
+    let raw_parser = Rc::new(move |pos, ctx: &mut Context| {
+        let (end, val) = literal.parse(pos, ctx)?;
+        match convert(val) {
+            Some(val) => Ok((end, val)),
+            None => Err(ctx.abort(pos, Reason::Message(message))),
+        }
+    });
+    Parser::new(String::from(name), raw_parser).with_first(first)
+}
+
+/// Matches a decimal integer with an optional sign, `-` only for signed types, and
+/// underscores between digits as in `1_000_000`. A value that doesn't fit in `T` ends the
+/// parse with the error "integer literal out of range" at the start of the literal, which
+/// no alternative is tried after.
+///
+/// ```
+/// use packrust::*;
+///
+/// assert_eq!(integer::<i64>().run("-1_024"), Ok(-1024));
+/// assert_eq!(integer::<u8>().run("+255"), Ok(255));
+/// let error = integer::<u8>().run("256").unwrap_err();
+/// assert_eq!(error.to_string(), "256\n^\ninteger literal out of range\n");
+/// ```
+pub fn integer<'a, T: Integer>() -> Parser<'a, T> {
+    let literal = sign(T::SIGNED).and(digits(10));
+    checked(
+        "integer",
+        literal,
+        |(sign, digits)| T::from_str_radix(&format!("{}{}", sign, digits), 10),
+        "integer literal out of range",
+    )
+}
+
+/// Matches an integer like `integer`, or one in another base after its prefix: `0x` for
+/// hexadecimal, `0o` for octal and `0b` for binary, in either case, as in `-0xFF_FF`.
+pub fn radix_integer<'a, T: Integer>() -> Parser<'a, T> {
+    let prefixed = |prefix, radix| {
+        char('0')
+            .and(char_ci(prefix))
+            .andr(digits(radix))
+            .map(move |digits| (radix, digits))
+    };
+    let number = prefixed('x', 16)
+        .or(prefixed('o', 8))
+        .or(prefixed('b', 2))
+        .or(digits(10).map(|digits| (10, digits)));
+    checked(
+        "radix_integer",
+        sign(T::SIGNED).and(number),
+        |(sign, (radix, digits))| T::from_str_radix(&format!("{}{}", sign, digits), radix),
+        "integer literal out of range",
+    )
+}
+
+/// Matches a decimal number with an optional sign, fraction and exponent, as in `-1.5`,
+/// `6.022_140e23` or `1E-9`, with underscores between digits. The integer part is required
+/// and so are digits after the `.`, so `1.` matches just `1`. A value too large for `T`
+/// ends the parse with the error "float literal out of range", like an integer overflow.
+///
+/// ```
+/// use packrust::*;
+///
+/// assert_eq!(float::<f64>().run("-2.5e3"), Ok(-2500.0));
+/// assert_eq!(float::<f32>().run("1_000.25"), Ok(1000.25));
+/// assert!(float::<f32>().run("1e39").is_err());
+/// ```
+pub fn float<'a, T: Float>() -> Parser<'a, T> {
+    let fraction = char('.').andr(digits(10)).opt();
+    let exponent = char_ci('e').andr(sign(true)).and(digits(10)).opt();
+    let literal = sign(true).and(digits(10)).and(fraction).and(exponent);
+    checked(
+        "float",
+        literal,
+        |(((sign, int), fraction), exponent)| {
+            let mut text = sign + &int;
+            if let Some(fraction) = fraction {
+                text = text + "." + &fraction;
+            }
+            if let Some((sign, exponent)) = exponent {
+                text = text + "e" + &sign + &exponent;
+            }
+            text.parse::<T>().ok().filter(Float::is_finite)
+        },
+        "float literal out of range",
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn test_integer() {
+        assert_eq!(integer::<i32>().run("42"), Ok(42));
+        assert_eq!(integer::<i8>().run("-128"), Ok(-128));
+        assert_eq!(integer::<i128>().run("1_0_0"), Ok(100));
+        assert!(integer::<i8>().run("128").is_err());
+        // unsigned literals have no `-`, and separators only go between digits
+        assert!(integer::<u32>().run("-1").is_err());
+        assert!(integer::<u32>().end().run("1__0").is_err());
+        assert!(integer::<u32>().end().run("10_").is_err());
+        assert!(integer::<u32>().run("_1").is_err());
+
+        let int = radix_integer::<i16>();
+        assert_eq!(int.run("0x7FfF"), Ok(0x7fff));
+        assert_eq!(int.run("-0X8000"), Ok(-0x8000));
+        assert_eq!(int.run("0o17"), Ok(0o17));
+        assert_eq!(int.run("0b1010_1010"), Ok(0b1010_1010));
+        assert_eq!(int.run("019"), Ok(19));
+        assert!(int.clone().end().run("0b102").is_err());
+        let error = int.run("0x8000").unwrap_err();
+        assert_eq!(error.pos(), 0);
+        assert_eq!(
+            error.to_string().lines().last(),
+            Some("integer literal out of range")
+        );
+
+        // an overflow is reported where it is, not hidden by backtracking
+        let value = radix_integer::<u8>().or(keyword("0x100").map(|_| 0));
+        assert!(value.run("0x100").is_err());
+        let values = integer::<u8>().sep_by(char(',')).end();
+        let error = values.run("1,2,300").unwrap_err();
+        assert_eq!(error.pos(), 4);
+    }
+
+    #[test]
+    fn test_float() {
+        let float = float::<f64>();
+        assert_eq!(float.run("0"), Ok(0.0));
+        assert_eq!(float.run("+3.25"), Ok(3.25));
+        assert_eq!(float.run("1e3"), Ok(1000.0));
+        assert_eq!(float.run("1_5.0_5E-1"), Ok(1.505));
+        assert_eq!(float.run("-0.5e+2"), Ok(-50.0));
+        // a `.` without digits after it is left for whatever follows, like a method call
+        let call = float.clone().andl(keyword(".abs"));
+        assert_eq!(call.run("2.abs"), Ok(2.0));
+        assert!(float.run(".5").is_err());
+        assert!(float.clone().end().run("1e").is_err());
+
+        let error = float.run("1e400").unwrap_err();
+        assert_eq!(error.to_string(), "1e400\n^\nfloat literal out of range\n");
+    }
+}
@@ -0,0 +1,114 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::ParseError;
+
+/// Callbacks for the events of a parse, installed with `Context::set_observer`. Rules are
+/// identified by name; every method does nothing unless overridden.
+pub trait ParseObserver {
+    fn on_enter(&mut self, _rule: &str, _pos: usize) {}
+
+    // `result` is the end position on success
+    fn on_exit(&mut self, _rule: &str, _pos: usize, _result: Result<usize, &ParseError>) {}
+
+    // called between `on_enter` and `on_exit` when the result came from the memo table
+    fn on_memo_hit(&mut self, _rule: &str, _pos: usize) {}
+
+    // a left-recursive rule at `pos` grew its match to end at `end`
+    fn on_lr_grow(&mut self, _rule: &str, _pos: usize, _end: usize) {}
+}
+
+// lets the caller keep a handle on an observer the context owns
+impl<O: ParseObserver> ParseObserver for Rc<RefCell<O>> {
+    fn on_enter(&mut self, rule: &str, pos: usize) {
+        self.borrow_mut().on_enter(rule, pos)
+    }
+
+    fn on_exit(&mut self, rule: &str, pos: usize, result: Result<usize, &ParseError>) {
+        self.borrow_mut().on_exit(rule, pos, result)
+    }
+
+    fn on_memo_hit(&mut self, rule: &str, pos: usize) {
+        self.borrow_mut().on_memo_hit(rule, pos)
+    }
+
+    fn on_lr_grow(&mut self, rule: &str, pos: usize, end: usize) {
+        self.borrow_mut().on_lr_grow(rule, pos, end)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Default)]
+    struct Events(Vec<String>);
+
+    impl ParseObserver for Events {
+        fn on_enter(&mut self, rule: &str, pos: usize) {
+            self.0.push(format!("enter {} {}", rule, pos));
+        }
+
+        fn on_exit(&mut self, rule: &str, pos: usize, result: Result<usize, &ParseError>) {
+            match result {
+                Ok(end) => self.0.push(format!("exit {} {} {}", rule, pos, end)),
+                Err(_) => self.0.push(format!("fail {} {}", rule, pos)),
+            }
+        }
+
+        fn on_memo_hit(&mut self, rule: &str, pos: usize) {
+            self.0.push(format!("hit {} {}", rule, pos));
+        }
+
+        fn on_lr_grow(&mut self, rule: &str, pos: usize, end: usize) {
+            self.0.push(format!("grow {} {} {}", rule, pos, end));
+        }
+    }
+
+    #[test]
+    fn test_observer() {
+        let a = satisfy("a", |c| c == 'a');
+        let p = a.clone().andl(char('b')).or(a.andl(char('c')));
+
+        let events = Rc::new(RefCell::new(Events::default()));
+        let ctx = &mut Context::new("ac");
+        ctx.set_observer(events.clone());
+        assert_eq!(p.parse(0, ctx), Ok((2, 'a')));
+        assert_eq!(
+            events.borrow().0,
+            [
+                "enter ((a'b')/(a'c')) 0",
+                "enter (a'b') 0",
+                "enter a 0",
+                "exit a 0 1",
+                "enter 'b' 1",
+                "fail 'b' 1",
+                "fail (a'b') 0",
+                "enter (a'c') 0",
+                "enter a 0",
+                "hit a 0",
+                "exit a 0 1",
+                "enter 'c' 1",
+                "exit 'c' 1 2",
+                "exit (a'c') 0 2",
+                "exit ((a'b')/(a'c')) 0 2",
+            ]
+        );
+
+        let sum = fixture::sum();
+        let events = Rc::new(RefCell::new(Events::default()));
+        let ctx = &mut Context::new("1+2+3");
+        ctx.set_observer(events.clone());
+        assert_eq!(sum.parse(0, ctx), Ok((5, 6)));
+        let grows: Vec<_> = events
+            .borrow()
+            .0
+            .iter()
+            .filter(|event| event.starts_with("grow"))
+            .cloned()
+            .collect();
+        assert_eq!(grows, ["grow sum 0 3", "grow sum 0 5"]);
+    }
+}
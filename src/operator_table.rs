@@ -0,0 +1,132 @@
+use std::rc::Rc;
+
+use crate::{Assoc, Parser, keyword, lazy};
+
+type BinaryOp<'a, T> = (String, Rc<dyn Fn(T, T) -> T + 'a>);
+
+struct Level<'a, T> {
+    assoc: Assoc,
+    ops: Vec<BinaryOp<'a, T>>,
+}
+
+// levels are declared loosest first and each one becomes its own memoized rule,
+// `expr -> expr '+' term / term` for left and `pow -> atom '^' pow / atom` for right
+pub struct OperatorTable<'a, T> {
+    levels: Vec<Level<'a, T>>,
+}
+
+impl<T> Clone for OperatorTable<'_, T> {
+    fn clone(&self) -> Self {
+        OperatorTable {
+            levels: self
+                .levels
+                .iter()
+                .map(|level| Level {
+                    assoc: level.assoc,
+                    ops: level.ops.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl<T> Default for OperatorTable<'_, T> {
+    fn default() -> Self {
+        OperatorTable { levels: Vec::new() }
+    }
+}
+
+impl<'a, T> OperatorTable<'a, T>
+where
+    T: Clone + 'static,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn left(self, op: impl Into<String>, f: impl Fn(T, T) -> T + 'a) -> Self {
+        self.level(Assoc::Left, op.into(), Rc::new(f))
+    }
+
+    pub fn right(self, op: impl Into<String>, f: impl Fn(T, T) -> T + 'a) -> Self {
+        self.level(Assoc::Right, op.into(), Rc::new(f))
+    }
+
+    /// Adds another operator to the most recently declared level, sharing its precedence
+    /// and associativity.
+    pub fn also(mut self, op: impl Into<String>, f: impl Fn(T, T) -> T + 'a) -> Self {
+        let level = self
+            .levels
+            .last_mut()
+            .expect("OperatorTable::also called before any level was declared");
+        level.ops.push((op.into(), Rc::new(f)));
+        self
+    }
+
+    fn level(mut self, assoc: Assoc, op: String, f: Rc<dyn Fn(T, T) -> T + 'a>) -> Self {
+        self.levels.push(Level {
+            assoc,
+            ops: vec![(op, f)],
+        });
+        self
+    }
+
+    pub fn build(&self, atom: Parser<'a, T>) -> Parser<'a, T> {
+        self.levels.iter().rev().fold(atom, |next, level| {
+            let name = level
+                .ops
+                .iter()
+                .map(|(op, _)| op.as_str())
+                .collect::<Vec<_>>()
+                .join("|");
+            let assoc = level.assoc;
+            let ops = level.ops.clone();
+
+            lazy(format!("({})", name), move |this| {
+                ops.iter().rev().fold(next.clone(), |rest, (op, f)| {
+                    let f = f.clone();
+                    let (left, right) = match assoc {
+                        Assoc::Left => (this.clone(), next.clone()),
+                        Assoc::Right => (next.clone(), this.clone()),
+                    };
+                    left.andl(keyword(op.clone()))
+                        .and(right)
+                        .map(move |(l, r)| f(l, r))
+                        .or(rest)
+                })
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn test_operator_table() {
+        let digit = satisfy("digit", |c| c.is_ascii_digit());
+        let int = digit
+            .many()
+            .try_map(|c| c.iter().collect::<String>().parse::<i64>().ok());
+        let table = OperatorTable::new()
+            .left("+", |l, r| l + r)
+            .also("-", |l, r| l - r)
+            .left("*", |l, r| l * r)
+            .right("^", |l, r: i64| l.pow(r as u32));
+        let expr = lazy("expr", move |expr| {
+            table.build(int.clone().or(char('(').andr(expr).andl(char(')'))))
+        });
+
+        let ctx = &mut Context::new("1+2*3");
+        assert_eq!(expr.parse(0, ctx), Ok((5, 7)));
+        let ctx = &mut Context::new("10-3-2");
+        assert_eq!(expr.parse(0, ctx), Ok((6, 5)));
+        let ctx = &mut Context::new("2^3^2");
+        assert_eq!(expr.parse(0, ctx), Ok((5, 512)));
+        let ctx = &mut Context::new("(1+1)*2-3");
+        assert_eq!(expr.parse(0, ctx), Ok((9, 1)));
+        let ctx = &mut Context::new("");
+        assert!(expr.parse(0, ctx).is_err());
+    }
+}
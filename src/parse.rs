@@ -0,0 +1,177 @@
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use rustc_hash::FxHashMap;
+
+use crate::{Parser, any_char, char, lazy, satisfy};
+
+/// Types with a canonical parser, which `#[derive(Parser)]` implements for annotated AST
+/// types and uses to parse their fields.
+pub trait Parse: Clone + 'static {
+    fn parser() -> Parser<'static, Self>;
+}
+
+thread_local! {
+    // the rule of every derived type being built or already built on this thread, so that
+    // recursive types refer back to their own placeholder instead of building themselves
+    // forever
+    static RULES: RefCell<FxHashMap<TypeId, Box<dyn Any>>> = RefCell::default();
+}
+
+// the shared rule for `T`, built once per thread through `lazy` so recursion (including
+// left recursion) between derived types goes through memoized placeholders
+#[doc(hidden)]
+pub fn recursive<T: Clone + 'static>(
+    name: &str,
+    build: impl Fn() -> Parser<'static, T> + 'static,
+) -> Parser<'static, T> {
+    let cached = RULES.with(|rules| {
+        rules
+            .borrow()
+            .get(&TypeId::of::<T>())
+            .and_then(|rule| rule.downcast_ref::<Parser<'static, T>>())
+            .cloned()
+    });
+    if let Some(rule) = cached {
+        return rule;
+    }
+
+    lazy(name, move |this| {
+        RULES.with(|rules| {
+            rules
+                .borrow_mut()
+                .insert(TypeId::of::<T>(), Box::new(this.clone()))
+        });
+        build()
+    })
+}
+
+impl<T: Parse> Parse for Box<T> {
+    fn parser() -> Parser<'static, Self> {
+        T::parser().map(Box::new)
+    }
+}
+
+impl<T: Parse> Parse for Rc<T> {
+    fn parser() -> Parser<'static, Self> {
+        T::parser().map(Rc::new)
+    }
+}
+
+impl<T: Parse> Parse for Vec<T> {
+    fn parser() -> Parser<'static, Self> {
+        T::parser().many()
+    }
+}
+
+impl<T: Parse> Parse for Option<T> {
+    fn parser() -> Parser<'static, Self> {
+        T::parser().opt()
+    }
+}
+
+impl Parse for char {
+    fn parser() -> Parser<'static, Self> {
+        any_char()
+    }
+}
+
+fn digits() -> Parser<'static, String> {
+    satisfy("digit", |c| c.is_ascii_digit())
+        .and(satisfy("digit", |c| c.is_ascii_digit()).many())
+        .map(|(first, rest)| std::iter::once(first).chain(rest).collect())
+}
+
+macro_rules! unsigned {
+    ($($ty:ty)*) => {$(
+        impl Parse for $ty {
+            fn parser() -> Parser<'static, Self> {
+                digits().try_map(|digits| digits.parse().ok())
+            }
+        }
+    )*};
+}
+
+macro_rules! signed {
+    ($($ty:ty)*) => {$(
+        impl Parse for $ty {
+            fn parser() -> Parser<'static, Self> {
+                char('-')
+                    .opt()
+                    .and(digits())
+                    .try_map(|(sign, digits)| {
+                        let digits = if sign.is_some() { format!("-{}", digits) } else { digits };
+                        digits.parse().ok()
+                    })
+            }
+        }
+    )*};
+}
+
+unsigned!(u8 u16 u32 u64 u128 usize);
+signed!(i8 i16 i32 i64 i128 isize);
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[cfg(feature = "macros")]
+    #[test]
+    fn test_derive() {
+        #[derive(Debug, Clone, PartialEq, Parser)]
+        enum Expr {
+            #[rule(left "+" right)]
+            Add { left: Box<Expr>, right: Term },
+            #[rule(_)]
+            Term(Term),
+        }
+
+        #[derive(Debug, Clone, PartialEq, Parser)]
+        enum Term {
+            #[rule(_ '*' _)]
+            Mul(Box<Term>, Atom),
+            #[rule(_)]
+            Atom(Atom),
+        }
+
+        #[derive(Debug, Clone, PartialEq, Parser)]
+        enum Atom {
+            #[rule("(" _ ")")]
+            Paren(Box<Expr>),
+            #[rule("-" _)]
+            Neg(Box<Atom>),
+            #[rule(_)]
+            Num(u32),
+        }
+
+        #[derive(Debug, Clone, PartialEq, Parser)]
+        #[rule("[" items "]")]
+        struct List {
+            items: Vec<Atom>,
+        }
+
+        let num = |n| Term::Atom(Atom::Num(n));
+        assert_eq!(
+            Expr::parser().run("1+2*-3"),
+            Ok(Expr::Add {
+                left: Box::new(Expr::Term(num(1))),
+                right: Term::Mul(Box::new(num(2)), Atom::Neg(Box::new(Atom::Num(3)))),
+            })
+        );
+        assert_eq!(
+            List::parser().run("[1(2)]"),
+            Ok(List {
+                items: vec![Atom::Num(1), Atom::Paren(Box::new(Expr::Term(num(2))))]
+            })
+        );
+        assert!(Expr::parser().end().run("1+").is_err());
+    }
+
+    #[test]
+    fn test_integers() {
+        assert_eq!(i32::parser().run("-42"), Ok(-42));
+        assert_eq!(u8::parser().run("255"), Ok(255));
+        assert!(u8::parser().run("256").is_err());
+    }
+}
@@ -0,0 +1,270 @@
+use std::fmt::Write;
+use std::rc::Rc;
+
+use rustc_hash::FxHashMap;
+
+use crate::Parser;
+use crate::shape::{RuleShape, Shape};
+
+// binding strength of each form, so parentheses are only written where they're needed
+const CHOICE: u8 = 0;
+const SEQ: u8 = 1;
+const PREFIX: u8 = 2;
+const SUFFIX: u8 = 3;
+const PRIMARY: u8 = 4;
+
+fn strength(shape: &Shape) -> u8 {
+    match shape {
+        Shape::Choice(_) => CHOICE,
+        Shape::Seq(items) if items.is_empty() => PRIMARY,
+        Shape::Seq(_) => SEQ,
+        Shape::Not(_) | Shape::And(_) | Shape::Eof => PREFIX,
+        Shape::Many(_) | Shape::Opt(_) => SUFFIX,
+        _ => PRIMARY,
+    }
+}
+
+// prints rules as `name <- body`, starting from the roots and following every rule they
+// reference; two distinct rules sharing a name are told apart with a numeric suffix
+struct PegWriter<'s, 'a> {
+    out: String,
+    names: FxHashMap<usize, String>,
+    taken: FxHashMap<String, usize>,
+    queue: Vec<&'s Rc<RuleShape<'a>>>,
+}
+
+impl<'s, 'a> PegWriter<'s, 'a> {
+    fn new() -> Self {
+        PegWriter {
+            out: String::new(),
+            names: FxHashMap::default(),
+            taken: FxHashMap::default(),
+            queue: Vec::new(),
+        }
+    }
+
+    fn name(&mut self, rule: &'s Rc<RuleShape<'a>>) -> String {
+        if let Some(name) = self.names.get(&rule.id()) {
+            return name.clone();
+        }
+        let count = self.taken.entry(rule.name.clone()).or_insert(0);
+        *count += 1;
+        let name = match *count {
+            1 => rule.name.clone(),
+            n => format!("{}_{}", rule.name, n),
+        };
+        self.names.insert(rule.id(), name.clone());
+        self.queue.push(rule);
+        name
+    }
+
+    fn rules(mut self, roots: impl IntoIterator<Item = &'s Rc<RuleShape<'a>>>) -> String {
+        roots.into_iter().for_each(|rule| {
+            self.name(rule);
+        });
+        let mut next = 0;
+        while let Some(&rule) = self.queue.get(next) {
+            next += 1;
+            let name = self.names[&rule.id()].clone();
+            let _ = write!(self.out, "{} <- ", name);
+            match rule.body() {
+                Some(body) => self.shape(body, CHOICE),
+                None => self.out.push_str("<undefined>"),
+            }
+            self.out.push('\n');
+        }
+        self.out
+    }
+
+    fn shape(&mut self, shape: &'s Shape<'a>, context: u8) {
+        let strength = strength(shape);
+        if context > strength {
+            self.out.push('(');
+        }
+        match shape {
+            Shape::Seq(items) if items.is_empty() => self.out.push_str("''"),
+            Shape::Seq(items) | Shape::Choice(items) => {
+                let separator = if strength == SEQ { " " } else { " / " };
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(separator);
+                    }
+                    self.shape(item, strength + 1);
+                }
+            }
+            Shape::Not(item) | Shape::And(item) => {
+                self.out.push(if matches!(shape, Shape::Not(_)) {
+                    '!'
+                } else {
+                    '&'
+                });
+                self.shape(item, PREFIX);
+            }
+            Shape::Many(item) | Shape::Opt(item) => {
+                self.shape(item, PRIMARY);
+                self.out.push(if matches!(shape, Shape::Many(_)) {
+                    '*'
+                } else {
+                    '?'
+                });
+            }
+            Shape::Char(c) => self.literal(&c.to_string()),
+            Shape::Keyword(keyword) => self.literal(keyword),
+            Shape::Any => self.out.push('.'),
+            Shape::Eof => self.out.push_str("!."),
+            // character classes keep their own notation, other predicates and opaque parsers
+            // have none, so they're written as externally defined names
+            Shape::Class(label, _) if label.starts_with('[') && label.ends_with(']') => {
+                self.out.push_str(label)
+            }
+            Shape::Class(label, _) | Shape::Opaque(label, _) => {
+                let _ = write!(self.out, "<{}>", label);
+            }
+            Shape::Rule(rule) => {
+                let name = self.name(rule);
+                self.out.push_str(&name);
+            }
+        }
+        if context > strength {
+            self.out.push(')');
+        }
+    }
+
+    fn literal(&mut self, text: &str) {
+        self.out.push('\'');
+        for c in text.chars() {
+            match c {
+                '\'' => self.out.push_str("\\'"),
+                '\\' => self.out.push_str("\\\\"),
+                '\n' => self.out.push_str("\\n"),
+                '\r' => self.out.push_str("\\r"),
+                '\t' => self.out.push_str("\\t"),
+                c => self.out.push(c),
+            }
+        }
+        self.out.push('\'');
+    }
+}
+
+// a single expression, rules it mentions are written by name only
+pub(crate) fn shape_to_peg(shape: &Shape) -> String {
+    let mut writer = PegWriter::new();
+    writer.shape(shape, CHOICE);
+    writer.out
+}
+
+pub(crate) fn rules_to_peg<'s, 'a: 's>(
+    roots: impl IntoIterator<Item = &'s Rc<RuleShape<'a>>>,
+) -> String {
+    PegWriter::new().rules(roots)
+}
+
+impl<'a, T> Parser<'a, T>
+where
+    T: Clone + 'static,
+{
+    /// Writes this parser and every rule it reaches in PEG notation, one `name <- body`
+    /// definition per line. A parser that isn't itself a rule is listed first under its own
+    /// name.
+    pub fn to_peg(&self) -> String {
+        let root = self.as_rule();
+        rules_to_peg([&root])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn test_parser_to_peg() {
+        let digit = satisfy("digit", |c| c.is_ascii_digit());
+        let expr: Parser<char> = lazy("expr", move |expr| {
+            expr.clone()
+                .andl(char('+'))
+                .andr(digit.clone())
+                .or(digit.clone())
+                .or(char('(').andr(expr).andl(keyword("')")))
+        });
+        assert_eq!(
+            expr.to_peg(),
+            "expr <- expr '+' <digit> / <digit> / '(' expr '\\')'\n"
+        );
+
+        let list = char('a').or(char('b')).many().and(char(';').opt()).end();
+        assert_eq!(list.to_peg(), "end <- ('a' / 'b')* ';'? !.\n");
+    }
+
+    #[test]
+    fn test_grammar_to_peg() {
+        let mut g = GrammarBuilder::new();
+        g.rule("sum", |g| {
+            g.get::<char>("sum")
+                .andl(char('+'))
+                .andr(g.get::<char>("atom"))
+                .or(g.get("atom"))
+        })
+        .rule("atom", |_| any_char().andl(string("ok").opt()));
+
+        assert_eq!(
+            g.build().unwrap().to_peg(),
+            "atom <- . 'ok'?\nsum <- sum '+' atom / atom\n"
+        );
+    }
+
+    #[cfg(feature = "macros")]
+    #[test]
+    fn test_peg_macro() {
+        #[derive(Debug, Clone, PartialEq)]
+        enum Expr {
+            Add(Box<Expr>, Box<Expr>),
+            Mul(Box<Expr>, Box<Expr>),
+            Num(i64),
+        }
+
+        peg! {
+            grammar Arith {
+                expr: Expr = l:expr '+' r:term { Expr::Add(Box::new(l), Box::new(r)) } / term;
+                term: Expr = l:term '*' r:atom { Expr::Mul(Box::new(l), Box::new(r)) } / atom;
+                atom: Expr = '(' e:expr ')' { e } / n:#{ int() } { Expr::Num(n) };
+                digits: Vec<char> = ['0'..='9']+;
+                pair: (String, Option<char>, ()) = "let" ' '? !'=';
+                hex: (String, Vec<char>) = "0x" ['0'..='9' | 'a'..='f']+;
+                stmt: (String, char) = "if"kw ' ';
+            }
+        }
+
+        fn int() -> Parser<'static, i64> {
+            satisfy("digit", |c| c.is_ascii_digit())
+                .many()
+                .try_map(|s| s.iter().collect::<String>().parse().ok())
+        }
+
+        let arith = Arith::new();
+        let num = |n| Box::new(Expr::Num(n));
+        assert_eq!(
+            arith.expr.run("1+2*(3+4)"),
+            Ok(Expr::Add(
+                num(1),
+                Box::new(Expr::Mul(num(2), Box::new(Expr::Add(num(3), num(4)))))
+            ))
+        );
+        assert_eq!(arith.digits.run("42"), Ok(vec!['4', '2']));
+        assert_eq!(
+            arith.pair.run("let x"),
+            Ok((String::from("let"), Some(' '), ()))
+        );
+        assert!(arith.pair.run("let=").is_err());
+        // strings match whatever follows them, keywords only whole words
+        assert_eq!(
+            arith.hex.run("0xff"),
+            Ok((String::from("0x"), vec!['f', 'f']))
+        );
+        assert!(arith.stmt.run("if ").is_ok());
+        assert!(arith.stmt.run("iffy ").is_err());
+        assert_eq!(
+            arith.expr.to_peg(),
+            "expr <- expr '+' term / term\nterm <- term '*' atom / atom\natom <- '(' expr ')' / <(digit*)>\n"
+        );
+    }
+}
@@ -0,0 +1,462 @@
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::shape::Shape;
+use crate::{
+    Context, Grammar, GrammarBuilder, GrammarError, ParseError, Parser, Substr, any_char, char,
+    keyword, lazy, satisfy, string,
+};
+
+/// A generic parse tree, produced by rules loaded with [`load_peg`].
+///
+/// It displays as an S-expression on one line, `(sum 0..3 (num 0..1 "1") "+" (num 2..3 "2"))`,
+/// or with `{:#}` indented one node per line; both are stable, so they can be compared
+/// against stored snapshots. Only rules loaded with [`load_peg`] build trees, so this is no
+/// dump of what parsers built from combinators match; for those, snapshot the events of
+/// [`Context::with_events`] instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Tree {
+    // a rule that matched, with the trees of the rules and tokens its body matched
+    Node {
+        rule: Rc<str>,
+        span: Range<usize>,
+        children: Vec<Tree>,
+    },
+    // text matched by a literal, a character class or `.`
+    Token(Substr),
+}
+
+impl Tree {
+    pub fn span(&self) -> Range<usize> {
+        match self {
+            Tree::Node { span, .. } => span.clone(),
+            Tree::Token(text) => text.range(),
+        }
+    }
+
+    /// The rule that produced this node, None for tokens.
+    pub fn rule(&self) -> Option<&str> {
+        match self {
+            Tree::Node { rule, .. } => Some(rule),
+            Tree::Token(_) => None,
+        }
+    }
+
+    pub fn children(&self) -> &[Tree] {
+        match self {
+            Tree::Node { children, .. } => children,
+            Tree::Token(_) => &[],
+        }
+    }
+}
+
+impl std::fmt::Display for Tree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn write(tree: &Tree, depth: usize, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let pretty = f.alternate();
+            if pretty {
+                write!(f, "{:1$}", "", depth * 2)?;
+            }
+            match tree {
+                Tree::Token(text) => write!(f, "{:?}", text.to_string())?,
+                Tree::Node {
+                    rule,
+                    span,
+                    children,
+                } => {
+                    if !pretty {
+                        f.write_str("(")?;
+                    }
+                    write!(f, "{} {}..{}", rule, span.start, span.end)?;
+                    for child in children {
+                        f.write_str(if pretty { "\n" } else { " " })?;
+                        write(child, depth + 1, f)?;
+                    }
+                    if !pretty {
+                        f.write_str(")")?;
+                    }
+                }
+            }
+            Ok(())
+        }
+        write(self, 0, f)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PegError {
+    // the grammar text itself doesn't parse
+    Syntax(ParseError),
+    // it parses, but refers to undefined rules, defines one twice or has one that is
+    // hidden left-recursive
+    Grammar(Vec<GrammarError>),
+}
+
+impl std::fmt::Display for PegError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PegError::Syntax(e) => write!(f, "{}", e),
+            PegError::Grammar(errors) => errors.iter().try_for_each(|e| writeln!(f, "{}", e)),
+        }
+    }
+}
+
+impl std::error::Error for PegError {}
+
+// a parsed grammar expression, before it is lowered onto the combinators
+#[derive(Debug, Clone)]
+enum Expr {
+    Literal(String),
+    Class {
+        label: String,
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+    Any,
+    Ref(String),
+    Seq(Vec<Expr>),
+    Choice(Vec<Expr>),
+    Many(Box<Expr>),
+    Many1(Box<Expr>),
+    Opt(Box<Expr>),
+    Not(Box<Expr>),
+    And(Box<Expr>),
+}
+
+/// Loads a grammar from PEG text at runtime. Every rule becomes a `Parser<Tree>` that can
+/// be looked up by name in the returned [`Grammar`]:
+///
+/// ```
+/// use packrust::*;
+///
+/// let grammar = load_peg("sum <- sum '+' num / num\nnum <- [0-9]+").unwrap();
+/// let sum = grammar.get::<Tree>("sum").unwrap();
+/// assert_eq!(sum.run("1+23").unwrap().span(), 0..4);
+/// ```
+///
+/// Definitions are written `name <- expression`, with `=`, `::=` or `:` accepted in place of
+/// `<-` and an optional trailing `;`. Alternatives are separated by `/` or `|`, and
+/// expressions are built from `'literals'` or `"literals"`, character classes like
+/// `[a-z_]` or `[^"]`, `.`, rule names, grouping, `e*`, `e+`, `e?`, EBNF style `{ e }`
+/// repetition and the `&e` / `!e` lookaheads. `#` starts a comment.
+pub fn load_peg(text: &str) -> Result<Grammar, PegError> {
+    let definitions = meta_grammar().run(text).map_err(PegError::Syntax)?;
+
+    let mut g = GrammarBuilder::new();
+    for (name, expr) in definitions {
+        let rule: Rc<str> = Rc::from(name.as_str());
+        g.rule(&name, |g| node(rule, lower(g, &expr)));
+    }
+    g.build().map_err(PegError::Grammar)
+}
+
+fn node(rule: Rc<str>, body: Parser<'static, Vec<Tree>>) -> Parser<'static, Tree> {
+    let shape = body.shape.clone();
+    let raw_parser = Rc::new(move |pos, ctx: &mut Context| {
+        let (end, children) = body.parse(pos, ctx)?;
+        let tree = Tree::Node {
+            rule: rule.clone(),
+            span: pos..end,
+            children,
+        };
+        Ok((end, tree))
+    });
+    Parser::new(String::from("node"), raw_parser).with_shape(shape)
+}
+
+fn empty() -> Parser<'static, Vec<Tree>> {
+    Parser::new(
+        String::from("''"),
+        Rc::new(|pos, _: &mut Context| Ok((pos, Vec::new()))),
+    )
+    .with_shape(Rc::new(Shape::Seq(Vec::new())))
+}
+
+fn token(p: Parser<'static, Substr>) -> Parser<'static, Vec<Tree>> {
+    p.map(|text| vec![Tree::Token(text)])
+}
+
+fn concat(
+    left: Parser<'static, Vec<Tree>>,
+    right: Parser<'static, Vec<Tree>>,
+) -> Parser<'static, Vec<Tree>> {
+    left.and(right).map(|(mut left, right)| {
+        left.extend(right);
+        left
+    })
+}
+
+fn lower(g: &mut GrammarBuilder, expr: &Expr) -> Parser<'static, Vec<Tree>> {
+    match expr {
+        Expr::Literal(text) => {
+            let mut chars = text.chars();
+            match (chars.next(), chars.next()) {
+                (None, _) => empty(),
+                (Some(c), None) => token(char(c).recognize()),
+                _ => token(string(text.as_str()).recognize()),
+            }
+        }
+        Expr::Class {
+            label,
+            ranges,
+            negated,
+        } => {
+            let (ranges, negated) = (ranges.clone(), *negated);
+            let class = satisfy(label.as_str(), move |c| {
+                negated != ranges.iter().any(|(lo, hi)| (*lo..=*hi).contains(&c))
+            });
+            token(class.recognize())
+        }
+        Expr::Any => token(any_char().recognize()),
+        Expr::Ref(name) => g.get::<Tree>(name).map(|tree| vec![tree]),
+        Expr::Seq(items) => items
+            .iter()
+            .map(|item| lower(g, item))
+            .reduce(concat)
+            .unwrap_or_else(empty),
+        Expr::Choice(alternatives) => alternatives
+            .iter()
+            .map(|alternative| lower(g, alternative))
+            .reduce(Parser::or)
+            .unwrap_or_else(empty),
+        Expr::Many(item) => lower(g, item).many().map(|trees| trees.concat()),
+        Expr::Many1(item) => {
+            let item = lower(g, item);
+            concat(item.clone(), item.many().map(|trees| trees.concat()))
+        }
+        Expr::Opt(item) => lower(g, item).opt().map(Option::unwrap_or_default),
+        Expr::Not(item) => lower(g, item).not_ahead().map(|_| Vec::new()),
+        Expr::And(item) => lower(g, item).peek().map(|_| Vec::new()),
+    }
+}
+
+fn class_label(ranges: &[(char, char)], negated: bool) -> String {
+    let escape = |c: char| match c {
+        '\n' => String::from("\\n"),
+        '\r' => String::from("\\r"),
+        '\t' => String::from("\\t"),
+        ']' | '\\' | '-' | '^' => format!("\\{}", c),
+        c => c.to_string(),
+    };
+    let mut label = String::from(if negated { "[^" } else { "[" });
+    for &(lo, hi) in ranges {
+        label.push_str(&escape(lo));
+        if lo != hi {
+            label.push('-');
+            label.push_str(&escape(hi));
+        }
+    }
+    label.push(']');
+    label
+}
+
+// the grammar of grammars, written with the combinators it is loading into
+fn meta_grammar() -> Parser<'static, Vec<(String, Expr)>> {
+    let comment = char('#').and(char('\n').not_ahead().andr(any_char()).many());
+    let spacing = satisfy("whitespace", char::is_whitespace)
+        .map(|_| ())
+        .or(comment.map(|_| ()))
+        .many()
+        .map(|_| ());
+    let lexeme = {
+        let spacing = spacing.clone();
+        move |p: Parser<'static, char>| p.andl(spacing.clone())
+    };
+
+    let ident = satisfy("letter", |c| c.is_ascii_alphabetic() || c == '_')
+        .and(satisfy("letter or digit", |c| c.is_ascii_alphanumeric() || c == '_').many())
+        .recognize()
+        .map(|name| name.to_string())
+        .andl(spacing.clone());
+    let arrow = keyword("<-")
+        .or(keyword("::="))
+        .or(keyword("="))
+        .or(keyword(":"))
+        .andl(spacing.clone());
+
+    let escaped = char('\\').andr(any_char()).map(|c| match c {
+        'n' => '\n',
+        'r' => '\r',
+        't' => '\t',
+        c => c,
+    });
+    let quoted = |quote: char| {
+        let body = char(quote)
+            .not_ahead()
+            .andr(escaped.clone().or(any_char()))
+            .many();
+        char(quote)
+            .andr(body)
+            .andl(char(quote))
+            .map(|chars| Expr::Literal(chars.into_iter().collect()))
+    };
+    let literal = quoted('\'').or(quoted('"')).andl(spacing.clone());
+
+    let class_char = char(']').not_ahead().andr(escaped.clone().or(any_char()));
+    let range = class_char
+        .clone()
+        .andl(char('-'))
+        .and(class_char.clone())
+        .or(class_char.map(|c| (c, c)));
+    let class = char('[')
+        .andr(char('^').opt())
+        .and(range.many())
+        .andl(char(']'))
+        .andl(spacing.clone())
+        .map(|(negated, ranges)| Expr::Class {
+            label: class_label(&ranges, negated.is_some()),
+            ranges,
+            negated: negated.is_some(),
+        });
+
+    // a rule name followed by an arrow starts the next definition, not a reference
+    let (word, definition_start) = (ident.clone(), arrow.clone());
+    let punct = lexeme.clone();
+    let expression = lazy("expression", move |expression| {
+        let primary = word
+            .clone()
+            .andl(definition_start.clone().not_ahead())
+            .map(Expr::Ref)
+            .or(punct(char('('))
+                .andr(expression.clone())
+                .andl(punct(char(')'))))
+            .or(punct(char('{'))
+                .andr(expression.clone())
+                .andl(punct(char('}')))
+                .map(|e| Expr::Many(Box::new(e))))
+            .or(literal.clone())
+            .or(class.clone())
+            .or(punct(char('.')).map(|_| Expr::Any));
+        let suffix = primary
+            .and(punct(char('*').or(char('+')).or(char('?'))).opt())
+            .map(|(e, suffix)| match suffix {
+                Some('*') => Expr::Many(Box::new(e)),
+                Some('+') => Expr::Many1(Box::new(e)),
+                Some(_) => Expr::Opt(Box::new(e)),
+                None => e,
+            });
+        let prefix =
+            punct(char('&').or(char('!')))
+                .opt()
+                .and(suffix)
+                .map(|(prefix, e)| match prefix {
+                    Some('&') => Expr::And(Box::new(e)),
+                    Some(_) => Expr::Not(Box::new(e)),
+                    None => e,
+                });
+        let sequence = prefix.many().map(|mut items| match items.len() {
+            1 => items.pop().unwrap(),
+            _ => Expr::Seq(items),
+        });
+        sequence
+            .sep_by(punct(char('/').or(char('|'))))
+            .map(|mut alternatives| match alternatives.len() {
+                1 => alternatives.pop().unwrap(),
+                _ => Expr::Choice(alternatives),
+            })
+    });
+
+    let definition = ident
+        .andl(arrow)
+        .and(expression)
+        .andl(lexeme(char(';')).opt());
+    spacing
+        .andr(definition.clone().and(definition.many()))
+        .map(|(first, mut rest)| {
+            rest.insert(0, first);
+            rest
+        })
+        .end()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    fn shape(tree: &Tree) -> String {
+        match tree {
+            Tree::Node { rule, children, .. } => {
+                let children: Vec<_> = children.iter().map(shape).collect();
+                format!("{}({})", rule, children.join(" "))
+            }
+            Tree::Token(text) => text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_load_peg() {
+        let grammar = load_peg(
+            r#"
+            # left recursion works as it does for hand written rules
+            expr <- expr '+' term / term
+            term <- term '*' atom | atom
+            atom = '(' expr ')' / [0-9]+ ;
+            "#,
+        )
+        .unwrap();
+        let expr = grammar.get::<Tree>("expr").unwrap();
+
+        let tree = expr.run("12+3*(4)").unwrap();
+        assert_eq!(tree.span(), 0..8);
+        assert_eq!(
+            shape(&tree),
+            "expr(expr(term(atom(1 2))) + term(term(atom(3)) * atom(( expr(term(atom(4))) ))))"
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        let grammar = load_peg("sum <- num '+' num\nnum <- [0-9] / '\"'").unwrap();
+        let tree = grammar.get::<Tree>("sum").unwrap().run("1+\"").unwrap();
+        assert_eq!(
+            tree.to_string(),
+            r#"(sum 0..3 (num 0..1 "1") "+" (num 2..3 "\""))"#
+        );
+        assert_eq!(
+            format!("{:#}", tree),
+            r#"sum 0..3
+  num 0..1
+    "1"
+  "+"
+  num 2..3
+    "\"""#
+        );
+    }
+
+    #[test]
+    fn test_lookahead_and_classes() {
+        let grammar = load_peg(
+            r#"
+            string <- '"' { !'"' ("\\" . / [^\\"]) } '"'
+            word   <- &[a-z] [a-zA-Z_]+ !.
+            "#,
+        )
+        .unwrap();
+
+        let string = grammar.get::<Tree>("string").unwrap();
+        assert_eq!(string.run(r#""a\"b""#).unwrap().span(), 0..6);
+        let word = grammar.get::<Tree>("word").unwrap();
+        assert!(word.run("snake_Case").is_ok());
+        assert!(word.run("Snake").is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let text = "a <- 'x'* b? / !'y' [^a-c] &.\nb <- ('z' / .) '\\'' a\n";
+        let grammar = load_peg(text).unwrap();
+        assert_eq!(grammar.to_peg(), text);
+        assert_eq!(load_peg(&grammar.to_peg()).unwrap().to_peg(), text);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(load_peg("a <- 'x"), Err(PegError::Syntax(_))));
+        assert_eq!(
+            load_peg("a <- b c\nb <- 'x'\nb <- 'y'").err(),
+            Some(PegError::Grammar(vec![
+                GrammarError::Redefined(String::from("b")),
+                GrammarError::Undefined(String::from("c")),
+            ]))
+        );
+    }
+}
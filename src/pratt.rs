@@ -0,0 +1,201 @@
+use std::rc::Rc;
+
+use crate::Context;
+use crate::ParseResult;
+use crate::Parser;
+use crate::Pos;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assoc {
+    Left,
+    Right,
+}
+
+type Prefix<'a, T> = (Parser<'a, ()>, u32, Rc<dyn Fn(T) -> T + 'a>);
+type Infix<'a, T> = (Parser<'a, ()>, u32, u32, Rc<dyn Fn(T, T) -> T + 'a>);
+type Postfix<'a, T> = (Parser<'a, ()>, u32, Rc<dyn Fn(T) -> T + 'a>);
+
+struct Table<'a, T> {
+    atom: Parser<'a, T>,
+    prefix: Vec<Prefix<'a, T>>,
+    infix: Vec<Infix<'a, T>>,
+    postfix: Vec<Postfix<'a, T>>,
+}
+
+// binding powers are doubled internally so that associativity can be expressed by
+// nudging one side up by one, operators with a higher `bp` bind tighter
+pub struct Pratt<'a, T> {
+    name: String,
+    table: Table<'a, T>,
+}
+
+pub fn pratt<'a, T: Clone + 'static>(atom: Parser<'a, T>) -> Pratt<'a, T> {
+    Pratt {
+        name: format!("pratt({})", atom.name),
+        table: Table {
+            atom,
+            prefix: Vec::new(),
+            infix: Vec::new(),
+            postfix: Vec::new(),
+        },
+    }
+}
+
+impl<'a, T> Pratt<'a, T>
+where
+    T: Clone + 'static,
+{
+    pub fn prefix<O: Clone + 'static>(
+        mut self,
+        op: Parser<'a, O>,
+        bp: u32,
+        f: impl Fn(T) -> T + 'a,
+    ) -> Self {
+        self.table.prefix.push((op.map(|_| ()), bp * 2, Rc::new(f)));
+        self
+    }
+
+    pub fn infix<O: Clone + 'static>(
+        mut self,
+        op: Parser<'a, O>,
+        assoc: Assoc,
+        bp: u32,
+        f: impl Fn(T, T) -> T + 'a,
+    ) -> Self {
+        let (left_bp, right_bp) = match assoc {
+            Assoc::Left => (bp * 2, bp * 2 + 1),
+            Assoc::Right => (bp * 2 + 1, bp * 2),
+        };
+        self.table
+            .infix
+            .push((op.map(|_| ()), left_bp, right_bp, Rc::new(f)));
+        self
+    }
+
+    pub fn postfix<O: Clone + 'static>(
+        mut self,
+        op: Parser<'a, O>,
+        bp: u32,
+        f: impl Fn(T) -> T + 'a,
+    ) -> Self {
+        self.table
+            .postfix
+            .push((op.map(|_| ()), bp * 2, Rc::new(f)));
+        self
+    }
+
+    pub fn build(self) -> Parser<'a, T> {
+        let table = self.table;
+        let raw_parser = Rc::new(move |pos, ctx: &mut Context| expr_bp(&table, 0, pos, ctx));
+        Parser::new(self.name, raw_parser)
+    }
+}
+
+fn expr_bp<T>(table: &Table<'_, T>, min_bp: u32, pos: Pos, ctx: &mut Context) -> ParseResult<T>
+where
+    T: Clone + 'static,
+{
+    // a prefix operator without an operand after it may start an atom instead, like the
+    // `-` of a negative literal, so the next prefix or the atom gets its turn
+    let (mut pos, mut lhs) = 'lhs: {
+        for (op, right_bp, f) in &table.prefix {
+            if let Ok((op_pos, ())) = op.parse(pos, ctx)
+                && let Ok((pos, rhs)) = expr_bp(table, *right_bp, op_pos, ctx)
+            {
+                break 'lhs (pos, f(rhs));
+            }
+        }
+        table.atom.parse(pos, ctx)?
+    };
+
+    'operators: loop {
+        for (op, left_bp, f) in &table.postfix {
+            if let Ok((op_pos, ())) = op.parse(pos, ctx) {
+                if *left_bp < min_bp {
+                    break 'operators;
+                }
+                pos = op_pos;
+                lhs = f(lhs);
+                continue 'operators;
+            }
+        }
+
+        for (op, left_bp, right_bp, f) in &table.infix {
+            if let Ok((op_pos, ())) = op.parse(pos, ctx) {
+                if *left_bp < min_bp {
+                    break 'operators;
+                }
+                let Ok((rhs_pos, rhs)) = expr_bp(table, *right_bp, op_pos, ctx) else {
+                    break 'operators;
+                };
+                pos = rhs_pos;
+                lhs = f(lhs, rhs);
+                continue 'operators;
+            }
+        }
+
+        break;
+    }
+
+    Ok((pos, lhs))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    fn calc() -> Parser<'static, i64> {
+        let digit = satisfy("digit", |c| c.is_ascii_digit());
+        let int = digit
+            .many()
+            .try_map(|c| c.iter().collect::<String>().parse::<i64>().ok());
+
+        lazy("expr", move |expr| {
+            let atom = int.clone().or(char('(').andr(expr).andl(char(')')));
+            pratt(atom)
+                .prefix(char('-'), 3, |x| -x)
+                .infix(char('+'), Assoc::Left, 1, |l, r| l + r)
+                .infix(char('-'), Assoc::Left, 1, |l, r| l - r)
+                .infix(char('*'), Assoc::Left, 2, |l, r| l * r)
+                .infix(char('^'), Assoc::Right, 4, |l, r| l.pow(r as u32))
+                .postfix(char('!'), 5, |x| (1..=x).product())
+                .build()
+        })
+    }
+
+    #[test]
+    fn test_pratt() {
+        let expr = calc();
+
+        let ctx = &mut Context::new("1+2*3");
+        assert_eq!(expr.parse(0, ctx), Ok((5, 7)));
+        let ctx = &mut Context::new("10-3-2");
+        assert_eq!(expr.parse(0, ctx), Ok((6, 5)));
+        let ctx = &mut Context::new("2^3^2");
+        assert_eq!(expr.parse(0, ctx), Ok((5, 512)));
+        let ctx = &mut Context::new("-3!+(1+1)*2");
+        assert_eq!(expr.parse(0, ctx), Ok((11, -2)));
+        let ctx = &mut Context::new("1+");
+        assert_eq!(expr.parse(0, ctx), Ok((1, 1)));
+        let ctx = &mut Context::new("");
+        assert!(expr.parse(0, ctx).is_err());
+    }
+
+    #[test]
+    fn test_pratt_prefix_fallback() {
+        // `-` negates an operand, but `-` followed by `-` is an atom of its own
+        let int = satisfy("digit", |c| c.is_ascii_digit()).map(|c| c.to_digit(10).unwrap() as i64);
+        let atom = int.or(string("--").map(|_| 0));
+        let expr = pratt(atom)
+            .prefix(char('-'), 3, |x| -x)
+            .infix(char('+'), Assoc::Left, 1, |l, r| l + r)
+            .build();
+
+        let ctx = &mut Context::new("-2+--");
+        assert_eq!(expr.parse(0, ctx), Ok((5, -2)));
+        let ctx = &mut Context::new("--+1");
+        assert_eq!(expr.parse(0, ctx), Ok((4, 1)));
+        let ctx = &mut Context::new("-x");
+        assert!(expr.parse(0, ctx).is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use rustc_hash::FxHashMap;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RuleProfile {
+    pub(crate) calls: usize,
+    pub(crate) memo_hits: usize,
+    pub(crate) time: Duration,
+    active: usize,
+}
+
+// per-rule counters, keyed by parser name so that clones and renamed copies of a rule add up
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Profile {
+    pub(crate) rules: FxHashMap<String, RuleProfile>,
+}
+
+impl Profile {
+    fn rule(&mut self, name: &str) -> &mut RuleProfile {
+        if !self.rules.contains_key(name) {
+            self.rules.insert(name.to_string(), RuleProfile::default());
+        }
+        self.rules.get_mut(name).unwrap()
+    }
+
+    pub(crate) fn record_hit(&mut self, name: &str) {
+        self.rule(name).memo_hits += 1;
+    }
+
+    pub(crate) fn enter(&mut self, name: &str) -> Instant {
+        let rule = self.rule(name);
+        rule.calls += 1;
+        rule.active += 1;
+        Instant::now()
+    }
+
+    // only the outermost activation of a rule contributes time, so recursive rules aren't counted twice
+    pub(crate) fn exit(&mut self, name: &str, started: Instant) {
+        let rule = self.rule(name);
+        rule.active -= 1;
+        if rule.active == 0 {
+            rule.time += started.elapsed();
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleReport {
+    pub name: String,
+    // calls that ran the rule, memo hits are counted separately
+    pub calls: usize,
+    pub memo_hits: usize,
+    pub time: Duration,
+}
+
+impl RuleReport {
+    /// Share of all calls to this rule that were answered from the memo table.
+    pub fn hit_ratio(&self) -> f64 {
+        match self.calls + self.memo_hits {
+            0 => 0.0,
+            total => self.memo_hits as f64 / total as f64,
+        }
+    }
+}
+
+// rules sorted by the time spent in them, slowest first
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileReport {
+    pub rules: Vec<RuleReport>,
+}
+
+impl ProfileReport {
+    pub(crate) fn new(profile: &Profile) -> Self {
+        let mut rules: Vec<RuleReport> = profile
+            .rules
+            .iter()
+            .map(|(name, rule)| RuleReport {
+                name: name.clone(),
+                calls: rule.calls,
+                memo_hits: rule.memo_hits,
+                time: rule.time,
+            })
+            .collect();
+        rules.sort_by(|a, b| b.time.cmp(&a.time).then_with(|| a.name.cmp(&b.name)));
+
+        ProfileReport { rules }
+    }
+}
+
+impl std::fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:<32} {:>10} {:>10} {:>8} {:>12}",
+            "rule", "calls", "hits", "hit %", "time"
+        )?;
+        for rule in &self.rules {
+            writeln!(
+                f,
+                "{:<32} {:>10} {:>10} {:>7.1}% {:>10.3}ms",
+                rule.name,
+                rule.calls,
+                rule.memo_hits,
+                rule.hit_ratio() * 100.0,
+                rule.time.as_secs_f64() * 1000.0
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleComparison {
+    pub name: String,
+    pub memo_calls: usize,
+    pub plain_calls: usize,
+    pub memo_time: Duration,
+    pub plain_time: Duration,
+}
+
+impl RuleComparison {
+    pub fn calls_saved(&self) -> isize {
+        self.plain_calls as isize - self.memo_calls as isize
+    }
+
+    /// Time memoization saved on this rule, negative when the memo table cost more than it saved.
+    pub fn time_saved_secs(&self) -> f64 {
+        self.plain_time.as_secs_f64() - self.memo_time.as_secs_f64()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoComparison {
+    pub rules: Vec<RuleComparison>,
+    pub memo_time: Duration,
+    pub plain_time: Duration,
+}
+
+impl MemoComparison {
+    pub(crate) fn new(
+        memo: Profile,
+        memo_time: Duration,
+        plain: Profile,
+        plain_time: Duration,
+    ) -> Self {
+        let mut plain = plain.rules;
+        let mut rules: Vec<RuleComparison> = memo
+            .rules
+            .into_iter()
+            .map(|(name, memo)| {
+                let plain = plain.remove(&name).unwrap_or_default();
+                RuleComparison {
+                    name,
+                    memo_calls: memo.calls,
+                    plain_calls: plain.calls,
+                    memo_time: memo.time,
+                    plain_time: plain.time,
+                }
+            })
+            .collect();
+        rules.extend(plain.into_iter().map(|(name, plain)| RuleComparison {
+            name,
+            memo_calls: 0,
+            plain_calls: plain.calls,
+            memo_time: Duration::ZERO,
+            plain_time: plain.time,
+        }));
+        rules.sort_by(|a, b| {
+            b.calls_saved()
+                .cmp(&a.calls_saved())
+                .then_with(|| a.name.cmp(&b.name))
+        });
+
+        MemoComparison {
+            rules,
+            memo_time,
+            plain_time,
+        }
+    }
+}
+
+impl std::fmt::Display for MemoComparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:<32} {:>10} {:>10} {:>10} {:>14}",
+            "rule", "memo", "plain", "saved", "time saved"
+        )?;
+        for rule in &self.rules {
+            writeln!(
+                f,
+                "{:<32} {:>10} {:>10} {:>10} {:>12.3}ms",
+                rule.name,
+                rule.memo_calls,
+                rule.plain_calls,
+                rule.calls_saved(),
+                rule.time_saved_secs() * 1000.0
+            )?;
+        }
+        writeln!(
+            f,
+            "total: {:.3}ms with memoization, {:.3}ms without",
+            self.memo_time.as_secs_f64() * 1000.0,
+            self.plain_time.as_secs_f64() * 1000.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn test_compare_memo() {
+        let a = char('a');
+        let p = a.clone().and(char('b')).or(a.and(char('c')));
+
+        let comparison = p.compare_memo("ac");
+        let a = comparison
+            .rules
+            .iter()
+            .find(|rule| rule.name == "'a'")
+            .unwrap();
+        assert_eq!((a.memo_calls, a.plain_calls), (1, 2));
+        assert_eq!(a.calls_saved(), 1);
+    }
+
+    #[test]
+    fn test_report() {
+        let a = char('a');
+        let p = a.clone().and(char('b')).or(a.and(char('c')));
+
+        let ctx = &mut Context::new("ac");
+        assert!(ctx.report().rules.is_empty());
+        let ctx = &mut Context::new("ac").with_profiling();
+        assert!(p.parse(0, ctx).is_ok());
+
+        let report = ctx.report();
+        let a = report.rules.iter().find(|rule| rule.name == "'a'").unwrap();
+        assert_eq!((a.calls, a.memo_hits), (1, 1));
+        assert_eq!(a.hit_ratio(), 0.5);
+        assert!(report.rules.windows(2).all(|w| w[0].time >= w[1].time));
+        assert!(report.to_string().starts_with("rule"));
+    }
+
+    #[test]
+    fn test_compare_memo_left_recursion() {
+        let sum = fixture::sum();
+
+        // the plain parse stops where the rule first calls itself
+        let comparison = sum.compare_memo("1+2+3");
+        let sum = comparison
+            .rules
+            .iter()
+            .find(|rule| rule.name == "sum")
+            .unwrap();
+        assert_eq!((sum.memo_calls, sum.plain_calls), (1, 2));
+    }
+}
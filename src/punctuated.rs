@@ -0,0 +1,43 @@
+use std::ops::Range;
+
+// items with the separators between them kept in order, `separators[i]` sits between
+// `items[i]` and `items[i + 1]` and carries the span it was parsed from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Punctuated<T, S> {
+    pub items: Vec<T>,
+    pub separators: Vec<(Range<usize>, S)>,
+}
+
+impl<T, S> Punctuated<T, S> {
+    pub fn new() -> Self {
+        Punctuated {
+            items: Vec::new(),
+            separators: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn pairs(&self) -> impl Iterator<Item = (&T, Option<&(Range<usize>, S)>)> {
+        self.items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| (item, self.separators.get(i)))
+    }
+
+    pub fn into_items(self) -> Vec<T> {
+        self.items
+    }
+}
+
+impl<T, S> Default for Punctuated<T, S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,174 @@
+use std::hash::{Hash, Hasher};
+use std::mem::discriminant;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use rustc_hash::FxHashMap;
+
+use crate::shape::{RuleShape, Shape};
+use crate::{Context, ParseError, Parser};
+
+// entry points shaped for incremental computation frameworks such as salsa: the parser
+// is compared by the grammar it describes, so rebuilding the same grammar gives an equal
+// key, and the outcome is a plain value that can be hashed and compared
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ParseOutcome<T> {
+    pub result: Result<T, ParseError>,
+    pub consumed: usize,
+}
+
+// parsers are equal when their shapes are, with rules matched by name and position in the
+// grammar; closures are only known by their labels, so two `satisfy` parsers with the same
+// label and different predicates, or two opaque parsers of the same name, count as equal
+impl<T: Clone + 'static> PartialEq for Parser<'_, T> {
+    fn eq(&self, other: &Self) -> bool {
+        let (a, b) = (self.as_rule(), other.as_rule());
+        same_rule(&a, &b, &mut FxHashMap::default(), &mut FxHashMap::default())
+    }
+}
+
+impl<T: Clone + 'static> Eq for Parser<'_, T> {}
+
+impl<T: Clone + 'static> Hash for Parser<'_, T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_rule(&self.as_rule(), state, &mut FxHashMap::default());
+    }
+}
+
+// rules already paired up stay paired, which is what ends the walk around a cycle
+fn same_rule(
+    a: &Rc<RuleShape>,
+    b: &Rc<RuleShape>,
+    pairs: &mut FxHashMap<usize, usize>,
+    back: &mut FxHashMap<usize, usize>,
+) -> bool {
+    match (pairs.get(&a.id()), back.get(&b.id())) {
+        (None, None) => {}
+        (a_pair, b_pair) => return a_pair == Some(&b.id()) && b_pair == Some(&a.id()),
+    }
+    if a.name != b.name {
+        return false;
+    }
+    pairs.insert(a.id(), b.id());
+    back.insert(b.id(), a.id());
+    match (a.body(), b.body()) {
+        (Some(a), Some(b)) => same_shape(a, b, pairs, back),
+        (a, b) => a.is_none() && b.is_none(),
+    }
+}
+
+fn same_shape(
+    a: &Shape,
+    b: &Shape,
+    pairs: &mut FxHashMap<usize, usize>,
+    back: &mut FxHashMap<usize, usize>,
+) -> bool {
+    match (a, b) {
+        (Shape::Char(a), Shape::Char(b)) => a == b,
+        (Shape::Keyword(a), Shape::Keyword(b)) => a == b,
+        (Shape::Any, Shape::Any) | (Shape::Eof, Shape::Eof) => true,
+        (Shape::Class(a, _), Shape::Class(b, _)) | (Shape::Opaque(a, _), Shape::Opaque(b, _)) => {
+            a == b
+        }
+        (Shape::Seq(a), Shape::Seq(b)) | (Shape::Choice(a), Shape::Choice(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same_shape(a, b, pairs, back))
+        }
+        (Shape::Many(a), Shape::Many(b))
+        | (Shape::Opt(a), Shape::Opt(b))
+        | (Shape::Not(a), Shape::Not(b))
+        | (Shape::And(a), Shape::And(b)) => same_shape(a, b, pairs, back),
+        (Shape::Rule(a), Shape::Rule(b)) => same_rule(a, b, pairs, back),
+        _ => false,
+    }
+}
+
+// a rule seen before is hashed as the order it was first seen in, so equal grammars hash
+// alike whatever their rules' addresses
+fn hash_rule<H: Hasher>(rule: &Rc<RuleShape>, state: &mut H, seen: &mut FxHashMap<usize, usize>) {
+    if let Some(index) = seen.get(&rule.id()) {
+        index.hash(state);
+        return;
+    }
+    seen.insert(rule.id(), seen.len());
+    rule.name.hash(state);
+    if let Some(body) = rule.body() {
+        hash_shape(body, state, seen);
+    }
+}
+
+fn hash_shape<H: Hasher>(shape: &Shape, state: &mut H, seen: &mut FxHashMap<usize, usize>) {
+    discriminant(shape).hash(state);
+    match shape {
+        Shape::Char(c) => c.hash(state),
+        Shape::Keyword(label) | Shape::Class(label, _) | Shape::Opaque(label, _) => {
+            label.hash(state)
+        }
+        Shape::Any | Shape::Eof => {}
+        Shape::Seq(items) | Shape::Choice(items) => {
+            items.len().hash(state);
+            for item in items {
+                hash_shape(item, state, seen);
+            }
+        }
+        Shape::Many(item) | Shape::Opt(item) | Shape::Not(item) | Shape::And(item) => {
+            hash_shape(item, state, seen)
+        }
+        Shape::Rule(rule) => hash_rule(rule, state, seen),
+    }
+}
+
+pub fn parse_file<T: Clone + 'static>(parser: &Parser<'_, T>, text: &str) -> Arc<ParseOutcome<T>> {
+    let ctx = &mut Context::new(text);
+    let outcome = match parser.parse(0, ctx) {
+        Ok((pos, val)) => ParseOutcome {
+            result: Ok(val),
+            consumed: pos,
+        },
+        Err(e) => ParseOutcome {
+            result: Err(e),
+            consumed: 0,
+        },
+    };
+    Arc::new(outcome)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_parse_file() {
+        let digits = satisfy("digit", |c| c.is_ascii_digit()).many_collect::<String>();
+
+        let first = parse_file(&digits, "123a");
+        let second = parse_file(&digits.clone(), "123a");
+        assert_eq!(first, second);
+        assert_eq!(first.consumed, 3);
+        assert_eq!(first.result, Ok(String::from("123")));
+        assert_ne!(first, parse_file(&digits, "12"));
+
+        let outcomes: HashSet<_> = [first, second].into_iter().collect();
+        assert_eq!(outcomes.len(), 1);
+        // parsers hash by the grammar they describe, the memo they carry inside never
+        // takes part, so a grammar built twice is the same key
+        let rebuilt = satisfy("digit", |c| c.is_ascii_digit()).many_collect::<String>();
+        assert!(digits == rebuilt);
+        #[allow(clippy::mutable_key_type)]
+        let grammars: HashSet<_> = [digits.clone(), rebuilt, digits].into_iter().collect();
+        assert_eq!(grammars.len(), 1);
+
+        // rules are compared through their cycles, by name and by where they are called
+        assert!(fixture::sum() == fixture::sum());
+        let other = lazy("sum", |sum| sum.andl(char('-')).or(fixture::digit()));
+        assert!(fixture::sum() != other);
+        let renamed = lazy("total", |sum| sum.andl(char('+')).or(fixture::digit()));
+        let sum = lazy("sum", |sum| sum.andl(char('+')).or(fixture::digit()));
+        assert!(sum != renamed);
+        #[allow(clippy::mutable_key_type)]
+        let grammars: HashSet<_> = [fixture::sum(), fixture::sum(), other]
+            .into_iter()
+            .collect();
+        assert_eq!(grammars.len(), 2);
+    }
+}
@@ -0,0 +1,344 @@
+use std::fmt::Write;
+
+use crate::Parser;
+use crate::peg::shape_to_peg;
+use crate::shape::Shape;
+
+// spacing of the layout, in SVG user units
+const GAP: f64 = 10.0;
+const RADIUS: f64 = 10.0;
+const BOX_HEIGHT: f64 = 22.0;
+const CHAR_WIDTH: f64 = 8.0;
+
+// a diagram laid out around a horizontal line it is entered and left on, the lengths it
+// takes up above and below that line are `up` and `down`
+enum Diagram {
+    Skip,
+    // a literal or class in a rounded box, or a rule reference in a square one
+    Terminal(String),
+    NonTerminal(String),
+    Sequence(Vec<Diagram>),
+    // the first alternative runs along the line, the others branch off below it
+    Choice(Vec<Diagram>),
+    OneOrMore(Box<Diagram>),
+    // a lookahead, drawn as a dashed frame labelled `!` or `&`
+    Frame(char, Box<Diagram>),
+}
+
+impl Diagram {
+    fn from_shape(shape: &Shape) -> Diagram {
+        match shape {
+            Shape::Seq(items) if items.is_empty() => Diagram::Skip,
+            Shape::Seq(items) => {
+                Diagram::Sequence(items.iter().map(|i| Diagram::from_shape(i)).collect())
+            }
+            Shape::Choice(items) => {
+                Diagram::Choice(items.iter().map(|i| Diagram::from_shape(i)).collect())
+            }
+            Shape::Many(item) => Diagram::Choice(vec![
+                Diagram::Skip,
+                Diagram::OneOrMore(Box::new(Diagram::from_shape(item))),
+            ]),
+            Shape::Opt(item) => Diagram::Choice(vec![Diagram::Skip, Diagram::from_shape(item)]),
+            Shape::Not(item) => Diagram::Frame('!', Box::new(Diagram::from_shape(item))),
+            Shape::And(item) => Diagram::Frame('&', Box::new(Diagram::from_shape(item))),
+            Shape::Rule(rule) => Diagram::NonTerminal(rule.name.clone()),
+            _ => Diagram::Terminal(shape_to_peg(shape)),
+        }
+    }
+
+    fn width(&self) -> f64 {
+        match self {
+            Diagram::Skip => 0.0,
+            Diagram::Terminal(text) | Diagram::NonTerminal(text) => {
+                text.chars().count() as f64 * CHAR_WIDTH + 2.0 * GAP
+            }
+            Diagram::Sequence(items) => {
+                items.iter().map(Diagram::width).sum::<f64>()
+                    + GAP * items.len().saturating_sub(1) as f64
+            }
+            Diagram::Choice(items) => {
+                items.iter().map(Diagram::width).fold(0.0, f64::max) + 4.0 * RADIUS
+            }
+            Diagram::OneOrMore(item) => item.width() + 2.0 * RADIUS,
+            Diagram::Frame(_, item) => item.width() + 2.0 * GAP,
+        }
+    }
+
+    fn up(&self) -> f64 {
+        match self {
+            Diagram::Skip => 0.0,
+            Diagram::Terminal(_) | Diagram::NonTerminal(_) => BOX_HEIGHT / 2.0,
+            Diagram::Sequence(items) => items.iter().map(Diagram::up).fold(0.0, f64::max),
+            Diagram::Choice(items) => items.first().map_or(0.0, Diagram::up),
+            Diagram::OneOrMore(item) => item.up(),
+            Diagram::Frame(_, item) => item.up() + BOX_HEIGHT,
+        }
+    }
+
+    fn down(&self) -> f64 {
+        match self {
+            Diagram::Skip => 0.0,
+            Diagram::Terminal(_) | Diagram::NonTerminal(_) => BOX_HEIGHT / 2.0,
+            Diagram::Sequence(items) => items.iter().map(Diagram::down).fold(0.0, f64::max),
+            Diagram::Choice(items) => items
+                .iter()
+                .zip(self.branch_offsets())
+                .map(|(item, offset)| offset + item.down())
+                .fold(0.0, f64::max),
+            Diagram::OneOrMore(item) => loop_offset(item),
+            Diagram::Frame(_, item) => item.down() + GAP,
+        }
+    }
+
+    // how far below the line each alternative of a choice runs
+    fn branch_offsets(&self) -> Vec<f64> {
+        let Diagram::Choice(items) = self else {
+            return Vec::new();
+        };
+        let mut offsets = Vec::new();
+        let mut offset = 0.0;
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                let previous = &items[i - 1];
+                offset += f64::max(previous.down() + GAP + item.up(), 2.0 * RADIUS);
+            }
+            offsets.push(offset);
+        }
+        offsets
+    }
+
+    // draws the diagram entered at (`x`, `y`)
+    fn draw(&self, x: f64, y: f64, out: &mut String) {
+        let width = self.width();
+        match self {
+            Diagram::Skip => {}
+            Diagram::Terminal(text) | Diagram::NonTerminal(text) => {
+                let rounded = if matches!(self, Diagram::Terminal(_)) {
+                    RADIUS
+                } else {
+                    0.0
+                };
+                let _ = writeln!(
+                    out,
+                    "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"{}\"/>",
+                    x,
+                    y - BOX_HEIGHT / 2.0,
+                    width,
+                    BOX_HEIGHT,
+                    rounded
+                );
+                let _ = writeln!(
+                    out,
+                    "<text x=\"{}\" y=\"{}\">{}</text>",
+                    x + width / 2.0,
+                    y,
+                    escape(text)
+                );
+            }
+            Diagram::Sequence(items) => {
+                let mut x = x;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        line(out, x, y, x + GAP, y);
+                        x += GAP;
+                    }
+                    item.draw(x, y, out);
+                    x += item.width();
+                }
+            }
+            Diagram::Choice(items) => {
+                let inner = x + 2.0 * RADIUS;
+                let end = x + width;
+                for (item, offset) in items.iter().zip(self.branch_offsets()) {
+                    let branch = y + offset;
+                    if offset == 0.0 {
+                        line(out, x, y, inner, y);
+                    } else {
+                        let _ = writeln!(
+                            out,
+                            "<path d=\"M{x} {y} Q{a} {y} {a} {b} L{a} {c} Q{a} {branch} {inner} {branch}\"/>",
+                            a = x + RADIUS,
+                            b = y + RADIUS,
+                            c = branch - RADIUS,
+                        );
+                        let _ = writeln!(
+                            out,
+                            "<path d=\"M{d} {branch} Q{a} {branch} {a} {c} L{a} {b} Q{a} {y} {end} {y}\"/>",
+                            d = end - 2.0 * RADIUS,
+                            a = end - RADIUS,
+                            b = y + RADIUS,
+                            c = branch - RADIUS,
+                        );
+                    }
+                    item.draw(inner, branch, out);
+                    line(
+                        out,
+                        inner + item.width(),
+                        branch,
+                        end - 2.0 * RADIUS,
+                        branch,
+                    );
+                    if offset == 0.0 {
+                        line(out, end - 2.0 * RADIUS, y, end, y);
+                    }
+                }
+            }
+            Diagram::OneOrMore(item) => {
+                let back = y + loop_offset(item);
+                let end = x + width;
+                line(out, x, y, x + RADIUS, y);
+                item.draw(x + RADIUS, y, out);
+                line(out, end - RADIUS, y, end, y);
+                let _ = writeln!(
+                    out,
+                    "<path d=\"M{r} {y} Q{end} {y} {end} {b} L{end} {c} Q{end} {back} {r} {back} L{l} {back} Q{x} {back} {x} {c} L{x} {b} Q{x} {y} {l} {y}\"/>",
+                    r = end - RADIUS,
+                    l = x + RADIUS,
+                    b = y + RADIUS,
+                    c = back - RADIUS,
+                );
+            }
+            Diagram::Frame(label, item) => {
+                let _ = writeln!(
+                    out,
+                    "<rect class=\"frame\" x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"/>",
+                    x,
+                    y - self.up() + BOX_HEIGHT / 2.0,
+                    width,
+                    self.up() + self.down() - BOX_HEIGHT / 2.0
+                );
+                let _ = writeln!(
+                    out,
+                    "<text x=\"{}\" y=\"{}\">{}</text>",
+                    x + GAP,
+                    y - item.up() - GAP,
+                    label
+                );
+                line(out, x, y, x + GAP, y);
+                item.draw(x + GAP, y, out);
+                line(out, x + width - GAP, y, x + width, y);
+            }
+        }
+    }
+}
+
+// how far below the line the way back of a repetition runs
+fn loop_offset(item: &Diagram) -> f64 {
+    f64::max(item.down() + GAP, 2.0 * RADIUS)
+}
+
+fn line(out: &mut String, x1: f64, y1: f64, x2: f64, y2: f64) {
+    if x1 != x2 || y1 != y2 {
+        let _ = writeln!(out, "<path d=\"M{} {} L{} {}\"/>", x1, y1, x2, y2);
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// a standalone SVG document with the rule's name above its diagram, which starts and ends
+// with a short bar like the railroad diagrams in language references
+pub(crate) fn railroad_svg(name: &str, shape: &Shape) -> String {
+    let diagram = Diagram::from_shape(shape);
+    let title = BOX_HEIGHT;
+    let width = diagram.width() + 6.0 * GAP;
+    let height = title + diagram.up() + diagram.down() + 2.0 * GAP;
+    let y = title + GAP + diagram.up();
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">",
+        width, height, width, height
+    );
+    out.push_str(concat!(
+        "<style>path, rect { fill: none; stroke: black; stroke-width: 1.5 } ",
+        ".frame { stroke-dasharray: 4 } ",
+        "text { font: 12px monospace; text-anchor: middle; dominant-baseline: central } ",
+        ".title { font-weight: bold; text-anchor: start }</style>\n"
+    ));
+    let _ = writeln!(
+        out,
+        "<text class=\"title\" x=\"{}\" y=\"{}\">{}</text>",
+        GAP,
+        title / 2.0,
+        escape(name)
+    );
+    let _ = writeln!(
+        out,
+        "<path d=\"M{} {} L{} {}\"/>",
+        GAP,
+        y - GAP,
+        GAP,
+        y + GAP
+    );
+    line(&mut out, GAP, y, 3.0 * GAP, y);
+    diagram.draw(3.0 * GAP, y, &mut out);
+    let end = 3.0 * GAP + diagram.width();
+    line(&mut out, end, y, end + 2.0 * GAP, y);
+    let _ = writeln!(
+        out,
+        "<path d=\"M{} {} L{} {}\"/>",
+        end + 2.0 * GAP,
+        y - GAP,
+        end + 2.0 * GAP,
+        y + GAP
+    );
+    out.push_str("</svg>\n");
+    out
+}
+
+impl<'a, T> Parser<'a, T>
+where
+    T: Clone + 'static,
+{
+    /// Draws this parser as an SVG railroad diagram. Rules it references appear as boxes
+    /// with their names, see [`Grammar::to_railroad`](crate::Grammar::to_railroad) for one
+    /// diagram per rule.
+    pub fn to_railroad(&self) -> String {
+        let rule = self.as_rule();
+        match rule.body() {
+            Some(body) => railroad_svg(&rule.name, body),
+            None => railroad_svg(&rule.name, &Shape::Seq(Vec::new())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn test_railroad() {
+        let mut g = GrammarBuilder::new();
+        g.rule("list", |g| {
+            char('[')
+                .andr(g.get::<char>("item").sep_by(char(',')))
+                .andl(char(']'))
+        })
+        .rule("item", |_| {
+            satisfy("[a-z]", |c| c.is_ascii_lowercase())
+                .andl(char('<').not_ahead())
+                .or(char('*'))
+        });
+        let diagrams = g.build().unwrap().to_railroad();
+
+        assert_eq!(diagrams.keys().collect::<Vec<_>>(), ["item", "list"]);
+        let list = &diagrams["list"];
+        assert!(list.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+        assert!(list.ends_with("</svg>\n"));
+        assert!(list.contains(">list</text>"));
+        assert!(list.contains(">item</text>"));
+        assert!(list.contains(">'['</text>"));
+        assert!(diagrams["item"].contains(">'&lt;'</text>"));
+        assert!(diagrams["item"].contains("class=\"frame\""));
+
+        let svg = char('a').many().to_railroad();
+        assert_eq!(svg.matches("<rect").count(), 1);
+    }
+}
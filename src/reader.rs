@@ -0,0 +1,385 @@
+use std::io::{self, ErrorKind, Read};
+
+use crate::{Context, ParseError, Parser, Reason};
+
+// the smallest read asked of a reader; after that each read waits for as much as is
+// buffered already, so the input doubles and parsing it again from the start costs linear
+// time overall
+const MIN_READ: usize = 8 * 1024;
+
+// bytes read so far decoded as UTF-8 into a streaming context, whatever they are read with
+pub(crate) struct Feeder {
+    pub(crate) ctx: Context,
+    // the start of a char split across two reads
+    pending: Vec<u8>,
+    eof: bool,
+    // where the record being parsed starts; the records before it are only dropped from
+    // the context once they make up half of it, so each byte is copied at most once
+    start: usize,
+}
+
+impl Feeder {
+    pub(crate) fn new() -> Self {
+        Feeder {
+            ctx: Context::new("").with_streaming(),
+            pending: Vec::new(),
+            eof: false,
+            start: 0,
+        }
+    }
+
+    pub(crate) fn want(&self) -> usize {
+        (self.ctx.source.len() - self.start).max(MIN_READ)
+    }
+
+    pub(crate) fn is_drained(&self) -> bool {
+        self.eof && self.start == self.ctx.source.len()
+    }
+
+    // takes the bytes of one read, none meaning the input is over
+    pub(crate) fn feed(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if bytes.is_empty() {
+            if !self.pending.is_empty() {
+                return Err(invalid_utf8());
+            }
+            self.eof = true;
+            self.ctx.finish();
+            return Ok(());
+        }
+        self.pending.extend_from_slice(bytes);
+        let valid = match std::str::from_utf8(&self.pending) {
+            Ok(text) => text.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => return Err(invalid_utf8()),
+        };
+        let text = std::str::from_utf8(&self.pending[..valid]).unwrap();
+        self.ctx.feed(text);
+        self.pending.drain(..valid);
+        Ok(())
+    }
+
+    // parses at the start of the record, None if more input is needed to decide
+    pub(crate) fn parse<T: Clone + 'static>(
+        &mut self,
+        parser: &Parser<'_, T>,
+    ) -> Option<Result<(usize, T), ParseError>> {
+        match parser.parse(self.start, &mut self.ctx) {
+            Err(e) if e.is_incomplete() => None,
+            result => Some(result),
+        }
+    }
+
+    // moves past the text a record was parsed from
+    pub(crate) fn consume(&mut self, end: usize) {
+        self.start = end;
+        if self.start * 2 >= self.ctx.source.len() {
+            self.compact();
+        }
+    }
+
+    // drops the text of the records already parsed, so the next one starts at 0
+    fn compact(&mut self) {
+        let len = self.ctx.source.len();
+        let rest = self.ctx.source.slice(self.start..len).to_string();
+        self.ctx.reset(rest);
+        self.start = 0;
+        if self.eof {
+            self.ctx.finish();
+        }
+    }
+
+    // what the next record in the input is, None once it is all parsed
+    pub(crate) fn next_record<T: Clone + 'static>(
+        &mut self,
+        parser: &Parser<'_, T>,
+    ) -> Option<Option<Result<T, ParseError>>> {
+        if self.is_drained() {
+            return Some(None);
+        }
+        match self.parse(parser)? {
+            Ok((end, _)) if end == self.start => {
+                self.compact();
+                Some(Some(Err(ParseError {
+                    source: self.ctx.clone_source(),
+                    pos: 0,
+                    reason: Reason::Message("record parser matched nothing"),
+                })))
+            }
+            Ok((end, val)) => {
+                self.consume(end);
+                Some(Some(Ok(val)))
+            }
+            // parsed again on its own so the error counts from the start of the record;
+            // iteration stops at it, so this happens once
+            Err(_) if self.start > 0 => {
+                self.compact();
+                self.next_record(parser)
+            }
+            Err(e) => Some(Some(Err(e))),
+        }
+    }
+}
+
+fn invalid_utf8() -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, "stream did not contain valid UTF-8")
+}
+
+// reads until `buf` is full or the input is over, so the text is parsed again only once it
+// has doubled rather than after every short read a pipe or socket hands back
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+impl<'a, T> Parser<'a, T>
+where
+    T: Clone + 'static,
+{
+    /// Like `run`, but reads the text from `reader`, only as far as the parser needs to
+    /// decide: it is parsed as it arrives, and more is read whenever the parse is
+    /// incomplete. Failing to read, or text that isn't UTF-8, is the outer error.
+    pub fn run_reader(&self, mut reader: impl Read) -> io::Result<Result<T, ParseError>> {
+        let mut feeder = Feeder::new();
+        loop {
+            let mut buf = vec![0; feeder.want()];
+            let n = read_full(&mut reader, &mut buf)?;
+            feeder.feed(&buf[..n])?;
+            if let Some(result) = feeder.parse(self) {
+                return Ok(result.map(|(_, val)| val));
+            }
+        }
+    }
+
+    /// Parses the text from `reader` as a sequence of records this parser matches one at a
+    /// time, like the lines of a log, keeping only the record being parsed in memory, so
+    /// inputs larger than memory can be worked through. Error positions are relative to
+    /// the start of the record; iteration stops after the first error.
+    pub fn run_reader_each<R: Read>(
+        &self,
+        mut reader: R,
+    ) -> impl Iterator<Item = io::Result<Result<T, ParseError>>> {
+        let mut feeder = Feeder::new();
+        let mut buf = Vec::new();
+        let mut failed = false;
+        std::iter::from_fn(move || {
+            if failed {
+                return None;
+            }
+            let record = loop {
+                if let Some(record) = feeder.next_record(self) {
+                    break record;
+                }
+                buf.resize(feeder.want(), 0);
+                let read = read_full(&mut reader, &mut buf).and_then(|n| feeder.feed(&buf[..n]));
+                if let Err(e) = read {
+                    failed = true;
+                    return Some(Err(e));
+                }
+            };
+            failed = matches!(record, Some(Err(_)));
+            record.map(Ok)
+        })
+    }
+}
+
+// `read_full` for an async reader
+#[cfg(feature = "futures")]
+async fn read_full_async(
+    reader: &mut (impl futures_io::AsyncRead + Unpin),
+    buf: &mut [u8],
+) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = std::future::poll_fn(|cx| {
+            loop {
+                match std::pin::Pin::new(&mut *reader).poll_read(cx, &mut buf[filled..]) {
+                    std::task::Poll::Ready(Err(e)) if e.kind() == ErrorKind::Interrupted => {}
+                    poll => return poll,
+                }
+            }
+        })
+        .await?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    Ok(filled)
+}
+
+#[cfg(feature = "futures")]
+impl<'a, T> Parser<'a, T>
+where
+    T: Clone + 'static,
+{
+    /// Like `run_reader`, but awaits the text from an async reader, so a server doesn't have
+    /// to block a thread on it. Tokio readers can be passed through `tokio_util::compat`.
+    pub async fn run_async(
+        &self,
+        mut reader: impl futures_io::AsyncRead + Unpin,
+    ) -> io::Result<Result<T, ParseError>> {
+        let mut feeder = Feeder::new();
+        loop {
+            let mut buf = vec![0; feeder.want()];
+            let n = read_full_async(&mut reader, &mut buf).await?;
+            feeder.feed(&buf[..n])?;
+            if let Some(result) = feeder.parse(self) {
+                return Ok(result.map(|(_, val)| val));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Read};
+
+    use super::{Feeder, read_full};
+    use crate::*;
+
+    // hands out its text a few bytes at a time, splitting chars, like a pipe would
+    struct Trickle<'s>(&'s [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.0.len()).min(3);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_run_reader() {
+        let words = satisfy("letter", char::is_alphabetic)
+            .many_collect::<String>()
+            .sep_by(char(' '))
+            .end();
+        let text = "grüße aus köln";
+        assert_eq!(
+            words.run_reader(Trickle(text.as_bytes())).unwrap(),
+            words.run(text)
+        );
+
+        let error = words.run_reader(Trickle(b"ab 1")).unwrap().unwrap_err();
+        assert_eq!(error.pos(), 3);
+        let error = words.run_reader(Trickle(b"ab \xff")).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_run_reader_each() {
+        let line = satisfy("digit", |c| c.is_ascii_digit())
+            .many_collect::<String>()
+            .andl(char('\n'));
+        let log = (0..5000).map(|i| format!("{}\n", i)).collect::<String>();
+        let lines: Vec<String> = line
+            .run_reader_each(log.as_bytes())
+            .map(|line| line.unwrap().unwrap())
+            .collect();
+        assert_eq!(lines.len(), 5000);
+        assert_eq!(lines[4999], "4999");
+
+        let mut lines = line.run_reader_each(Trickle(b"12\n3x\n4\n"));
+        assert_eq!(lines.next().unwrap().unwrap(), Ok(String::from("12")));
+        let error = lines.next().unwrap().unwrap().unwrap_err();
+        assert_eq!(error.pos(), 1);
+        assert!(lines.next().is_none());
+    }
+
+    #[test]
+    fn test_read_full() {
+        let mut buf = [0; 8];
+        let mut reader = Trickle(b"0123456789");
+        assert_eq!(read_full(&mut reader, &mut buf).unwrap(), 8);
+        assert_eq!(&buf, b"01234567");
+        assert_eq!(read_full(&mut reader, &mut buf).unwrap(), 2);
+        assert_eq!(read_full(&mut reader, &mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_feeder_consume() {
+        let line = satisfy("digit", |c| c.is_ascii_digit()).andl(char('\n'));
+        let mut feeder = Feeder::new();
+        feeder.feed(b"1\n2\n3\n4\n5\n").unwrap();
+        // parsed records stay in the context until they are half of it
+        assert_eq!(feeder.next_record(&line), Some(Some(Ok('1'))));
+        assert_eq!(feeder.next_record(&line), Some(Some(Ok('2'))));
+        assert_eq!(feeder.ctx.source().len(), 10);
+        assert_eq!(feeder.next_record(&line), Some(Some(Ok('3'))));
+        assert_eq!(feeder.ctx.source().to_string(), "4\n5\n");
+
+        // errors count from the start of their record
+        let mut feeder = Feeder::new();
+        feeder.feed(b"1\n2x\n3\n4\n5\n").unwrap();
+        assert_eq!(feeder.next_record(&line), Some(Some(Ok('1'))));
+        let error = feeder.next_record(&line).unwrap().unwrap().unwrap_err();
+        assert_eq!(error.pos(), 1);
+        assert!(error.to_string().starts_with("2x\n3\n"));
+    }
+
+    #[cfg(feature = "futures")]
+    #[test]
+    fn test_run_async() {
+        use std::pin::Pin;
+        use std::task::{Context, Poll, Waker};
+
+        // like Trickle, but every other read isn't ready yet
+        struct Slow<'s>(Trickle<'s>, bool);
+
+        impl futures_io::AsyncRead for Slow<'_> {
+            fn poll_read(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &mut [u8],
+            ) -> Poll<io::Result<usize>> {
+                self.1 = !self.1;
+                if self.1 {
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                Poll::Ready(self.0.read(buf))
+            }
+        }
+
+        // polls `future` to completion, counting how often it wasn't ready
+        fn block_on<F: Future>(future: F) -> (F::Output, usize) {
+            let mut future = std::pin::pin!(future);
+            let mut pending = 0;
+            loop {
+                match future
+                    .as_mut()
+                    .poll(&mut Context::from_waker(Waker::noop()))
+                {
+                    Poll::Ready(output) => return (output, pending),
+                    Poll::Pending => pending += 1,
+                }
+            }
+        }
+
+        let words = satisfy("letter", char::is_alphabetic)
+            .many_collect::<String>()
+            .sep_by(char(' '))
+            .end();
+        let text = "grüße aus köln";
+        let (result, pending) = block_on(words.run_async(Slow(Trickle(text.as_bytes()), false)));
+        assert!(pending > 5);
+        assert_eq!(result.unwrap(), words.run(text));
+
+        // short reads are gathered until the buffer is full
+        let mut buf = [0; 8];
+        let mut reader = Slow(Trickle(b"0123456789"), false);
+        let (n, _) = block_on(super::read_full_async(&mut reader, &mut buf));
+        assert_eq!(n.unwrap(), 8);
+        let (n, _) = block_on(super::read_full_async(&mut reader, &mut buf));
+        assert_eq!(n.unwrap(), 2);
+    }
+}
@@ -0,0 +1,127 @@
+use std::ops::Range;
+
+use crate::{Context, Found, ParseError, Parser, Reason, Source};
+
+/// One open document, the way a language server holds it: edits are applied as they
+/// arrive, and the document is parsed again only when its tree or diagnostics are asked
+/// for, reusing everything the edits since the last parse left alone.
+///
+/// ```
+/// use packrust::*;
+///
+/// let digits = satisfy("digit", |c| c.is_ascii_digit()).many_collect::<String>();
+/// let mut session = Session::open(digits.sep_by(char(',')), "1,22,3");
+/// assert_eq!(session.tree(), Some(&vec![String::from("1"), String::from("22"), String::from("3")]));
+///
+/// session.edit(2..4, "x");
+/// assert_eq!(session.tree(), None);
+/// assert_eq!(session.diagnostics()[0].range, 2..3);
+/// ```
+pub struct Session<'a, T> {
+    parser: Parser<'a, T>,
+    ctx: Context,
+    // the outcome of the last parse, None once an edit has made it stale
+    parsed: Option<Result<T, ParseError>>,
+}
+
+/// A problem found in a document, over the byte range of the text it is about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub range: Range<usize>,
+    pub message: String,
+}
+
+impl<'a, T> Session<'a, T>
+where
+    T: Clone + 'static,
+{
+    /// Starts a session for a document containing `text`, parsed as a whole by `parser`.
+    pub fn open(parser: Parser<'a, T>, text: impl Into<String>) -> Self {
+        Session {
+            parser,
+            ctx: Context::new(text),
+            parsed: None,
+        }
+    }
+
+    /// Replaces the text in `range`, a byte range of the current text, with `text`.
+    pub fn edit(&mut self, range: Range<usize>, text: &str) {
+        self.ctx.edit(range, text);
+        self.parsed = None;
+    }
+
+    pub fn text(&self) -> &Source {
+        self.ctx.source()
+    }
+
+    /// What the parser produced for the whole document, None if it doesn't parse.
+    pub fn tree(&mut self) -> Option<&T> {
+        self.parse().as_ref().ok()
+    }
+
+    /// Why the document doesn't parse, empty when it does.
+    pub fn diagnostics(&mut self) -> Vec<Diagnostic> {
+        let Err(error) = self.parse() else {
+            return Vec::new();
+        };
+        let (start, message) = (error.offset(), error.reason.to_string());
+        let len = self.ctx.source.get(start).map_or(0, char::len_utf8);
+        vec![Diagnostic {
+            range: start..start + len,
+            message,
+        }]
+    }
+
+    fn parse(&mut self) -> &Result<T, ParseError> {
+        self.parsed.get_or_insert_with(|| {
+            let ctx = &mut self.ctx;
+            match self.parser.parse(0, ctx) {
+                Ok((pos, val)) if pos == ctx.source.len() => Ok(val),
+                Ok((pos, _)) => Err(ParseError {
+                    source: ctx.clone_source(),
+                    pos,
+                    reason: Reason::ExpectedEof {
+                        found: Found::from(ctx.source.get(pos)),
+                    },
+                }),
+                Err(error) => Err(error),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn test_session() {
+        let digit = satisfy("digit", |c| c.is_ascii_digit());
+        let number = digit.clone().and(digit.many()).map(|(first, rest)| {
+            rest.into_iter().fold(first.to_digit(10).unwrap(), |n, d| {
+                n * 10 + d.to_digit(10).unwrap()
+            })
+        });
+        let list = number.sep_by(char(','));
+
+        let mut session = Session::open(list, "1,2,3");
+        assert_eq!(session.tree(), Some(&vec![1, 2, 3]));
+        assert!(session.diagnostics().is_empty());
+
+        session.edit(2..3, "");
+        session.edit(2..2, "é");
+        assert_eq!(session.text().to_string(), "1,é,3");
+        assert_eq!(session.tree(), None);
+        assert_eq!(
+            session.diagnostics(),
+            [Diagnostic {
+                range: 1..2,
+                message: String::from("expected EOF found ,"),
+            }]
+        );
+
+        session.edit(2..4, "20");
+        assert_eq!(session.tree(), Some(&vec![1, 20, 3]));
+        assert!(session.diagnostics().is_empty());
+    }
+}
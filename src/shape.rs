@@ -0,0 +1,85 @@
+use std::cell::OnceCell;
+use std::rc::Rc;
+
+use crate::{Context, ParseError, Pos};
+
+pub(crate) type Predicate<'a> = Rc<dyn Fn(char) -> bool + 'a>;
+pub(crate) type Recognizer<'a> = Rc<dyn Fn(Pos, &mut Context) -> Result<Pos, ParseError> + 'a>;
+
+// what a parser matches, recorded alongside its closure so that whole grammars can be
+// inspected and lowered without running them; semantic actions like `map` leave the shape
+// untouched, and anything the combinators can't describe stays an opaque call
+pub(crate) enum Shape<'a> {
+    Char(char),
+    Keyword(Rc<str>),
+    Any,
+    // a `satisfy` predicate, known only by its label
+    Class(Rc<str>, Predicate<'a>),
+    Seq(Vec<Rc<Shape<'a>>>),
+    Choice(Vec<Rc<Shape<'a>>>),
+    Many(Rc<Shape<'a>>),
+    Opt(Rc<Shape<'a>>),
+    // lookahead, matching without consuming
+    Not(Rc<Shape<'a>>),
+    And(Rc<Shape<'a>>),
+    Eof,
+    // a named rule made with `lazy` or a grammar builder, the only place shapes can cycle
+    Rule(Rc<RuleShape<'a>>),
+    // any other parser, known only by its name
+    Opaque(Rc<str>, Recognizer<'a>),
+}
+
+pub(crate) struct RuleShape<'a> {
+    pub(crate) name: String,
+    pub(crate) body: OnceCell<Rc<Shape<'a>>>,
+}
+
+impl<'a> RuleShape<'a> {
+    pub(crate) fn new(name: &str) -> Rc<Self> {
+        Rc::new(RuleShape {
+            name: name.to_string(),
+            body: OnceCell::new(),
+        })
+    }
+
+    pub(crate) fn body(&self) -> Option<&Rc<Shape<'a>>> {
+        self.body.get()
+    }
+
+    // rules are compared by address, two rules may well share a name
+    pub(crate) fn id(self: &Rc<Self>) -> usize {
+        Rc::as_ptr(self).addr()
+    }
+}
+
+impl<'a> Shape<'a> {
+    pub(crate) fn seq(left: &Rc<Shape<'a>>, right: &Rc<Shape<'a>>) -> Rc<Shape<'a>> {
+        let mut items = Vec::new();
+        for shape in [left, right] {
+            match shape.as_ref() {
+                Shape::Seq(inner) => items.extend(inner.iter().cloned()),
+                _ => items.push(shape.clone()),
+            }
+        }
+        Rc::new(Shape::Seq(items))
+    }
+
+    pub(crate) fn choice(left: &Rc<Shape<'a>>, right: &Rc<Shape<'a>>) -> Rc<Shape<'a>> {
+        let mut items = Vec::new();
+        for shape in [left, right] {
+            match shape.as_ref() {
+                Shape::Choice(inner) => items.extend(inner.iter().cloned()),
+                _ => items.push(shape.clone()),
+            }
+        }
+        Rc::new(Shape::Choice(items))
+    }
+
+    pub(crate) fn many(item: &Rc<Shape<'a>>) -> Rc<Shape<'a>> {
+        Rc::new(Shape::Many(item.clone()))
+    }
+
+    pub(crate) fn opt(item: &Rc<Shape<'a>>) -> Rc<Shape<'a>> {
+        Rc::new(Shape::Opt(item.clone()))
+    }
+}
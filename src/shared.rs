@@ -0,0 +1,140 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::sync::{Arc, Weak};
+
+use crate::{ParseError, Parser, PartialParse};
+
+type Build<T> = dyn Fn() -> Parser<'static, T> + Send + Sync;
+
+// a `Parser<'static, T>`, by the definition it was built from
+type Built = (Weak<dyn Any + Send + Sync>, Box<dyn Any>);
+
+struct Definition<T> {
+    build: Box<Build<T>>,
+}
+
+thread_local! {
+    // the parsers this thread built; entries whose definition is gone are dropped whenever
+    // a new one is added
+    static BUILT: RefCell<Vec<Built>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A parser that can be shared between threads, to parse many inputs in parallel with the
+/// same grammar. A [`Parser`] owns its memo tables and the closures it was made of, neither
+/// of which can cross threads, so what is shared is the function that builds it: each
+/// thread calls it once, the first time it uses the parser, and keeps the result.
+///
+/// ```
+/// use packrust::*;
+///
+/// let digits = SharedParser::new(|| satisfy("digit", |c| c.is_ascii_digit()).many());
+/// let lengths: Vec<usize> = std::thread::scope(|scope| {
+///     let digits = &digits;
+///     ["1", "22", "333"]
+///         .map(|input| scope.spawn(move || digits.run(input).map(|d| d.len())))
+///         .map(|thread| thread.join().unwrap().unwrap())
+///         .into()
+/// });
+/// assert_eq!(lengths, [1, 2, 3]);
+/// ```
+pub struct SharedParser<T> {
+    definition: Arc<Definition<T>>,
+}
+
+impl<T> Clone for SharedParser<T> {
+    fn clone(&self) -> Self {
+        SharedParser {
+            definition: self.definition.clone(),
+        }
+    }
+}
+
+impl<T> SharedParser<T>
+where
+    T: Clone + 'static,
+{
+    pub fn new(build: impl Fn() -> Parser<'static, T> + Send + Sync + 'static) -> Self {
+        SharedParser {
+            definition: Arc::new(Definition {
+                build: Box::new(build),
+            }),
+        }
+    }
+
+    /// This thread's instance of the parser, built on first use.
+    pub fn local(&self) -> Parser<'static, T> {
+        let built = BUILT.with_borrow(|built| {
+            built
+                .iter()
+                .find(|(definition, _)| {
+                    std::ptr::addr_eq(definition.as_ptr(), Arc::as_ptr(&self.definition))
+                })
+                .map(|(_, parser)| parser.downcast_ref::<Parser<'static, T>>().unwrap().clone())
+        });
+        if let Some(parser) = built {
+            return parser;
+        }
+
+        // built without holding on to the table, the grammar may use shared parsers itself
+        let parser = (self.definition.build)();
+        let definition: Weak<Definition<T>> = Arc::downgrade(&self.definition);
+        let definition: Weak<dyn Any + Send + Sync> = definition;
+        BUILT.with_borrow_mut(|built| {
+            built.retain(|(definition, _)| definition.strong_count() > 0);
+            built.push((definition, Box::new(parser.clone())));
+        });
+        parser
+    }
+
+    pub fn run(&self, source: impl Into<String>) -> Result<T, ParseError> {
+        self.local().run(source)
+    }
+
+    /// See [`Parser::run_partial`], which returns no prefix when the parser fails outright.
+    pub fn run_partial(&self, source: impl Into<String>) -> Result<T, PartialParse<T>> {
+        self.local().run_partial(source)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::*;
+
+    fn send_sync<S: Send + Sync>() {}
+
+    #[test]
+    fn test_shared_parser() {
+        send_sync::<SharedParser<i32>>();
+        send_sync::<ParseError>();
+        send_sync::<PartialParse<i32>>();
+
+        let sum = SharedParser::new(|| fixture::sum().end());
+        let results: Vec<Result<u32, ParseError>> = std::thread::scope(|scope| {
+            let sum = &sum;
+            let threads: Vec<_> = ["1+2", "3+4+5", "6+"]
+                .into_iter()
+                .map(|input| scope.spawn(move || sum.run(input)))
+                .collect();
+            threads.into_iter().map(|t| t.join().unwrap()).collect()
+        });
+        assert_eq!(results[0], Ok(3));
+        assert_eq!(results[1], Ok(12));
+        assert!(results[2].is_err());
+
+        let builds = std::sync::Arc::new(AtomicUsize::new(0));
+        let counted = builds.clone();
+        let digit = SharedParser::new(move || {
+            counted.fetch_add(1, Ordering::Relaxed);
+            satisfy("digit", |c| c.is_ascii_digit())
+        });
+        assert_eq!(digit.run("1"), Ok('1'));
+        assert_eq!(digit.clone().run("2"), Ok('2'));
+        assert_eq!(builds.load(Ordering::Relaxed), 1);
+        std::thread::scope(|scope| {
+            scope.spawn(|| digit.run("3")).join().unwrap().unwrap();
+        });
+        assert_eq!(builds.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,597 @@
+use std::ops::Range;
+use std::rc::Rc;
+
+#[cfg(feature = "memmap2")]
+use crate::Mapping;
+use crate::Text;
+
+// input text indexed by byte offset, either as one contiguous string or as chunks of
+// roughly fixed size so that very large inputs never need a single huge allocation; the
+// storage is shared, so cloning a source (or slicing it) never copies the text
+#[derive(Debug, Clone)]
+pub struct Source {
+    repr: Rc<Repr>,
+    len: usize,
+}
+
+#[derive(Debug, Clone)]
+enum Repr {
+    Contiguous(Text),
+    // chunks are split on char boundaries, `starts[i]` is the offset of `chunks[i]`
+    Segmented {
+        chunks: Vec<Text>,
+        starts: Vec<usize>,
+    },
+    #[cfg(feature = "ropey")]
+    Rope(ropey::Rope),
+    // a file mapped into memory, checked to be UTF-8 when it was mapped
+    #[cfg(feature = "memmap2")]
+    Mapped(Mapping),
+}
+
+// maps the file at `path` into memory for `Source::from_file` and `Bytes::from_file`
+#[cfg(feature = "memmap2")]
+pub(crate) unsafe fn map_file(path: &std::path::Path) -> std::io::Result<memmap2::Mmap> {
+    let file = std::fs::File::open(path)?;
+    // SAFETY: the map is only ever read, and it stays valid as long as the file isn't
+    // changed, which the callers of `from_file` promise
+    unsafe { memmap2::Mmap::map(&file) }
+}
+
+#[cfg(feature = "memmap2")]
+fn mapped_str(map: &memmap2::Mmap) -> &str {
+    // SAFETY: the map was validated as UTF-8 by `Source::from_file`, and nothing can change
+    // it through this process
+    unsafe { std::str::from_utf8_unchecked(map) }
+}
+
+impl Source {
+    pub fn new(source: impl Into<String>) -> Self {
+        let text: Text = source.into().into();
+        Source {
+            len: text.len(),
+            repr: Rc::new(Repr::Contiguous(text)),
+        }
+    }
+
+    pub fn segmented(source: impl IntoIterator<Item = char>, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "chunk size must be positive");
+
+        let mut chunks = Vec::new();
+        let mut starts = Vec::new();
+        let mut chunk = String::with_capacity(chunk_size);
+        let mut len = 0;
+        for c in source {
+            if !chunk.is_empty() && chunk.len() + c.len_utf8() > chunk_size {
+                starts.push(len - chunk.len());
+                chunks
+                    .push(std::mem::replace(&mut chunk, String::with_capacity(chunk_size)).into());
+            }
+            chunk.push(c);
+            len += c.len_utf8();
+        }
+        if !chunk.is_empty() {
+            starts.push(len - chunk.len());
+            chunks.push(chunk.into());
+        }
+
+        Source {
+            len,
+            repr: Rc::new(Repr::Segmented { chunks, starts }),
+        }
+    }
+
+    // ropes are cheap to clone and share their tree, so the editor buffer itself is parsed
+    #[cfg(feature = "ropey")]
+    pub fn from_rope(rope: ropey::Rope) -> Self {
+        Source {
+            len: rope.len_bytes(),
+            repr: Rc::new(Repr::Rope(rope)),
+        }
+    }
+
+    /// Maps the file at `path` into memory and parses it in place, failing if it isn't
+    /// UTF-8. The text is read from the file directly, and errors share the mapping rather
+    /// than copying it.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be changed, by this process or any other, while the source, a
+    /// slice of it or an error pointing into it is alive; the text would change under the
+    /// parser, and may stop being UTF-8.
+    #[cfg(feature = "memmap2")]
+    pub unsafe fn from_file(path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        // SAFETY: passed on to the caller
+        let map = unsafe { map_file(path.as_ref())? };
+        std::str::from_utf8(&map)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        Ok(Source {
+            len: map.len(),
+            repr: Rc::new(Repr::Mapped(Mapping::new(map))),
+        })
+    }
+
+    /// This source with the text in `range` replaced by `text`. A rope stays a rope and a
+    /// segmented source stays segmented, both sharing what the edit didn't touch, anything
+    /// else is copied into a contiguous string.
+    pub fn edit(&self, range: Range<usize>, text: &str) -> Source {
+        assert!(
+            range.start <= range.end
+                && [range.start, range.end]
+                    .iter()
+                    .all(|&pos| pos == self.len || self.get(pos).is_some()),
+            "edit {:?} out of bounds or not on char boundaries in source of length {}",
+            range,
+            self.len
+        );
+        #[cfg(feature = "ropey")]
+        if let Repr::Rope(rope) = self.repr.as_ref() {
+            let mut rope = rope.clone();
+            let start = rope.byte_to_char(range.start);
+            rope.remove(start..rope.byte_to_char(range.end));
+            rope.insert(start, text);
+            return Source::from_rope(rope);
+        }
+        if let Repr::Segmented { chunks, starts } = self.repr.as_ref()
+            && !chunks.is_empty()
+        {
+            // the segments the edit starts and ends in are copied into one holding the new
+            // text, the others are shared
+            let segment = |pos| starts.partition_point(|&start| start <= pos) - 1;
+            let (first, last) = (segment(range.start), segment(range.end));
+            let mut spliced = String::from(&chunks[first][..range.start - starts[first]]);
+            spliced.push_str(text);
+            spliced.push_str(&chunks[last][range.end - starts[last]..]);
+            let mut chunks = chunks.clone();
+            chunks.splice(
+                first..=last,
+                (!spliced.is_empty()).then(|| Text::from(spliced)),
+            );
+            let mut starts = Vec::with_capacity(chunks.len());
+            let mut len = 0;
+            for chunk in &chunks {
+                starts.push(len);
+                len += chunk.len();
+            }
+            return Source {
+                len,
+                repr: Rc::new(Repr::Segmented { chunks, starts }),
+            };
+        }
+        let mut edited = self.to_string();
+        edited.replace_range(range, text);
+        Source::new(edited)
+    }
+
+    /// Adds `text` at the end, as a segment of its own so the text already there is shared
+    /// rather than copied. A rope stays a rope.
+    pub fn append(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        #[cfg(feature = "ropey")]
+        if let Repr::Rope(rope) = self.repr.as_ref() {
+            let mut rope = rope.clone();
+            rope.insert(rope.len_chars(), text);
+            *self = Source::from_rope(rope);
+            return;
+        }
+        if !matches!(self.repr.as_ref(), Repr::Segmented { .. }) {
+            let first = match self.repr.as_ref() {
+                Repr::Contiguous(first) => first.clone(),
+                _ => Text::from(self.to_string()),
+            };
+            let (chunks, starts) = match self.len {
+                0 => (Vec::new(), Vec::new()),
+                _ => (vec![first], vec![0]),
+            };
+            self.repr = Rc::new(Repr::Segmented { chunks, starts });
+        }
+        if let Repr::Segmented { chunks, starts } = Rc::make_mut(&mut self.repr) {
+            chunks.push(Text::from(text));
+            starts.push(self.len);
+        }
+        self.len += text.len();
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // the char starting at byte offset `pos`, None at the end or inside a multi-byte char
+    pub fn get(&self, pos: usize) -> Option<char> {
+        let (chunk, start) = self.chunk_at(pos)?;
+        chunk.get(pos - start..)?.chars().next()
+    }
+
+    // compared a chunk at a time, so a rope is searched once rather than once per char
+    pub fn starts_with_at(&self, pos: usize, needle: &str) -> bool {
+        self.starts_with_bytes_at(pos, needle.as_bytes())
+    }
+
+    // like `starts_with_at`, for a needle that may end inside a char
+    pub(crate) fn starts_with_bytes_at(&self, mut pos: usize, mut needle: &[u8]) -> bool {
+        while !needle.is_empty() {
+            let Some((chunk, start)) = self.chunk_at(pos) else {
+                return false;
+            };
+            let rest = &chunk.as_bytes()[pos - start..];
+            let n = rest.len().min(needle.len());
+            if n == 0 || rest[..n] != needle[..n] {
+                return false;
+            }
+            needle = &needle[n..];
+            pos += n;
+        }
+        true
+    }
+
+    // where the line holding byte offset `pos` starts, after a `\n`, or with `universal`
+    // after a `\r` not followed by one as well
+    pub(crate) fn line_start(&self, mut pos: usize, universal: bool) -> usize {
+        while pos > 0 {
+            let Some((chunk, start)) = self.chunk_at(pos - 1) else {
+                return 0;
+            };
+            let before = &chunk.as_bytes()[..pos - start];
+            let is_break = |i: usize| match before[i] {
+                b'\n' => true,
+                b'\r' => universal && self.get(start + i + 1) != Some('\n'),
+                _ => false,
+            };
+            if let Some(i) = (0..before.len()).rev().find(|&i| is_break(i)) {
+                return start + i + 1;
+            }
+            pos = start;
+        }
+        0
+    }
+
+    // the chunk holding byte offset `pos` and the offset it starts at, the last chunk for
+    // the end of the text
+    fn chunk_at(&self, pos: usize) -> Option<(&str, usize)> {
+        if pos > self.len {
+            return None;
+        }
+        match self.repr.as_ref() {
+            Repr::Contiguous(text) => Some((text, 0)),
+            Repr::Segmented { chunks, starts } => {
+                let i = starts
+                    .partition_point(|&start| start <= pos)
+                    .checked_sub(1)?;
+                Some((&chunks[i], starts[i]))
+            }
+            #[cfg(feature = "ropey")]
+            Repr::Rope(rope) => {
+                let (chunk, start, _, _) = rope.get_chunk_at_byte(pos)?;
+                Some((chunk, start))
+            }
+            #[cfg(feature = "memmap2")]
+            Repr::Mapped(map) => Some((mapped_str(map), 0)),
+        }
+    }
+
+    /// The text in the pieces it is stored in, in order: one for a contiguous source,
+    /// otherwise its segments or the leaves of its rope.
+    pub fn chunks(&self) -> Box<dyn Iterator<Item = &str> + '_> {
+        match self.repr.as_ref() {
+            Repr::Contiguous(text) => Box::new(std::iter::once(&**text)),
+            Repr::Segmented { chunks, .. } => Box::new(chunks.iter().map(|chunk| &**chunk)),
+            #[cfg(feature = "ropey")]
+            Repr::Rope(rope) => Box::new(rope.chunks()),
+            #[cfg(feature = "memmap2")]
+            Repr::Mapped(map) => Box::new(std::iter::once(mapped_str(map))),
+        }
+    }
+
+    pub fn chars(&self) -> Box<dyn Iterator<Item = char> + '_> {
+        Box::new(self.chunks().flat_map(str::chars))
+    }
+
+    // the whole text as one `&str` when it is stored contiguously
+    pub fn as_str(&self) -> Option<&str> {
+        match self.repr.as_ref() {
+            Repr::Contiguous(text) => Some(text),
+            #[cfg(feature = "memmap2")]
+            Repr::Mapped(map) => Some(mapped_str(map)),
+            _ => None,
+        }
+    }
+
+    // the whole text, sharing the storage where it is in one piece and copying it otherwise
+    pub(crate) fn shared_text(&self) -> SharedText {
+        match self.repr.as_ref() {
+            Repr::Contiguous(text) => SharedText(SharedRepr::Str(text.clone())),
+            #[cfg(feature = "memmap2")]
+            Repr::Mapped(map) => SharedText(SharedRepr::Mapped(map.clone())),
+            _ => SharedText::from(self.to_string()),
+        }
+    }
+
+    pub fn slice(&self, range: Range<usize>) -> Substr {
+        assert!(
+            range.start <= range.end && range.end <= self.len,
+            "slice {:?} out of bounds for source of length {}",
+            range,
+            self.len
+        );
+        Substr {
+            source: self.clone(),
+            range,
+        }
+    }
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use std::fmt::Write;
+
+        self.chars().try_for_each(|c| f.write_char(c))
+    }
+}
+
+/// The whole text of an input, as [`Input::text`](crate::Input::text) hands it to errors;
+/// it shares the input's storage where it can, a file mapped into memory included.
+#[derive(Clone)]
+pub struct SharedText(SharedRepr);
+
+#[derive(Clone)]
+enum SharedRepr {
+    Str(Text),
+    #[cfg(feature = "memmap2")]
+    Mapped(Mapping),
+}
+
+impl std::ops::Deref for SharedText {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        match &self.0 {
+            SharedRepr::Str(text) => text,
+            #[cfg(feature = "memmap2")]
+            SharedRepr::Mapped(map) => mapped_str(map),
+        }
+    }
+}
+
+impl From<Text> for SharedText {
+    fn from(text: Text) -> Self {
+        SharedText(SharedRepr::Str(text))
+    }
+}
+
+impl From<&str> for SharedText {
+    fn from(text: &str) -> Self {
+        SharedText::from(Text::from(text))
+    }
+}
+
+impl From<String> for SharedText {
+    fn from(text: String) -> Self {
+        SharedText::from(Text::from(text))
+    }
+}
+
+impl std::fmt::Display for SharedText {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self)
+    }
+}
+
+impl std::fmt::Debug for SharedText {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", &**self)
+    }
+}
+
+impl PartialEq for SharedText {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for SharedText {}
+
+impl std::hash::Hash for SharedText {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        (**self).hash(state);
+    }
+}
+
+// a range of a source that borrows its storage instead of copying the text out
+#[derive(Clone)]
+pub struct Substr {
+    source: Source,
+    range: Range<usize>,
+}
+
+impl Substr {
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.range.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.range.is_empty()
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        self.source.as_str()?.get(self.range.clone())
+    }
+
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        let mut pos = self.range.start;
+        std::iter::from_fn(move || {
+            if pos >= self.range.end {
+                return None;
+            }
+            let c = self.source.get(pos)?;
+            pos += c.len_utf8();
+            Some(c)
+        })
+    }
+}
+
+impl std::fmt::Display for Substr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use std::fmt::Write;
+
+        self.chars().try_for_each(|c| f.write_char(c))
+    }
+}
+
+impl std::fmt::Debug for Substr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}@{:?}", self.to_string(), self.range)
+    }
+}
+
+impl PartialEq for Substr {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.chars().eq(other.chars())
+    }
+}
+
+impl Eq for Substr {}
+
+impl PartialEq<str> for Substr {
+    fn eq(&self, other: &str) -> bool {
+        self.chars().eq(other.chars())
+    }
+}
+
+impl PartialEq<&str> for Substr {
+    fn eq(&self, other: &&str) -> bool {
+        self.chars().eq(other.chars())
+    }
+}
+
+impl std::hash::Hash for Substr {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.chars().for_each(|c| c.hash(state));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_segmented() {
+        let contiguous = Source::new("héllo, wörld");
+        let segmented = Source::segmented("héllo, wörld".chars(), 5);
+
+        assert_eq!(segmented.len(), contiguous.len());
+        for pos in 0..=contiguous.len() {
+            assert_eq!(segmented.get(pos), contiguous.get(pos));
+        }
+        assert!(segmented.starts_with_at(8, "wörld"));
+        assert!(!segmented.starts_with_at(9, "wörld"));
+        assert_eq!(segmented.to_string(), "héllo, wörld");
+    }
+
+    #[test]
+    fn test_edit_segmented() {
+        let text = "héllo, wörld";
+        let segmented = Source::segmented(text.chars(), 5);
+        let edits = [
+            (0..0, "¡"),
+            (1..3, "e"),
+            (3..9, ""),
+            (9..14, "ö!"),
+            (0..14, ""),
+        ];
+        for (range, replacement) in edits {
+            let mut expected = text.to_string();
+            expected.replace_range(range.clone(), replacement);
+            let edited = segmented.edit(range, replacement);
+            assert_eq!(edited.to_string(), expected);
+            let contiguous = Source::new(expected);
+            assert_eq!(edited.len(), contiguous.len());
+            for pos in 0..=contiguous.len() {
+                assert_eq!(edited.get(pos), contiguous.get(pos));
+            }
+        }
+
+        // the segments the edit doesn't touch are shared, not copied into one string
+        let edited = segmented.edit(1..3, "e");
+        assert!(edited.chunks().count() > 1);
+        assert_eq!(
+            edited.chunks().last().unwrap().as_ptr(),
+            segmented.chunks().last().unwrap().as_ptr()
+        );
+    }
+
+    #[test]
+    fn test_append() {
+        let mut source = Source::new("héllo");
+        let shared = source.clone();
+        source.append(", wö");
+        source.append("");
+        source.append("rld");
+        assert_eq!(source.to_string(), "héllo, wörld");
+        assert_eq!(
+            source.chunks().collect::<Vec<_>>(),
+            ["héllo", ", wö", "rld"]
+        );
+        assert!(source.starts_with_at(8, "wörld"));
+        // the text that was there is shared, not copied
+        assert_eq!(
+            source.chunks().next().unwrap().as_ptr(),
+            shared.as_str().unwrap().as_ptr()
+        );
+        assert_eq!(shared.to_string(), "héllo");
+    }
+
+    #[test]
+    fn test_byte_offsets() {
+        let source = Source::new("aé😀b");
+
+        assert_eq!(source.len(), 8);
+        assert_eq!(source.get(1), Some('é'));
+        assert_eq!(source.get(2), None);
+        assert_eq!(source.get(3), Some('😀'));
+        assert_eq!(source.get(7), Some('b'));
+        assert_eq!(source.slice(1..7).as_str(), Some("é😀"));
+        assert_eq!(source.slice(1..7), "é😀");
+    }
+
+    #[cfg(feature = "ropey")]
+    #[test]
+    fn test_rope() {
+        let mut rope = ropey::Rope::from_str("héllo, world");
+        rope.insert(7, "big ");
+        let source = Source::from_rope(rope);
+
+        assert_eq!(source.len(), 17);
+        assert_eq!(source.get(1), Some('é'));
+        assert_eq!(source.get(2), None);
+        assert!(source.starts_with_at(12, "world"));
+        assert_eq!(source.to_string(), "héllo, big world");
+
+        // big enough to span many leaves, which are matched across
+        let text = "ünïcödé text ".repeat(2000);
+        let rope = Source::from_rope(ropey::Rope::from_str(&text));
+        assert!(rope.chunks().count() > 1);
+        let contiguous = Source::new(text.as_str());
+        let mut start = 0;
+        for chunk in rope.chunks().take(5) {
+            start += chunk.len();
+            assert_eq!(rope.get(start), contiguous.get(start));
+            let (from, to) = (
+                text.floor_char_boundary(start - 2),
+                text.ceil_char_boundary(start + 20),
+            );
+            assert!(rope.starts_with_at(from, &text[from..to]));
+        }
+        assert!(!rope.starts_with_at(text.len() - 1, "  "));
+        assert_eq!(rope.chars().count(), contiguous.chars().count());
+    }
+}
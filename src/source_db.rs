@@ -0,0 +1,164 @@
+use std::ops::Range;
+
+use crate::{Context, SourceMap, Text};
+
+/// Names one of the sources added to a [`SourceDb`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SourceId(usize);
+
+/// A span of the text of one source in a [`SourceDb`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SourceSpan {
+    pub source: SourceId,
+    pub range: Range<usize>,
+}
+
+/// The sources a multi-file parse reads, each under a name such as its path. Contexts
+/// made with [`SourceDb::context`] stamp their errors with the source they came from, so
+/// an error found while parsing an included file says which file that was.
+///
+/// ```
+/// use packrust::*;
+///
+/// let mut db = SourceDb::new();
+/// let main = db.add("main.cfg", "a = 1\n");
+/// let lib = db.add("lib.cfg", "b = 2\nc = \n");
+///
+/// let value = satisfy("digit", |c| c.is_ascii_digit());
+/// let line = satisfy("letter", char::is_alphabetic)
+///     .andl(keyword(" = "))
+///     .and(value)
+///     .andl(char('\n'));
+/// let file = line.many().end();
+/// assert!(file.parse(0, &mut db.context(main)).is_ok());
+///
+/// let error = file.parse(0, &mut db.context(lib)).unwrap_err();
+/// assert_eq!(error.source_id(), Some(lib));
+/// assert!(error.to_string().starts_with("lib.cfg:2:1\n"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SourceDb {
+    sources: Vec<(Text, SourceMap)>,
+}
+
+impl SourceDb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `text` under `name`, even if a source of that name was added already.
+    pub fn add(&mut self, name: &str, text: &str) -> SourceId {
+        self.sources.push((Text::from(name), SourceMap::new(text)));
+        SourceId(self.sources.len() - 1)
+    }
+
+    /// The source added under `name`, the latest one if there are several.
+    pub fn find(&self, name: &str) -> Option<SourceId> {
+        self.sources
+            .iter()
+            .rposition(|(source, _)| **source == *name)
+            .map(SourceId)
+    }
+
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = SourceId> {
+        (0..self.sources.len()).map(SourceId)
+    }
+
+    pub fn name(&self, id: SourceId) -> &str {
+        &self.sources[id.0].0
+    }
+
+    pub fn text(&self, id: SourceId) -> &str {
+        self.sources[id.0].1.text()
+    }
+
+    pub fn source_map(&self, id: SourceId) -> &SourceMap {
+        &self.sources[id.0].1
+    }
+
+    /// A context for parsing the source `id`, whose errors carry its id and name.
+    pub fn context(&self, id: SourceId) -> Context {
+        let mut ctx = Context::new(self.text(id));
+        ctx.origin = Some((id, self.sources[id.0].0.clone()));
+        ctx
+    }
+
+    /// The text a span covers.
+    pub fn slice(&self, span: &SourceSpan) -> &str {
+        &self.text(span.source)[span.range.clone()]
+    }
+
+    /// Where a span starts, as `name:line:column` counting lines and columns from 1.
+    pub fn describe(&self, span: &SourceSpan) -> String {
+        let (line, col) = self.source_map(span.source).line_col(span.range.start);
+        format!("{}:{}:{}", self.name(span.source), line + 1, col + 1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn test_source_db() {
+        let mut db = SourceDb::new();
+        let a = db.add("a.txt", "one two");
+        let b = db.add("dir/b.txt", "three\nfour 5");
+        assert_eq!(db.len(), 2);
+        assert_eq!(db.find("dir/b.txt"), Some(b));
+        assert_eq!(db.find("c.txt"), None);
+        assert_eq!(db.ids().collect::<Vec<_>>(), [a, b]);
+
+        let word = satisfy("letter", char::is_alphabetic)
+            .many_collect::<String>()
+            .located();
+        let words = word.sep_by(satisfy("space", char::is_whitespace)).end();
+
+        let (_, parsed) = words.parse(0, &mut db.context(a)).unwrap();
+        let span = SourceSpan {
+            source: a,
+            range: parsed[1].span.clone(),
+        };
+        assert_eq!(db.slice(&span), "two");
+        assert_eq!(db.describe(&span), "a.txt:1:5");
+
+        let error = words.parse(0, &mut db.context(b)).unwrap_err();
+        assert_eq!(error.source_id(), Some(b));
+        assert_eq!(error.source_name(), Some("dir/b.txt"));
+        assert_eq!(
+            error.source_span(),
+            Some(SourceSpan {
+                source: b,
+                range: 11..11,
+            })
+        );
+        assert_eq!(
+            error.to_string(),
+            "dir/b.txt:2:6\nthree\nfour 5\n           ^\nexpected EOF found 5\n"
+        );
+
+        // a context that isn't from a database has no source
+        let error = words.parse(0, &mut Context::new("x 1")).unwrap_err();
+        assert_eq!(error.source_id(), None);
+        assert!(error.to_string().starts_with("x 1\n"));
+
+        // nor does one whose text was replaced or changed since
+        let ctx = &mut db.context(b);
+        ctx.reset("x 1");
+        assert_eq!(words.parse(0, ctx).unwrap_err().source_id(), None);
+        let ctx = &mut db.context(b);
+        ctx.edit(0..0, "x ");
+        assert_eq!(words.parse(0, ctx).unwrap_err().source_name(), None);
+        let ctx = &mut db.context(b);
+        ctx.feed(" 6");
+        assert_eq!(words.parse(0, ctx).unwrap_err().source_id(), None);
+    }
+}
@@ -0,0 +1,202 @@
+use std::ops::Range;
+
+use crate::SharedText;
+
+/// Converts between the ways positions in a text are counted: byte offsets, which is what
+/// positions and spans in this crate are, char offsets, and lines and columns, with
+/// columns in chars or in UTF-16 code units as the Language Server Protocol counts them.
+/// Lines and columns start at 0, and a line ends after its `\n`, or with
+/// [`SourceMap::universal`] after a lone `\r` too.
+///
+/// ```
+/// use packrust::*;
+///
+/// let map = SourceMap::new("let x = 1;\nlet 𝑦 = x;\n");
+/// assert_eq!(map.line_col(19), (1, 5));
+/// assert_eq!(map.line_col_utf16(19), (1, 6));
+/// assert_eq!(map.offset(1, 5), Some(19));
+/// assert_eq!(map.snippet(15..16), "let 𝑦 = x;");
+/// ```
+#[derive(Debug, Clone)]
+pub struct SourceMap {
+    text: SharedText,
+    // where each line starts, so a position is found in its line by binary search
+    lines: Vec<usize>,
+}
+
+impl SourceMap {
+    pub fn new(text: &str) -> Self {
+        Self::from_text(SharedText::from(text), false)
+    }
+
+    /// A map for text whose lines may end in `\n`, `\r\n` or a lone `\r`, as text from
+    /// different platforms mixes them.
+    pub fn universal(text: &str) -> Self {
+        Self::from_text(SharedText::from(text), true)
+    }
+
+    pub(crate) fn from_text(text: SharedText, universal: bool) -> Self {
+        let bytes = text.as_bytes();
+        let is_break = |i: usize| match bytes[i] {
+            b'\n' => true,
+            b'\r' => universal && bytes.get(i + 1) != Some(&b'\n'),
+            _ => false,
+        };
+        let lines = std::iter::once(0)
+            .chain((0..bytes.len()).filter(|&i| is_break(i)).map(|i| i + 1))
+            .collect();
+        SourceMap { text, lines }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// The text of line `line`, without its line break.
+    pub fn line(&self, line: usize) -> Option<&str> {
+        let span = self.line_span(line)?;
+        let text = &self.text[span];
+        let text = text.strip_suffix('\n').unwrap_or(text);
+        Some(text.strip_suffix('\r').unwrap_or(text))
+    }
+
+    /// The line a byte offset is on.
+    pub fn line_of(&self, offset: usize) -> usize {
+        self.lines.partition_point(|&start| start <= offset) - 1
+    }
+
+    /// The line and the column in chars of a byte offset.
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let (line, before) = self.before(offset);
+        (line, before.chars().count())
+    }
+
+    /// The line and the column in UTF-16 code units of a byte offset, as a language server
+    /// reports a position.
+    pub fn line_col_utf16(&self, offset: usize) -> (usize, usize) {
+        let (line, before) = self.before(offset);
+        (line, before.encode_utf16().count())
+    }
+
+    /// The line and the column in grapheme clusters of a byte offset, counting a letter with
+    /// combining marks or an emoji sequence as one column, as a reader would.
+    #[cfg(feature = "unicode-segmentation")]
+    pub fn line_col_graphemes(&self, offset: usize) -> (usize, usize) {
+        let (line, before) = self.before(offset);
+        (line, crate::graphemes::count(before))
+    }
+
+    /// The byte offset of the column in chars `col` of line `line`, None past the end of
+    /// the line.
+    pub fn offset(&self, line: usize, col: usize) -> Option<usize> {
+        let start = *self.lines.get(line)?;
+        let text = self.line(line)?;
+        text.char_indices()
+            .map(|(i, _)| i)
+            .chain(std::iter::once(text.len()))
+            .nth(col)
+            .map(|i| start + i)
+    }
+
+    /// The byte offset of the column in UTF-16 code units `col` of line `line`, as a
+    /// language server sends a position, None past the end of the line or inside a char.
+    pub fn offset_utf16(&self, line: usize, col: usize) -> Option<usize> {
+        let start = *self.lines.get(line)?;
+        let text = self.line(line)?;
+        let mut units = 0;
+        for (i, c) in text.char_indices() {
+            if units == col {
+                return Some(start + i);
+            }
+            units += c.len_utf16();
+        }
+        (units == col).then_some(start + text.len())
+    }
+
+    /// How many chars come before a byte offset.
+    pub fn char_offset(&self, offset: usize) -> usize {
+        let (line, before) = self.before(offset);
+        self.text[..self.lines[line]].chars().count() + before.chars().count()
+    }
+
+    /// The byte offset of the char at char offset `chars`, None past the end of the text.
+    pub fn byte_offset(&self, chars: usize) -> Option<usize> {
+        self.text
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(std::iter::once(self.text.len()))
+            .nth(chars)
+    }
+
+    /// The whole lines a span touches, to show it in context, without the final line
+    /// break.
+    pub fn snippet(&self, span: Range<usize>) -> &str {
+        let start = self.lines[self.line_of(span.start)];
+        let end = self
+            .line_span(self.line_of(span.end))
+            .map_or(start, |line| line.end);
+        let text = &self.text[start..end.max(start)];
+        let text = text.strip_suffix('\n').unwrap_or(text);
+        text.strip_suffix('\r').unwrap_or(text)
+    }
+
+    fn line_span(&self, line: usize) -> Option<Range<usize>> {
+        let start = *self.lines.get(line)?;
+        let end = self.lines.get(line + 1).copied().unwrap_or(self.text.len());
+        Some(start..end)
+    }
+
+    // the line of a byte offset and its text up to the offset, which is clamped to the text
+    fn before(&self, offset: usize) -> (usize, &str) {
+        let offset = offset.min(self.text.len());
+        let line = self.line_of(offset);
+        (line, &self.text[self.lines[line]..offset])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn test_source_map() {
+        let text = "ab\r\nçé😀x\n\nend";
+        let map = SourceMap::new(text);
+        assert_eq!(map.line_count(), 4);
+        assert_eq!(map.line(0), Some("ab"));
+        assert_eq!(map.line(1), Some("çé😀x"));
+        assert_eq!(map.line(2), Some(""));
+        assert_eq!(map.line(4), None);
+
+        let x = text.find('x').unwrap();
+        assert_eq!(map.line_col(x), (1, 3));
+        assert_eq!(map.line_col_utf16(x), (1, 4));
+        assert_eq!(map.offset(1, 3), Some(x));
+        assert_eq!(map.offset_utf16(1, 4), Some(x));
+        // in the middle of the surrogate pair of 😀
+        assert_eq!(map.offset_utf16(1, 3), None);
+        assert_eq!(map.offset(3, 3), Some(text.len()));
+        assert_eq!(map.offset(3, 4), None);
+
+        assert_eq!(map.char_offset(x), 7);
+        assert_eq!(map.byte_offset(7), Some(x));
+        assert_eq!(map.byte_offset(text.chars().count()), Some(text.len()));
+        assert_eq!(map.byte_offset(text.chars().count() + 1), None);
+
+        assert_eq!(map.snippet(1..x), "ab\r\nçé😀x");
+        assert_eq!(map.snippet(text.len()..text.len()), "end");
+
+        // a context builds its map once, for the text it is parsing
+        let ctx = Context::new(text);
+        assert_eq!(ctx.source_map().unwrap().line_col(x), (1, 3));
+        assert!(
+            Context::from_source(Bytes::new(vec![1, 2]))
+                .source_map()
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use std::ops::{Deref, DerefMut, Range};
+use std::rc::Rc;
+
+use crate::{Context, Input, Parser};
+
+/// A value along with the span of the input it was parsed from, as `located` produces it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Spanned<T> {
+    pub value: T,
+    pub span: Range<usize>,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(value: T, span: Range<usize>) -> Self {
+        Spanned { value, span }
+    }
+
+    /// Applies `f` to the value, keeping the span.
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Spanned<U> {
+        Spanned {
+            value: f(self.value),
+            span: self.span,
+        }
+    }
+
+    pub fn as_ref(&self) -> Spanned<&T> {
+        Spanned {
+            value: &self.value,
+            span: self.span.clone(),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> Deref for Spanned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for Spanned<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<'a, T, I> Parser<'a, T, I>
+where
+    T: Clone + 'static,
+    I: Input,
+{
+    /// Wraps the value this parser produces in a [`Spanned`] with the span it matched.
+    pub fn located(self) -> Parser<'a, Spanned<T>, I> {
+        let name = self.name.clone();
+        let first = self.first.clone();
+        let shape = self.shape.clone();
+        let raw_parser = Rc::new(move |pos, ctx: &mut Context<I>| {
+            let (end, value) = self.parse(pos, ctx)?;
+            Ok((end, Spanned::new(value, pos..end)))
+        });
+
+        Parser::new(name, raw_parser)
+            .with_first(first)
+            .with_shape(shape)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn test_located() {
+        let digit = satisfy("digit", |c| c.is_ascii_digit());
+        let number = digit
+            .clone()
+            .and(digit.many_collect::<String>())
+            .map(|(first, rest)| format!("{}{}", first, rest))
+            .located();
+        let numbers = number.sep_by(char(' '));
+
+        let ctx = &mut Context::new("1 23 456");
+        let (_, numbers) = numbers.parse(0, ctx).unwrap();
+        let spans: Vec<_> = numbers.iter().map(|n| n.span.clone()).collect();
+        assert_eq!(spans, [0..1, 2..4, 5..8]);
+        // derefs to the value, and maps it without losing the span
+        assert_eq!(numbers[1].len(), 2);
+        let parsed = numbers[2].clone().map(|n| n.parse::<u32>().unwrap());
+        assert_eq!(parsed, Spanned::new(456, 5..8));
+    }
+}
@@ -0,0 +1,202 @@
+use std::rc::Rc;
+
+use crate::{Context, Input, ParseError, Parser, Reason};
+
+/// Matches nothing and produces the state of the context, set with
+/// [`Context::with_state`]. Panics if the context has no state of type `S`.
+///
+/// A call that reads the state, directly or through the parsers it is made of, isn't
+/// memoized, so the same parser called at the same position again under a different
+/// state, such as one a backtrack rolled back, matches afresh.
+///
+/// ```
+/// use packrust::*;
+///
+/// let name = satisfy("letter", char::is_alphabetic).many_collect::<String>();
+/// let declare = keyword("let ")
+///     .andr(name.clone())
+///     .update_state(|names: &mut Vec<String>, name| names.push(name.clone()));
+/// let known = name
+///     .and(state())
+///     .try_map(|(name, names): (String, Vec<String>)| names.contains(&name).then_some(name));
+/// let program = declare.andl(char(';')).many().andr(known).end();
+///
+/// let ctx = &mut Context::new("let a;let b;a").with_state(Vec::<String>::new());
+/// assert!(program.parse(0, ctx).is_ok());
+/// let ctx = &mut Context::new("let a;c").with_state(Vec::<String>::new());
+/// assert!(program.parse(0, ctx).is_err());
+/// ```
+pub fn state<'a, S: Clone + 'static, I: Input>() -> Parser<'a, S, I> {
+    let raw_parser = Rc::new(|pos, ctx: &mut Context<I>| {
+        let state = ctx.expect_state::<S>();
+        Ok((pos, state.clone()))
+    });
+    Parser::new(String::from("state"), raw_parser)
+}
+
+/// Matches nothing where `f` accepts the state of the context, and fails there otherwise,
+/// for decisions that depend on what was parsed before. Panics if the context has no state
+/// of type `S`. Like anything that reads the state, the parsers around it aren't memoized
+/// where it was called.
+///
+/// ```
+/// use packrust::*;
+///
+/// // `>>` is a shift, except inside generic arguments where it closes two of them
+/// let shift = guard(|generics: &usize| *generics == 0).andr(keyword(">>"));
+/// let op = shift.or(keyword(">"));
+///
+/// let ctx = &mut Context::new(">>").with_state(0usize);
+/// assert_eq!(op.parse(0, ctx), Ok((2, String::from(">>"))));
+/// let ctx = &mut Context::new(">>").with_state(1usize);
+/// assert_eq!(op.parse(0, ctx), Ok((1, String::from(">"))));
+/// ```
+pub fn guard<'a, S: 'static, I: Input>(f: impl Fn(&S) -> bool + 'a) -> Parser<'a, (), I> {
+    let raw_parser = Rc::new(move |pos, ctx: &mut Context<I>| {
+        let state = ctx.expect_state::<S>();
+        if !f(state) {
+            return Err(ParseError {
+                source: ctx.clone_source(),
+                pos,
+                reason: Reason::Message("guard failed"),
+            });
+        }
+        Ok((pos, ()))
+    });
+    Parser::new(String::from("guard"), raw_parser)
+}
+
+impl<'a, T, I> Parser<'a, T, I>
+where
+    T: Clone + 'static,
+    I: Input,
+{
+    /// Changes the state of the context with `f` and the value this parser produces
+    /// whenever it matches. Panics if the context has no state of type `S`.
+    pub fn update_state<S: Clone + 'static>(self, f: impl Fn(&mut S, &T) + 'a) -> Parser<'a, T, I> {
+        let name = self.name.clone();
+        let first = self.first.clone();
+        let shape = self.shape.clone();
+        let raw_parser = Rc::new(move |pos, ctx: &mut Context<I>| {
+            let (end, val) = self.parse(pos, ctx)?;
+            ctx.update_state(|state| f(state, &val));
+            Ok((end, val))
+        });
+
+        Parser::new(name, raw_parser)
+            .with_first(first)
+            .with_shape(shape)
+    }
+
+    /// Matches what this parser does where `f` accepts the state of the context and the
+    /// value, and fails otherwise. Panics if the context has no state of type `S`.
+    pub fn when<S: 'static>(self, f: impl Fn(&S, &T) -> bool + 'a) -> Parser<'a, T, I> {
+        let name = self.name.clone();
+        let first = self.first.clone();
+        let raw_parser = Rc::new(move |pos, ctx: &mut Context<I>| {
+            let (end, val) = self.parse(pos, ctx)?;
+            let state = ctx.expect_state::<S>();
+            if !f(state, &val) {
+                return Err(ParseError {
+                    source: ctx.clone_source(),
+                    pos,
+                    reason: Reason::Message("when failed: predicate rejected the value"),
+                });
+            }
+            Ok((end, val))
+        });
+        Parser::new(name, raw_parser).with_first(first)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn test_state_rollback() {
+        let digit = satisfy("digit", |c| c.is_ascii_digit());
+        let counted = digit.update_state(|count: &mut usize, _| *count += 1);
+        // the first branch counts two digits before failing, the second counts them again
+        let pair = counted.clone().and(counted.clone()).andl(char('!'));
+        let either = pair.or(counted.clone().and(counted.clone())).many();
+
+        let ctx = &mut Context::new("1234").with_state(0usize);
+        assert_eq!(either.parse(0, ctx).map(|(end, _)| end), Ok(4));
+        assert_eq!(ctx.state::<usize>(), Some(&4));
+
+        // a failed parse leaves the state as it was before it
+        let ctx = &mut Context::new("12x").with_state(0usize);
+        assert!(either.end().parse(0, ctx).is_err());
+        assert_eq!(ctx.state::<usize>(), Some(&0));
+
+        // looking ahead doesn't change it either
+        let ctx = &mut Context::new("1").with_state(0usize);
+        assert!(counted.peek().parse(0, ctx).is_ok());
+        assert_eq!(ctx.state::<usize>(), Some(&0));
+    }
+
+    #[test]
+    fn test_state_read_after_rollback() {
+        let digit = satisfy("digit", |c| c.is_ascii_digit());
+        let counted = digit
+            .clone()
+            .update_state(|count: &mut usize, _| *count += 1);
+        // read once by the first branch, after its digit was counted, then again by the
+        // second once that count has been rolled back
+        let read = char(':').andr(state::<usize, _>());
+        let p = counted
+            .andr(read.clone())
+            .andl(char('!'))
+            .or(digit.andr(read));
+
+        let ctx = &mut Context::new("1:").with_state(0usize);
+        assert_eq!(p.parse(0, ctx), Ok((2, 0)));
+        assert_eq!(ctx.state::<usize>(), Some(&0));
+    }
+
+    #[test]
+    fn test_state_left_recursion() {
+        let num = satisfy("digit", |c| c.is_ascii_digit())
+            .map(|c| c.to_digit(10).unwrap())
+            .update_state(|count: &mut usize, _| *count += 1);
+        let sum = fixture::sum_of(num, |a, b| a + b);
+
+        // the attempt to grow the seed that doesn't, and is thrown away, counts nothing
+        let ctx = &mut Context::new("1+2+3").with_state(0usize);
+        assert_eq!(sum.parse(0, ctx), Ok((5, 6)));
+        assert_eq!(ctx.state::<usize>(), Some(&3));
+    }
+
+    #[test]
+    fn test_guard_memo() {
+        let shift = guard(|generics: &usize| *generics == 0).andr(keyword(">>"));
+        let open = char('<').update_state(|generics: &mut usize, _| *generics += 1);
+        // `shift` is tried at 1 once inside generic arguments, where it fails, and once
+        // after that `<` has been rolled back
+        let p = open
+            .andr(shift.clone())
+            .andl(char('!'))
+            .or(any_char().andr(shift));
+
+        let ctx = &mut Context::new("<>>").with_state(0usize);
+        assert_eq!(p.parse(0, ctx), Ok((3, String::from(">>"))));
+        let ctx = &mut Context::new("<>>").with_state(0usize).without_memo();
+        assert_eq!(p.parse(0, ctx), Ok((3, String::from(">>"))));
+    }
+
+    #[test]
+    fn test_when() {
+        // digits up to the base in the state
+        let digit = satisfy("digit", |c| c.is_ascii_digit())
+            .when(|base: &u32, c| c.to_digit(10).unwrap() < *base)
+            .many_collect::<String>()
+            .end();
+
+        let ctx = &mut Context::new("0110").with_state(2u32);
+        assert_eq!(digit.parse(0, ctx), Ok((4, String::from("0110"))));
+        let ctx = &mut Context::new("0120").with_state(2u32);
+        let error = digit.parse(0, ctx).unwrap_err();
+        assert_eq!(error.pos(), 2);
+    }
+}
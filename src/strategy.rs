@@ -0,0 +1,81 @@
+use proptest::prelude::*;
+use proptest::test_runner::{RngAlgorithm, TestRng};
+
+use crate::Parser;
+
+// a generator seeded from a proptest value, so failing cases replay and shrink like any
+// other input: shrinking lowers the depth, which makes for shorter sentences
+fn rng(seed: u64) -> TestRng {
+    let mut bytes = [0; 16];
+    bytes[..8].copy_from_slice(&seed.to_le_bytes());
+    bytes[8..].copy_from_slice(&(!seed).to_le_bytes());
+    TestRng::from_seed(RngAlgorithm::XorShift, &bytes)
+}
+
+impl<T> Parser<'static, T>
+where
+    T: Clone + 'static,
+{
+    /// A proptest strategy producing strings this parser accepts, built with
+    /// [`Parser::generate`] from rules expanded at most `max_depth` deep.
+    pub fn sentences(&self, max_depth: usize) -> impl Strategy<Value = String> + use<T> {
+        let parser = self.clone();
+        (0..=max_depth, any::<u64>()).prop_filter_map(
+            "no accepted sentence was generated",
+            move |(depth, seed)| parser.generate(&mut rng(seed), depth),
+        )
+    }
+
+    /// A proptest strategy producing near misses: accepted sentences with one char
+    /// removed, inserted, replaced or swapped with its neighbour. Most of them are
+    /// rejected, which makes them good inputs for testing error reporting and recovery.
+    pub fn near_misses(&self, max_depth: usize) -> impl Strategy<Value = String> + use<T> {
+        (self.sentences(max_depth), any::<u64>(), any::<char>()).prop_map(|(sentence, seed, c)| {
+            let mut chars: Vec<char> = sentence.chars().collect();
+            let at = (seed >> 2) as usize % (chars.len() + 1);
+            match (seed % 4, at < chars.len()) {
+                (0, true) => {
+                    chars.remove(at);
+                }
+                (1, true) => chars[at] = c,
+                (2, true) if at + 1 < chars.len() => chars.swap(at, at + 1),
+                _ => chars.insert(at, c),
+            }
+            chars.into_iter().collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use proptest::test_runner::TestRunner;
+
+    use crate::*;
+
+    fn list() -> Parser<'static, Vec<char>> {
+        char('[')
+            .andr(satisfy("digit", |c| c.is_ascii_digit()).sep_by(char(',')))
+            .andl(char(']'))
+    }
+
+    #[test]
+    fn test_sentences() {
+        let whole = list().end();
+        TestRunner::default()
+            .run(&list().sentences(3), |sentence| {
+                prop_assert!(whole.run(&sentence).is_ok(), "{:?} rejected", sentence);
+                Ok(())
+            })
+            .unwrap();
+
+        let rejected = std::cell::Cell::new(0);
+        TestRunner::default()
+            .run(&list().near_misses(3), |sentence| {
+                rejected.set(rejected.get() + whole.run(&sentence).is_err() as usize);
+                Ok(())
+            })
+            .unwrap();
+        assert!(rejected.get() > 0);
+    }
+}
@@ -0,0 +1,236 @@
+use std::rc::Rc;
+
+use rustc_hash::FxHashMap;
+
+use crate::input::{found, next_char};
+use crate::{Context, ParseError, Parser, Pos, Reason, Text};
+
+/// Configures a parser for quoted string literals, made with [`string_literal`].
+#[derive(Debug, Clone)]
+pub struct StringLiteralBuilder {
+    quotes: Vec<char>,
+    escapes: FxHashMap<char, char>,
+    hex_escapes: bool,
+    unicode_escapes: bool,
+    multiline: bool,
+}
+
+/// Starts a parser for string literals, producing their text with escape sequences
+/// replaced. By default a literal is quoted with `"`, may not hold a line break, and knows
+/// the escapes `\n`, `\r`, `\t`, `\0`, `\\`, `\xNN` for chars up to U+00FF, `\u{...}` with
+/// up to six hex digits, and a backslash before any of its quotes.
+///
+/// ```
+/// use packrust::*;
+///
+/// let literal = string_literal().build();
+/// assert_eq!(literal.run(r#""tab\tand \u{1F980}""#), Ok(String::from("tab\tand 🦀")));
+///
+/// let literal = string_literal().quotes(['"', '\'']).multiline(true).build();
+/// assert_eq!(literal.run("'say \"hi\"\n'"), Ok(String::from("say \"hi\"\n")));
+/// assert!(literal.run(r#""\q""#).is_err());
+/// ```
+pub fn string_literal() -> StringLiteralBuilder {
+    let escapes = [
+        ('n', '\n'),
+        ('r', '\r'),
+        ('t', '\t'),
+        ('0', '\0'),
+        ('\\', '\\'),
+    ];
+    StringLiteralBuilder {
+        quotes: vec!['"'],
+        escapes: escapes.into_iter().collect(),
+        hex_escapes: true,
+        unicode_escapes: true,
+        multiline: false,
+    }
+}
+
+impl StringLiteralBuilder {
+    /// The chars a literal may be quoted with; it ends at the same one it starts with, and
+    /// the others stand for themselves inside it.
+    pub fn quotes(mut self, quotes: impl IntoIterator<Item = char>) -> Self {
+        self.quotes = quotes.into_iter().collect();
+        self
+    }
+
+    /// Makes a backslash followed by `c` stand for `replacement`, in place of what it
+    /// stood for before if anything.
+    pub fn escape(mut self, c: char, replacement: char) -> Self {
+        self.escapes.insert(c, replacement);
+        self
+    }
+
+    /// Whether `\xNN` stands for the char with the hex code NN.
+    pub fn hex_escapes(mut self, enabled: bool) -> Self {
+        self.hex_escapes = enabled;
+        self
+    }
+
+    /// Whether `\u{...}` stands for the char with the hex code in the braces.
+    pub fn unicode_escapes(mut self, enabled: bool) -> Self {
+        self.unicode_escapes = enabled;
+        self
+    }
+
+    /// Whether a literal may span lines, holding line breaks as they are.
+    pub fn multiline(mut self, allowed: bool) -> Self {
+        self.multiline = allowed;
+        self
+    }
+
+    pub fn build<'a>(self) -> Parser<'a, String> {
+        let first = Some(Rc::from(self.quotes.as_slice()));
+        let raw_parser = Rc::new(move |pos, ctx: &mut Context| {
+            let quote = next_char(ctx, pos).filter(|c| self.quotes.contains(c));
+            let Some(quote) = quote else {
+                return Err(expected(ctx, pos, "string literal"));
+            };
+            let (mut pos, mut value) = (pos + quote.len_utf8(), String::new());
+            loop {
+                match next_char(ctx, pos) {
+                    Some(c) if c == quote => return Ok((pos + c.len_utf8(), value)),
+                    Some('\\') => {
+                        let (c, end) = self.unescape(ctx, pos + 1)?;
+                        value.push(c);
+                        pos = end;
+                    }
+                    Some('\n' | '\r') if !self.multiline => {
+                        return Err(expected(ctx, pos, &format!("'{}'", quote)));
+                    }
+                    Some(c) => {
+                        value.push(c);
+                        pos += c.len_utf8();
+                    }
+                    None => return Err(expected(ctx, pos, &format!("'{}'", quote))),
+                }
+            }
+        });
+        Parser::new(String::from("string_literal"), raw_parser).with_first(first)
+    }
+
+    // the char the escape sequence after a backslash at `pos - 1` stands for, and its end
+    fn unescape(&self, ctx: &mut Context, pos: Pos) -> Result<(char, Pos), ParseError> {
+        match next_char(ctx, pos) {
+            Some('x') if self.hex_escapes => {
+                let (code, end) = hex_digits(ctx, pos + 1, 2)?;
+                Ok((char::from(code as u8), end))
+            }
+            Some('u') if self.unicode_escapes => {
+                if next_char(ctx, pos + 1) != Some('{') {
+                    return Err(expected(ctx, pos + 1, "'{'"));
+                }
+                let (code, end) = hex_digits(ctx, pos + 2, 6)?;
+                if next_char(ctx, end) != Some('}') {
+                    return Err(expected(ctx, end, "'}'"));
+                }
+                match char::from_u32(code) {
+                    Some(c) => Ok((c, end + 1)),
+                    None => Err(ParseError {
+                        source: ctx.clone_source(),
+                        pos: pos - 1,
+                        reason: Reason::Message("invalid unicode escape"),
+                    }),
+                }
+            }
+            Some(c) if self.quotes.contains(&c) => Ok((c, pos + c.len_utf8())),
+            Some(c) => match self.escapes.get(&c) {
+                Some(&replacement) => Ok((replacement, pos + c.len_utf8())),
+                None => Err(expected(ctx, pos, "escape sequence")),
+            },
+            None => Err(expected(ctx, pos, "escape sequence")),
+        }
+    }
+}
+
+// at least one and at most `max` hex digits, exactly `max` for `\xNN`, read as a number
+fn hex_digits(ctx: &mut Context, pos: Pos, max: usize) -> Result<(u32, Pos), ParseError> {
+    let (mut code, mut end) = (0, pos);
+    while end - pos < max
+        && let Some(digit) = next_char(ctx, end).and_then(|c| c.to_digit(16))
+    {
+        code = code * 16 + digit;
+        end += 1;
+    }
+    if end == pos || (max == 2 && end - pos < 2) {
+        return Err(expected(ctx, end, "hex digit"));
+    }
+    Ok((code, end))
+}
+
+fn expected(ctx: &mut Context, pos: Pos, label: &str) -> ParseError {
+    ParseError {
+        source: ctx.clone_source(),
+        pos,
+        reason: Reason::Expected {
+            label: Text::from(label),
+            found: found(&ctx.source, pos),
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn test_string_literal() {
+        let literal = string_literal().build();
+        assert_eq!(literal.run(r#""""#), Ok(String::new()));
+        assert_eq!(
+            literal.run(r#""a\"b\\c\n\x41\u{e9}\u{10FFFF}""#),
+            Ok(String::from("a\"b\\c\nAé\u{10ffff}"))
+        );
+        assert_eq!(literal.run(r#""é\xff""#), Ok(String::from("éÿ")));
+
+        let error_at = |text: &str| {
+            let error = literal.run(text).unwrap_err();
+            (
+                error.pos(),
+                error.to_string().lines().last().unwrap().to_string(),
+            )
+        };
+        assert_eq!(error_at("\"ab"), (3, String::from("expected '\"' got EOF")));
+        // unless multiline, a literal has to close on the line it opens on
+        assert_eq!(literal.run("\"a\nb\"").unwrap_err().pos(), 2);
+        assert_eq!(
+            error_at(r#""\q""#),
+            (2, String::from("expected escape sequence got q"))
+        );
+        assert_eq!(
+            error_at(r#""\x4""#),
+            (4, String::from("expected hex digit got \""))
+        );
+        assert_eq!(
+            error_at(r#""\u41""#),
+            (3, String::from("expected '{' got 4"))
+        );
+        assert_eq!(
+            error_at(r#""\u{1234567}""#),
+            (10, String::from("expected '}' got 7"))
+        );
+        assert_eq!(
+            error_at(r#""\u{d800}""#),
+            (1, String::from("invalid unicode escape"))
+        );
+        assert_eq!(
+            error_at("'a'"),
+            (0, String::from("expected string literal got '"))
+        );
+
+        // single quotes, raw line breaks, an escape of its own and no hex escapes
+        let literal = string_literal()
+            .quotes(['\''])
+            .multiline(true)
+            .escape('e', '\u{1b}')
+            .hex_escapes(false)
+            .build();
+        assert_eq!(
+            literal.run("'it\\'s\r\n\"\\e'"),
+            Ok(String::from("it's\r\n\"\u{1b}"))
+        );
+        assert!(literal.run(r"'\x41'").is_err());
+        assert!(literal.run(r#"'\"'"#).is_err());
+    }
+}
@@ -0,0 +1,431 @@
+use std::fmt::Debug;
+use std::ops::Range;
+use std::rc::Rc;
+
+#[cfg(feature = "logos")]
+use crate::{ErrorSource, Found, ParseError, Reason, Text};
+use crate::{Input, Pos, SharedText, Spans};
+
+// the output of a lexer, indexed by token; like a source, the tokens are shared, so cloning
+// or slicing them never copies them. Tokens lexed from text can keep the span of text each
+// came from, so errors and results can be traced back to it
+pub struct Tokens<Tok> {
+    tokens: Rc<[Tok]>,
+    source: Option<(SharedText, Spans)>,
+    range: Range<usize>,
+}
+
+impl<Tok> Tokens<Tok> {
+    pub fn new(tokens: impl Into<Rc<[Tok]>>) -> Self {
+        let tokens = tokens.into();
+        Tokens {
+            range: 0..tokens.len(),
+            tokens,
+            source: None,
+        }
+    }
+
+    /// Tokens paired with the span of `source` each was lexed from.
+    pub fn with_spans(
+        tokens: impl IntoIterator<Item = (Tok, Range<usize>)>,
+        source: impl Into<String>,
+    ) -> Self {
+        Tokens::with_text(tokens, SharedText::from(source.into()))
+    }
+
+    pub(crate) fn with_text(
+        tokens: impl IntoIterator<Item = (Tok, Range<usize>)>,
+        text: SharedText,
+    ) -> Self {
+        let (tokens, spans): (Vec<Tok>, Vec<Range<usize>>) = tokens.into_iter().unzip();
+        Tokens {
+            source: Some((text, Spans::from(spans))),
+            ..Tokens::new(tokens)
+        }
+    }
+
+    pub fn as_slice(&self) -> &[Tok] {
+        &self.tokens[self.range.clone()]
+    }
+
+    /// Where these tokens start in the tokens they were sliced from.
+    pub fn offset(&self) -> usize {
+        self.range.start
+    }
+
+    pub fn len(&self) -> usize {
+        self.range.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.range.is_empty()
+    }
+
+    /// The span of source text these tokens were lexed from, if it is known.
+    pub fn span(&self) -> Option<Range<usize>> {
+        let (_, spans) = self.source.as_ref()?;
+        // no tokens is an empty span where the next one starts
+        let at = |i: usize| {
+            spans.get(i).map_or_else(
+                || spans.last().map_or(0, |span| span.end),
+                |span| span.start,
+            )
+        };
+        match self.as_slice() {
+            [] => Some(at(self.range.start)..at(self.range.start)),
+            _ => Some(spans[self.range.start].start..spans[self.range.end - 1].end),
+        }
+    }
+}
+
+impl<Tok> Clone for Tokens<Tok> {
+    fn clone(&self) -> Self {
+        Tokens {
+            tokens: self.tokens.clone(),
+            source: self.source.clone(),
+            range: self.range.clone(),
+        }
+    }
+}
+
+impl<Tok> From<Vec<Tok>> for Tokens<Tok> {
+    fn from(tokens: Vec<Tok>) -> Self {
+        Tokens::new(tokens)
+    }
+}
+
+impl<Tok: Clone> From<&[Tok]> for Tokens<Tok> {
+    fn from(tokens: &[Tok]) -> Self {
+        Tokens::new(tokens)
+    }
+}
+
+impl<Tok: Debug> Debug for Tokens<Tok> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}@{:?}", self.as_slice(), self.range)
+    }
+}
+
+impl<Tok: PartialEq> PartialEq for Tokens<Tok> {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl<Tok: PartialEq> PartialEq<[Tok]> for Tokens<Tok> {
+    fn eq(&self, other: &[Tok]) -> bool {
+        self.as_slice() == other
+    }
+}
+
+impl<Tok: PartialEq> PartialEq<&[Tok]> for Tokens<Tok> {
+    fn eq(&self, other: &&[Tok]) -> bool {
+        self.as_slice() == *other
+    }
+}
+
+impl<Tok: PartialEq, const N: usize> PartialEq<[Tok; N]> for Tokens<Tok> {
+    fn eq(&self, other: &[Tok; N]) -> bool {
+        self.as_slice() == other
+    }
+}
+
+impl<Tok> Input for Tokens<Tok>
+where
+    Tok: Clone + PartialEq + Debug + 'static,
+{
+    type Elem = Tok;
+    type Slice = Tokens<Tok>;
+
+    fn len(&self) -> usize {
+        self.range.len()
+    }
+
+    fn next(&self, pos: Pos) -> Option<(Tok, Pos)> {
+        self.as_slice().get(pos).map(|tok| (tok.clone(), pos + 1))
+    }
+
+    fn slice(&self, range: Range<Pos>) -> Tokens<Tok> {
+        assert!(
+            range.start <= range.end && range.end <= self.len(),
+            "slice {:?} out of bounds for {} tokens",
+            range,
+            self.len()
+        );
+        Tokens {
+            tokens: self.tokens.clone(),
+            source: self.source.clone(),
+            range: self.range.start + range.start..self.range.start + range.end,
+        }
+    }
+
+    fn text(&self) -> Option<SharedText> {
+        self.source.as_ref().map(|(text, _)| text.clone())
+    }
+
+    fn spans(&self) -> Option<Spans> {
+        let (_, spans) = self.source.as_ref()?;
+        if self.range == (0..spans.len()) {
+            return Some(spans.clone());
+        }
+        Some(Spans::from(&spans[self.range.clone()]))
+    }
+}
+
+#[cfg(feature = "logos")]
+impl<Tok> Tokens<Tok> {
+    /// Collects what a [`logos`] lexer produces, along with the span of source text each
+    /// token came from so errors point into it. Fails at the first text the lexer doesn't
+    /// recognize. As with any input, the tokens must own their payloads.
+    pub fn from_logos<'s>(mut lexer: logos::Lexer<'s, Tok>) -> Result<Self, ParseError>
+    where
+        Tok: logos::Logos<'s, Source = str>,
+    {
+        let mut tokens = Vec::new();
+        while let Some(token) = lexer.next() {
+            let span = lexer.span();
+            match token {
+                Ok(tok) => tokens.push((tok, span)),
+                Err(_) => {
+                    let source = lexer.source();
+                    return Err(ParseError {
+                        source: Some(ErrorSource {
+                            text: SharedText::from(source),
+                            spans: None,
+                            origin: None,
+                            universal_newlines: false,
+                        }),
+                        pos: span.start,
+                        reason: Reason::Expected {
+                            label: Text::from("token"),
+                            found: Found::from(source[span.start..].chars().next()),
+                        },
+                    });
+                }
+            }
+        }
+        Ok(Tokens::with_spans(tokens, lexer.source()))
+    }
+}
+
+/// Matches one element against patterns, producing the value of the arm that matched, so
+/// payloads can be pulled out of tokens as they are matched. Arms may have guards; the
+/// element is matched by value.
+///
+/// ```
+/// use packrust::*;
+///
+/// #[derive(Debug, Clone, PartialEq)]
+/// enum Tok {
+///     Ident(String),
+///     Int(i64),
+///     Comma,
+/// }
+///
+/// let atom = select! {
+///     Tok::Ident(name) => name,
+///     Tok::Int(n) if n >= 0 => n.to_string(),
+/// };
+/// let list = atom.sep_by(just(Tok::Comma));
+/// let ctx = &mut Context::from_tokens(&[Tok::Ident("x".into()), Tok::Comma, Tok::Int(4)]);
+/// assert_eq!(list.parse(0, ctx), Ok((3, vec!["x".to_string(), "4".to_string()])));
+/// ```
+#[macro_export]
+macro_rules! select {
+    ($($pattern:pat $(if $guard:expr)? => $out:expr),+ $(,)?) => {
+        $crate::elem_map(
+            [$(stringify!($pattern)),+].join(" or "),
+            move |elem| match elem {
+                $($pattern $(if $guard)? => Some($out),)+
+                #[allow(unreachable_patterns)]
+                _ => None,
+            },
+        )
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use std::ops::Range;
+
+    use crate::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Tok {
+        Num(u32),
+        Plus,
+        Star,
+    }
+
+    fn lex(source: &str) -> Tokens<Tok> {
+        let mut tokens: Vec<(Tok, Range<usize>)> = Vec::new();
+        for (i, c) in source.char_indices() {
+            let tok = match c {
+                '+' => Tok::Plus,
+                '*' => Tok::Star,
+                ' ' => continue,
+                _ => {
+                    let digit = c.to_digit(10).unwrap();
+                    match tokens.last_mut() {
+                        Some((Tok::Num(n), span)) if span.end == i => {
+                            (*n, span.end) = (*n * 10 + digit, i + 1);
+                            continue;
+                        }
+                        _ => Tok::Num(digit),
+                    }
+                }
+            };
+            tokens.push((tok, i..i + c.len_utf8()));
+        }
+        Tokens::with_spans(tokens, source)
+    }
+
+    fn expr<'a>() -> Parser<'a, u32, Tokens<Tok>> {
+        let num = elem("number", |tok| matches!(tok, Tok::Num(_))).map(|tok| match tok {
+            Tok::Num(n) => n,
+            _ => unreachable!(),
+        });
+        let plus = elem("'+'", |tok| *tok == Tok::Plus);
+        let star = elem("'*'", |tok| *tok == Tok::Star);
+        let term = lazy("term", move |term| {
+            term.andl(star.clone())
+                .and(num.clone())
+                .map(|(a, b)| a * b)
+                .or(num.clone())
+        });
+        lazy("expr", move |expr| {
+            expr.andl(plus.clone())
+                .and(term.clone())
+                .map(|(a, b)| a + b)
+                .or(term.clone())
+        })
+        .end()
+    }
+
+    #[test]
+    fn test_tokens() {
+        let ctx = &mut Context::from_source(lex("1 + 22 * 3+4"));
+        assert_eq!(ctx.source().len(), 7);
+        assert_eq!(expr().parse(0, ctx), Ok((7, 71)));
+
+        // positions count tokens, errors point at where they came from
+        let ctx = &mut Context::from_source(lex("1 + * 3"));
+        let error = expr().parse(0, ctx).unwrap_err();
+        assert_eq!((error.pos(), error.offset()), (1, 2));
+        assert_eq!(error.to_string(), "1 + * 3\n  ^\nexpected EOF found Plus\n");
+        let ctx = &mut Context::from_source(lex("12 +  "));
+        let error = expr().parse(0, ctx).unwrap_err();
+        assert_eq!((error.pos(), error.offset()), (1, 3));
+
+        let product = elem("number", |tok| matches!(tok, Tok::Num(_)))
+            .and(elem("'*'", |tok| *tok == Tok::Star))
+            .and(elem("number", |tok| matches!(tok, Tok::Num(_))))
+            .recognize();
+        let ctx = &mut Context::from_source(lex("1 + 22 * 3"));
+        let (_, tokens) = product.parse(2, ctx).unwrap();
+        assert_eq!(tokens, [Tok::Num(22), Tok::Star, Tok::Num(3)]);
+        assert_eq!(tokens.span(), Some(4..10));
+
+        let ctx = &mut Context::from_tokens(&[Tok::Num(2), Tok::Star, Tok::Num(3)]);
+        assert_eq!(expr().parse(0, ctx), Ok((3, 6)));
+        let ctx = &mut Context::from_tokens(&[Tok::Num(2), Tok::Star]);
+        let error = expr().parse(0, ctx).unwrap_err();
+        assert_eq!(error.to_string(), "at 1\nexpected EOF found Star\n");
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Call {
+        Ident(String),
+        Int(i64),
+        LParen,
+        RParen,
+        Comma,
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Expr {
+        Var(String),
+        Int(i64),
+        Call(String, Vec<Expr>),
+    }
+
+    #[test]
+    fn test_just_and_select() {
+        let expr = lazy("expr", |expr| {
+            let call = select! { Call::Ident(name) => name }
+                .andl(just(Call::LParen))
+                .and(expr.sep_by(just(Call::Comma)))
+                .andl(just(Call::RParen))
+                .map(|(name, args)| Expr::Call(name, args));
+            call.or(select! {
+                Call::Ident(name) => Expr::Var(name),
+                Call::Int(n) => Expr::Int(n),
+            })
+        });
+
+        let tokens = [
+            Call::Ident("f".into()),
+            Call::LParen,
+            Call::Ident("a".into()),
+            Call::Comma,
+            Call::Int(1),
+            Call::RParen,
+        ];
+        let ctx = &mut Context::from_tokens(&tokens);
+        assert_eq!(
+            expr.parse(0, ctx),
+            Ok((
+                6,
+                Expr::Call("f".into(), vec![Expr::Var("a".into()), Expr::Int(1)])
+            ))
+        );
+
+        let ctx = &mut Context::from_tokens(&[Call::Comma]);
+        let error = expr.parse(0, ctx).unwrap_err();
+        let atom = select! { Call::Ident(name) => name, Call::Int(n) => n.to_string() };
+        assert_eq!(
+            atom.parse(0, ctx).unwrap_err().to_string(),
+            "at 0\nexpected Call::Ident(name) or Call::Int(n) got Comma\n"
+        );
+        assert_eq!(
+            error.to_string(),
+            "at 0\nexpected Call::Ident(name) got Comma\n"
+        );
+        let ctx = &mut Context::from_tokens(&tokens[..5]);
+        let error = expr.end().parse(0, ctx).unwrap_err();
+        assert_eq!(error.to_string(), "at 1\nexpected EOF found LParen\n");
+    }
+
+    #[cfg(feature = "logos")]
+    #[test]
+    fn test_logos() {
+        use logos::Logos;
+
+        #[derive(Logos, Debug, Clone, PartialEq)]
+        #[logos(skip r"[ \t]+")]
+        enum Lexed {
+            #[regex("[0-9]+", |lex| lex.slice().parse().ok())]
+            Num(u32),
+            #[token("+")]
+            Plus,
+        }
+
+        let num = select! { Lexed::Num(n) => n };
+        let sum = just(Lexed::Plus)
+            .andr(num.clone())
+            .foldl(num, |a, b| a + b)
+            .end();
+
+        let ctx = &mut Context::from_logos(Lexed::lexer("1 + 20 +3")).unwrap();
+        assert_eq!(sum.parse(0, ctx), Ok((5, 24)));
+        let ctx = &mut Context::from_logos(Lexed::lexer("1 + 20 3")).unwrap();
+        let error = sum.parse(0, ctx).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "1 + 20 3\n       ^\nexpected EOF found Num(3)\n"
+        );
+
+        let error = Context::from_logos(Lexed::lexer("1 + x")).err().unwrap();
+        assert_eq!(error.to_string(), "1 + x\n    ^\nexpected token got x\n");
+    }
+}
@@ -0,0 +1,392 @@
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::time::Instant;
+
+use crate::{ParseError, ParseObserver};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    // a `trace_event` JSON array for chrome://tracing or Perfetto, where calls nest into
+    // a flame chart
+    Chrome,
+    // one JSON object per event and line, easy to grep or load into anything
+    JsonLines,
+}
+
+/// An observer that writes every parse event to `W` as it happens, either in Chrome's
+/// `trace_event` format or as JSON lines. Write errors are kept and reported by `finish`.
+pub struct TraceWriter<W: Write> {
+    out: W,
+    format: Format,
+    started: Instant,
+    events: usize,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn chrome(out: W) -> Self {
+        Self::new(out, Format::Chrome)
+    }
+
+    pub fn json_lines(out: W) -> Self {
+        Self::new(out, Format::JsonLines)
+    }
+
+    fn new(out: W, format: Format) -> Self {
+        TraceWriter {
+            out,
+            format,
+            started: Instant::now(),
+            events: 0,
+            error: None,
+        }
+    }
+
+    /// Closes the Chrome event array and flushes, returning the first error hit while writing.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        if self.format == Format::Chrome {
+            let open = if self.events == 0 { "[" } else { "" };
+            write!(self.out, "{}]", open)?;
+        }
+        self.out.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    // `fields` is the rest of the JSON object, already formatted
+    fn event(&mut self, kind: &str, rule: &str, fields: &str) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let ts = self.started.elapsed().as_secs_f64() * 1e6;
+        let mut line = String::new();
+        match self.format {
+            Format::Chrome => {
+                let (name, phase) = match kind {
+                    "enter" => (rule.to_string(), "\"ph\":\"B\""),
+                    "exit" | "fail" => (rule.to_string(), "\"ph\":\"E\""),
+                    _ => (format!("{} {}", kind, rule), "\"ph\":\"i\",\"s\":\"t\""),
+                };
+                let separator = if self.events == 0 { "[\n" } else { ",\n" };
+                let _ = write!(
+                    line,
+                    "{}{{\"name\":{},{},\"ts\":{:.3},\"pid\":0,\"tid\":0,\"args\":{{{}}}}}",
+                    separator,
+                    json_string(&name),
+                    phase,
+                    ts,
+                    fields
+                );
+            }
+            Format::JsonLines => {
+                let _ = writeln!(
+                    line,
+                    "{{\"ts\":{:.3},\"event\":\"{}\",\"rule\":{},{}}}",
+                    ts,
+                    kind,
+                    json_string(rule),
+                    fields
+                );
+            }
+        }
+
+        self.events += 1;
+        if let Err(error) = self.out.write_all(line.as_bytes()) {
+            self.error = Some(error);
+        }
+    }
+}
+
+impl<W: Write> ParseObserver for TraceWriter<W> {
+    fn on_enter(&mut self, rule: &str, pos: usize) {
+        self.event("enter", rule, &format!("\"pos\":{}", pos));
+    }
+
+    fn on_exit(&mut self, rule: &str, pos: usize, result: Result<usize, &ParseError>) {
+        match result {
+            Ok(end) => self.event("exit", rule, &format!("\"pos\":{},\"end\":{}", pos, end)),
+            Err(_) => self.event("fail", rule, &format!("\"pos\":{}", pos)),
+        }
+    }
+
+    fn on_memo_hit(&mut self, rule: &str, pos: usize) {
+        self.event("memo_hit", rule, &format!("\"pos\":{}", pos));
+    }
+
+    fn on_lr_grow(&mut self, rule: &str, pos: usize, end: usize) {
+        self.event("lr_grow", rule, &format!("\"pos\":{},\"end\":{}", pos, end));
+    }
+}
+
+// every call of a parse as a tree, kept by the context for `dump_trace_html`
+#[derive(Debug, Default)]
+pub(crate) struct CallTree {
+    calls: Vec<Call>,
+    roots: Vec<usize>,
+    stack: Vec<usize>,
+}
+
+#[derive(Debug)]
+struct Call {
+    rule: String,
+    pos: usize,
+    // None while running and after a failure
+    end: Option<usize>,
+    memo_hit: bool,
+    children: Vec<Step>,
+}
+
+// what happened inside a call, in order; a left-recursive rule re-runs its body after
+// every growth, so its children repeat with the growths in between
+#[derive(Debug)]
+enum Step {
+    Call(usize),
+    Grow(usize),
+}
+
+impl ParseObserver for CallTree {
+    fn on_enter(&mut self, rule: &str, pos: usize) {
+        let id = self.calls.len();
+        self.calls.push(Call {
+            rule: rule.to_string(),
+            pos,
+            end: None,
+            memo_hit: false,
+            children: Vec::new(),
+        });
+        match self.stack.last() {
+            Some(&parent) => self.calls[parent].children.push(Step::Call(id)),
+            None => self.roots.push(id),
+        }
+        self.stack.push(id);
+    }
+
+    fn on_exit(&mut self, _rule: &str, _pos: usize, result: Result<usize, &ParseError>) {
+        if let Some(id) = self.stack.pop() {
+            self.calls[id].end = result.ok();
+        }
+    }
+
+    fn on_memo_hit(&mut self, _rule: &str, _pos: usize) {
+        if let Some(&id) = self.stack.last() {
+            self.calls[id].memo_hit = true;
+        }
+    }
+
+    fn on_lr_grow(&mut self, _rule: &str, _pos: usize, end: usize) {
+        if let Some(&id) = self.stack.last() {
+            self.calls[id].children.push(Step::Grow(end));
+        }
+    }
+}
+
+impl CallTree {
+    pub(crate) fn write_html(&self, source: &str, mut out: impl Write) -> io::Result<()> {
+        write!(
+            out,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>packrust trace</title>\n<style>{}</style>\n</head>\n<body>\n\
+             <h1>packrust trace</h1>\n<pre class=\"source\">{}</pre>\n\
+             <p>{} calls. Click a call to fold it.</p>\n",
+            STYLE,
+            html_escape(source),
+            self.calls.len()
+        )?;
+        for &root in &self.roots {
+            self.write_call(root, source, &mut out)?;
+        }
+        writeln!(out, "</body>\n</html>")?;
+        out.flush()
+    }
+
+    fn write_call(&self, id: usize, source: &str, out: &mut impl Write) -> io::Result<()> {
+        let call = &self.calls[id];
+        let (class, outcome) = match call.end {
+            Some(end) => (
+                "matched",
+                format!(
+                    "matched {}..{} <code>{}</code>",
+                    call.pos,
+                    end,
+                    html_escape(source.get(call.pos..end).unwrap_or(""))
+                ),
+            ),
+            None => ("failed", format!("failed at {}", call.pos)),
+        };
+        let hit = if call.memo_hit {
+            " <span class=\"hit\">memo hit</span>"
+        } else {
+            ""
+        };
+        let open = if call.end.is_some() { " open" } else { "" };
+
+        if call.children.is_empty() {
+            return writeln!(
+                out,
+                "<div class=\"leaf {}\"><b>{}</b> {}{}</div>",
+                class,
+                html_escape(&call.rule),
+                outcome,
+                hit
+            );
+        }
+
+        writeln!(
+            out,
+            "<details class=\"{}\"{}><summary><b>{}</b> {}{}</summary>",
+            class,
+            open,
+            html_escape(&call.rule),
+            outcome,
+            hit
+        )?;
+        for step in &call.children {
+            match *step {
+                Step::Call(child) => self.write_call(child, source, out)?,
+                Step::Grow(end) => writeln!(
+                    out,
+                    "<div class=\"grow\">left recursion grown to {}..{}</div>",
+                    call.pos, end
+                )?,
+            }
+        }
+        writeln!(out, "</details>")
+    }
+}
+
+const STYLE: &str = "body { font-family: sans-serif; } \
+    .source { background: #f4f4f4; padding: 0.5em; } \
+    details, .leaf, .grow { margin-left: 1.5em; } \
+    summary { cursor: pointer; } \
+    .matched > summary, .leaf.matched { color: #1a7f37; } \
+    .failed > summary, .leaf.failed { color: #b42318; } \
+    .hit { color: #666; font-style: italic; } \
+    .grow { color: #8250df; font-weight: bold; }";
+
+fn html_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn grammar() -> Parser<'static, char> {
+        let a = satisfy("a", |c| c == 'a');
+        a.clone().andl(keyword("b")).or(a.andl(char('c')))
+    }
+
+    // timestamps differ from run to run, so they are cut out before comparing
+    fn without_timestamps(trace: &str) -> String {
+        trace
+            .split("\"ts\":")
+            .enumerate()
+            .map(|(i, part)| match i {
+                0 => part,
+                _ => part.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.'),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let trace = Rc::new(RefCell::new(TraceWriter::json_lines(Vec::new())));
+        let ctx = &mut Context::new("ac");
+        ctx.set_observer(trace.clone());
+        assert!(grammar().parse(0, ctx).is_ok());
+        trace.borrow_mut().finish().unwrap();
+
+        let out = String::from_utf8(trace.borrow().out.clone()).unwrap();
+        let lines: Vec<_> = out.lines().map(without_timestamps).collect();
+        assert_eq!(lines.len(), 15);
+        assert_eq!(lines[4], r#"{,"event":"enter","rule":"b","pos":1}"#);
+        assert_eq!(lines[9], r#"{,"event":"memo_hit","rule":"a","pos":0}"#);
+        assert_eq!(
+            lines[14],
+            r#"{,"event":"exit","rule":"((ab)/(a'c'))","pos":0,"end":2}"#
+        );
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(super::json_string("'a'"), r#""'a'""#);
+        assert_eq!(super::json_string("\"\\\n\u{1}"), r#""\"\\\n\u0001""#);
+    }
+
+    #[test]
+    fn test_chrome() {
+        let trace = Rc::new(RefCell::new(TraceWriter::chrome(Vec::new())));
+        let ctx = &mut Context::new("ac");
+        ctx.set_observer(trace.clone());
+        assert!(grammar().parse(0, ctx).is_ok());
+        trace.borrow_mut().finish().unwrap();
+
+        let out = without_timestamps(&String::from_utf8(trace.borrow().out.clone()).unwrap());
+        assert!(out.starts_with("[\n{\"name\":\"((a"));
+        assert!(out.ends_with("\"args\":{\"pos\":0,\"end\":2}}]"));
+        assert_eq!(out.matches("\"ph\":\"B\"").count(), 7);
+        assert_eq!(out.matches("\"ph\":\"E\"").count(), 7);
+        assert!(out.contains("{\"name\":\"memo_hit a\",\"ph\":\"i\",\"s\":\"t\","));
+
+        let empty = &mut TraceWriter::chrome(Vec::new());
+        empty.finish().unwrap();
+        assert_eq!(empty.out, b"[]");
+    }
+
+    #[test]
+    fn test_trace_html() {
+        let sum = fixture::sum();
+
+        let ctx = &mut Context::new("1+2<3").with_trace();
+        assert_eq!(sum.parse(0, ctx), Ok((3, 3)));
+        let mut html = Vec::new();
+        ctx.write_trace_html(&mut html).unwrap();
+        let html = String::from_utf8(html).unwrap();
+
+        assert!(html.contains("<pre class=\"source\">1+2&lt;3</pre>"));
+        assert!(html.contains("<details class=\"matched\" open><summary><b>sum</b> matched 0..3 <code>1+2</code></summary>"));
+        assert!(html.contains("<div class=\"grow\">left recursion grown to 0..3</div>"));
+        assert!(html.contains("<span class=\"hit\">memo hit</span>"));
+        assert!(html.contains("<div class=\"leaf failed\"><b>'+'</b> failed at 3</div>"));
+        assert_eq!(
+            html.matches("<details").count(),
+            html.matches("</details>").count()
+        );
+    }
+}
@@ -0,0 +1,205 @@
+#[cfg(feature = "unicode-categories")]
+pub use unicode_general_category::GeneralCategory;
+#[cfg(feature = "unicode-categories")]
+use unicode_general_category::get_general_category;
+
+#[cfg(feature = "unicode-case")]
+use caseless::Caseless;
+
+#[cfg(any(
+    feature = "unicode-ident",
+    feature = "unicode-categories",
+    feature = "unicode-case"
+))]
+use crate::{Parser, satisfy};
+
+/// Matches an identifier as Unicode defines them (UAX #31), the way Rust, Python and
+/// JavaScript do: a char with the XID_Start property or `_`, followed by chars with the
+/// XID_Continue property.
+///
+/// ```
+/// use packrust::*;
+///
+/// assert_eq!(identifier().run("größe"), Ok(String::from("größe")));
+/// assert_eq!(identifier().run("_переменная2"), Ok(String::from("_переменная2")));
+/// assert!(identifier().run("2x").is_err());
+/// ```
+#[cfg(feature = "unicode-ident")]
+pub fn identifier<'a>() -> Parser<'a, String> {
+    let start = satisfy("identifier", |c| c == '_' || unicode_ident::is_xid_start(c));
+    let rest = satisfy("identifier char", unicode_ident::is_xid_continue);
+    start
+        .and(rest.many_collect::<String>())
+        .map(|(first, rest)| format!("{}{}", first, rest))
+        .rename("identifier")
+}
+
+// appends the full Unicode case folding of `c`, which may be several chars, as ß folds to ss
+#[cfg(feature = "unicode-case")]
+fn case_fold(c: char, folded: &mut String) {
+    folded.extend(std::iter::once(c).default_case_fold());
+}
+
+/// Matches the text `text` in any case, comparing them by full Unicode case folding as
+/// caseless matching does, so `"straße"` matches `STRASSE` and `"σ"` matches `ς`. Produces
+/// the text as it was written in the input.
+///
+/// ```
+/// use packrust::*;
+///
+/// let street = string_caseless("Straße");
+/// assert_eq!(street.run("STRASSE"), Ok(String::from("STRASSE")));
+/// assert_eq!(street.run("strasse"), Ok(String::from("strasse")));
+/// assert!(string_ci("Straße").run("STRASSE").is_err());
+/// ```
+#[cfg(feature = "unicode-case")]
+pub fn string_caseless<'a>(text: impl Into<String>) -> Parser<'a, String> {
+    let text = text.into();
+    crate::combinators::string_folded(format!("\"{}\"i", text), &text, case_fold)
+}
+
+/// Matches any char that case folds the same as `c`, such as `Ω` and `ω` for `Ω`, or the
+/// Kelvin sign for `k`. Produces the char as it was written in the input.
+#[cfg(feature = "unicode-case")]
+pub fn char_caseless<'a>(c: char) -> Parser<'a, char> {
+    let mut folded = String::new();
+    case_fold(c, &mut folded);
+    satisfy(format!("'{}'i", c), move |x| {
+        let mut other = String::new();
+        case_fold(x, &mut other);
+        other == folded
+    })
+}
+
+/// Matches a char of the Unicode general category `category`, named by its abbreviation.
+///
+/// ```
+/// use packrust::*;
+///
+/// let upper = category(GeneralCategory::UppercaseLetter);
+/// assert_eq!(upper.run("Ärger"), Ok('Ä'));
+/// assert!(upper.run("ärger").is_err());
+/// ```
+#[cfg(feature = "unicode-categories")]
+pub fn category<'a>(category: GeneralCategory) -> Parser<'a, char> {
+    satisfy(category.abbreviation(), move |c| {
+        get_general_category(c) == category
+    })
+}
+
+// the categories whose abbreviations start with `major`, such as L for all the letters
+#[cfg(feature = "unicode-categories")]
+fn major_category<'a>(name: &str, major: char) -> Parser<'a, char> {
+    satisfy(name, move |c| {
+        get_general_category(c).abbreviation().starts_with(major)
+    })
+}
+
+/// Matches a letter, of any of the categories Lu, Ll, Lt, Lm and Lo.
+#[cfg(feature = "unicode-categories")]
+pub fn letter<'a>() -> Parser<'a, char> {
+    major_category("letter", 'L')
+}
+
+/// Matches a combining mark, of any of the categories Mn, Mc and Me.
+#[cfg(feature = "unicode-categories")]
+pub fn mark<'a>() -> Parser<'a, char> {
+    major_category("mark", 'M')
+}
+
+/// Matches a number, of any of the categories Nd, Nl and No.
+#[cfg(feature = "unicode-categories")]
+pub fn number<'a>() -> Parser<'a, char> {
+    major_category("number", 'N')
+}
+
+/// Matches punctuation, of any of the categories Pc, Pd, Ps, Pe, Pi, Pf and Po.
+#[cfg(feature = "unicode-categories")]
+pub fn punctuation<'a>() -> Parser<'a, char> {
+    major_category("punctuation", 'P')
+}
+
+/// Matches a symbol, of any of the categories Sm, Sc, Sk and So.
+#[cfg(feature = "unicode-categories")]
+pub fn symbol<'a>() -> Parser<'a, char> {
+    major_category("symbol", 'S')
+}
+
+/// Matches a separator, of any of the categories Zs, Zl and Zp.
+#[cfg(feature = "unicode-categories")]
+pub fn separator<'a>() -> Parser<'a, char> {
+    major_category("separator", 'Z')
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(feature = "unicode-ident")]
+    #[test]
+    fn test_identifier() {
+        use crate::*;
+
+        let idents = identifier().sep_by(char(' ')).end();
+        assert_eq!(
+            idents.run("x_1 日本語 Ωmega ℮"),
+            Ok(["x_1", "日本語", "Ωmega", "℮"].map(String::from).to_vec())
+        );
+        // combining marks and connectors only continue an identifier
+        assert!(identifier().run("\u{301}a").is_err());
+        assert_eq!(identifier().run("e\u{301}"), Ok(String::from("e\u{301}")));
+        assert!(identifier().end().run("a-b").is_err());
+    }
+
+    #[cfg(feature = "unicode-categories")]
+    #[test]
+    fn test_categories() {
+        use crate::*;
+
+        let classes = [
+            ("letter", letter()),
+            ("mark", mark()),
+            ("number", number()),
+            ("punctuation", punctuation()),
+            ("symbol", symbol()),
+            ("separator", separator()),
+        ];
+        let classify = |c: char| {
+            let text = c.to_string();
+            classes
+                .iter()
+                .filter(|(_, class)| class.run(text.as_str()).is_ok())
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(classify('ǅ'), ["letter"]);
+        assert_eq!(classify('\u{301}'), ["mark"]);
+        assert_eq!(classify('Ⅻ'), ["number"]);
+        assert_eq!(classify('«'), ["punctuation"]);
+        assert_eq!(classify('€'), ["symbol"]);
+        assert_eq!(classify('\u{3000}'), ["separator"]);
+        assert!(classify('\n').is_empty());
+
+        let titlecase = category(GeneralCategory::TitlecaseLetter);
+        let error = titlecase.run("D").unwrap_err();
+        assert_eq!(error.to_string(), "D\n^\nexpected Lt got D\n");
+    }
+
+    #[cfg(feature = "unicode-case")]
+    #[test]
+    fn test_caseless() {
+        use crate::*;
+
+        let keyword = string_caseless("Größe").andl(char(';'));
+        assert_eq!(keyword.run("GRÖSSE;"), Ok(String::from("GRÖSSE")));
+        assert_eq!(keyword.run("größe;"), Ok(String::from("größe")));
+        // a fold that runs past the end of the text doesn't match part of it
+        assert!(string_caseless("s").run("ß").is_err());
+        let error = keyword.run("Grüße;").unwrap_err();
+        assert_eq!(error.pos(), 0);
+        assert!(error.to_string().ends_with("expected \"Größe\"i got G\n"));
+
+        let kelvin = char_caseless('k');
+        assert_eq!(kelvin.run("\u{212a}"), Ok('\u{212a}'));
+        assert_eq!(kelvin.run("K"), Ok('K'));
+        assert!(kelvin.run("x").is_err());
+    }
+}
@@ -0,0 +1,606 @@
+use std::rc::Rc;
+
+use rustc_hash::FxHashMap;
+
+use crate::combinators::NO_PROGRESS;
+use crate::memo::{Completed, grows};
+use crate::shape::{Predicate, Recognizer, RuleShape, Shape};
+use crate::{Context, Found, ParseError, Pos, Reason, Text};
+
+type Addr = usize;
+type RuleIndex = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Inst {
+    Char(char),
+    Keyword(usize),
+    Any,
+    Class(usize),
+    Eof,
+    // push a backtrack entry resuming at the address with the current position
+    Choice(Addr),
+    // drop the top backtrack entry and jump
+    Commit(Addr),
+    // move the top backtrack entry to the current position and jump, closing a loop; a
+    // loop whose body matched nothing fails, as `many` does, instead of spinning forever
+    PartialCommit(Addr),
+    // drop the top backtrack entry and fail, ending a negative lookahead whose body matched
+    FailTwice,
+    Call(RuleIndex),
+    Opaque(usize),
+    Return,
+}
+
+struct Rule {
+    name: String,
+    start: Addr,
+}
+
+/// A grammar lowered to a flat instruction array, produced by
+/// [`Grammar::compile`](crate::Grammar::compile).
+///
+/// The program only recognizes input, it never builds values: semantic actions such as `map`
+/// are skipped, and only named rules are memoized, so checking that input is well formed
+/// costs no closure calls or allocations per combinator. To get values, run the parsers
+/// themselves; a program accepts the same input they do, and like `many` a repetition whose
+/// body matches nothing fails. Parsers whose structure the combinators can't describe
+/// (`try_map`, `pratt`, ...) are called as they are.
+///
+/// Rule calls are kept on a stack of their own rather than made as native calls, so however
+/// deeply untrusted input nests, recognizing it can't overflow the thread's stack; only the
+/// parsers called as they are still recurse natively. Only recognizing is stack-safe this
+/// way: parsing the same input into values goes through [`Parser::parse`](crate::Parser::parse),
+/// which recurses natively and needs [`Context::with_depth_limit`](crate::Context::with_depth_limit)
+/// to turn away input nested too deeply.
+pub struct Program {
+    code: Vec<Inst>,
+    rules: Vec<Rule>,
+    keywords: Vec<Rc<str>>,
+    classes: Vec<(Text, Predicate<'static>)>,
+    opaque: Vec<Recognizer<'static>>,
+}
+
+#[derive(Clone, Copy)]
+enum Memo {
+    // `left_recursive` once the call was re-entered, so its result is only a seed to grow
+    InProgress { left_recursive: bool },
+    Done(Option<Pos>),
+}
+
+struct Compiler {
+    program: Program,
+    // rule shapes by address, in the same order as `program.rules`
+    indices: FxHashMap<usize, RuleIndex>,
+    pending: Vec<(RuleIndex, Rc<RuleShape<'static>>)>,
+}
+
+impl Program {
+    pub(crate) fn compile<'r>(rules: impl IntoIterator<Item = &'r Rc<RuleShape<'static>>>) -> Self {
+        let mut compiler = Compiler {
+            program: Program {
+                code: Vec::new(),
+                rules: Vec::new(),
+                keywords: Vec::new(),
+                classes: Vec::new(),
+                opaque: Vec::new(),
+            },
+            indices: FxHashMap::default(),
+            pending: Vec::new(),
+        };
+        for rule in rules {
+            compiler.rule(rule);
+        }
+        while let Some((index, rule)) = compiler.pending.pop() {
+            compiler.program.rules[index].start = compiler.program.code.len();
+            let body = rule.body().expect("rule compiled before it was defined");
+            compiler.shape(body);
+            compiler.emit(Inst::Return);
+        }
+        compiler.program
+    }
+
+    /// The number of instructions, a rough measure of the grammar's size.
+    pub fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty()
+    }
+
+    /// Matches the rule called `name` at the start of `source`, returning where the match
+    /// ended. Panics if the program has no such rule.
+    pub fn recognize(&self, name: &str, source: impl Into<String>) -> Result<Pos, ParseError> {
+        let rule = self
+            .rules
+            .iter()
+            .position(|rule| rule.name == name)
+            .unwrap_or_else(|| panic!("no rule named `{}`", name));
+
+        let mut vm = Vm {
+            program: self,
+            ctx: Context::new(source),
+            memo: FxHashMap::default(),
+            log: Completed::default(),
+            stack: Vec::new(),
+            frames: Vec::new(),
+            farthest: None,
+        };
+        match vm.run(rule, 0) {
+            Some(end) => Ok(end),
+            None => Err(vm.error()),
+        }
+    }
+}
+
+impl Compiler {
+    fn emit(&mut self, inst: Inst) -> Addr {
+        self.program.code.push(inst);
+        self.program.code.len() - 1
+    }
+
+    fn patch(&mut self, at: Addr) {
+        let target = self.program.code.len();
+        match &mut self.program.code[at] {
+            Inst::Choice(addr) | Inst::Commit(addr) => *addr = target,
+            inst => unreachable!("cannot patch {:?}", inst),
+        }
+    }
+
+    fn not(&mut self, body: impl FnOnce(&mut Self)) {
+        let choice = self.emit(Inst::Choice(0));
+        body(self);
+        self.emit(Inst::FailTwice);
+        self.patch(choice);
+    }
+
+    fn rule(&mut self, rule: &Rc<RuleShape<'static>>) -> RuleIndex {
+        if let Some(&index) = self.indices.get(&rule.id()) {
+            return index;
+        }
+        let index = self.program.rules.len();
+        self.program.rules.push(Rule {
+            name: rule.name.clone(),
+            start: 0,
+        });
+        self.indices.insert(rule.id(), index);
+        self.pending.push((index, rule.clone()));
+        index
+    }
+
+    fn shape(&mut self, shape: &Shape<'static>) {
+        match shape {
+            Shape::Char(c) => {
+                self.emit(Inst::Char(*c));
+            }
+            Shape::Keyword(keyword) => {
+                self.program.keywords.push(keyword.clone());
+                self.emit(Inst::Keyword(self.program.keywords.len() - 1));
+            }
+            Shape::Any => {
+                self.emit(Inst::Any);
+            }
+            Shape::Class(label, predicate) => {
+                self.program
+                    .classes
+                    .push((Text::from(label.as_ref()), predicate.clone()));
+                self.emit(Inst::Class(self.program.classes.len() - 1));
+            }
+            Shape::Eof => {
+                self.emit(Inst::Eof);
+            }
+            Shape::Seq(items) => items.iter().for_each(|item| self.shape(item)),
+            Shape::Choice(alternatives) => {
+                let (last, init) = alternatives.split_last().expect("empty choice");
+                let mut commits = Vec::new();
+                for alternative in init {
+                    let choice = self.emit(Inst::Choice(0));
+                    self.shape(alternative);
+                    commits.push(self.emit(Inst::Commit(0)));
+                    self.patch(choice);
+                }
+                self.shape(last);
+                commits.into_iter().for_each(|commit| self.patch(commit));
+            }
+            Shape::Many(item) => {
+                let choice = self.emit(Inst::Choice(0));
+                let body = self.program.code.len();
+                self.shape(item);
+                self.emit(Inst::PartialCommit(body));
+                self.patch(choice);
+            }
+            Shape::Opt(item) => {
+                let choice = self.emit(Inst::Choice(0));
+                self.shape(item);
+                let commit = self.emit(Inst::Commit(0));
+                self.patch(choice);
+                self.patch(commit);
+            }
+            Shape::Not(item) => self.not(|compiler| compiler.shape(item)),
+            Shape::And(item) => self.not(|compiler| compiler.not(|compiler| compiler.shape(item))),
+            Shape::Rule(rule) => {
+                let index = self.rule(rule);
+                self.emit(Inst::Call(index));
+            }
+            Shape::Opaque(_, recognizer) => {
+                self.program.opaque.push(recognizer.clone());
+                self.emit(Inst::Opaque(self.program.opaque.len() - 1));
+            }
+        }
+    }
+}
+
+struct Vm<'p> {
+    program: &'p Program,
+    ctx: Context,
+    memo: FxHashMap<(RuleIndex, Pos), Memo>,
+    log: Completed,
+    // backtrack entries of every active rule body, each body only touches its own top
+    stack: Vec<(Addr, Pos)>,
+    frames: Vec<Frame>,
+    farthest: Option<(Pos, Reason)>,
+}
+
+// a rule call in progress; calls are kept on the heap rather than the native stack, so
+// how deeply the input nests is bounded by memory alone
+struct Frame {
+    rule: RuleIndex,
+    pos: Pos,
+    // the `Call` instruction to continue after
+    caller: Addr,
+    // where this call's backtrack entries start
+    base: usize,
+    // where the memo keys completed during this call start
+    mark: usize,
+    // the seed grown so far, once the call turned out to be left recursive
+    best: Option<Pos>,
+}
+
+enum Entered {
+    Done(Option<Pos>),
+    Started(Addr),
+}
+
+enum Left {
+    // the body runs again from the start to grow a left-recursive seed
+    Again(Addr, Pos),
+    Returned(Addr, Option<Pos>),
+}
+
+impl Vm<'_> {
+    fn enter(&mut self, rule: RuleIndex, pos: Pos, caller: Addr) -> Entered {
+        let key = (rule, pos);
+        match self.memo.get(&key) {
+            Some(Memo::Done(result)) => return Entered::Done(*result),
+            Some(Memo::InProgress { .. }) => {
+                self.memo.insert(
+                    key,
+                    Memo::InProgress {
+                        left_recursive: true,
+                    },
+                );
+                return Entered::Done(None);
+            }
+            None => {}
+        }
+
+        self.memo.insert(
+            key,
+            Memo::InProgress {
+                left_recursive: false,
+            },
+        );
+        self.frames.push(Frame {
+            rule,
+            pos,
+            caller,
+            base: self.stack.len(),
+            mark: self.log.mark(),
+            best: None,
+        });
+        Entered::Started(self.program.rules[rule].start)
+    }
+
+    // the body of the innermost call finished with `result`
+    fn leave(&mut self, result: Option<Pos>) -> Left {
+        let frame = self.frames.last_mut().expect("return without call");
+        let key = (frame.rule, frame.pos);
+        let start = self.program.rules[frame.rule].start;
+        let grown = match (frame.best, result) {
+            (None, _) => {
+                let seed = self.memo.insert(key, Memo::Done(result));
+                let left_recursive = matches!(
+                    seed,
+                    Some(Memo::InProgress {
+                        left_recursive: true
+                    })
+                );
+                result.filter(|_| left_recursive)
+            }
+            (Some(best), end) if grows(best, end) => {
+                self.memo.insert(key, Memo::Done(result));
+                end
+            }
+            (Some(_), _) => None,
+        };
+        if let Some(end) = grown {
+            frame.best = Some(end);
+            let pos = frame.pos;
+            for stale in self.log.forget(frame.mark) {
+                self.memo.remove(&stale);
+            }
+            return Left::Again(start, pos);
+        }
+
+        let frame = self.frames.pop().unwrap();
+        self.log
+            .complete(key, self.frames.last().map(|caller| caller.pos));
+        Left::Returned(frame.caller, frame.best.or(result))
+    }
+
+    fn run(&mut self, rule: RuleIndex, pos: Pos) -> Option<Pos> {
+        let bottom = self.frames.len();
+        let (mut pc, mut pos) = match self.enter(rule, pos, 0) {
+            Entered::Done(result) => return result,
+            Entered::Started(start) => (start, pos),
+        };
+        loop {
+            let matched = match self.program.code[pc] {
+                Inst::Char(c) => match self.ctx.source.get(pos) {
+                    Some(found) if found == c => Some(pos + c.len_utf8()),
+                    _ => self.fail(pos, || format!("'{}'", c).into()),
+                },
+                Inst::Keyword(i) => {
+                    let keyword = &self.program.keywords[i];
+                    if self.ctx.source.starts_with_at(pos, keyword) {
+                        Some(pos + keyword.len())
+                    } else {
+                        self.fail(pos, || format!("\"{}\"", keyword).into())
+                    }
+                }
+                Inst::Any => match self.ctx.source.get(pos) {
+                    Some(c) => Some(pos + c.len_utf8()),
+                    None => self.fail(pos, || "(any char)".into()),
+                },
+                Inst::Class(i) => {
+                    let (label, predicate) = &self.program.classes[i];
+                    match self.ctx.source.get(pos) {
+                        Some(c) if predicate(c) => Some(pos + c.len_utf8()),
+                        _ => self.fail(pos, || label.clone()),
+                    }
+                }
+                Inst::Eof => match self.ctx.source.get(pos) {
+                    None => Some(pos),
+                    Some(c) => {
+                        self.record(
+                            pos,
+                            Reason::ExpectedEof {
+                                found: Found::Char(c),
+                            },
+                        );
+                        None
+                    }
+                },
+                Inst::Choice(alternative) => {
+                    self.stack.push((alternative, pos));
+                    Some(pos)
+                }
+                Inst::Commit(target) => {
+                    self.stack.pop();
+                    pc = target;
+                    continue;
+                }
+                Inst::PartialCommit(body) => {
+                    let top = self
+                        .stack
+                        .last_mut()
+                        .expect("partial commit without choice");
+                    if top.1 == pos {
+                        self.stack.pop();
+                        // what the body failed on last at this position is beside the point
+                        if self.farthest.as_ref().is_none_or(|(at, _)| pos >= *at) {
+                            self.farthest = Some((pos, Reason::Message(NO_PROGRESS)));
+                        }
+                        None
+                    } else {
+                        top.1 = pos;
+                        pc = body;
+                        continue;
+                    }
+                }
+                Inst::FailTwice => {
+                    self.stack.pop();
+                    None
+                }
+                Inst::Call(rule) => match self.enter(rule, pos, pc) {
+                    Entered::Done(result) => result,
+                    Entered::Started(start) => {
+                        pc = start;
+                        continue;
+                    }
+                },
+                Inst::Opaque(i) => match (self.program.opaque[i])(pos, &mut self.ctx) {
+                    Ok(end) => Some(end),
+                    Err(e) => {
+                        self.record(e.pos, e.reason);
+                        None
+                    }
+                },
+                Inst::Return => match self.unwind(Some(pos), bottom) {
+                    Ok(resume) => {
+                        (pc, pos) = resume;
+                        continue;
+                    }
+                    Err(result) => return result,
+                },
+            };
+
+            match matched {
+                Some(end) => {
+                    pos = end;
+                    pc += 1;
+                }
+                None => match self.backtrack() {
+                    Some(resume) => (pc, pos) = resume,
+                    // the body failed as a whole, so the call returns a failure
+                    None => match self.unwind(None, bottom) {
+                        Ok(resume) => (pc, pos) = resume,
+                        Err(result) => return result,
+                    },
+                },
+            }
+        }
+    }
+
+    // the innermost call returned `result`: a failure makes its caller backtrack, which
+    // may have to return in turn, so this goes on until some body can carry on from the
+    // address and position it gives back, or the outermost call is left with its result
+    fn unwind(
+        &mut self,
+        mut result: Option<Pos>,
+        bottom: usize,
+    ) -> Result<(Addr, Pos), Option<Pos>> {
+        loop {
+            match self.leave(result) {
+                Left::Again(start, pos) => return Ok((start, pos)),
+                Left::Returned(_, returned) if self.frames.len() == bottom => return Err(returned),
+                Left::Returned(caller, Some(end)) => return Ok((caller + 1, end)),
+                Left::Returned(_, None) => match self.backtrack() {
+                    Some(resume) => return Ok(resume),
+                    None => result = None,
+                },
+            }
+        }
+    }
+
+    // resumes at the innermost choice of the current body, if it has one left
+    fn backtrack(&mut self) -> Option<(Addr, Pos)> {
+        let base = self.frames.last().expect("no call in progress").base;
+        if self.stack.len() > base {
+            self.stack.pop()
+        } else {
+            None
+        }
+    }
+
+    // the label is only built for a failure that will actually be reported
+    fn fail(&mut self, pos: Pos, label: impl FnOnce() -> Text) -> Option<Pos> {
+        if self.farthest.as_ref().is_none_or(|(at, _)| pos > *at) {
+            let found = Found::from(self.ctx.source.get(pos));
+            self.record(
+                pos,
+                Reason::Expected {
+                    label: label(),
+                    found,
+                },
+            );
+        }
+        None
+    }
+
+    // keeps the failure that got the farthest, which is the most useful one to report
+    fn record(&mut self, pos: Pos, reason: Reason) {
+        if self.farthest.as_ref().is_none_or(|(at, _)| pos > *at) {
+            self.farthest = Some((pos, reason));
+        }
+    }
+
+    fn error(&mut self) -> ParseError {
+        let (pos, reason) = self
+            .farthest
+            .take()
+            .unwrap_or((0, Reason::Message("no match")));
+        ParseError {
+            source: self.ctx.clone_source(),
+            pos,
+            reason,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    fn arithmetic() -> Grammar {
+        let mut g = GrammarBuilder::new();
+        g.rule("expr", |g| {
+            g.get::<i32>("expr")
+                .andl(char('+'))
+                .and(g.get::<i32>("term"))
+                .map(|(l, r)| l + r)
+                .or(g.get("term"))
+        })
+        .rule("term", |g| {
+            g.get::<i32>("term")
+                .andl(char('*'))
+                .and(g.get::<i32>("factor"))
+                .map(|(l, r)| l * r)
+                .or(g.get("factor"))
+        })
+        .rule("factor", |g| {
+            satisfy("digit", |c| c.is_ascii_digit())
+                .many()
+                .try_map(|s| s.iter().collect::<String>().parse::<i32>().ok())
+                .or(char('(').andr(g.get("expr")).andl(char(')')))
+        })
+        .rule("file", |g| g.get::<i32>("expr").end());
+        g.build().unwrap()
+    }
+
+    #[test]
+    fn test_recognize() {
+        let grammar = arithmetic();
+        let program = grammar.compile();
+        let expr = grammar.get::<i32>("expr").unwrap();
+
+        for source in ["1", "1+2*3", "(1+2)*3+4", "2*(3+4)+1", "1+", "(1", "+"] {
+            let expected = expr.parse(0, &mut Context::new(source)).map(|(end, _)| end);
+            assert_eq!(program.recognize("expr", source).ok(), expected.ok());
+        }
+        assert_eq!(program.recognize("file", "1+(2*3)"), Ok(7));
+        assert_eq!(program.recognize("file", "1+(2*3").unwrap_err().pos, 6);
+    }
+
+    #[test]
+    fn test_nullable_loop() {
+        let mut g = GrammarBuilder::new();
+        g.rule("spaces", |_| char(' ').opt().many());
+        g.rule("words", |_| string("ab").many().andl(char('x')));
+        let grammar = g.build().unwrap();
+        let program = grammar.compile();
+
+        // a body that matches nothing fails the loop, as it fails `many`
+        let spaces = grammar.get::<Vec<Option<char>>>("spaces").unwrap();
+        let error = program.recognize("spaces", "   x").unwrap_err();
+        assert_eq!(
+            error,
+            spaces.parse(0, &mut Context::new("   x")).unwrap_err()
+        );
+        assert_eq!(error.pos, 3);
+        assert_eq!(program.recognize("words", "ababx"), Ok(5));
+    }
+
+    #[test]
+    fn test_deep_nesting() {
+        let grammar = arithmetic();
+        let program = grammar.compile();
+
+        // far deeper than the native stack of a test thread allows recursive calls
+        let depth = 20_000;
+        let deep = format!("{}1{}", "(".repeat(depth), "+2)".repeat(depth));
+        assert_eq!(program.recognize("file", deep.as_str()), Ok(deep.len()));
+        let unclosed = &deep[..deep.len() - 1];
+        assert_eq!(
+            program.recognize("file", unclosed).unwrap_err().pos,
+            unclosed.len()
+        );
+
+        // parsing it into a value recurses natively, and is only kept from overflowing the
+        // stack by a depth limit that turns it away
+        let file = grammar.get::<i32>("file").unwrap();
+        let ctx = &mut Context::new(deep.as_str()).with_depth_limit(200);
+        let error = file.parse(0, ctx).unwrap_err();
+        assert!(error.to_string().contains("nesting too deep"));
+    }
+}